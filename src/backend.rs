@@ -0,0 +1,114 @@
+use crate::{SshClient, SshStream};
+use anyhow::Result;
+use serde::Deserialize;
+use std::io::Read;
+use std::sync::atomic::AtomicBool;
+
+mod local;
+#[cfg(unix)]
+mod openssh;
+mod process;
+#[cfg(feature = "russh")]
+mod russh;
+
+/// Implementation of the SSH protocol used by an `SshClient`.
+///
+/// The default is [`SshBackend::Libssh2`], which supports every operation of `SshClient`.
+/// Other backends support executing commands (including [`SshClient::execute_with_env`] and
+/// the operations built on it, such as [`SshClient::gather_facts`]), and transferring files
+/// and directories through commands like `cat` and `tar` on the remote host. Other operations,
+/// such as [`SshClient::shell_interactive`], fail with an error.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SshBackend {
+    /// libssh2, through the `ssh2` crate.
+    #[default]
+    Libssh2,
+    /// No SSH at all: commands run in a local `sh` process, and files are copied locally,
+    /// which is useful to include the controller itself in an inventory without running `sshd`
+    /// on it (see `MasshHostConfig::local`).
+    ///
+    /// The user, address and authentication method of the client are ignored.
+    Local,
+    /// The local `ssh` binary, which is run once per command through a shared master connection
+    /// (Unix only).
+    ///
+    /// Settings like proxies, multiplexing, and exotic authentication methods are read from
+    /// `ssh_config` as usual, and host keys are verified against `known_hosts`, so they can't be
+    /// pinned with [`SshClient::set_host_key`]. Agent keys can't be selected by comment or
+    /// fingerprint, and passwords and passphrases require OpenSSH 8.4 or later.
+    #[cfg(unix)]
+    #[serde(rename = "openssh")]
+    OpenSsh,
+    /// russh, a pure-Rust implementation which doesn't link OpenSSL, and thus is easier to
+    /// cross-compile (requires the `russh` feature).
+    ///
+    /// Algorithm preferences aren't supported, and host keys can only be pinned
+    /// to SHA-256 fingerprints.
+    #[cfg(feature = "russh")]
+    Russh,
+}
+
+impl std::fmt::Display for SshBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SshBackend::Libssh2 => write!(f, "libssh2"),
+            SshBackend::Local => write!(f, "local"),
+            #[cfg(unix)]
+            SshBackend::OpenSsh => write!(f, "openssh"),
+            #[cfg(feature = "russh")]
+            SshBackend::Russh => write!(f, "russh"),
+        }
+    }
+}
+
+/// Authenticated connection to a host through a backend other than libssh2.
+pub(crate) trait Connection: Send {
+    /// Runs a command, with its standard input streamed from a reader if specified (or empty
+    /// otherwise), and passes its output to a closure as it's received.
+    ///
+    /// If `stop` is specified, the command isn't subject to the timeout, and is terminated once
+    /// `stop` is set (e.g. for streamed commands).
+    ///
+    /// Returns the exit status of the command, the name of the signal that killed it, if any,
+    /// and whether it dumped core.
+    fn run(
+        &mut self,
+        command: &str,
+        input: Option<&mut dyn Read>,
+        stop: Option<&AtomicBool>,
+        output: &mut dyn FnMut(SshStream, &[u8]) -> Result<()>,
+    ) -> Result<(i32, Option<String>, bool)>;
+
+    /// Returns whether the connection was closed, in which case a new one must be established.
+    fn is_closed(&self) -> bool;
+}
+
+/// Checks that the settings of a client are supported by its configured backend, which requires
+/// a POSIX shell unless it's libssh2.
+pub(crate) fn check(client: &SshClient) -> Result<()> {
+    if client.get_backend() != SshBackend::Libssh2 {
+        client.get_shell().ensure_posix()?;
+    }
+    match client.get_backend() {
+        SshBackend::Libssh2 | SshBackend::Local => Ok(()),
+        #[cfg(unix)]
+        SshBackend::OpenSsh => openssh::check(client),
+        #[cfg(feature = "russh")]
+        SshBackend::Russh => russh::check(client),
+    }
+}
+
+/// Establishes an authenticated connection to the configured host of a client,
+/// through its configured backend other than libssh2.
+pub(crate) fn connect(client: &SshClient) -> Result<Box<dyn Connection>> {
+    client.get_shell().ensure_posix()?;
+    match client.get_backend() {
+        SshBackend::Libssh2 => unreachable!("libssh2 sessions are established by `SshClient`"),
+        SshBackend::Local => Ok(Box::new(local::LocalConnection::connect(client)?)),
+        #[cfg(unix)]
+        SshBackend::OpenSsh => Ok(Box::new(openssh::OpenSshConnection::connect(client)?)),
+        #[cfg(feature = "russh")]
+        SshBackend::Russh => Ok(Box::new(russh::RusshConnection::connect(client)?)),
+    }
+}
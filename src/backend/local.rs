@@ -0,0 +1,84 @@
+use super::{process, Connection};
+use crate::{SshClient, SshStream};
+use anyhow::Result;
+use std::io::Read;
+use std::process::{Command, ExitStatus};
+use std::sync::atomic::AtomicBool;
+
+/// Connection to the local host itself, which runs each command in a local `sh` process
+/// instead of going through SSH.
+pub(crate) struct LocalConnection {
+    timeout: u64,
+}
+
+impl LocalConnection {
+    /// Establishes a connection to the local host, which never fails.
+    pub(crate) fn connect(client: &SshClient) -> Result<Self> {
+        Ok(LocalConnection {
+            timeout: client.get_timeout(),
+        })
+    }
+}
+
+impl Connection for LocalConnection {
+    fn run(
+        &mut self,
+        command: &str,
+        input: Option<&mut dyn Read>,
+        stop: Option<&AtomicBool>,
+        output: &mut dyn FnMut(SshStream, &[u8]) -> Result<()>,
+    ) -> Result<(i32, Option<String>, bool)> {
+        // Run the shell in its own process group, so that all the processes of the command are
+        // terminated if it's stopped.
+        let mut sh = Command::new("sh");
+        sh.arg("-c").arg(command);
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut sh, 0);
+        let status = process::run(&mut sh, input, stop, output, self.timeout)?;
+        let exit_status = status.code().unwrap_or(-1);
+        Ok((exit_status, exit_signal(status), core_dumped(status)))
+    }
+
+    fn is_closed(&self) -> bool {
+        false
+    }
+}
+
+/// Returns the name of the signal that killed a process, without the `SIG` prefix, if any.
+#[cfg(unix)]
+fn exit_signal(status: ExitStatus) -> Option<String> {
+    use std::os::unix::process::ExitStatusExt;
+    let name = match status.signal()? {
+        libc::SIGABRT => "ABRT",
+        libc::SIGHUP => "HUP",
+        libc::SIGINT => "INT",
+        libc::SIGKILL => "KILL",
+        libc::SIGPIPE => "PIPE",
+        libc::SIGQUIT => "QUIT",
+        libc::SIGSEGV => "SEGV",
+        libc::SIGTERM => "TERM",
+        libc::SIGUSR1 => "USR1",
+        libc::SIGUSR2 => "USR2",
+        signal => return Some(signal.to_string()),
+    };
+    Some(name.to_string())
+}
+
+/// Returns the name of the signal that killed a process, which is always `None` on Windows.
+#[cfg(not(unix))]
+fn exit_signal(_: ExitStatus) -> Option<String> {
+    None
+}
+
+/// Returns whether a process dumped core when it was killed by a signal.
+#[cfg(unix)]
+fn core_dumped(status: ExitStatus) -> bool {
+    use std::os::unix::process::ExitStatusExt;
+    status.core_dumped()
+}
+
+/// Returns whether a process dumped core, which is always false on Windows.
+#[cfg(not(unix))]
+fn core_dumped(_: ExitStatus) -> bool {
+    false
+}
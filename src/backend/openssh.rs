@@ -0,0 +1,241 @@
+use super::{process, Connection};
+use crate::ssh_client::run_secret_command;
+use crate::{MethodType, SshAuth, SshClient, SshStream};
+use anyhow::Result;
+use std::io::{Read, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Connection to a host through the local `ssh` binary: a master process which authenticates
+/// once, and whose control socket is then shared by one `ssh` process per command.
+pub(crate) struct OpenSshConnection {
+    args: Vec<String>,
+    dir: PathBuf,
+    master: Child,
+    timeout: u64,
+}
+
+impl OpenSshConnection {
+    /// Establishes an authenticated connection to the configured host of a client.
+    pub(crate) fn connect(client: &SshClient) -> Result<Self> {
+        // Check that no unsupported settings are configured.
+        check(client)?;
+
+        // Start the master process, with a private directory for its control socket, its log,
+        // and the askpass script.
+        let dir = create_private_dir()?;
+        let (args, master) = match spawn_master(client, &dir) {
+            Ok(spawned) => spawned,
+            Err(e) => {
+                let _ = std::fs::remove_dir_all(&dir);
+                return Err(e);
+            }
+        };
+        let mut connection = OpenSshConnection {
+            args,
+            dir,
+            master,
+            timeout: client.get_timeout(),
+        };
+
+        // Wait until the master process is authenticated and listening on its control socket,
+        // or until it fails.
+        let start = Instant::now();
+        while connection.is_closed() {
+            if connection.master.try_wait()?.is_some() {
+                let log = std::fs::read_to_string(connection.dir.join("log")).unwrap_or_default();
+                return Err(anyhow::anyhow!("ssh failed: {}", log.trim_end()));
+            }
+            let timeout = Duration::from_millis(connection.timeout);
+            if connection.timeout != 0 && start.elapsed() > timeout {
+                return Err(anyhow::anyhow!("Timed out waiting for ssh to authenticate"));
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        Ok(connection)
+    }
+}
+
+impl Connection for OpenSshConnection {
+    fn run(
+        &mut self,
+        command: &str,
+        input: Option<&mut dyn Read>,
+        stop: Option<&AtomicBool>,
+        output: &mut dyn FnMut(SshStream, &[u8]) -> Result<()>,
+    ) -> Result<(i32, Option<String>, bool)> {
+        // Run command through the control socket of the master process.
+        let mut ssh = Command::new("ssh");
+        ssh.args(["-T", "-o", "ControlMaster=no"])
+            .args(&self.args)
+            .arg(command);
+        let status = process::run(&mut ssh, input, stop, output, self.timeout)?;
+
+        // Retrieve the exit status, which is 255 if `ssh` itself failed. The `ssh` binary doesn't
+        // report the signal that killed the command, if any.
+        Ok((status.code().unwrap_or(255), None, false))
+    }
+
+    fn is_closed(&self) -> bool {
+        !self.dir.join("control").exists()
+    }
+}
+
+impl Drop for OpenSshConnection {
+    fn drop(&mut self) {
+        let _ = self.master.kill();
+        let _ = self.master.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Checks that the settings of a client are supported by the openssh backend.
+pub(crate) fn check(client: &SshClient) -> Result<()> {
+    if client.get_host_key().is_some() {
+        return Err(anyhow::anyhow!(
+            "Host key pinning is not supported by the openssh backend (use known_hosts instead)"
+        ));
+    }
+    if let SshAuth::AgentKey(_) | SshAuth::AgentSocket { key: Some(_), .. } = client.get_auth() {
+        return Err(anyhow::anyhow!(
+            "Selecting an agent key is not supported by the openssh backend"
+        ));
+    }
+    Ok(())
+}
+
+/// Spawns the master process of a connection, and returns the arguments shared by all processes
+/// (i.e. the options translated from the settings of the client, and the destination).
+fn spawn_master(client: &SshClient, dir: &Path) -> Result<(Vec<String>, Child)> {
+    // Translate the settings of the client into options.
+    let mut options = vec![format!("ControlPath={}", dir.join("control").display())];
+    if client.get_timeout() != 0 {
+        let secs = client.get_timeout().div_ceil(1000);
+        options.push(format!("ConnectTimeout={}", secs));
+    }
+    if client.get_keepalive() != 0 {
+        options.push(format!("ServerAliveInterval={}", client.get_keepalive()));
+    }
+    if client.get_compression() {
+        options.push("Compression=yes".to_string());
+    }
+    let methods = [
+        (MethodType::Kex, "KexAlgorithms"),
+        (MethodType::HostKey, "HostKeyAlgorithms"),
+        (MethodType::CryptCs, "Ciphers"),
+        (MethodType::MacCs, "MACs"),
+    ];
+    for (method, option) in methods {
+        if let Some(prefs) = client.get_method_pref(method) {
+            options.push(format!("{}={}", option, prefs));
+        }
+    }
+    let mut args = Vec::new();
+    for option in options {
+        args.extend(["-o".to_string(), option]);
+    }
+    let port = client.get_addr().port().to_string();
+    let user = client.get_user().to_string();
+    args.extend(["-p".to_string(), port, "-l".to_string(), user]);
+    args.push(client.get_addr().ip().to_string());
+
+    // Translate the authentication method into options of the master process, and its secret,
+    // if any, which is passed to `ssh` through an askpass script.
+    let mut master = Command::new("ssh");
+    let (auth_options, secret) = match client.get_auth() {
+        SshAuth::Agent | SshAuth::AgentKey(_) => (vec![], None),
+        SshAuth::AgentSocket { path, .. } => {
+            (vec![format!("IdentityAgent={}", path.display())], None)
+        }
+        SshAuth::Password(password) => (password_options(), Some(password.clone())),
+        SshAuth::PasswordCmd(command) => (password_options(), Some(run_secret_command(command)?)),
+        SshAuth::Pubkey(path) => (identity_options(path), None),
+        SshAuth::PubkeyPassphraseCmd {
+            path,
+            passphrase_cmd,
+        } => (
+            identity_options(path),
+            Some(run_secret_command(passphrase_cmd)?),
+        ),
+    };
+    match secret {
+        Some(secret) => {
+            let askpass_path = dir.join("askpass");
+            write_askpass_script(&askpass_path)?;
+            master
+                .env("SSH_ASKPASS", askpass_path)
+                .env("SSH_ASKPASS_REQUIRE", "force")
+                .env("MASSH_ASKPASS_SECRET", secret);
+        }
+        None => {
+            master.args(["-o", "BatchMode=yes"]);
+        }
+    }
+    for option in auth_options {
+        master.args(["-o", &option]);
+    }
+
+    // Spawn the master process, which doesn't run any command, and logs to a file.
+    let master = master
+        .args(["-M", "-N", "-E"])
+        .arg(dir.join("log"))
+        .args(&args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("Failed to run ssh: {}", e))?;
+    Ok((args, master))
+}
+
+/// Returns the options of the master process for password authentication.
+fn password_options() -> Vec<String> {
+    vec!["PreferredAuthentications=keyboard-interactive,password".to_string()]
+}
+
+/// Returns the options of the master process for public key authentication with a private key file.
+fn identity_options(path: &Path) -> Vec<String> {
+    vec![
+        format!("IdentityFile={}", path.display()),
+        "IdentitiesOnly=yes".to_string(),
+    ]
+}
+
+/// Creates a new, uniquely named temporary directory only accessible by the current user.
+fn create_private_dir() -> Result<PathBuf> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    loop {
+        let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let name = format!("massh-openssh-{}-{}", std::process::id(), count);
+        let path = std::env::temp_dir().join(name);
+        match std::fs::DirBuilder::new().mode(0o700).create(&path) {
+            Ok(()) => return Ok(path),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Writes the askpass script, which prints the secret passed in its environment by the
+/// master process, so that the secret never touches the disk.
+///
+/// Only password and passphrase prompts are answered with the secret: any other prompt (e.g.
+/// whether to trust an unknown host key) is declined, like with `BatchMode=yes`.
+fn write_askpass_script(path: &Path) -> Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o700)
+        .open(path)?;
+    file.write_all(
+        b"#!/bin/sh\n\
+        case \"$1\" in\n\
+        *[Pp]assword*|*[Pp]assphrase*) printf '%s\\n' \"$MASSH_ASKPASS_SECRET\" ;;\n\
+        *) exit 1 ;;\n\
+        esac\n",
+    )?;
+    Ok(())
+}
@@ -0,0 +1,132 @@
+use crate::SshStream;
+use anyhow::Result;
+use std::io::Read;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// Interval at which `run` checks whether a process must be stopped.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Runs a local process, with its standard input streamed from a reader if specified (or empty
+/// otherwise), and passes its output to a closure as it's received, within the timeout
+/// (in milliseconds) between chunks of output, if non-zero.
+///
+/// If `stop` is specified, the process isn't subject to the timeout, and is killed (with its
+/// process group, if it leads one) once `stop` is set.
+pub(super) fn run(
+    command: &mut Command,
+    input: Option<&mut dyn Read>,
+    stop: Option<&AtomicBool>,
+    output: &mut dyn FnMut(SshStream, &[u8]) -> Result<()>,
+    timeout: u64,
+) -> Result<ExitStatus> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let mut child = command
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("Failed to run {}: {}", program, e))?;
+
+    // Read stdout and stderr concurrently in threads, which send their chunks in
+    // arrival order.
+    let (sender, receiver) = mpsc::channel();
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+    let readers = [
+        forward_chunks(stdout, SshStream::Stdout, sender.clone()),
+        forward_chunks(stderr, SshStream::Stderr, sender),
+    ];
+
+    // Stream the standard input, if any, then send EOF by closing it.
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        if let Err(e) = std::io::copy(input, &mut stdin) {
+            kill(&mut child);
+            let _ = child.wait();
+            return Err(e.into());
+        }
+    }
+
+    // Pass the output to the closure until both streams are closed, within the timeout, or
+    // until stopped.
+    loop {
+        if stop.is_some_and(|stop| stop.load(Ordering::Relaxed)) {
+            kill(&mut child);
+            return Ok(child.wait()?);
+        }
+        let chunk = if stop.is_some() {
+            match receiver.recv_timeout(POLL_INTERVAL) {
+                Ok(chunk) => Some(chunk),
+                Err(mpsc::RecvTimeoutError::Disconnected) => None,
+                Err(mpsc::RecvTimeoutError::Timeout) => continue,
+            }
+        } else if timeout == 0 {
+            receiver.recv().ok()
+        } else {
+            match receiver.recv_timeout(Duration::from_millis(timeout)) {
+                Ok(chunk) => Some(chunk),
+                Err(mpsc::RecvTimeoutError::Disconnected) => None,
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    kill(&mut child);
+                    let _ = child.wait();
+                    return Err(anyhow::anyhow!(
+                        "Timed out waiting for the output of the command"
+                    ));
+                }
+            }
+        };
+        match chunk {
+            Some((stream, Ok(bytes))) => output(stream, &bytes)?,
+            Some((_, Err(e))) => {
+                kill(&mut child);
+                let _ = child.wait();
+                return Err(e.into());
+            }
+            None => break,
+        }
+    }
+    for reader in readers {
+        let _ = reader.join();
+    }
+
+    Ok(child.wait()?)
+}
+
+/// Kills a process, and its process group if it leads one.
+fn kill(child: &mut Child) {
+    // A process group whose identifier is the PID of the process only exists if it leads it.
+    #[cfg(unix)]
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    let _ = child.kill();
+}
+
+/// Spawns a thread which reads a stream in chunks and sends them to a channel until EOF.
+fn forward_chunks(
+    mut reader: impl Read + Send + 'static,
+    stream: SshStream,
+    sender: mpsc::Sender<(SshStream, std::io::Result<Vec<u8>>)>,
+) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || {
+        let mut buffer = vec![0; 32 * 1024];
+        loop {
+            let chunk = match reader.read(&mut buffer) {
+                Ok(0) => return,
+                Ok(len) => Ok(buffer[..len].to_vec()),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => Err(e),
+            };
+            let failed = chunk.is_err();
+            if sender.send((stream, chunk)).is_err() || failed {
+                return;
+            }
+        }
+    })
+}
@@ -0,0 +1,333 @@
+use super::Connection;
+use crate::server_info::is_fingerprint;
+use crate::ssh_client::{connect_any, run_secret_command};
+use crate::{MethodType, SshAuth, SshClient, SshStream};
+use anyhow::Result;
+use russh::client::{self, Handle};
+use russh::keys::agent::client::AgentClient;
+use russh::keys::{HashAlg, PrivateKeyWithHashAlg, PublicKey};
+use russh::{ChannelMsg, Preferred, Sig};
+use std::borrow::Cow;
+use std::io::Read;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::runtime::Runtime;
+
+/// Interval at which `RusshConnection::run` checks whether a streamed command must be stopped.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Connection to a host through russh, driven by its own single-threaded Tokio runtime.
+pub(crate) struct RusshConnection {
+    handle: Handle<Handler>,
+    runtime: Runtime,
+    timeout: u64,
+}
+
+/// Handler of the events of a russh session, which verifies the host key.
+struct Handler {
+    host_key: Option<String>,
+}
+
+impl client::Handler for Handler {
+    type Error = anyhow::Error;
+
+    async fn check_server_key(&mut self, server_public_key: &PublicKey) -> Result<bool> {
+        let expected = match &self.host_key {
+            Some(expected) => expected,
+            None => return Ok(true),
+        };
+        if !is_fingerprint(expected) {
+            return Err(anyhow::anyhow!(
+                "Invalid host key fingerprint {:?}",
+                expected
+            ));
+        }
+        let actual = server_public_key.fingerprint(HashAlg::Sha256).to_string();
+        if actual != expected.trim_end_matches('=') {
+            return Err(anyhow::anyhow!(
+                "Host key verification failed: expected {}, but the server's key is {}",
+                expected,
+                actual
+            ));
+        }
+        Ok(true)
+    }
+}
+
+impl RusshConnection {
+    /// Establishes an authenticated connection to the configured host of a client.
+    pub(crate) fn connect(client: &SshClient) -> Result<Self> {
+        // Check that no unsupported settings are configured.
+        check(client)?;
+
+        // Configure the session.
+        let mut config = client::Config::default();
+        if client.get_compression() {
+            config.preferred = Preferred {
+                compression: Cow::Borrowed(&[
+                    russh::compression::ZLIB,
+                    russh::compression::ZLIB_LEGACY,
+                    russh::compression::NONE,
+                ]),
+                ..Preferred::default()
+            };
+        }
+        if client.get_keepalive() != 0 {
+            let interval = Duration::from_secs(client.get_keepalive() as u64);
+            config.keepalive_interval = Some(interval);
+        }
+        let handler = Handler {
+            host_key: client.get_host_key().map(str::to_string),
+        };
+
+        // Connect and authenticate, within the timeout if non-zero.
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let timeout = client.get_timeout();
+        let (stream, _) = connect_any(&client.addrs_to_try(), timeout)?;
+        stream.set_nonblocking(true)?;
+        let handle = runtime.block_on(with_timeout(timeout, async {
+            let stream = tokio::net::TcpStream::from_std(stream)?;
+            let mut handle = client::connect_stream(Arc::new(config), stream, handler).await?;
+            if !authenticate(&mut handle, client.get_user(), client.get_auth()).await? {
+                return Err(anyhow::anyhow!("Authentication failed"));
+            }
+            Ok(handle)
+        }))?;
+
+        Ok(RusshConnection {
+            handle,
+            runtime,
+            timeout,
+        })
+    }
+}
+
+impl Connection for RusshConnection {
+    fn run(
+        &mut self,
+        command: &str,
+        input: Option<&mut dyn Read>,
+        stop: Option<&AtomicBool>,
+        output: &mut dyn FnMut(SshStream, &[u8]) -> Result<()>,
+    ) -> Result<(i32, Option<String>, bool)> {
+        // Streamed commands aren't subject to the timeout, but are polled to check `stop`.
+        let handle = &self.handle;
+        let timeout = if stop.is_some() { 0 } else { self.timeout };
+        self.runtime.block_on(async {
+            // Open channel and execute command.
+            let mut channel = handle.channel_open_session().await?;
+            channel.exec(true, command).await?;
+
+            // Stream the standard input, if any, then send EOF, while receiving the output until
+            // the channel is closed, so that a command which doesn't read all of its input
+            // before writing its output doesn't deadlock.
+            let mut input = input;
+            let mut writer = channel.make_writer();
+            let (mut buffer, mut pending) = (vec![0; 32 * 1024], 0..0);
+            let mut eof_sent = false;
+            let (mut exit_status, mut exit_signal, mut core_dumped) = (0, None, false);
+            loop {
+                if pending.is_empty() && !eof_sent {
+                    match input.as_mut().map(|input| input.read(&mut buffer)) {
+                        Some(Ok(0)) | None => {
+                            channel.eof().await?;
+                            eof_sent = true;
+                        }
+                        Some(Ok(len)) => pending = 0..len,
+                        Some(Err(e)) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                        Some(Err(e)) => return Err(e.into()),
+                    }
+                }
+                let message = tokio::select! {
+                    written = writer.write(&buffer[pending.clone()]), if !pending.is_empty() => {
+                        pending.start += written?;
+                        continue;
+                    }
+                    _ = tokio::time::sleep(POLL_INTERVAL), if stop.is_some() => {
+                        if stop.is_some_and(|stop| stop.load(Ordering::Relaxed)) {
+                            let _ = channel.close().await;
+                            break;
+                        }
+                        continue;
+                    }
+                    message = with_timeout(timeout, async { Ok(channel.wait().await) }) => {
+                        message.map_err(|_| {
+                            anyhow::anyhow!("Timed out waiting for the output of the command")
+                        })?
+                    }
+                };
+                match message {
+                    Some(ChannelMsg::Data { data }) => output(SshStream::Stdout, &data)?,
+                    Some(ChannelMsg::ExtendedData { data, ext: 1 }) => {
+                        output(SshStream::Stderr, &data)?
+                    }
+                    Some(ChannelMsg::ExitStatus {
+                        exit_status: status,
+                    }) => exit_status = status as i32,
+                    Some(ChannelMsg::ExitSignal {
+                        signal_name,
+                        core_dumped: dumped,
+                        ..
+                    }) => {
+                        exit_signal = Some(match signal_name {
+                            Sig::Custom(name) => name,
+                            signal => format!("{:?}", signal),
+                        });
+                        core_dumped = dumped;
+                    }
+                    Some(ChannelMsg::Failure) => {
+                        return Err(anyhow::anyhow!("Failed to execute the command"))
+                    }
+                    Some(ChannelMsg::Close) | None => break,
+                    Some(_) => {}
+                }
+            }
+            Ok((exit_status, exit_signal, core_dumped))
+        })
+    }
+
+    fn is_closed(&self) -> bool {
+        self.handle.is_closed()
+    }
+}
+
+/// Checks that the settings of a client are supported by the russh backend.
+pub(crate) fn check(client: &SshClient) -> Result<()> {
+    let methods = [
+        MethodType::Kex,
+        MethodType::HostKey,
+        MethodType::CryptCs,
+        MethodType::CryptSc,
+        MethodType::MacCs,
+        MethodType::MacSc,
+    ];
+    if methods.iter().any(|m| client.get_method_pref(*m).is_some()) {
+        return Err(anyhow::anyhow!(
+            "Algorithm preferences are not supported by the russh backend"
+        ));
+    }
+    // Invalid fingerprints are reported when the host key is verified.
+    if let Some(host_key) = client.get_host_key() {
+        if is_fingerprint(host_key) && !host_key.starts_with("SHA256:") {
+            return Err(anyhow::anyhow!(
+                "Only SHA256 host key fingerprints are supported by the russh backend"
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Runs a future to completion, failing if it takes longer than the timeout, in milliseconds,
+/// unless it's zero.
+async fn with_timeout<T>(
+    timeout: u64,
+    future: impl std::future::Future<Output = Result<T>>,
+) -> Result<T> {
+    if timeout == 0 {
+        return future.await;
+    }
+    match tokio::time::timeout(Duration::from_millis(timeout), future).await {
+        Ok(result) => result,
+        Err(_) => Err(anyhow::anyhow!("Timed out")),
+    }
+}
+
+/// Performs authentication with the specified method, and returns whether it succeeded.
+async fn authenticate(handle: &mut Handle<Handler>, user: &str, auth: &SshAuth) -> Result<bool> {
+    match auth {
+        SshAuth::Agent => authenticate_agent(handle, user, None, None).await,
+        SshAuth::AgentKey(key) => authenticate_agent(handle, user, None, Some(key)).await,
+        SshAuth::AgentSocket { path, key } => {
+            authenticate_agent(handle, user, Some(path), key.as_deref()).await
+        }
+        SshAuth::Password(password) => Ok(handle
+            .authenticate_password(user, password)
+            .await?
+            .success()),
+        SshAuth::PasswordCmd(command) => {
+            let password = run_secret_command(command)?;
+            Ok(handle
+                .authenticate_password(user, password)
+                .await?
+                .success())
+        }
+        SshAuth::Pubkey(path) => authenticate_key(handle, user, path, None).await,
+        SshAuth::PubkeyPassphraseCmd {
+            path,
+            passphrase_cmd,
+        } => {
+            let passphrase = run_secret_command(passphrase_cmd)?;
+            authenticate_key(handle, user, path, Some(&passphrase)).await
+        }
+    }
+}
+
+/// Performs public key authentication with a private key file.
+async fn authenticate_key(
+    handle: &mut Handle<Handler>,
+    user: &str,
+    path: &Path,
+    passphrase: Option<&str>,
+) -> Result<bool> {
+    let key = russh::keys::load_secret_key(path, passphrase)
+        .map_err(|e| anyhow::anyhow!("Failed to load private key {:?}: {}", path, e))?;
+    let hash_alg = handle.best_supported_rsa_hash().await?.flatten();
+    let key = PrivateKeyWithHashAlg::new(Arc::new(key), hash_alg);
+    Ok(handle.authenticate_publickey(user, key).await?.success())
+}
+
+/// Performs agent authentication with the agent listening on a socket if specified (or the
+/// default agent otherwise), with the key matching a SHA-256 fingerprint or comment if specified
+/// (or each key in order until one is accepted otherwise).
+async fn authenticate_agent(
+    handle: &mut Handle<Handler>,
+    user: &str,
+    socket: Option<&Path>,
+    key: Option<&str>,
+) -> Result<bool> {
+    #[cfg(unix)]
+    let agent = match socket {
+        Some(socket) => AgentClient::connect_uds(socket).await,
+        None => AgentClient::connect_env().await,
+    };
+    #[cfg(windows)]
+    let agent = match socket {
+        Some(socket) => AgentClient::connect_named_pipe(socket).await,
+        None => Ok(AgentClient::connect_pageant().await),
+    };
+    let mut agent = agent.map_err(|e| anyhow::anyhow!("Failed to connect to the agent: {}", e))?;
+
+    let identities: Vec<PublicKey> = agent
+        .request_identities()
+        .await?
+        .into_iter()
+        .filter(|identity| match key {
+            Some(key) => {
+                identity.comment() == key
+                    || identity.fingerprint(HashAlg::Sha256).to_string() == key
+            }
+            None => true,
+        })
+        .collect();
+    if identities.is_empty() {
+        return Err(match key {
+            Some(key) => anyhow::anyhow!("No key matching {:?} found in the agent", key),
+            None => anyhow::anyhow!("No keys found in the agent"),
+        });
+    }
+    let hash_alg = handle.best_supported_rsa_hash().await?.flatten();
+    for identity in identities {
+        let result = handle
+            .authenticate_publickey_with(user, identity, hash_alg, &mut agent)
+            .await?;
+        if result.success() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
@@ -0,0 +1,56 @@
+use crate::SshClient;
+use anyhow::Result;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+/// Durations of the phases of a round trip to a host, measured by [`SshClient::bench`].
+///
+/// With backends other than libssh2, the handshake and the authentication are part of the
+/// connection, so their durations are zero.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct SshLatency {
+    /// Time to authenticate the user
+    pub auth: Duration,
+    /// Time to execute a trivial command on the established session
+    pub command: Duration,
+    /// Time to open the TCP connection to the host
+    pub connect: Duration,
+    /// Time of the SSH handshake, i.e. the key exchange and the verification of the host key
+    pub handshake: Duration,
+}
+
+impl SshLatency {
+    /// Returns the total duration of the round trip.
+    pub fn total(&self) -> Duration {
+        self.connect + self.handshake + self.auth + self.command
+    }
+}
+
+impl SshClient {
+    /// Attempts to measure the latency of the configured host, by establishing a new session
+    /// and executing a trivial command (`exit 0`) on it.
+    ///
+    /// It returns the duration of each phase, which helps locate slow name resolution, packet
+    /// loss, or overloaded bastions. See [`SshLatency`] for more details. The cached session,
+    /// if any, is replaced by the new one.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// if let Ok(latency) = ssh.bench() {
+    ///     println!("handshake: {:?}, auth: {:?}", latency.handshake, latency.auth);
+    /// }
+    /// ```
+    pub fn bench(&mut self) -> Result<SshLatency> {
+        self.disconnect();
+        self.connect()?;
+        let mut latency = self.connect_latency();
+        let start = Instant::now();
+        self.execute_checked("exit 0", "Command failed")?;
+        latency.command = start.elapsed();
+        Ok(latency)
+    }
+}
@@ -0,0 +1,111 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Local archive in which the `--archive` option of `scp-download` combines the files of all
+/// hosts, written by the local `tar` or `zip` program depending on its extension.
+pub struct Archive {
+    path: PathBuf,
+    format: Format,
+    dir: PathBuf,
+}
+
+/// Formats of the archives written by the `--archive` option.
+enum Format {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl Archive {
+    /// Constructs the archive at a path ending with `.tar`, `.tar.gz`, `.tgz` or `.zip`, whose
+    /// files are first downloaded in a temporary directory.
+    pub fn new(path: &Path) -> Result<Self> {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let format = if name.ends_with(".tar") {
+            Format::Tar
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Format::TarGz
+        } else if name.ends_with(".zip") {
+            Format::Zip
+        } else {
+            return Err(anyhow::anyhow!(
+                "Archive {:?} must end with .tar, .tar.gz, .tgz or .zip",
+                path
+            ));
+        };
+        let dir = std::env::temp_dir().join(format!("massh-archive-{}", std::process::id()));
+        Ok(Archive {
+            path: std::env::current_dir()?.join(path),
+            format,
+            dir,
+        })
+    }
+
+    /// Creates the temporary directory in which the files of all hosts are downloaded.
+    pub fn create_dir(&self) -> Result<&Path> {
+        std::fs::create_dir_all(&self.dir).map_err(|error| {
+            anyhow::anyhow!("Failed to create directory {:?}: {}", self.dir, error)
+        })?;
+        Ok(&self.dir)
+    }
+
+    /// Writes the archive with the downloaded files of the temporary directory, as entries
+    /// named after their path relative to it, replacing the archive if it exists.
+    pub fn write(&self, files: &[PathBuf]) -> Result<()> {
+        if files.is_empty() {
+            return Err(anyhow::anyhow!(
+                "No file was downloaded, so {:?} wasn't written",
+                self.path
+            ));
+        }
+        let entries = files
+            .iter()
+            .map(|file| file.strip_prefix(&self.dir).unwrap_or(file));
+
+        let mut command = match self.format {
+            Format::Tar | Format::TarGz => {
+                let mode = if let Format::TarGz = self.format {
+                    "czf"
+                } else {
+                    "cf"
+                };
+                let mut tar = Command::new("tar");
+                tar.arg(mode).arg(&self.path).arg("--");
+                tar
+            }
+            Format::Zip => {
+                // Unlike `tar`, `zip` adds the entries to an existing archive.
+                if let Err(error) = std::fs::remove_file(&self.path) {
+                    if error.kind() != std::io::ErrorKind::NotFound {
+                        return Err(error.into());
+                    }
+                }
+                let mut zip = Command::new("zip");
+                zip.arg("-qr").arg(&self.path).arg("--");
+                zip
+            }
+        };
+        let program = command.get_program().to_string_lossy().into_owned();
+        let output = command
+            .args(entries)
+            .current_dir(&self.dir)
+            .output()
+            .map_err(|error| anyhow::anyhow!("Failed to run local {}: {}", program, error))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(anyhow::anyhow!(
+                "Local {} failed: {}",
+                program,
+                stderr.trim_end()
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Archive {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
@@ -0,0 +1,2396 @@
+mod archive;
+mod report;
+
+use ansi_term::Color::{self, Blue, Cyan, Green, Purple, Red, Yellow};
+use ansi_term::{ANSIString, Style};
+use anyhow::Error;
+use archive::Archive;
+use indicatif::{ProgressBar, ProgressStyle};
+use massh::{
+    DirTransfer, MasshClient, MasshConfig, MasshDiagnosticKind, MasshDownloadLayout,
+    MasshDuplicatePolicy, MasshFailurePolicy, MasshHost, MasshHostSelector, MasshObserver,
+    MasshPlan, MasshReceiver, MasshSeverity, MasshSkipped, SshFacts, SshLatency, SshOutput,
+    SshServerInfo, SshStream, SyncOptions, SyncReport,
+};
+use report::{HostReport, Report};
+use similar::TextDiff;
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use structopt::StructOpt;
+
+/// A Rust version of the parallel SSH program pssh(1), configured with JSON or YAML.
+///
+/// For more details and examples, check the documentation:
+/// https://docs.rs/massh/latest/massh/struct.MasshConfig.html
+#[derive(StructOpt)]
+struct Opt {
+    #[structopt(subcommand)]
+    cmd: Command,
+    /// Prompts for the password which sudo asks for to execute commands as the become user
+    /// (overrides the configuration file)
+    #[structopt(long)]
+    ask_become_password: bool,
+    /// Executes commands as this user through sudo (overrides the configuration file)
+    #[structopt(long)]
+    become_user: Option<String>,
+    /// Prints the operation and the number of hosts, then asks to type "yes" before running it
+    #[structopt(long)]
+    confirm: bool,
+    /// Prints the hosts and the exact command or paths of the operation on each host, without
+    /// connecting to them
+    #[structopt(long)]
+    dry_run: bool,
+    /// Path of JSON file listing the hosts to run on, as saved by --save-failed
+    #[structopt(long)]
+    from_failed: Option<PathBuf>,
+    /// Seconds to wait for the hosts which are still running after Ctrl-C, before reporting
+    /// them as interrupted (a second Ctrl-C quits immediately)
+    #[structopt(long, default_value = "10")]
+    grace_period: u64,
+    /// Maximum number of failed hosts after which the remaining hosts are skipped
+    /// (overrides the configuration file)
+    #[structopt(long)]
+    max_failures: Option<usize>,
+    /// Disables colors, which are also disabled if the standard output isn't a terminal
+    #[structopt(long)]
+    no_color: bool,
+    /// Only prints the status of each host, without its output
+    #[structopt(short, long)]
+    quiet: bool,
+    /// Path of JSON file in which to save the hosts that failed, had a nonzero exit status,
+    /// or were skipped, to retry them with --from-failed
+    #[structopt(long)]
+    save_failed: Option<PathBuf>,
+    /// Restricts the hosts to those selected by an expression on their group, labels and
+    /// identifier (e.g. "group:web and label:dc=fra and not host:~canary"), combining
+    /// group:PATTERN, label:KEY[=PATTERN] and host:PATTERN with and, or, not and parentheses,
+    /// where a pattern is a glob pattern or a substring prefixed with ~
+    #[structopt(long)]
+    select: Option<MasshHostSelector>,
+    /// Only prints the number of hosts for each outcome
+    #[structopt(long)]
+    summary_only: bool,
+    /// Prints the connection time, the command's run time and the errors of each host to the
+    /// standard error, and each connection attempt if repeated (-vv)
+    #[structopt(short, long, parse(from_occurrences))]
+    verbose: u8,
+    /// Path of JSON configuration file
+    #[structopt(short, long, conflicts_with("yaml"), required_unless("yaml"))]
+    json: Option<PathBuf>,
+    /// Path of YAML configuration file
+    #[structopt(short, long, conflicts_with("json"), required_unless("json"))]
+    yaml: Option<PathBuf>,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// Checks the configuration file for likely mistakes
+    CheckConfig,
+    /// Authorizes a public key on the configured hosts (like ssh-copy-id)
+    CopyId {
+        /// Path of the public key file on local machine (e.g. ~/.ssh/id_ed25519.pub)
+        #[structopt(short, long)]
+        pubkey: PathBuf,
+    },
+    /// Executes a command on the configured hosts
+    Execute {
+        /// Command to be executed over SSH, or `-` to read it from the standard input
+        #[structopt(required_unless("command-file"), conflicts_with("command-file"))]
+        command: Option<String>,
+        /// Path of a file containing the command to be executed over SSH (e.g. a multi-line
+        /// script)
+        #[structopt(long)]
+        command_file: Option<PathBuf>,
+        /// Prints standard output and standard error merged in arrival order
+        #[structopt(short, long)]
+        merge: bool,
+        /// Prefixes each line of output with the time elapsed since the command was executed
+        /// when it was received (which implies --merge)
+        #[structopt(short, long)]
+        timestamps: bool,
+        /// Output format, either `text` or `csv` (one row per host with its host, ip, port,
+        /// exit_status, duration_ms and error)
+        #[structopt(short, long, default_value = "text", parse(try_from_str = parse_output_format))]
+        output: OutputFormat,
+        /// Adds a stdout column to the CSV output, truncated to the given number of bytes
+        #[structopt(long)]
+        csv_stdout: Option<usize>,
+        /// Prints the results once all hosts are done, sorted by `host`, `status` (success,
+        /// warning, failure, then skipped) or `duration` (slowest first)
+        #[structopt(long, parse(try_from_str = parse_sort_key))]
+        sort: Option<SortKey>,
+        /// Prints the results once all hosts are done, grouped by `status`
+        #[structopt(long, parse(try_from_str = parse_group_by))]
+        group_by: Option<GroupBy>,
+        /// Shows a progress bar of the hosts which are done, running and failed on the standard
+        /// error, and prints the results once all hosts are done
+        #[structopt(long)]
+        progress: bool,
+        /// Writes a report of the results of each host once all hosts are done, as KIND:PATH
+        /// where KIND is `junit` (a JUnit XML report) or `html` (a standalone HTML report),
+        /// which can be repeated
+        #[structopt(long = "report", number_of_values = 1)]
+        reports: Vec<Report>,
+        /// Directory in which to write the exact standard output and standard error of each
+        /// host, as files named `username@ip_address:port.stdout` and `.stderr`
+        #[structopt(long)]
+        raw_outdir: Option<PathBuf>,
+        /// Environment variable (as KEY=VALUE) to set for the command, which can be repeated
+        #[structopt(short, long = "env", number_of_values = 1, parse(try_from_str = parse_env))]
+        env: Vec<(String, String)>,
+    },
+    /// Compares the output of a command (or the contents of a file) across the configured hosts
+    Diff {
+        /// Command to be executed over SSH, or path of a file on remote machine with --file
+        target: String,
+        /// Compares the contents of the file at the given path instead of a command's output
+        #[structopt(short, long)]
+        file: bool,
+        /// Host (as username@ip_address:port, or its alias) to use as the baseline instead of
+        /// the majority
+        #[structopt(short, long)]
+        baseline: Option<String>,
+    },
+    /// Downloads a directory recursively from the configured hosts
+    DownloadDir {
+        /// Path of download's source directory on remote machine
+        remote_path: PathBuf,
+        /// Path of download's destination directory on local machine
+        local_path: PathBuf,
+        /// Streams a tar archive instead of transferring each file separately
+        #[structopt(short, long)]
+        tar: bool,
+    },
+    /// Downloads the files matching a glob pattern from the configured hosts
+    DownloadGlob {
+        /// Glob pattern of download's source files on remote machine (e.g. "/var/log/*.log")
+        pattern: String,
+        /// Path of download's destination directory on local machine
+        local_path: PathBuf,
+    },
+    /// Downloads a file from the configured hosts
+    ScpDownload {
+        /// Path of download's source file on remote machine
+        remote_path: PathBuf,
+        /// Path of download's destination directory on local machine, or of the destination
+        /// file of each host if it contains placeholders like {host}, {ip} or {label:KEY}
+        local_path: PathBuf,
+        /// Combines the files of all hosts into a single archive at the destination path
+        /// (ending with .tar, .tar.gz, .tgz or .zip), with an entry per host named after it
+        /// as host/filename (or after --template)
+        #[structopt(short, long)]
+        archive: bool,
+        /// Keeps the remote filename in a subdirectory named after each host
+        #[structopt(short, long, conflicts_with("template"))]
+        subdirectory: bool,
+        /// Names the file of each host after a template in the destination directory,
+        /// with placeholders like {name}, {host}, {ip} or {label:KEY} (e.g. "{name}.{ip}")
+        #[structopt(short, long)]
+        template: Option<String>,
+    },
+    /// Uploads a file to the configured hosts
+    ScpUpload {
+        /// Checks the free space at the destination before uploading, failing fast if it's
+        /// insufficient (overrides `check_free_space` of the configuration file)
+        #[structopt(long)]
+        check_free_space: bool,
+        /// Uploads large files in this number of chunks, in parallel over as many sessions
+        /// (overrides `upload_chunks` of the configuration file)
+        #[structopt(long)]
+        chunks: Option<usize>,
+        /// Path of upload's source file on local machine
+        /// (may contain placeholders like {host}, {ip} or {label:KEY})
+        local_path: PathBuf,
+        /// Path of upload's destination file on remote machine
+        /// (may contain placeholders like {host}, {ip} or {label:KEY})
+        remote_path: PathBuf,
+    },
+    /// Opens an interactive shell on one of the configured hosts
+    Shell {
+        /// Host (as username@ip_address:port, or its alias)
+        host: String,
+    },
+    /// Prints the last lines of a file on the configured hosts, optionally following it
+    Tail {
+        /// Path of the file on remote machine
+        path: String,
+        /// Keeps printing the lines appended to the file until interrupted with Ctrl-C
+        #[structopt(short, long)]
+        follow: bool,
+        /// Number of last lines to print initially
+        #[structopt(short = "n", long, default_value = "10")]
+        lines: u64,
+    },
+    /// Runs the steps of a plan file (JSON or YAML) in order on the configured hosts
+    Run {
+        /// Path of the plan file
+        plan: PathBuf,
+    },
+    /// Inspects the SSH servers of the configured hosts (banner, negotiated algorithms,
+    /// host key fingerprints, authentication methods offered to the user)
+    #[structopt(alias = "audit")]
+    AuditSsh {
+        /// Prints the results of all hosts as CSV, with a row per host
+        #[structopt(long, conflicts_with("json"))]
+        csv: bool,
+        /// Prints the results of all hosts as a JSON object keyed by host
+        #[structopt(long)]
+        json: bool,
+    },
+    /// Measures the latency of the configured hosts (TCP connection, SSH handshake,
+    /// authentication, and round trip of a trivial command) and prints its percentiles
+    Bench {
+        /// Number of sessions established with each host
+        #[structopt(short = "n", long, default_value = "5", parse(try_from_str = parse_iterations))]
+        iterations: usize,
+        /// Prints the percentiles of all hosts, in milliseconds, as a JSON object keyed by host
+        #[structopt(long)]
+        json: bool,
+    },
+    /// Gathers facts about the configured hosts (OS, kernel, CPUs, memory, disk, uptime)
+    Facts {
+        /// Prints the facts of all hosts as a JSON object keyed by host
+        #[structopt(long)]
+        json: bool,
+    },
+    /// Uploads a directory recursively to the configured hosts
+    UploadDir {
+        /// Path of upload's source directory on local machine
+        local_path: PathBuf,
+        /// Path of upload's destination directory on remote machine
+        remote_path: PathBuf,
+        /// Streams a tar archive instead of transferring each file separately
+        #[structopt(short, long)]
+        tar: bool,
+    },
+    /// Executes a command on the configured hosts repeatedly, showing a status table
+    Watch {
+        /// Command to be executed over SSH
+        command: String,
+        /// Interval between executions, in seconds
+        #[structopt(short = "n", long, default_value = "2")]
+        interval: f64,
+    },
+    /// Synchronizes a directory to the configured hosts, uploading only changed files
+    Sync {
+        /// Path of the source directory on local machine
+        local_path: PathBuf,
+        /// Path of the destination directory on remote machine
+        remote_path: PathBuf,
+        /// Compares files by checksum instead of size and modification time
+        #[structopt(short, long)]
+        checksum: bool,
+        /// Deletes remote files which don't exist locally
+        #[structopt(short, long)]
+        delete: bool,
+        /// Only prints what would be changed
+        #[structopt(short = "n", long)]
+        dry_run: bool,
+        /// Streams a tar archive of changed files instead of transferring each file separately
+        #[structopt(short, long)]
+        tar: bool,
+    },
+}
+
+/// Configuration file formats supported by the `MasshConfig` struct.
+enum Format {
+    Json,
+    Yaml,
+}
+
+/// Output formats of the `execute` subcommand.
+#[derive(Clone, Copy, PartialEq)]
+enum OutputFormat {
+    Text,
+    Csv,
+}
+
+/// Sort keys of the results of the `execute` subcommand.
+#[derive(Clone, Copy, PartialEq)]
+enum SortKey {
+    Host,
+    Status,
+    Duration,
+}
+
+/// Groups of the results of the `execute` subcommand.
+#[derive(Clone, Copy, PartialEq)]
+enum GroupBy {
+    Status,
+}
+
+/// Outcomes of an operation on a host, in the order of the summary.
+const STATUSES: [&str; 5] = ["success", "warning", "failure", "interrupted", "skipped"];
+
+/// Whether the output is colored, unless disabled with `--no-color` or because the standard
+/// output isn't a terminal.
+static COLOR: AtomicBool = AtomicBool::new(true);
+
+/// Whether the output of each host is omitted, with `--quiet` or `--summary-only`.
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Whether the status of each host is omitted too, with `--summary-only`.
+static SUMMARY_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// Seconds to wait for the running hosts after Ctrl-C, with `--grace-period`.
+static GRACE_PERIOD: AtomicU64 = AtomicU64::new(10);
+
+/// When the operation was interrupted by Ctrl-C, if it was.
+static INTERRUPTED: OnceLock<Instant> = OnceLock::new();
+
+/// Error of a host which wasn't done yet when the grace period after Ctrl-C elapsed.
+#[derive(Debug)]
+struct Interrupted;
+
+impl std::fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "interrupted")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// Observer which records how long an operation took on each host, and prints the progress of
+/// each host to the standard error with `--verbose` (and each connection attempt with `-vv`).
+#[derive(Default)]
+struct CliObserver {
+    durations: Mutex<HashMap<MasshHost, Duration>>,
+    progress: Option<Progress>,
+    verbose: u8,
+}
+
+impl CliObserver {
+    /// Prints a message to the standard error, above the progress bar if any.
+    fn log(&self, message: String) {
+        match &self.progress {
+            Some(progress) => progress.bar.suspend(|| eprintln!("{}", message)),
+            None => eprintln!("{}", message),
+        }
+    }
+}
+
+impl MasshObserver for CliObserver {
+    fn on_connect_start(&self, host: &MasshHost) {
+        if self.verbose >= 2 {
+            self.log(format!("[{}]: connecting", host));
+        }
+    }
+
+    fn on_connect_ok(&self, host: &MasshHost, duration: Duration) {
+        if self.verbose >= 1 {
+            let ms = duration.as_millis();
+            self.log(format!("[{}]: connected in {} ms", host, ms));
+        }
+    }
+
+    fn on_connect_err(&self, host: &MasshHost, duration: Duration, error: &Error) {
+        if self.verbose >= 1 {
+            let ms = duration.as_millis();
+            self.log(format!(
+                "[{}]: failed to connect in {} ms: {}",
+                host, ms, error
+            ));
+        }
+    }
+
+    fn on_command_done(&self, host: &MasshHost, duration: Duration, exit_status: i32) {
+        if self.verbose >= 1 {
+            let ms = duration.as_millis();
+            self.log(format!(
+                "[{}]: command exited with status {} in {} ms",
+                host, exit_status, ms
+            ));
+        }
+    }
+
+    fn on_command_err(&self, host: &MasshHost, duration: Duration, error: &Error) {
+        if self.verbose >= 1 {
+            let ms = duration.as_millis();
+            self.log(format!(
+                "[{}]: command failed in {} ms: {}",
+                host, ms, error
+            ));
+        }
+    }
+
+    fn on_host_start(&self, _host: &MasshHost) {
+        if let Some(progress) = &self.progress {
+            progress.update(|running, _| *running += 1);
+        }
+    }
+
+    fn on_host_done(&self, host: &MasshHost, duration: Duration, _failed: bool) {
+        self.durations
+            .lock()
+            .unwrap()
+            .insert(host.clone(), duration);
+        if let Some(progress) = &self.progress {
+            progress.update(|running, _| *running -= 1);
+        }
+        if self.verbose >= 1 {
+            self.log(format!("[{}]: done in {} ms", host, duration.as_millis()));
+        }
+    }
+}
+
+/// Progress bar of the `--progress` option, with the number of hosts which are done, running
+/// and failed so far.
+struct Progress {
+    bar: ProgressBar,
+    /// Number of hosts running and failed so far, updated together with the progress bar.
+    counts: Mutex<(usize, usize)>,
+}
+
+impl Progress {
+    /// Constructs a `Progress` for the given number of hosts, drawn on the standard error unless
+    /// it isn't a terminal.
+    fn new(hosts: usize) -> Self {
+        let template = "[{elapsed_precise}] {bar:40} {pos}/{len} hosts done ({msg})";
+        let bar = ProgressBar::new(hosts as u64)
+            .with_style(ProgressStyle::with_template(template).unwrap());
+        bar.enable_steady_tick(Duration::from_millis(100));
+        let progress = Progress {
+            bar,
+            counts: Mutex::default(),
+        };
+        progress.update(|_, _| {});
+        progress
+    }
+
+    /// Advances the progress bar once the result of a host is received.
+    fn advance(&self, result: &Result<SshOutput, Error>) {
+        let failed = matches!(result_status(result), 1 | 2);
+        self.update(|_, failures| *failures += failed as usize);
+        self.bar.inc(1);
+    }
+
+    /// Updates the number of hosts running and failed, then the message of the progress bar.
+    fn update(&self, f: impl FnOnce(&mut usize, &mut usize)) {
+        let mut counts = self.counts.lock().unwrap();
+        let (running, failures) = &mut *counts;
+        f(running, failures);
+        let message = format!("{} running, {} failed", running, failures);
+        self.bar.set_message(message);
+    }
+}
+
+fn main() {
+    // Build an `Opt` struct from the command line arguments.
+    // Print an error message and exit the program on failure.
+    let mut opt = Opt::from_args();
+
+    // Configure how much is printed, and whether it's colored.
+    let color = !opt.no_color && std::io::stdout().is_terminal();
+    COLOR.store(color, Ordering::Relaxed);
+    QUIET.store(opt.quiet || opt.summary_only, Ordering::Relaxed);
+    SUMMARY_ONLY.store(opt.summary_only, Ordering::Relaxed);
+    GRACE_PERIOD.store(opt.grace_period, Ordering::Relaxed);
+
+    // Read the command of the `execute` subcommand from the standard input or a file if specified.
+    // Print an error message and exit the program on failure.
+    if let Command::Execute {
+        command,
+        command_file,
+        ..
+    } = &mut opt.cmd
+    {
+        let (path, confirm) = (command_file.as_deref(), opt.confirm);
+        if let Err(error) = read_command(command, path, confirm, opt.ask_become_password) {
+            eprintln!("{}", paint(Red, error.to_string()));
+            std::process::exit(1);
+        }
+    }
+
+    // Extract the configuration file's path and format from the `Opt` struct.
+    let (path, format) = if let Some(path) = opt.json {
+        (path, Format::Json)
+    } else if let Some(path) = opt.yaml {
+        (path, Format::Yaml)
+    } else {
+        unreachable!();
+    };
+
+    // Build a `MasshClient` struct from the configuration file.
+    // Print an error message and exit the program on failure.
+    let result = match format {
+        Format::Json => MasshConfig::from_json_file(&path),
+        Format::Yaml => MasshConfig::from_yaml_file(&path),
+    };
+    let mut config = result.unwrap_or_else(|error| {
+        let message = paint(Red, error.to_string());
+        eprintln!("{}", message);
+        std::process::exit(1);
+    });
+
+    // Override the configuration file with the command line arguments.
+    if let Some(max_failures) = opt.max_failures {
+        config.max_failures = max_failures;
+    }
+    if let Some(user) = &opt.become_user {
+        config.become_user = Some(user.clone());
+        for group in &mut config.groups {
+            group.become_user = None;
+            group
+                .hosts
+                .iter_mut()
+                .for_each(|host| host.become_user = None);
+        }
+        config
+            .hosts
+            .iter_mut()
+            .for_each(|host| host.become_user = None);
+    }
+    if opt.ask_become_password {
+        config.become_password = Some(read_password("Become password: "));
+        for group in &mut config.groups {
+            group.become_password = None;
+            group
+                .hosts
+                .iter_mut()
+                .for_each(|host| host.become_password = None);
+        }
+        config
+            .hosts
+            .iter_mut()
+            .for_each(|host| host.become_password = None);
+    }
+    if let Command::ScpUpload {
+        chunks: Some(chunks),
+        ..
+    } = &opt.cmd
+    {
+        config.upload_chunks = *chunks;
+    }
+    if let Command::ScpUpload {
+        check_free_space: true,
+        ..
+    } = &opt.cmd
+    {
+        config.check_free_space = true;
+    }
+    if let Command::Execute { merge: true, .. } = &opt.cmd {
+        config.merge_output = true;
+    }
+    if let Command::Execute {
+        timestamps: true, ..
+    } = &opt.cmd
+    {
+        config.timestamps = true;
+    }
+
+    // Print the diagnostics of the `check-config` subcommand, then exit the program
+    // with a nonzero exit status if any error was found.
+    if let Command::CheckConfig = &opt.cmd {
+        let diagnostics = config.validate();
+        let num_errors = diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.severity == MasshSeverity::Error)
+            .count();
+        for diagnostic in &diagnostics {
+            let color = match diagnostic.severity {
+                MasshSeverity::Warning => Yellow,
+                MasshSeverity::Error => Red,
+            };
+            println!("{}", paint(color, diagnostic.to_string()));
+        }
+        if diagnostics.is_empty() {
+            println!("{}", paint(Green, format!("{:?} is valid", path)));
+        }
+        std::process::exit(if num_errors > 0 { 1 } else { 0 });
+    }
+
+    // Refuse to run if any host is defined more than once and duplicates are errors.
+    if config.duplicate_hosts == MasshDuplicatePolicy::Error {
+        let duplicates: Vec<_> = config
+            .validate()
+            .into_iter()
+            .filter(|diagnostic| diagnostic.kind == MasshDiagnosticKind::DuplicateHost)
+            .collect();
+        for diagnostic in &duplicates {
+            eprintln!("{}", paint(Red, diagnostic.to_string()));
+        }
+        if !duplicates.is_empty() {
+            std::process::exit(1);
+        }
+    }
+
+    let mut massh = MasshClient::from(&config);
+
+    // Restrict the hosts to the ones saved by a previous run with `--save-failed`.
+    if let Some(path) = &opt.from_failed {
+        let hosts: Vec<String> = std::fs::read_to_string(path)
+            .map_err(Error::from)
+            .and_then(|json| Ok(serde_json::from_str(&json)?))
+            .unwrap_or_else(|error| {
+                let message = paint(Red, format!("Failed to read {:?}: {}", path, error));
+                eprintln!("{}", message);
+                std::process::exit(1);
+            });
+        massh = massh.only_hosts(&hosts);
+    }
+
+    // Restrict the hosts to the ones selected by the `--select` expression.
+    if let Some(selector) = &opt.select {
+        massh = massh.select(selector);
+    }
+
+    // Override the download layout of the configuration file with the command line arguments.
+    if let Command::ScpDownload {
+        archive,
+        subdirectory,
+        template,
+        ..
+    } = &opt.cmd
+    {
+        if *subdirectory || (*archive && template.is_none()) {
+            massh.set_download_layout(MasshDownloadLayout::Subdirectory);
+        } else if let Some(template) = template {
+            massh.set_download_layout(MasshDownloadLayout::Template(template.clone()));
+        }
+    }
+
+    // Write the exact output of each host to files, if specified.
+    if let Command::Execute {
+        raw_outdir: Some(raw_outdir),
+        ..
+    } = &opt.cmd
+    {
+        massh.set_output_dir(Some(raw_outdir.clone()));
+    }
+
+    // Record the duration of the operation on each host, and print its progress if verbose.
+    let observer = Arc::new(CliObserver {
+        durations: Mutex::default(),
+        progress: match &opt.cmd {
+            Command::Execute { progress: true, .. } => Some(Progress::new(massh.len())),
+            _ => None,
+        },
+        verbose: opt.verbose,
+    });
+    massh.set_observer(Some(observer.clone()));
+
+    // On Ctrl-C, stop starting the operation on more hosts and wait for the running hosts until
+    // the grace period elapses, or quit immediately on a second Ctrl-C.
+    let stop = Arc::new(AtomicBool::new(false));
+    let stop_handler = stop.clone();
+    let _ = ctrlc::set_handler(move || {
+        if INTERRUPTED.set(Instant::now()).is_err() {
+            std::process::exit(130);
+        }
+        stop_handler.store(true, Ordering::SeqCst);
+        let message = format!(
+            "Interrupted, waiting up to {} s for the running hosts (press Ctrl-C again to quit)",
+            GRACE_PERIOD.load(Ordering::Relaxed)
+        );
+        eprintln!("{}", paint(Yellow, message));
+    });
+    massh.set_stop(Some(stop.clone()));
+
+    // Print the operation on each host instead of running it, if a dry run.
+    if opt.dry_run {
+        dry_run(&massh, &opt.cmd);
+    }
+
+    // Ask to type "yes" before running the operation on all hosts, if specified.
+    if opt.confirm {
+        confirm(&massh, &opt.cmd);
+    }
+
+    // Match the subcommand and call the corresponding `MasshClient` method. These methods return
+    // the receiving half of a `std::sync::mpsc::channel` which receives exactly 1 message per host.
+    let mut summary = Summary::default();
+    let mut print_summary = true;
+    match &opt.cmd {
+        // Print the results of the `execute` subcommand as CSV, once all hosts are done.
+        Command::Execute {
+            command: Some(command),
+            env,
+            output: OutputFormat::Csv,
+            csv_stdout,
+            reports,
+            sort,
+            group_by,
+            ..
+        } => {
+            let env: Vec<_> = env.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+            let results = collect_results(&massh, massh.execute_with_env(command, &env), &observer);
+            let results = sort_results(results, *sort, *group_by, &observer);
+            let hosts = results
+                .iter()
+                .map(|(host, result)| HostReport::new(host, result))
+                .collect();
+            print_csv(&massh, results, &observer, *csv_stdout, &mut summary);
+            write_reports(&massh, reports, command, hosts, &observer);
+            print_summary = false;
+        }
+        // Process the received messages of the `execute` subcommand (or all of them once all hosts
+        // are done, if sorted, grouped or with a progress bar), then write its reports.
+        Command::Execute {
+            command: Some(command),
+            env,
+            reports,
+            sort,
+            group_by,
+            progress,
+            ..
+        } => {
+            let env: Vec<_> = env.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+            let rx = massh.execute_with_env(command, &env);
+            let deferred = sort.is_some() || group_by.is_some() || *progress;
+            let results: Box<dyn Iterator<Item = _>> = if deferred {
+                let results = collect_results(&massh, rx, &observer);
+                Box::new(sort_results(results, *sort, *group_by, &observer).into_iter())
+            } else {
+                Box::new(receive(&massh, rx))
+            };
+            let mut hosts = Vec::new();
+            let mut group = None;
+            for (host, result) in results {
+                // Print the status of the group in bold before its first host, if grouped.
+                let status = result_status(&result);
+                if group_by.is_some() && group != Some(status) {
+                    if show_statuses() {
+                        if group.is_some() {
+                            println!();
+                        }
+                        println!("{}", paint(Style::new().bold(), STATUSES[status]));
+                    }
+                    group = Some(status);
+                }
+                if !reports.is_empty() {
+                    hosts.push(HostReport::new(&host, &result));
+                }
+                match result {
+                    Ok(output) => {
+                        // Print green message if result is ok and exit status is zero,
+                        // or yellow message if exit status is nonzero or killed by a signal.
+                        print_exit(host, &mut summary, &output);
+                        // Print standard output in cyan and standard error in purple.
+                        print_output(&output);
+                    }
+                    // Print red message if result is not ok, or plain message if skipped.
+                    Err(error) => print_failure(host, &mut summary, error),
+                }
+            }
+            write_reports(&massh, reports, command, hosts, &observer);
+        }
+        // Process the received messages of the `copy-id` subcommand.
+        Command::CopyId { pubkey } => {
+            let public_key = std::fs::read_to_string(pubkey).unwrap_or_else(|error| {
+                let message = paint(Red, format!("Failed to read {:?}: {}", pubkey, error));
+                eprintln!("{}", message);
+                std::process::exit(1);
+            });
+            let rx = massh.copy_id(public_key);
+            for (host, result) in receive(&massh, rx) {
+                match result {
+                    Ok(added) => {
+                        // Print green message if result is ok, followed by what was done.
+                        print_success(host, &mut summary);
+                        if show_outputs() {
+                            let message = if added { "added" } else { "already authorized" };
+                            println!("{}", paint(Cyan, message));
+                        }
+                    }
+                    // Print red message if result is not ok, or plain message if skipped.
+                    Err(error) => print_failure(host, &mut summary, error),
+                }
+            }
+        }
+        // Process the received messages of the `sync` subcommand.
+        Command::Sync {
+            local_path,
+            remote_path,
+            checksum,
+            delete,
+            dry_run,
+            tar,
+        } => {
+            let options = SyncOptions {
+                checksum: *checksum,
+                delete: *delete,
+                dry_run: *dry_run,
+                transfer: dir_transfer(*tar),
+            };
+            let rx = massh.sync_dir(local_path, remote_path, options);
+            for (host, result) in receive(&massh, rx) {
+                match result {
+                    Ok(report) => {
+                        // Print green message if result is ok, followed by the changes.
+                        print_success(host, &mut summary);
+                        print_sync_report(&report);
+                    }
+                    // Print red message if result is not ok, or plain message if skipped.
+                    Err(error) => print_failure(host, &mut summary, error),
+                }
+            }
+        }
+        // Process the received messages of the `tail` subcommand, after its streamed lines.
+        Command::Tail {
+            path,
+            follow,
+            lines,
+        } => {
+            // Stop following the file on all hosts on Ctrl-C.
+            let rx = massh.tail(path, *lines, *follow, stop.clone(), |host, line| {
+                if show_outputs() {
+                    let prefix = paint(host_color(host), format!("[{}]", host));
+                    println!("{} {}", prefix, String::from_utf8_lossy(line));
+                }
+            });
+            for (host, result) in receive(&massh, rx) {
+                match result {
+                    // Print green message if result is ok and exit status is zero.
+                    Ok(0) => print_success(host, &mut summary),
+                    // Print yellow message if result is ok and exit status is nonzero.
+                    Ok(exit_status) => print_warning(host, &mut summary, exit_status),
+                    // Print red message if result is not ok, or plain message if skipped.
+                    Err(error) => print_failure(host, &mut summary, error),
+                }
+            }
+        }
+        // Process the received messages of the `diff` subcommand, once all hosts are done.
+        Command::Diff {
+            target,
+            file,
+            baseline,
+        } => {
+            let command = if *file {
+                format!("cat -- {}", massh::shell::quote(target))
+            } else {
+                target.clone()
+            };
+            let results = collect_ordered(&massh, massh.execute(command));
+            print_diff(results, baseline.as_deref(), &mut summary);
+        }
+        // Redraw the status table of the `watch` subcommand until interrupted with Ctrl-C.
+        Command::Watch { command, interval } => {
+            let interval = Duration::from_secs_f64(interval.max(0.1));
+            watch(&massh, command, interval, &stop);
+            return;
+        }
+        // Process the received messages of the `facts` subcommand.
+        Command::Facts { json: true } => {
+            // Print a JSON object of the facts (or error) of each host in configuration order.
+            print_json_results(collect_ordered(&massh, massh.gather_facts()));
+            return;
+        }
+        Command::Facts { json: false } => {
+            let rx = massh.gather_facts();
+            for (host, result) in receive(&massh, rx) {
+                match result {
+                    Ok(facts) => {
+                        // Print green message if result is ok, followed by the facts.
+                        print_success(host, &mut summary);
+                        print_facts(&facts);
+                    }
+                    // Print red message if result is not ok, or plain message if skipped.
+                    Err(error) => print_failure(host, &mut summary, error),
+                }
+            }
+        }
+        // Process the received messages of the `audit-ssh` subcommand.
+        Command::AuditSsh { json: true, .. } => {
+            // Print a JSON object of the server info (or error) of each host in configuration order.
+            print_json_results(collect_ordered(&massh, massh.server_info()));
+            return;
+        }
+        Command::AuditSsh { csv: true, .. } => {
+            // Print a CSV row of the server info (or error) of each host in configuration order.
+            print_server_info_csv(&massh, collect_ordered(&massh, massh.server_info()));
+            return;
+        }
+        // Process the received messages of the `bench` subcommand.
+        Command::Bench {
+            iterations,
+            json: true,
+        } => {
+            // Print a JSON object of the percentiles (or error) of each host in configuration
+            // order.
+            let results = collect_ordered(&massh, massh.bench(*iterations));
+            let results = results.into_iter().map(|(host, result)| {
+                let percentiles = result.map(|latencies| {
+                    let percentiles = latency_percentiles(&latencies).into_iter();
+                    let named = |durations: Vec<Duration>| {
+                        let names = PERCENTILES.iter().map(|(name, _)| *name);
+                        let millis = durations.iter().map(|d| d.as_micros() as f64 / 1000.0);
+                        names.zip(millis).collect::<BTreeMap<_, _>>()
+                    };
+                    let phases = percentiles.map(|(phase, durations)| (phase, named(durations)));
+                    phases.collect::<BTreeMap<_, _>>()
+                });
+                (host, percentiles)
+            });
+            print_json_results(results.collect());
+            return;
+        }
+        Command::Bench { iterations, .. } => {
+            let rx = massh.bench(*iterations);
+            let mut all = Vec::new();
+            for (host, result) in receive(&massh, rx) {
+                match result {
+                    Ok(latencies) => {
+                        // Print green message if result is ok, followed by the percentiles.
+                        print_success(host, &mut summary);
+                        print_latencies(&latencies);
+                        all.extend(latencies);
+                    }
+                    // Print red message if result is not ok, or plain message if skipped.
+                    Err(error) => print_failure(host, &mut summary, error),
+                }
+            }
+            // Print the percentiles of all hosts together, if there are several.
+            if summary.success > 1 && show_outputs() {
+                println!("{}", paint(Green, "[all hosts]"));
+                print_latencies(&all);
+            }
+        }
+        Command::AuditSsh { .. } => {
+            let rx = massh.server_info();
+            for (host, result) in receive(&massh, rx) {
+                match result {
+                    Ok(info) => {
+                        // Print green message if result is ok, followed by the server info.
+                        print_success(host, &mut summary);
+                        print_server_info(&info);
+                    }
+                    // Print red message if result is not ok, or plain message if skipped.
+                    Err(error) => print_failure(host, &mut summary, error),
+                }
+            }
+        }
+        // Open an interactive shell on the selected host, then exit with its exit status.
+        Command::Shell { host } => {
+            let result = massh.with_client(host, |client| {
+                let term = std::env::var("TERM").unwrap_or_else(|_| "xterm".to_string());
+                let _raw_mode = RawMode::enable();
+                let (input, output) = (std::io::stdin(), std::io::stdout());
+                client.shell_interactive(&term, input, output, window_size)
+            });
+            match result {
+                Some(Ok(exit_status)) => std::process::exit(exit_status),
+                Some(Err(error)) => eprintln!("{}", paint(Red, format!("[{}]: {}", host, error))),
+                None => eprintln!("{}", paint(Red, format!("Host {} is not configured", host))),
+            }
+            std::process::exit(1);
+        }
+        // Process the received messages of each step of the `run` subcommand.
+        Command::Run { plan } => {
+            let plan = read_plan(plan).unwrap_or_else(|error| {
+                let message = paint(Red, error.to_string());
+                eprintln!("{}", message);
+                std::process::exit(1);
+            });
+            run_plan(&massh, &plan, &mut summary);
+        }
+        // Process the received messages of the `download-glob` subcommand.
+        Command::DownloadGlob {
+            pattern,
+            local_path,
+        } => {
+            let rx = massh.download_glob(pattern, local_path);
+            for (host, result) in receive(&massh, rx) {
+                match result {
+                    Ok(paths) => {
+                        // Print green message if result is ok, followed by the downloaded files.
+                        print_success(host, &mut summary);
+                        if show_outputs() {
+                            for path in &paths {
+                                println!("{}", paint(Cyan, format!("downloaded {:?}", path)));
+                            }
+                        }
+                    }
+                    // Print red message if result is not ok, or plain message if skipped.
+                    Err(error) => print_failure(host, &mut summary, error),
+                }
+            }
+        }
+        // Download the file of each host into a temporary directory, then combine them into
+        // an archive. Print an error message and exit the program on failure.
+        Command::ScpDownload {
+            remote_path,
+            local_path,
+            archive: true,
+            ..
+        } => {
+            let exit = |error: Error| -> ! {
+                eprintln!("{}", paint(Red, error.to_string()));
+                std::process::exit(1);
+            };
+            let archive = Archive::new(local_path).unwrap_or_else(|error| exit(error));
+            let dir = archive.create_dir().unwrap_or_else(|error| exit(error));
+            let rx = massh.scp_download(remote_path.as_path(), dir);
+            let mut files = Vec::new();
+            for (host, result) in receive(&massh, rx) {
+                match result {
+                    // Print green message if result is ok, and remember the downloaded file.
+                    Ok(()) => {
+                        let file = massh.download_destination(&host, remote_path, dir);
+                        files.extend(file.and_then(Result::ok));
+                        print_success(host, &mut summary);
+                    }
+                    // Print red message if result is not ok, or plain message if skipped.
+                    Err(error) => print_failure(host, &mut summary, error),
+                }
+            }
+            files.sort();
+            if let Err(error) = archive.write(&files) {
+                drop(archive);
+                exit(error);
+            }
+            if show_outputs() {
+                let message = format!("archived {} files in {:?}", files.len(), local_path);
+                println!("{}", paint(Cyan, message));
+            }
+        }
+        // Process the received messages of the file and directory transfer subcommands.
+        _ => {
+            let rx = match &opt.cmd {
+                Command::DownloadDir {
+                    remote_path,
+                    local_path,
+                    tar,
+                } => massh.download_dir(remote_path, local_path, dir_transfer(*tar)),
+                Command::ScpDownload {
+                    remote_path,
+                    local_path,
+                    ..
+                } => massh.scp_download(remote_path, local_path),
+                Command::ScpUpload {
+                    local_path,
+                    remote_path,
+                    ..
+                } => massh.scp_upload(local_path, remote_path),
+                Command::UploadDir {
+                    local_path,
+                    remote_path,
+                    tar,
+                } => massh.upload_dir(local_path, remote_path, dir_transfer(*tar)),
+                Command::AuditSsh { .. }
+                | Command::CheckConfig
+                | Command::CopyId { .. }
+                | Command::DownloadGlob { .. }
+                | Command::Diff { .. }
+                | Command::Execute { .. }
+                | Command::Bench { .. }
+                | Command::Facts { .. }
+                | Command::Run { .. }
+                | Command::Shell { .. }
+                | Command::Sync { .. }
+                | Command::Tail { .. }
+                | Command::Watch { .. } => {
+                    unreachable!()
+                }
+            };
+            for (host, result) in receive(&massh, rx) {
+                match result {
+                    // Print green message if result is ok.
+                    Ok(()) => print_success(host, &mut summary),
+                    // Print red message if result is not ok, or plain message if skipped.
+                    Err(error) => print_failure(host, &mut summary, error),
+                }
+            }
+        }
+    }
+
+    // Print summaries of the number of successes, warnings, failures, and skipped hosts,
+    // unless the output is in a structured format.
+    if print_summary {
+        if show_statuses() {
+            println!();
+        }
+        summary.print();
+    }
+
+    // Save the hosts that failed, had a nonzero exit status, or were skipped if specified.
+    if let Some(path) = &opt.save_failed {
+        let json = serde_json::to_string_pretty(&summary.failed).unwrap();
+        if let Err(error) = std::fs::write(path, json + "\n") {
+            let message = paint(Red, format!("Failed to write {:?}: {}", path, error));
+            eprintln!("{}", message);
+            std::process::exit(1);
+        }
+    }
+
+    // Exit with the conventional exit status of SIGINT if interrupted by Ctrl-C.
+    if INTERRUPTED.get().is_some() {
+        std::process::exit(130);
+    }
+}
+
+/// Attempts to replace the command of the `execute` subcommand with the contents of the
+/// standard input if it's `-`, or with the contents of a file if specified.
+fn read_command(
+    command: &mut Option<String>,
+    path: Option<&Path>,
+    confirm: bool,
+    ask_become_password: bool,
+) -> Result<(), Error> {
+    let text = match (command.as_deref(), path) {
+        (Some("-"), _) if confirm => {
+            return Err(anyhow::anyhow!(
+                "Can't confirm a command read from the standard input"
+            ));
+        }
+        (Some("-"), _) if ask_become_password => {
+            return Err(anyhow::anyhow!(
+                "Can't prompt for the become password with a command read from the standard input"
+            ));
+        }
+        (Some("-"), _) => {
+            let mut text = String::new();
+            std::io::stdin()
+                .read_to_string(&mut text)
+                .map_err(|error| anyhow::anyhow!("Failed to read the standard input: {}", error))?;
+            text
+        }
+        (_, Some(path)) => std::fs::read_to_string(path)
+            .map_err(|error| anyhow::anyhow!("Failed to read {:?}: {}", path, error))?,
+        _ => return Ok(()),
+    };
+    if text.trim().is_empty() {
+        return Err(anyhow::anyhow!("The command is empty"));
+    }
+    *command = Some(text);
+    Ok(())
+}
+
+/// Attempts to read a plan file, in JSON if its extension is `.json` or else in YAML.
+fn read_plan(path: &Path) -> Result<MasshPlan, Error> {
+    match path.extension() {
+        Some(extension) if extension == "json" => MasshPlan::from_json_file(path),
+        _ => MasshPlan::from_yaml_file(path),
+    }
+}
+
+/// Describes the operation of a subcommand, with its commands and paths rendered for a host
+/// (as printed by `--dry-run`) or with their placeholders (as printed by `--confirm`).
+fn describe(massh: &MasshClient, cmd: &Command, host: Option<&str>) -> Result<String, Error> {
+    let info = host.and_then(|host| massh.get_host_info(host));
+    let render = |command: &str| match info {
+        Some(info) => info.render_command(command),
+        None => Ok(command.to_string()),
+    };
+    let render_path = |path: &Path| match info {
+        Some(info) => info.render_path(path),
+        None => Ok(path.to_path_buf()),
+    };
+    let as_tar = |tar: bool| if tar { " as a tar archive" } else { "" };
+    let description = match cmd {
+        Command::AuditSsh { .. } => "inspect the SSH server".to_string(),
+        Command::CheckConfig => "check the configuration file".to_string(),
+        Command::CopyId { pubkey } => format!("authorize the public key {:?}", pubkey),
+        Command::Diff {
+            target, file: true, ..
+        } => format!("compare the contents of {:?}", render(target)?),
+        Command::Diff { target, .. } => format!("compare the output of `{}`", render(target)?),
+        Command::DownloadDir {
+            remote_path,
+            local_path,
+            tar,
+        } => format!(
+            "download the directory {:?} to {:?}{}",
+            remote_path,
+            local_path,
+            as_tar(*tar)
+        ),
+        Command::DownloadGlob {
+            pattern,
+            local_path,
+        } => format!(
+            "download the files matching {:?} to {:?}",
+            pattern, local_path
+        ),
+        Command::Execute {
+            command: Some(command),
+            env,
+            ..
+        } => {
+            let mut description = String::from("execute `");
+            for (name, value) in env {
+                let value = massh::shell::quote(&render(value)?);
+                description.push_str(&format!("{}={} ", name, value));
+            }
+            format!("{}{}`", description, render(command)?.trim_end())
+        }
+        Command::Execute { command: None, .. } => unreachable!(),
+        Command::Bench { iterations, .. } => {
+            format!("establish {} sessions to measure the latency", iterations)
+        }
+        Command::Facts { .. } => "gather facts".to_string(),
+        Command::Run { plan } => {
+            let steps: Vec<_> = read_plan(plan)?
+                .steps
+                .iter()
+                .map(|step| step.describe())
+                .collect();
+            format!("run the plan {:?} ({})", plan, steps.join(", then "))
+        }
+        Command::ScpDownload {
+            remote_path,
+            local_path,
+            archive: true,
+            ..
+        } => {
+            Archive::new(local_path)?;
+            let remote_path = render_path(remote_path)?;
+            format!(
+                "download {:?} into the archive {:?}",
+                remote_path, local_path
+            )
+        }
+        Command::ScpDownload {
+            remote_path,
+            local_path,
+            ..
+        } => {
+            let local_path = match host
+                .and_then(|host| massh.download_destination(host, remote_path, local_path))
+            {
+                Some(destination) => destination?,
+                None => local_path.clone(),
+            };
+            let remote_path = render_path(remote_path)?;
+            format!("download {:?} to {:?}", remote_path, local_path)
+        }
+        Command::ScpUpload {
+            local_path,
+            remote_path,
+            ..
+        } => format!(
+            "upload {:?} to {:?}",
+            render_path(local_path)?,
+            render_path(remote_path)?
+        ),
+        Command::Shell { .. } => "open an interactive shell".to_string(),
+        Command::Sync {
+            local_path,
+            remote_path,
+            delete,
+            dry_run,
+            ..
+        } => {
+            let mut description = format!(
+                "synchronize the directory {:?} to {:?}",
+                local_path, remote_path
+            );
+            if *delete {
+                description.push_str(", deleting the remote files which don't exist locally");
+            }
+            if *dry_run {
+                description.push_str(" (dry run)");
+            }
+            description
+        }
+        Command::Tail {
+            path,
+            follow,
+            lines,
+        } => {
+            let follow = if *follow { ", then follow it" } else { "" };
+            format!("print the last {} lines of {:?}{}", lines, path, follow)
+        }
+        Command::UploadDir {
+            local_path,
+            remote_path,
+            tar,
+        } => format!(
+            "upload the directory {:?} to {:?}{}",
+            local_path,
+            remote_path,
+            as_tar(*tar)
+        ),
+        Command::Watch { command, interval } => {
+            format!("execute `{}` every {} s", render(command)?, interval)
+        }
+    };
+    Ok(description)
+}
+
+/// Prints the hosts of the `--dry-run` option with the operation of the subcommand on each host,
+/// or its error in red (e.g. an undefined label), then exits the program without connecting.
+fn dry_run(massh: &MasshClient, cmd: &Command) -> ! {
+    let hosts: Vec<&str> = match cmd {
+        Command::Shell { host } => vec![host],
+        _ => massh.iter().map(|(host, _)| host.as_str()).collect(),
+    };
+    let noun = if hosts.len() == 1 { "host" } else { "hosts" };
+    let header = format!("Dry run on {} {}, without connecting:", hosts.len(), noun);
+    println!("{}", paint(Style::new().bold(), header));
+    let mut failed = false;
+    for host in hosts {
+        match describe(massh, cmd, Some(host)) {
+            Ok(description) => println!("[{}]: {}", host, description),
+            Err(error) => {
+                println!("[{}]: {}", host, paint(Red, format!("failure: {}", error)));
+                failed = true;
+            }
+        }
+    }
+    std::process::exit(if failed { 1 } else { 0 });
+}
+
+/// Prints the operation of the subcommand and the number of hosts for the `--confirm` option,
+/// then exits the program unless "yes" is typed.
+fn confirm(massh: &MasshClient, cmd: &Command) {
+    let description = describe(massh, cmd, None).unwrap_or_else(|error| {
+        eprintln!("{}", paint(Red, error.to_string()));
+        std::process::exit(1);
+    });
+    let hosts = match cmd {
+        Command::Shell { .. } => 1,
+        _ => massh.len(),
+    };
+    let noun = if hosts == 1 { "host" } else { "hosts" };
+    let message = format!("About to {} on {} {}.", description, hosts, noun);
+    eprintln!("{}", paint(Yellow, message));
+    eprint!("Type \"yes\" to continue: ");
+    let mut answer = String::new();
+    let _ = std::io::stdin().read_line(&mut answer);
+    if answer.trim() != "yes" {
+        eprintln!("{}", paint(Red, "Aborted"));
+        std::process::exit(1);
+    }
+}
+
+/// Prompts for a password on the standard error, and reads it from the standard input without
+/// echoing it. It exits the program if the password can't be read.
+fn read_password(prompt: &str) -> String {
+    eprint!("{}", prompt);
+    let mut password = String::new();
+    let result = {
+        let _no_echo = RawMode::disable_echo();
+        std::io::stdin().read_line(&mut password)
+    };
+    eprintln!();
+    if let Err(error) = result {
+        let message = format!("Failed to read the password: {}", error);
+        eprintln!("{}", paint(Red, message));
+        std::process::exit(1);
+    }
+    password.trim_end_matches(['\r', '\n']).to_string()
+}
+
+/// Runs the steps of a plan in order, printing the results of each step and applying
+/// its failure policy, then sets the summary to the outcome of each host for the whole plan.
+fn run_plan(massh: &MasshClient, plan: &MasshPlan, summary: &mut Summary) {
+    let mut remaining = massh.filter(|_| true);
+    let mut hosts: Vec<MasshHost> = Vec::new();
+    let mut failed: Vec<MasshHost> = Vec::new();
+    let mut aborted = false;
+
+    for (index, step) in plan.steps.iter().enumerate() {
+        if index > 0 {
+            println!();
+        }
+        let header = format!(
+            "step {}/{}: {}",
+            index + 1,
+            plan.steps.len(),
+            step.describe()
+        );
+        println!("{}", paint(Style::new().bold(), header));
+
+        // Print the result of the step on each remaining host.
+        let mut step_summary = Summary::default();
+        let rx = remaining.run_step(step);
+        for (host, result) in receive(&remaining, rx) {
+            if index == 0 {
+                hosts.push(host.clone());
+            }
+            match result {
+                Ok(Some(output)) => {
+                    print_exit(host, &mut step_summary, &output);
+                    print_output(&output);
+                }
+                Ok(None) => print_success(host, &mut step_summary),
+                Err(error) => print_failure(host, &mut step_summary, error),
+            }
+        }
+        println!();
+        step_summary.print();
+
+        // Apply the failure policy of the step if it failed on some hosts.
+        if step_summary.failed.is_empty() {
+            continue;
+        }
+        failed.extend(step_summary.failed.iter().cloned());
+        match step.on_failure {
+            MasshFailurePolicy::Abort => {
+                aborted = index + 1 < plan.steps.len();
+                break;
+            }
+            MasshFailurePolicy::Continue => {}
+            MasshFailurePolicy::Exclude if step_summary.success == 0 => break,
+            MasshFailurePolicy::Exclude => {
+                remaining = remaining.filter(|host| !step_summary.failed.contains(host));
+            }
+        }
+    }
+
+    // Each host failed if any step failed on it, or was skipped if the plan was aborted.
+    for host in hosts {
+        if failed.contains(&host) {
+            summary.failure += 1;
+            summary.failed.push(host);
+        } else if aborted {
+            summary.skipped += 1;
+            summary.failed.push(host);
+        } else {
+            summary.success += 1;
+        }
+    }
+}
+
+/// Terminal mode of standard input, restored when dropped.
+struct RawMode {
+    #[cfg(unix)]
+    original: Option<libc::termios>,
+}
+
+impl RawMode {
+    /// Puts the local terminal in raw mode, so that every keystroke (including Ctrl-C) is sent
+    /// to the remote shell as is. It does nothing if standard input isn't a terminal.
+    fn enable() -> Self {
+        #[cfg(unix)]
+        unsafe {
+            let mut termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+                return RawMode { original: None };
+            }
+            let original = termios;
+            libc::cfmakeraw(&mut termios);
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios);
+            RawMode {
+                original: Some(original),
+            }
+        }
+        #[cfg(not(unix))]
+        RawMode {}
+    }
+
+    /// Disables the echo of the local terminal, to read a password. It does nothing if standard
+    /// input isn't a terminal.
+    fn disable_echo() -> Self {
+        #[cfg(unix)]
+        unsafe {
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+                return RawMode { original: None };
+            }
+            let original = termios;
+            termios.c_lflag &= !libc::ECHO;
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios);
+            RawMode {
+                original: Some(original),
+            }
+        }
+        #[cfg(not(unix))]
+        RawMode {}
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(original) = &self.original {
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, original) };
+        }
+    }
+}
+
+/// Returns the number of columns and rows of the local terminal, or 80x24 if unknown.
+fn window_size() -> (u32, u32) {
+    #[cfg(unix)]
+    unsafe {
+        let mut size: libc::winsize = std::mem::zeroed();
+        if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) == 0 && size.ws_col > 0 {
+            return (size.ws_col as u32, size.ws_row as u32);
+        }
+    }
+    (80, 24)
+}
+
+/// Parses an environment variable of the `--env` option, as `KEY=VALUE`.
+fn parse_env(string: &str) -> Result<(String, String), String> {
+    match string.split_once('=') {
+        Some((key, value)) => Ok((key.to_string(), value.to_string())),
+        None => Err(format!("expected KEY=VALUE, found {:?}", string)),
+    }
+}
+
+/// Parses the output format of the `--output` option.
+fn parse_output_format(string: &str) -> Result<OutputFormat, String> {
+    match string {
+        "text" => Ok(OutputFormat::Text),
+        "csv" => Ok(OutputFormat::Csv),
+        _ => Err(format!("expected text or csv, found {:?}", string)),
+    }
+}
+
+/// Parses the number of iterations of the `bench` subcommand, which must be positive.
+fn parse_iterations(string: &str) -> Result<usize, String> {
+    match string.parse() {
+        Ok(0) | Err(_) => Err(format!("expected a positive integer, found {:?}", string)),
+        Ok(iterations) => Ok(iterations),
+    }
+}
+
+/// Parses the sort key of the `--sort` option.
+fn parse_sort_key(string: &str) -> Result<SortKey, String> {
+    match string {
+        "host" => Ok(SortKey::Host),
+        "status" => Ok(SortKey::Status),
+        "duration" => Ok(SortKey::Duration),
+        _ => Err(format!(
+            "expected host, status or duration, found {:?}",
+            string
+        )),
+    }
+}
+
+/// Parses the groups of the `--group-by` option.
+fn parse_group_by(string: &str) -> Result<GroupBy, String> {
+    match string {
+        "status" => Ok(GroupBy::Status),
+        _ => Err(format!("expected status, found {:?}", string)),
+    }
+}
+
+/// Returns the directory transfer method selected by the `--tar` flag.
+fn dir_transfer(tar: bool) -> DirTransfer {
+    if tar {
+        DirTransfer::Tar
+    } else {
+        DirTransfer::Scp
+    }
+}
+
+/// Prints which hosts' output differs from the baseline (the output of the specified host, or
+/// else the most common output), with a unified diff for each distinct divergent output.
+fn print_diff(
+    results: Vec<(String, Result<SshOutput, Error>)>,
+    baseline: Option<&str>,
+    summary: &mut Summary,
+) {
+    // Group the hosts by output, in configuration order, reporting failures right away.
+    let mut groups: Vec<(Vec<u8>, Vec<String>)> = Vec::new();
+    for (host, result) in results {
+        match result {
+            Ok(output) if output.success() => {
+                match groups
+                    .iter_mut()
+                    .find(|(stdout, _)| *stdout == output.stdout)
+                {
+                    Some((_, hosts)) => hosts.push(host),
+                    None => groups.push((output.stdout, vec![host])),
+                }
+            }
+            Ok(output) => print_exit(host, summary, &output),
+            Err(error) => print_failure(host, summary, error),
+        }
+    }
+
+    // Select the baseline group, preferring the earliest group in case of a tie.
+    let index = match baseline {
+        Some(baseline) => groups
+            .iter()
+            .position(|(_, hosts)| hosts.iter().any(|host| host == baseline)),
+        None => groups
+            .iter()
+            .enumerate()
+            .max_by_key(|(index, (_, hosts))| (hosts.len(), std::cmp::Reverse(*index)))
+            .map(|(index, _)| index),
+    };
+    let index = match index {
+        Some(index) => index,
+        None if baseline.is_some() => {
+            let message = format!(
+                "baseline host {:?} has no output to compare",
+                baseline.unwrap()
+            );
+            println!("{}", paint(Red, message));
+            return;
+        }
+        None => return,
+    };
+    let (baseline_stdout, baseline_hosts) = groups.remove(index);
+    let baseline_label = match baseline {
+        Some(baseline) => baseline.to_string(),
+        None => "majority".to_string(),
+    };
+
+    // Print the hosts matching the baseline, then a diff for each divergent output.
+    for host in baseline_hosts {
+        summary.success += 1;
+        println!("[{}]: {}", host, paint(Green, "identical to baseline"));
+    }
+    let baseline_text = String::from_utf8_lossy(&baseline_stdout);
+    for (stdout, hosts) in groups {
+        for host in &hosts {
+            summary.warning += 1;
+            let message = paint(
+                Yellow,
+                format!("differs from baseline ({})", baseline_label),
+            );
+            println!("[{}]: {}", host, message);
+        }
+        let text = String::from_utf8_lossy(&stdout);
+        let diff = TextDiff::from_lines(baseline_text.as_ref(), text.as_ref());
+        let unified = diff
+            .unified_diff()
+            .context_radius(3)
+            .header(&format!("baseline ({})", baseline_label), &hosts.join(", "))
+            .to_string();
+        for line in unified.lines() {
+            let style = match line.chars().next() {
+                Some('+') => Green.normal(),
+                Some('-') => Red.normal(),
+                Some('@') => Cyan.normal(),
+                _ => Style::new(),
+            };
+            println!("{}", paint(style, line));
+        }
+    }
+}
+
+/// Executes a command on all hosts at every interval, reusing their sessions, and redraws a table
+/// of their status and output, highlighting the hosts whose result changed since the last time.
+/// Stops watching once the stop flag is set on Ctrl-C.
+fn watch(massh: &MasshClient, command: &str, interval: Duration, stop: &AtomicBool) {
+    let mut previous: HashMap<String, (String, String)> = HashMap::new();
+    let mut iteration = 0;
+    while !stop.load(Ordering::Relaxed) {
+        let start = Instant::now();
+        let results = collect_ordered(massh, massh.execute(command));
+        iteration += 1;
+
+        // Clear the screen, then print the header and 1 row per host.
+        print!("\x1b[2J\x1b[H");
+        let header = format!(
+            "Every {:?}: {} (iteration {})",
+            interval, command, iteration
+        );
+        println!("{}\n", paint(Style::new().bold(), header));
+        let width = results
+            .iter()
+            .map(|(host, _)| host.len())
+            .max()
+            .unwrap_or(0);
+        let mut current = HashMap::new();
+        for (host, result) in results {
+            let (style, status, output) = match result {
+                Ok(output) if output.success() => {
+                    (Green.normal(), "ok".to_string(), summarize(&output.stdout))
+                }
+                Ok(SshOutput {
+                    exit_signal: Some(signal),
+                    stderr,
+                    ..
+                }) => {
+                    let status = format!("sig {}", signal);
+                    (Yellow.normal(), status, summarize(&stderr))
+                }
+                Ok(output) => {
+                    let status = format!("exit {}", output.exit_status);
+                    (Yellow.normal(), status, summarize(&output.stderr))
+                }
+                Err(error) => (Red.normal(), "error".to_string(), error.to_string()),
+            };
+            let changed =
+                iteration > 1 && previous.get(&host) != Some(&(status.clone(), output.clone()));
+            let marker = if changed { "*" } else { " " };
+            let row = format!(
+                "{} {:<w$}  {:<7}  {}",
+                marker,
+                host,
+                status,
+                output,
+                w = width
+            );
+            let row_style = if changed {
+                style.bold().reverse()
+            } else {
+                style
+            };
+            println!("{}", paint(row_style, row));
+            current.insert(host, (status, output));
+        }
+        previous = current;
+
+        // Sleep until the next iteration, waking up regularly to check for Ctrl-C.
+        while !stop.load(Ordering::Relaxed) && start.elapsed() < interval {
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+}
+
+/// Summarizes the output of a command as its first line, truncated to 60 characters.
+fn summarize(bytes: &[u8]) -> String {
+    let text = String::from_utf8_lossy(bytes);
+    let mut lines = text.trim_end().lines();
+    let first = lines.next().unwrap_or_default();
+    let mut summary: String = first.chars().take(60).collect();
+    if summary.len() < first.len() || lines.next().is_some() {
+        summary.push_str(" ...");
+    }
+    summary
+}
+
+/// Prints the changes of a synchronized directory, created and uploaded files in cyan and
+/// deleted files in purple.
+fn print_sync_report(report: &SyncReport) {
+    if !show_outputs() {
+        return;
+    }
+    for path in &report.created {
+        println!("{}", paint(Cyan, format!("created {:?}", path)));
+    }
+    for path in &report.uploaded {
+        println!("{}", paint(Cyan, format!("uploaded {:?}", path)));
+    }
+    for path in &report.deleted {
+        println!("{}", paint(Purple, format!("deleted {:?}", path)));
+    }
+    let message = format!("{} unchanged files", report.unchanged);
+    println!("{}", paint(Cyan, message));
+}
+
+/// Prints the facts of a host in cyan, one per line, with sizes in human-readable units.
+fn print_facts(facts: &SshFacts) {
+    if !show_outputs() {
+        return;
+    }
+    let number = |value: Option<u64>| value.map_or("unknown".to_string(), |v| v.to_string());
+    let size = |value: Option<u64>| match value {
+        Some(bytes) => format!("{:.1} GiB", bytes as f64 / (1u64 << 30) as f64),
+        None => "unknown".to_string(),
+    };
+    let uptime = match facts.uptime {
+        Some(secs) => format!(
+            "{}d {}h {}m",
+            secs / 86400,
+            secs / 3600 % 24,
+            secs / 60 % 60
+        ),
+        None => "unknown".to_string(),
+    };
+    let rows = [
+        ("hostname", facts.hostname.clone()),
+        ("os", facts.os.clone()),
+        ("kernel", facts.kernel.clone()),
+        ("arch", facts.arch.clone()),
+        ("cpus", number(facts.cpus)),
+        (
+            "memory",
+            format!(
+                "{} available / {} total",
+                size(facts.memory_available),
+                size(facts.memory_total)
+            ),
+        ),
+        (
+            "disk",
+            format!(
+                "{} used / {} total",
+                size(facts.disk_used),
+                size(facts.disk_total)
+            ),
+        ),
+        ("uptime", uptime),
+    ];
+    for (label, value) in rows.iter() {
+        println!("{}", paint(Cyan, format!("{:<8} {}", label, value)));
+    }
+}
+
+/// Prints the server info of a host in cyan, one per line, with algorithms by direction
+/// when they differ.
+fn print_server_info(info: &SshServerInfo) {
+    if !show_outputs() {
+        return;
+    }
+    let pair = |client_to_server: &str, server_to_client: &str| {
+        if client_to_server == server_to_client {
+            client_to_server.to_string()
+        } else {
+            format!("{} (in: {})", client_to_server, server_to_client)
+        }
+    };
+    let rows = [
+        ("banner", info.banner.clone()),
+        ("kex", info.kex.clone()),
+        ("host key", info.host_key_algorithm.clone()),
+        (
+            "cipher",
+            pair(&info.cipher_client_to_server, &info.cipher_server_to_client),
+        ),
+        (
+            "mac",
+            pair(&info.mac_client_to_server, &info.mac_server_to_client),
+        ),
+        (
+            "compression",
+            pair(
+                &info.compression_client_to_server,
+                &info.compression_server_to_client,
+            ),
+        ),
+        ("sha256", info.fingerprint_sha256.clone()),
+        ("md5", info.fingerprint_md5.clone()),
+        ("auth", info.auth_methods.join(", ")),
+    ];
+    for (label, value) in rows.iter() {
+        println!("{}", paint(Cyan, format!("{:<11} {}", label, value)));
+    }
+}
+
+/// Prints one CSV row per host with its address and the info of its SSH server, or the error
+/// which prevented inspecting it, where the authentication methods are separated by spaces.
+fn print_server_info_csv(
+    massh: &MasshClient,
+    results: Vec<(String, Result<SshServerInfo, Error>)>,
+) {
+    let header = [
+        "host",
+        "ip",
+        "port",
+        "banner",
+        "kex",
+        "host_key_algorithm",
+        "fingerprint_sha256",
+        "fingerprint_md5",
+        "cipher_client_to_server",
+        "cipher_server_to_client",
+        "mac_client_to_server",
+        "mac_server_to_client",
+        "compression_client_to_server",
+        "compression_server_to_client",
+        "auth_methods",
+        "error",
+    ];
+    println!("{}", header.join(","));
+
+    let addrs: HashMap<_, _> = massh.iter().map(|(host, info)| (host, info.addr)).collect();
+    for (host, result) in results {
+        let (ip, port) = match addrs.get(&host) {
+            Some(addr) => (addr.ip().to_string(), addr.port().to_string()),
+            None => (String::new(), String::new()),
+        };
+        let mut row = vec![host, ip, port];
+        match result {
+            Ok(info) => {
+                row.extend([
+                    info.banner,
+                    info.kex,
+                    info.host_key_algorithm,
+                    info.fingerprint_sha256,
+                    info.fingerprint_md5,
+                    info.cipher_client_to_server,
+                    info.cipher_server_to_client,
+                    info.mac_client_to_server,
+                    info.mac_server_to_client,
+                    info.compression_client_to_server,
+                    info.compression_server_to_client,
+                    info.auth_methods.join(" "),
+                    String::new(),
+                ]);
+            }
+            Err(error) => {
+                row.resize(header.len() - 1, String::new());
+                row.push(error.to_string());
+            }
+        }
+        let row: Vec<_> = row.iter().map(|field| csv_field(field)).collect();
+        println!("{}", row.join(","));
+    }
+}
+
+/// Percentiles of the latency printed by the `bench` subcommand, with their names.
+const PERCENTILES: [(&str, f64); 5] = [
+    ("min", 0.0),
+    ("p50", 50.0),
+    ("p90", 90.0),
+    ("p99", 99.0),
+    ("max", 100.0),
+];
+
+/// Phases of the latency printed by the `bench` subcommand, with their names.
+const PHASES: [Phase; 5] = [
+    ("connect", |latency| latency.connect),
+    ("handshake", |latency| latency.handshake),
+    ("auth", |latency| latency.auth),
+    ("command", |latency| latency.command),
+    ("total", SshLatency::total),
+];
+
+/// Phase of the latency of a round trip, with its name.
+type Phase = (&'static str, fn(&SshLatency) -> Duration);
+
+/// Returns the `PERCENTILES` of each phase of the latency of several round trips, and of their
+/// total, by the nearest-rank method.
+fn latency_percentiles(latencies: &[SshLatency]) -> Vec<(&'static str, Vec<Duration>)> {
+    PHASES
+        .iter()
+        .map(|(phase, duration)| {
+            let mut durations: Vec<Duration> = latencies.iter().map(duration).collect();
+            durations.sort();
+            let percentiles = PERCENTILES.iter().map(|(_, percentile)| {
+                let rank = (percentile / 100.0 * durations.len() as f64).ceil() as usize;
+                let index = rank
+                    .saturating_sub(1)
+                    .min(durations.len().saturating_sub(1));
+                durations.get(index).copied().unwrap_or_default()
+            });
+            (*phase, percentiles.collect())
+        })
+        .collect()
+}
+
+/// Prints a table of the percentiles of each phase of the latency of several round trips,
+/// in milliseconds.
+fn print_latencies(latencies: &[SshLatency]) {
+    if !show_outputs() {
+        return;
+    }
+    let mut header = format!("{:<11}", format!("{} runs", latencies.len()));
+    for (name, _) in PERCENTILES.iter() {
+        header.push_str(&format!("{:>10}", name));
+    }
+    println!("{}", paint(Cyan, header));
+    for (phase, durations) in latency_percentiles(latencies) {
+        let mut row = format!("{:<11}", phase);
+        for duration in durations {
+            row.push_str(&format!("{:>10.1}", duration.as_secs_f64() * 1000.0));
+        }
+        println!("{}", paint(Cyan, row));
+    }
+}
+
+/// Prints a JSON object of the results of an operation keyed by host, where failed hosts
+/// have an `error` field.
+fn print_json_results<T: serde::Serialize>(results: Vec<(String, Result<T, Error>)>) {
+    let object: serde_json::Map<_, _> = results
+        .into_iter()
+        .map(|(host, result)| {
+            let value = match result {
+                Ok(value) => serde_json::to_value(value).unwrap(),
+                Err(error) => serde_json::json!({ "error": error.to_string() }),
+            };
+            (host, value)
+        })
+        .collect();
+    println!("{}", serde_json::to_string_pretty(&object).unwrap());
+}
+
+/// Prints one CSV row per host with its address, exit status, duration and error (and its
+/// standard output truncated to `stdout` bytes, if specified), and counts its outcome.
+fn print_csv(
+    massh: &MasshClient,
+    results: Vec<(String, Result<SshOutput, Error>)>,
+    observer: &CliObserver,
+    stdout: Option<usize>,
+    summary: &mut Summary,
+) {
+    let mut header = vec!["host", "ip", "port", "exit_status", "duration_ms", "error"];
+    if stdout.is_some() {
+        header.push("stdout");
+    }
+    println!("{}", header.join(","));
+
+    let addrs: HashMap<_, _> = massh.iter().map(|(host, info)| (host, info.addr)).collect();
+    let durations = observer.durations.lock().unwrap();
+    for (host, result) in results {
+        let (exit_status, error) = match &result {
+            Ok(output) => match &output.exit_signal {
+                None => (output.exit_status.to_string(), String::new()),
+                Some(signal) => (String::new(), format!("killed by signal {}", signal)),
+            },
+            Err(error) => (String::new(), error.to_string()),
+        };
+        let mut row = vec![
+            host.clone(),
+            addrs
+                .get(&host)
+                .map(|addr| addr.ip().to_string())
+                .unwrap_or_default(),
+            addrs
+                .get(&host)
+                .map(|addr| addr.port().to_string())
+                .unwrap_or_default(),
+            exit_status,
+            durations
+                .get(&host)
+                .map(|duration| duration.as_millis().to_string())
+                .unwrap_or_default(),
+            error,
+        ];
+        if let Some(max) = stdout {
+            let bytes = match &result {
+                Ok(output) => &output.stdout[..output.stdout.len().min(max)],
+                Err(_) => &[],
+            };
+            row.push(String::from_utf8_lossy(bytes).into_owned());
+        }
+        let row: Vec<_> = row.iter().map(|field| csv_field(field)).collect();
+        println!("{}", row.join(","));
+
+        match &result {
+            Ok(output) if output.success() => summary.success += 1,
+            Ok(_) => summary.warning += 1,
+            Err(error) if error.downcast_ref::<MasshSkipped>().is_some() => summary.skipped += 1,
+            Err(_) => summary.failure += 1,
+        }
+        if !matches!(&result, Ok(output) if output.success()) {
+            summary.failed.push(host);
+        }
+    }
+}
+
+/// Returns the outcome of a command on a host, as an index of `STATUSES`.
+fn result_status(result: &Result<SshOutput, Error>) -> usize {
+    match result {
+        Ok(output) if output.success() => 0,
+        Ok(_) => 1,
+        Err(error) if error.downcast_ref::<Interrupted>().is_some() => 3,
+        Err(error) if error.downcast_ref::<MasshSkipped>().is_some() => 4,
+        Err(_) => 2,
+    }
+}
+
+/// Receives the messages of an operation (exactly 1 per host) as they arrive. Once interrupted
+/// by Ctrl-C, each host which isn't done yet when the grace period elapses is reported with
+/// an `Interrupted` error instead, without waiting for it any longer.
+fn receive<T>(
+    massh: &MasshClient,
+    rx: MasshReceiver<T>,
+) -> impl Iterator<Item = (MasshHost, Result<T, Error>)> {
+    let mut pending: Vec<MasshHost> = massh.iter().map(|(host, _)| host.clone()).collect();
+    let grace_period = Duration::from_secs(GRACE_PERIOD.load(Ordering::Relaxed));
+    let elapsed = move || matches!(INTERRUPTED.get(), Some(at) if at.elapsed() >= grace_period);
+    std::iter::from_fn(move || loop {
+        if pending.is_empty() {
+            return None;
+        }
+        // Poll the channel regularly to notice the end of the grace period.
+        let timeout = if elapsed() {
+            Duration::ZERO
+        } else {
+            Duration::from_millis(100)
+        };
+        match rx.recv_timeout(timeout) {
+            Ok((host, result)) => {
+                // Ignore the late message of a host which was already reported as interrupted.
+                if let Some(index) = pending.iter().position(|pending| *pending == host) {
+                    pending.remove(index);
+                    return Some((host, result));
+                }
+            }
+            Err(RecvTimeoutError::Timeout) if elapsed() => {
+                return Some((pending.remove(0), Err(Interrupted.into())));
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return None,
+        }
+    })
+}
+
+/// Receives the messages of an operation like `receive`, and returns them once all hosts are
+/// done, sorted by the order of their host in the configuration.
+fn collect_ordered<T>(
+    massh: &MasshClient,
+    rx: MasshReceiver<T>,
+) -> Vec<(MasshHost, Result<T, Error>)> {
+    order_results(massh, receive(massh, rx).collect())
+}
+
+/// Sorts the results of an operation by the order of their host in the configuration.
+fn order_results<T>(massh: &MasshClient, mut results: Vec<(MasshHost, T)>) -> Vec<(MasshHost, T)> {
+    let order: HashMap<_, _> = massh
+        .iter()
+        .enumerate()
+        .map(|(i, (host, _))| (host, i))
+        .collect();
+    results.sort_by_key(|(host, _)| order.get(host).copied());
+    results
+}
+
+/// Receives the results of a command once all hosts are done, sorted by the order of their host
+/// in the configuration, and advances the progress bar of the `--progress` option (if any) as
+/// each result is received.
+fn collect_results(
+    massh: &MasshClient,
+    rx: MasshReceiver<SshOutput>,
+    observer: &CliObserver,
+) -> Vec<(String, Result<SshOutput, Error>)> {
+    let progress = match &observer.progress {
+        Some(progress) => progress,
+        None => return collect_ordered(massh, rx),
+    };
+    let results = receive(massh, rx)
+        .inspect(|(_, result)| progress.advance(result))
+        .collect();
+    progress.bar.finish_and_clear();
+    order_results(massh, results)
+}
+
+/// Sorts the results of a command, received in configuration order, by the key of the `--sort`
+/// option, then by status if grouped with the `--group-by` option.
+fn sort_results(
+    mut results: Vec<(String, Result<SshOutput, Error>)>,
+    sort: Option<SortKey>,
+    group_by: Option<GroupBy>,
+    observer: &CliObserver,
+) -> Vec<(String, Result<SshOutput, Error>)> {
+    let durations = observer.durations.lock().unwrap();
+    match sort {
+        Some(SortKey::Host) => results.sort_by(|(a, _), (b, _)| a.cmp(b)),
+        Some(SortKey::Status) => results.sort_by_key(|(_, result)| result_status(result)),
+        Some(SortKey::Duration) => {
+            results.sort_by_key(|(host, _)| Reverse(durations.get(host).copied()))
+        }
+        None => {}
+    }
+    if let Some(GroupBy::Status) = group_by {
+        results.sort_by_key(|(_, result)| result_status(result));
+    }
+    results
+}
+
+/// Writes the reports of the `--report` option, with the results of each host in configuration
+/// order and the time the command took on it, or exits the program on failure.
+fn write_reports(
+    massh: &MasshClient,
+    reports: &[Report],
+    command: &str,
+    mut hosts: Vec<HostReport>,
+    observer: &CliObserver,
+) {
+    let order: HashMap<_, _> = massh
+        .iter()
+        .enumerate()
+        .map(|(i, (host, _))| (host, i))
+        .collect();
+    hosts.sort_by_key(|host| order.get(&host.host).copied());
+    let durations = observer.durations.lock().unwrap();
+    for host in &mut hosts {
+        host.duration = durations.get(&host.host).copied();
+    }
+    for report in reports {
+        if let Err(error) = report.write(command, &hosts) {
+            eprintln!("{}", paint(Red, error.to_string()));
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Quotes a field of a CSV row if it contains a comma, a quote or a line break.
+fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+/// Paints a text with a color or style, unless colors are disabled.
+fn paint<'a>(style: impl Into<Style>, text: impl Into<Cow<'a, str>>) -> ANSIString<'a> {
+    if COLOR.load(Ordering::Relaxed) {
+        style.into().paint(text)
+    } else {
+        Style::new().paint(text)
+    }
+}
+
+/// Returns whether the output of each host is printed, i.e. unless `--quiet` or `--summary-only`.
+fn show_outputs() -> bool {
+    !QUIET.load(Ordering::Relaxed)
+}
+
+/// Returns whether the status of each host is printed, i.e. unless `--summary-only`.
+fn show_statuses() -> bool {
+    !SUMMARY_ONLY.load(Ordering::Relaxed)
+}
+
+/// Returns a color for the prefix of a host's streamed lines, which is stable across runs.
+fn host_color(host: &str) -> Color {
+    let colors = [Cyan, Green, Yellow, Blue, Purple, Red];
+    let hash = host.bytes().fold(0usize, |hash, byte| {
+        hash.wrapping_mul(31).wrapping_add(byte as usize)
+    });
+    colors[hash % colors.len()]
+}
+
+/// Number of hosts for each possible outcome of an operation.
+#[derive(Default)]
+struct Summary {
+    success: usize,
+    warning: usize,
+    failure: usize,
+    interrupted: usize,
+    skipped: usize,
+    /// Hosts with a warning, a failure, interrupted or skipped, in the order they were reported
+    failed: Vec<String>,
+}
+
+impl Summary {
+    /// Prints a summary of the number of hosts for each outcome, omitting zeros.
+    fn print(&self) {
+        let rows = [
+            ("success", self.success, Green.normal()),
+            ("warning", self.warning, Yellow.normal()),
+            ("failure", self.failure, Red.normal()),
+            ("interrupted", self.interrupted, Yellow.normal()),
+            ("skipped", self.skipped, Style::new()),
+        ];
+        for (label, count, style) in rows.iter() {
+            if *count > 0 {
+                let noun = if *count == 1 { "host" } else { "hosts" };
+                let message = format!("{}: {} {}", label, count, noun);
+                println!("{}", paint(*style, message));
+            }
+        }
+    }
+}
+
+/// Prints host's success message in green.
+fn print_success(host: String, summary: &mut Summary) {
+    summary.success += 1;
+    if show_statuses() {
+        println!("[{}]: {}", host, paint(Green, "success"));
+    }
+}
+
+/// Prints host's warning message in yellow.
+fn print_warning(host: String, summary: &mut Summary, exit_status: i32) {
+    summary.warning += 1;
+    summary.failed.push(host.clone());
+    if show_statuses() {
+        let message = paint(Yellow, format!("warning: exit status = {}", exit_status));
+        println!("[{}]: {}", host, message);
+    }
+}
+
+/// Prints host's success message in green if the command exited with a zero exit status,
+/// or its warning message in yellow with the exit status or the signal that killed it.
+fn print_exit(host: String, summary: &mut Summary, output: &SshOutput) {
+    let signal = match &output.exit_signal {
+        Some(signal) => signal,
+        None if output.exit_status == 0 => return print_success(host, summary),
+        None => return print_warning(host, summary, output.exit_status),
+    };
+    summary.warning += 1;
+    summary.failed.push(host.clone());
+    if !show_statuses() {
+        return;
+    }
+    let mut message = format!("warning: killed by signal {}", signal);
+    if output.core_dumped {
+        message.push_str(" (core dumped)");
+    }
+    if let Some(error_message) = &output.error_message {
+        message.push_str(&format!(": {}", error_message));
+    }
+    println!("[{}]: {}", host, paint(Yellow, message));
+}
+
+/// Prints host's failure message in red, its interrupted message in yellow, or its skipped
+/// message without color.
+fn print_failure(host: String, summary: &mut Summary, error: Error) {
+    summary.failed.push(host.clone());
+    let interrupted = error.downcast_ref::<Interrupted>().is_some();
+    let skipped = error.downcast_ref::<MasshSkipped>().is_some();
+    if interrupted {
+        summary.interrupted += 1;
+    } else if skipped {
+        summary.skipped += 1;
+    } else {
+        summary.failure += 1;
+    }
+    if !show_statuses() {
+        return;
+    }
+    if interrupted {
+        println!("[{}]: {}", host, paint(Yellow, "interrupted"));
+    } else if skipped {
+        println!("[{}]: skipped", host);
+    } else {
+        let message = paint(Red, format!("failure: {}", error));
+        println!("[{}]: {}", host, message);
+    }
+}
+
+/// Prints the standard output in cyan and the standard error in purple of a command,
+/// merged in arrival order if they were captured merged, and with the time at which each line
+/// was received if they were timestamped.
+fn print_output(output: &SshOutput) {
+    if !show_outputs() {
+        return;
+    }
+    if output.chunks.is_empty() {
+        print_bytes(&output.stdout, true);
+        print_bytes(&output.stderr, false);
+    } else {
+        let mut line_start = true;
+        for chunk in &output.chunks {
+            let bytes = output.chunk(chunk);
+            let color = match chunk.stream {
+                SshStream::Stdout => Cyan,
+                SshStream::Stderr => Purple,
+            };
+            let text = String::from_utf8_lossy(bytes);
+            for line in text.split_inclusive('\n') {
+                if let (true, Some(timestamp)) = (line_start, chunk.timestamp) {
+                    print!("[+{:.3}s] ", timestamp.elapsed.as_secs_f64());
+                }
+                print!("{}", paint(color, line));
+                line_start = line.ends_with('\n');
+            }
+        }
+        if !line_start {
+            println!();
+        }
+    }
+    print_overflow(output.stdout_dropped, &output.stdout_file, true);
+    print_overflow(output.stderr_dropped, &output.stderr_file, false);
+}
+
+/// Prints standard output in cyan or standard error in purple.
+fn print_bytes(bytes: &[u8], stdout: bool) {
+    if !bytes.is_empty() {
+        let color = if stdout { Cyan } else { Purple };
+        let label = if stdout { "stdout" } else { "stderr" };
+        if let Ok(message) = std::str::from_utf8(bytes) {
+            println!("{}", paint(color, message.trim_end()));
+        } else {
+            let message = format!("{} is not UTF-8 ({} bytes)", label, bytes.len());
+            println!("{}", paint(color, message));
+        }
+    }
+}
+
+/// Prints how much standard output or standard error exceeded the configured limit, if any.
+fn print_overflow(dropped: u64, file: &Option<PathBuf>, stdout: bool) {
+    if dropped > 0 {
+        let color = if stdout { Cyan } else { Purple };
+        let label = if stdout { "stdout" } else { "stderr" };
+        let message = match file {
+            Some(path) => format!(
+                "{} exceeded the limit by {} bytes: {:?}",
+                label, dropped, path
+            ),
+            None => format!(
+                "{} exceeded the limit by {} bytes (truncated)",
+                label, dropped
+            ),
+        };
+        println!("{}", paint(color, message));
+    }
+}
@@ -0,0 +1,297 @@
+use anyhow::{Error, Result};
+use massh::{MasshSkipped, SshOutput};
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Report of the results of a command on each host, written by the `--report` option once all
+/// hosts are done.
+pub enum Report {
+    /// JUnit XML report, with a test case per host which passes if the command exited with a
+    /// zero exit status.
+    Junit(PathBuf),
+    /// Standalone HTML report, with summary statistics, a sortable table of the hosts, and the
+    /// collapsible output of each host.
+    Html(PathBuf),
+}
+
+impl std::str::FromStr for Report {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, String> {
+        match string.split_once(':') {
+            Some(("junit", path)) if !path.is_empty() => Ok(Report::Junit(path.into())),
+            Some(("html", path)) if !path.is_empty() => Ok(Report::Html(path.into())),
+            _ => Err(format!(
+                "expected junit:PATH or html:PATH, found {:?}",
+                string
+            )),
+        }
+    }
+}
+
+impl Report {
+    /// Attempts to write this report of a command, with the results of each host.
+    pub fn write(&self, command: &str, hosts: &[HostReport]) -> Result<()> {
+        let (path, report) = match self {
+            Report::Junit(path) => (path, junit(command, hosts)),
+            Report::Html(path) => (path, html(command, hosts)),
+        };
+        std::fs::write(path, report)
+            .map_err(|error| anyhow::anyhow!("Failed to write {:?}: {}", path, error))
+    }
+}
+
+/// Result of a command on a host, as written in reports.
+pub struct HostReport {
+    pub host: String,
+    pub exit_status: Option<i32>,
+    pub exit_signal: Option<String>,
+    pub error: Option<String>,
+    pub skipped: bool,
+    pub duration: Option<Duration>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl HostReport {
+    /// Constructs the `HostReport` of a host from the result of its command.
+    pub fn new(host: &str, result: &Result<SshOutput, Error>) -> Self {
+        let mut report = HostReport {
+            host: host.to_string(),
+            exit_status: None,
+            exit_signal: None,
+            error: None,
+            skipped: false,
+            duration: None,
+            stdout: String::new(),
+            stderr: String::new(),
+        };
+        match result {
+            Ok(output) => {
+                match &output.exit_signal {
+                    Some(signal) => report.exit_signal = Some(signal.clone()),
+                    None => report.exit_status = Some(output.exit_status),
+                }
+                report.stdout = output.stdout_lossy().into_owned();
+                report.stderr = output.stderr_lossy().into_owned();
+            }
+            Err(error) if error.downcast_ref::<MasshSkipped>().is_some() => report.skipped = true,
+            Err(error) => report.error = Some(error.to_string()),
+        }
+        report
+    }
+
+    /// Returns the outcome of the command on the host, as printed by the CLI.
+    fn status(&self) -> &'static str {
+        match (self.exit_status, &self.error) {
+            _ if self.skipped => "skipped",
+            (Some(0), _) => "success",
+            (_, Some(_)) => "failure",
+            _ => "warning",
+        }
+    }
+
+    /// Returns a description of the outcome of the command on the host, i.e. its exit status,
+    /// the signal which killed it, or the error which prevented it from running.
+    fn outcome(&self) -> String {
+        match (&self.exit_status, &self.exit_signal, &self.error) {
+            (_, _, Some(error)) => error.clone(),
+            (_, Some(signal), _) => format!("killed by signal {}", signal),
+            (Some(status), _, _) => format!("exit status = {}", status),
+            _ => "skipped".to_string(),
+        }
+    }
+}
+
+/// Returns the JUnit XML report of a command, as a test suite named after the command with a
+/// test case per host. A nonzero exit status (or a signal) is a failure, with the standard error
+/// of the command as its text, and an error which prevented the command from running is an error.
+fn junit(command: &str, hosts: &[HostReport]) -> String {
+    let seconds = |duration: Option<Duration>| duration.unwrap_or_default().as_secs_f64();
+    let count = |f: fn(&HostReport) -> bool| hosts.iter().filter(|host| f(host)).count();
+    let failures = count(|host| host.status() == "warning");
+    let errors = count(|host| host.status() == "failure");
+    let skipped = count(|host| host.status() == "skipped");
+    let time: f64 = hosts.iter().map(|host| seconds(host.duration)).sum();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites>\n");
+    let _ = writeln!(
+        xml,
+        "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\" \
+         time=\"{:.3}\">",
+        escape(command),
+        hosts.len(),
+        failures,
+        errors,
+        skipped,
+        time
+    );
+    for host in hosts {
+        let _ = writeln!(
+            xml,
+            "    <testcase name=\"{}\" classname=\"massh\" time=\"{:.3}\">",
+            escape(&host.host),
+            seconds(host.duration)
+        );
+        match host.status() {
+            "skipped" => xml.push_str("      <skipped/>\n"),
+            "warning" => {
+                let _ = writeln!(
+                    xml,
+                    "      <failure message=\"{}\">{}</failure>",
+                    escape(&host.outcome()),
+                    escape(&host.stderr)
+                );
+            }
+            "failure" => {
+                let message = escape(&host.outcome());
+                let _ = writeln!(xml, "      <error message=\"{}\"/>", message);
+            }
+            _ => {}
+        }
+        if !host.stdout.is_empty() {
+            let stdout = escape(&host.stdout);
+            let _ = writeln!(xml, "      <system-out>{}</system-out>", stdout);
+        }
+        if !host.stderr.is_empty() {
+            let stderr = escape(&host.stderr);
+            let _ = writeln!(xml, "      <system-err>{}</system-err>", stderr);
+        }
+        xml.push_str("    </testcase>\n");
+    }
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    xml
+}
+
+/// Styles and script of the HTML report, which sorts the table by the clicked column.
+const HTML_HEAD: &str = r#"<style>
+body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; }
+th, td { border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; vertical-align: top; }
+th { background: #eee; cursor: pointer; }
+pre { margin: 0.3em 0; max-width: 80em; overflow-x: auto; }
+.success { color: #080; } .warning { color: #a60; } .failure { color: #c00; } .skipped { color: #888; }
+</style>
+<script>
+function sortTable(column) {
+  const table = document.getElementById("hosts");
+  const rows = Array.from(table.tBodies[0].rows);
+  const ascending = table.dataset.column != column || table.dataset.order != "asc";
+  const key = row => row.cells[column].textContent;
+  rows.sort((a, b) => {
+    const [x, y] = [key(a), key(b)];
+    const order = isNaN(x) || isNaN(y) || x === "" || y === "" ? x.localeCompare(y) : x - y;
+    return ascending ? order : -order;
+  });
+  rows.forEach(row => table.tBodies[0].appendChild(row));
+  table.dataset.column = column;
+  table.dataset.order = ascending ? "asc" : "desc";
+}
+</script>
+"#;
+
+/// Returns the standalone HTML report of a command, with the number of hosts for each outcome
+/// and their total and maximum durations, followed by a table of the hosts (sortable by clicking
+/// its headers) with their collapsible standard output and standard error.
+fn html(command: &str, hosts: &[HostReport]) -> String {
+    let durations = hosts.iter().filter_map(|host| host.duration);
+    let (total, max) = durations.fold((Duration::ZERO, Duration::ZERO), |(total, max), d| {
+        (total + d, max.max(d))
+    });
+
+    let mut html = String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    let _ = writeln!(html, "<title>massh: {}</title>", escape(command));
+    html.push_str(HTML_HEAD);
+    html.push_str("</head>\n<body>\n");
+    let _ = writeln!(html, "<h1>massh</h1>\n<pre>{}</pre>", escape(command));
+
+    html.push_str("<h2>Summary</h2>\n<ul>\n");
+    let _ = writeln!(html, "<li>hosts: {}</li>", hosts.len());
+    for status in &["success", "warning", "failure", "skipped"] {
+        let count = hosts.iter().filter(|host| host.status() == *status).count();
+        if count > 0 {
+            let _ = writeln!(html, "<li class=\"{0}\">{0}: {1}</li>", status, count);
+        }
+    }
+    let _ = writeln!(
+        html,
+        "<li>total duration: {:.3} s</li>",
+        total.as_secs_f64()
+    );
+    let _ = writeln!(
+        html,
+        "<li>maximum duration: {:.3} s</li>",
+        max.as_secs_f64()
+    );
+    html.push_str("</ul>\n");
+
+    html.push_str("<h2>Hosts</h2>\n<table id=\"hosts\">\n<thead><tr>");
+    let headers = [
+        "Host",
+        "Status",
+        "Exit status",
+        "Duration (ms)",
+        "Error",
+        "Output",
+    ];
+    for (column, header) in headers.iter().enumerate() {
+        let _ = write!(
+            html,
+            "<th onclick=\"sortTable({})\">{}</th>",
+            column, header
+        );
+    }
+    html.push_str("</tr></thead>\n<tbody>\n");
+    for host in hosts {
+        let status = host.status();
+        let exit_status = host.exit_status.map(|s| s.to_string()).unwrap_or_default();
+        let duration = host.duration.map(|d| d.as_millis().to_string());
+        let error = match host.exit_status {
+            None if !host.skipped => host.outcome(),
+            _ => String::new(),
+        };
+        let _ = write!(
+            html,
+            "<tr><td>{}</td><td class=\"{1}\">{1}</td><td>{2}</td><td>{3}</td><td>{4}</td><td>",
+            escape(&host.host),
+            status,
+            exit_status,
+            duration.unwrap_or_default(),
+            escape(&error)
+        );
+        for (label, output) in &[("stdout", &host.stdout), ("stderr", &host.stderr)] {
+            if !output.is_empty() {
+                let _ = write!(
+                    html,
+                    "<details><summary>{} ({} bytes)</summary><pre>{}</pre></details>",
+                    label,
+                    output.len(),
+                    escape(output)
+                );
+            }
+        }
+        html.push_str("</td></tr>\n");
+    }
+    html.push_str("</tbody>\n</table>\n</body>\n</html>\n");
+    html
+}
+
+/// Escapes the special characters of XML (and HTML) text or attribute values, and removes the
+/// control characters which aren't allowed in XML.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,644 @@
+use crate::auth::{AuditRecord, Auth, Caller, Forbidden};
+use crate::inventory::{GroupNotFound, Inventory, SharedInventory};
+use crate::jobs::{self, Job, JobQuery, JobRequest, SharedJobs};
+use crate::schedule::Schedule;
+use futures_util::{SinkExt, StreamExt};
+use massh::MasshClient;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use warp::http::StatusCode;
+use warp::reply::Response;
+use warp::ws::{Message, Ws};
+use warp::{Filter, Rejection, Reply};
+
+/// Maximum size of the body of a request, in bytes.
+const MAX_BODY_SIZE: u64 = 1024 * 1024;
+
+/// Rejection of a request without a valid bearer token, while authentication is enabled.
+#[derive(Debug)]
+struct Unauthorized;
+
+impl warp::reject::Reject for Unauthorized {}
+
+/// Returns a JSON response with the specified status code.
+fn json<T: serde::Serialize>(status: StatusCode, value: &T) -> Response {
+    warp::reply::with_status(warp::reply::json(value), status).into_response()
+}
+
+/// Returns a JSON error response, as an object with an `error` message.
+fn error(status: StatusCode, message: impl std::fmt::Display) -> Response {
+    json(status, &serde_json::json!({ "error": message.to_string() }))
+}
+
+/// Returns the response of a request about a job, or an error response if the job doesn't
+/// exist or if the caller isn't allowed to access it.
+fn with_job(
+    jobs: &SharedJobs,
+    id: u64,
+    caller: &Caller,
+    f: impl FnOnce(&mut Job) -> Response,
+) -> Response {
+    match jobs.lock().get_mut(id) {
+        Some(job) => match job.check_access(caller) {
+            Ok(()) => f(job),
+            Err(e) => error(StatusCode::FORBIDDEN, e),
+        },
+        None => error(StatusCode::NOT_FOUND, format!("Job {} not found", id)),
+    }
+}
+
+/// Returns the response of a request about the inventory, or an error response if masshd has
+/// no inventory.
+fn with_inventory(
+    inventory: &Option<SharedInventory>,
+    f: impl FnOnce(&SharedInventory) -> Response,
+) -> Response {
+    match inventory {
+        Some(inventory) => f(inventory),
+        None => error(StatusCode::NOT_FOUND, "masshd has no inventory"),
+    }
+}
+
+/// Returns the response of a modification of the inventory, which is audited, or an error
+/// response if the caller isn't allowed to modify the inventory or if the modification failed.
+fn modify_inventory(
+    inventory: &Option<SharedInventory>,
+    auth: &Option<Arc<Auth>>,
+    caller: &Caller,
+    action: &str,
+    group: Option<&str>,
+    f: impl FnOnce(&mut Inventory) -> anyhow::Result<Response>,
+) -> Response {
+    with_inventory(inventory, |inventory| {
+        let result = match caller {
+            Some(token) => token.check_admin().map_err(Into::into),
+            None => Ok(()),
+        };
+        let result = result.and_then(|()| f(&mut inventory.write()));
+        if let Some(auth) = auth {
+            let mut record = AuditRecord::new(action, caller);
+            record.group = group;
+            record.error = result.as_ref().err().map(|e| format!("{:#}", e));
+            auth.audit(&record);
+        }
+        match result {
+            Ok(response) => response,
+            Err(e) if e.is::<Forbidden>() => error(StatusCode::FORBIDDEN, e),
+            Err(e) if e.is::<GroupNotFound>() => error(StatusCode::NOT_FOUND, e),
+            Err(e) => error(StatusCode::BAD_REQUEST, format!("{:#}", e)),
+        }
+    })
+}
+
+/// Returns a filter which authenticates the caller of an endpoint with a bearer token, from the
+/// `Authorization` header or from the `access_token` query parameter (which browsers can set
+/// on WebSockets and Server-Sent Events), unless authentication is disabled.
+fn authenticate(
+    auth: Option<Arc<Auth>>,
+) -> impl Filter<Extract = (Caller,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(
+            move |header: Option<String>, query: HashMap<String, String>| {
+                let auth = auth.clone();
+                async move {
+                    let auth = match auth {
+                        Some(auth) => auth,
+                        None => return Ok(None),
+                    };
+                    let bearer = header.as_deref().and_then(|h| h.strip_prefix("Bearer "));
+                    let secret = bearer.or_else(|| query.get("access_token").map(String::as_str));
+                    match secret.and_then(|secret| auth.authenticate(secret)) {
+                        Some(token) => Ok(Some(token)),
+                        None => Err(warp::reject::custom(Unauthorized)),
+                    }
+                }
+            },
+        )
+}
+
+/// Returns the filter of the API endpoints, under `/api`, and of the `/metrics` endpoint.
+///
+/// The configurations of the job requests can act on the machine of masshd only if
+/// `trust_configs` is true (see `masshd --trust-configs`).
+pub fn api(
+    jobs: SharedJobs,
+    inventory: Option<SharedInventory>,
+    schedules: Vec<Arc<Schedule>>,
+    auth: Option<Arc<Auth>>,
+    trust_configs: bool,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    let caller = authenticate(auth.clone());
+    let jobs = warp::any().map(move || jobs.clone());
+    let inventory = warp::any().map(move || inventory.clone());
+    let auth = warp::any().map(move || auth.clone());
+
+    // POST /api/jobs starts a job, and returns its identifier.
+    let create = warp::path!("api" / "jobs")
+        .and(warp::post())
+        .and(caller.clone())
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+        .and(warp::body::json())
+        .and(jobs.clone())
+        .and(inventory.clone())
+        .and(auth.clone())
+        .map(
+            move |caller: Caller,
+                  request: JobRequest,
+                  jobs: SharedJobs,
+                  inventory: Option<SharedInventory>,
+                  auth: Option<Arc<Auth>>| {
+                let command = request.command.clone();
+                let config = inventory.map(|inventory| inventory.read().config());
+                let result = jobs::start(&jobs, request, config.as_deref(), &caller, trust_configs);
+                if let Some(auth) = &auth {
+                    let mut record = AuditRecord::new("create_job", &caller);
+                    record.job = result.as_ref().ok().copied();
+                    record.command = Some(&command);
+                    record.hosts = match &result {
+                        Ok(id) => jobs.lock().get(*id).map(Job::hosts).unwrap_or_default(),
+                        Err(_) => Vec::new(),
+                    };
+                    record.error = result.as_ref().err().map(|e| format!("{:#}", e));
+                    auth.audit(&record);
+                }
+                match result {
+                    Ok(id) => json(StatusCode::CREATED, &serde_json::json!({ "id": id })),
+                    Err(e) if e.is::<Forbidden>() => error(StatusCode::FORBIDDEN, e),
+                    Err(e) => error(StatusCode::BAD_REQUEST, format!("{:#}", e)),
+                }
+            },
+        );
+
+    // GET /api/jobs returns the status of the jobs matching the query string, if any.
+    let list = warp::path!("api" / "jobs")
+        .and(warp::get())
+        .and(caller.clone())
+        .and(warp::query::<JobQuery>())
+        .and(jobs.clone())
+        .map(|caller: Caller, query: JobQuery, jobs: SharedJobs| {
+            json(StatusCode::OK, &jobs.lock().list(&query, &caller))
+        });
+
+    // GET /api/jobs/:id returns the status of a job.
+    let status = warp::path!("api" / "jobs" / u64)
+        .and(warp::get())
+        .and(caller.clone())
+        .and(jobs.clone())
+        .map(|id, caller: Caller, jobs: SharedJobs| {
+            with_job(&jobs, id, &caller, |job| {
+                json(StatusCode::OK, &job.summary())
+            })
+        });
+
+    // DELETE /api/jobs/:id cancels a running job, and returns its results so far. The job is
+    // stopped on the hosts on which it's running, and skipped on the others.
+    let cancel = warp::path!("api" / "jobs" / u64)
+        .and(warp::delete())
+        .and(caller.clone())
+        .and(jobs.clone())
+        .and(auth.clone())
+        .map(
+            |id, caller: Caller, jobs: SharedJobs, auth: Option<Arc<Auth>>| {
+                with_job(&jobs, id, &caller, |job| {
+                    let result = job.cancel();
+                    if let Some(auth) = &auth {
+                        let mut record = AuditRecord::new("cancel_job", &caller);
+                        record.job = Some(id);
+                        record.command = Some(&job.command);
+                        record.hosts = job.hosts();
+                        record.error = result.as_ref().err().map(|e| format!("{:#}", e));
+                        auth.audit(&record);
+                    }
+                    match result {
+                        Ok(()) => json(StatusCode::OK, &job.results),
+                        Err(e) => error(StatusCode::CONFLICT, e),
+                    }
+                })
+            },
+        );
+
+    // GET /api/jobs/:id/results returns the result of a job on each host, in configuration order.
+    let results = warp::path!("api" / "jobs" / u64 / "results")
+        .and(warp::get())
+        .and(caller.clone())
+        .and(jobs.clone())
+        .map(|id, caller: Caller, jobs: SharedJobs| {
+            with_job(&jobs, id, &caller, |job| json(StatusCode::OK, &job.results))
+        });
+
+    // GET /api/jobs/:id/stream pushes the events of a job, as JSON objects in the text messages
+    // of a WebSocket if the connection is upgraded, or in Server-Sent Events otherwise.
+    let ws = warp::ws().map(Some).or(warp::any().map(|| None)).unify();
+    let stream = warp::path!("api" / "jobs" / u64 / "stream")
+        .and(warp::get())
+        .and(caller.clone())
+        .and(ws)
+        .and(jobs.clone())
+        .map(|id, caller: Caller, ws: Option<Ws>, jobs: SharedJobs| {
+            with_job(&jobs, id, &caller, |job| {
+                let events = job.events();
+                match ws {
+                    Some(ws) => ws
+                        .on_upgrade(|mut socket| async move {
+                            let mut events = Box::pin(events);
+                            while let Some(event) = events.next().await {
+                                let text = serde_json::to_string(&event).unwrap();
+                                if socket.send(Message::text(text)).await.is_err() {
+                                    return;
+                                }
+                            }
+                            let _ = socket.close().await;
+                        })
+                        .into_response(),
+                    None => {
+                        let events =
+                            events.map(|event| warp::sse::Event::default().json_data(event));
+                        warp::sse::reply(warp::sse::keep_alive().stream(events)).into_response()
+                    }
+                }
+            })
+        });
+
+    // GET /api/schedules returns the schedules whose groups the caller can access, with their
+    // next run and their last job, whose history is returned by `GET /api/jobs?schedule=...`.
+    let schedules = warp::path!("api" / "schedules")
+        .and(warp::get())
+        .and(caller.clone())
+        .and(jobs.clone())
+        .map(move |caller: Caller, jobs: SharedJobs| {
+            let jobs = jobs.lock();
+            let schedules: Vec<_> = schedules
+                .iter()
+                .filter(|schedule| match (&caller, &schedule.groups) {
+                    (Some(token), Some(groups)) => {
+                        groups.iter().all(|group| token.check_group(group).is_ok())
+                    }
+                    (Some(token), None) => !token.is_restricted(),
+                    (None, _) => true,
+                })
+                .map(|schedule| {
+                    let query = JobQuery {
+                        schedule: Some(schedule.name.clone()),
+                        limit: Some(1),
+                        ..Default::default()
+                    };
+                    serde_json::json!({
+                        "name": schedule.name,
+                        "cron": schedule.cron.to_string(),
+                        "command": schedule.command,
+                        "groups": schedule.groups,
+                        "next_run": schedule.cron.next(jobs::now()),
+                        "last_job": jobs.list(&query, &caller).first(),
+                    })
+                })
+                .collect();
+            json(StatusCode::OK, &schedules)
+        });
+
+    // GET /metrics returns the metrics of the jobs run since masshd started, in the text format
+    // of Prometheus.
+    let metrics = warp::path!("metrics")
+        .and(warp::get())
+        .and(caller.clone())
+        .and(jobs.clone())
+        .map(|caller: Caller, jobs: SharedJobs| match &caller {
+            Some(token) if token.is_restricted() => error(
+                StatusCode::FORBIDDEN,
+                format!(
+                    "Token `{}` is not allowed to access the metrics",
+                    token.name
+                ),
+            ),
+            _ => {
+                let metrics = jobs.lock().metrics();
+                let content_type = "text/plain; version=0.0.4";
+                warp::reply::with_header(metrics, "content-type", content_type).into_response()
+            }
+        });
+
+    // GET /api/inventory returns the hosts of the inventory that the caller can access, and the
+    // names of its groups.
+    let hosts = warp::path!("api" / "inventory")
+        .and(warp::get())
+        .and(caller.clone())
+        .and(inventory.clone())
+        .map(|caller: Caller, inventory: Option<SharedInventory>| {
+            with_inventory(&inventory, |inventory| {
+                let inventory = inventory.read();
+                let config = inventory.config();
+                let massh = MasshClient::from(&config);
+                let hosts: Vec<_> = massh
+                    .iter()
+                    .filter(|(host, info)| match &caller {
+                        Some(token) => token.check_host(host, info.group.as_deref()).is_ok(),
+                        None => true,
+                    })
+                    .map(|(_, info)| info)
+                    .collect();
+                let groups: Vec<_> = inventory
+                    .groups()
+                    .iter()
+                    .filter_map(|group| group["name"].as_str())
+                    .filter(|name| match &caller {
+                        Some(token) => token.check_group(name).is_ok(),
+                        None => true,
+                    })
+                    .collect();
+                json(
+                    StatusCode::OK,
+                    &serde_json::json!({ "groups": groups, "hosts": hosts }),
+                )
+            })
+        });
+
+    // GET /api/inventory/groups returns the groups of the inventory file that the caller can
+    // access, as configured.
+    let groups = warp::path!("api" / "inventory" / "groups")
+        .and(warp::get())
+        .and(caller.clone())
+        .and(inventory.clone())
+        .map(|caller: Caller, inventory: Option<SharedInventory>| {
+            with_inventory(&inventory, |inventory| {
+                let inventory = inventory.read();
+                let groups: Vec<_> = inventory
+                    .groups()
+                    .iter()
+                    .filter(|group| match (&caller, group["name"].as_str()) {
+                        (Some(token), Some(name)) => token.check_group(name).is_ok(),
+                        (Some(token), None) => !token.is_restricted(),
+                        (None, _) => true,
+                    })
+                    .collect();
+                json(StatusCode::OK, &groups)
+            })
+        });
+
+    // GET /api/inventory/groups/:name returns a group of the inventory file, as configured.
+    let get_group = warp::path!("api" / "inventory" / "groups" / String)
+        .and(warp::get())
+        .and(caller.clone())
+        .and(inventory.clone())
+        .map(
+            |name: String, caller: Caller, inventory: Option<SharedInventory>| {
+                with_inventory(&inventory, |inventory| {
+                    if let Some(Err(e)) = caller.as_ref().map(|token| token.check_group(&name)) {
+                        return error(StatusCode::FORBIDDEN, e);
+                    }
+                    match inventory.read().group(&name) {
+                        Ok(group) => json(StatusCode::OK, group),
+                        Err(e) => error(StatusCode::NOT_FOUND, e),
+                    }
+                })
+            },
+        );
+
+    // PUT /api/inventory/groups/:name creates or replaces a group of the inventory file, and
+    // returns it.
+    let put_group = warp::path!("api" / "inventory" / "groups" / String)
+        .and(warp::put())
+        .and(caller.clone())
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+        .and(warp::body::json())
+        .and(inventory.clone())
+        .and(auth.clone())
+        .map(
+            |name: String,
+             caller: Caller,
+             group: Value,
+             inventory: Option<SharedInventory>,
+             auth: Option<Arc<Auth>>| {
+                let action = "put_group";
+                modify_inventory(
+                    &inventory,
+                    &auth,
+                    &caller,
+                    action,
+                    Some(&name),
+                    |inventory| {
+                        inventory.put_group(&name, group)?;
+                        Ok(json(StatusCode::OK, inventory.group(&name)?))
+                    },
+                )
+            },
+        );
+
+    // DELETE /api/inventory/groups/:name deletes a group of the inventory file.
+    let delete_group = warp::path!("api" / "inventory" / "groups" / String)
+        .and(warp::delete())
+        .and(caller.clone())
+        .and(inventory.clone())
+        .and(auth.clone())
+        .map(
+            |name: String,
+             caller: Caller,
+             inventory: Option<SharedInventory>,
+             auth: Option<Arc<Auth>>| {
+                let action = "delete_group";
+                modify_inventory(
+                    &inventory,
+                    &auth,
+                    &caller,
+                    action,
+                    Some(&name),
+                    |inventory| {
+                        inventory.delete_group(&name)?;
+                        Ok(StatusCode::NO_CONTENT.into_response())
+                    },
+                )
+            },
+        );
+
+    // GET /api/inventory/hosts returns the ungrouped hosts of the inventory file, as configured.
+    let get_hosts = warp::path!("api" / "inventory" / "hosts")
+        .and(warp::get())
+        .and(caller.clone())
+        .and(inventory.clone())
+        .map(|caller: Caller, inventory: Option<SharedInventory>| {
+            with_inventory(&inventory, |inventory| match &caller {
+                Some(token) if token.is_restricted() => error(
+                    StatusCode::FORBIDDEN,
+                    format!(
+                        "Token `{}` is not allowed to access ungrouped hosts",
+                        token.name
+                    ),
+                ),
+                _ => json(StatusCode::OK, inventory.read().hosts()),
+            })
+        });
+
+    // PUT /api/inventory/hosts replaces the ungrouped hosts of the inventory file, and returns
+    // them.
+    let put_hosts = warp::path!("api" / "inventory" / "hosts")
+        .and(warp::put())
+        .and(caller)
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+        .and(warp::body::json())
+        .and(inventory)
+        .and(auth)
+        .map(
+            |caller: Caller,
+             hosts: Value,
+             inventory: Option<SharedInventory>,
+             auth: Option<Arc<Auth>>| {
+                modify_inventory(&inventory, &auth, &caller, "put_hosts", None, |inventory| {
+                    inventory.put_hosts(hosts)?;
+                    Ok(json(StatusCode::OK, inventory.hosts()))
+                })
+            },
+        );
+
+    create
+        .or(list)
+        .unify()
+        .or(status)
+        .unify()
+        .or(cancel)
+        .unify()
+        .or(results)
+        .unify()
+        .or(stream)
+        .unify()
+        .or(schedules)
+        .unify()
+        .or(metrics)
+        .unify()
+        .or(hosts)
+        .unify()
+        .or(groups)
+        .unify()
+        .or(get_group)
+        .unify()
+        .or(put_group)
+        .unify()
+        .or(delete_group)
+        .unify()
+        .or(get_hosts)
+        .unify()
+        .or(put_hosts)
+        .unify()
+}
+
+/// Converts the rejections of unauthenticated requests to `401 Unauthorized` responses, and
+/// keeps the other rejections as is.
+pub async fn recover(rejection: Rejection) -> Result<Response, Rejection> {
+    if rejection.find::<Unauthorized>().is_some() {
+        let response = error(StatusCode::UNAUTHORIZED, "Missing or invalid bearer token");
+        let challenge = "Bearer realm=\"masshd\"";
+        return Ok(
+            warp::reply::with_header(response, "www-authenticate", challenge).into_response(),
+        );
+    }
+    Err(rejection)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobs::Status;
+
+    const AUTH: &str = "
+audit_log: /dev/null
+tokens:
+  - name: monitoring
+    token: secret
+    commands: ['uptime']
+";
+
+    /// Posts a job request with the token of `AUTH` to a masshd without inventory, and returns
+    /// the status of the response.
+    async fn post_job(body: Value) -> StatusCode {
+        let auth = Arc::new(Auth::from_yaml(AUTH).unwrap());
+        let filter = api(SharedJobs::default(), None, Vec::new(), Some(auth), false);
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/jobs")
+            .header("authorization", "Bearer secret")
+            .json(&body)
+            .reply(&filter)
+            .await;
+        response.status()
+    }
+
+    /// Posts a job request to a masshd without authentication, and returns the status of the
+    /// response.
+    async fn post_job_unauthenticated(body: Value, trust_configs: bool) -> StatusCode {
+        let filter = api(SharedJobs::default(), None, Vec::new(), None, trust_configs);
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/jobs")
+            .json(&body)
+            .reply(&filter)
+            .await;
+        response.status()
+    }
+
+    #[tokio::test]
+    async fn commands_only_token_cannot_post_config() {
+        let config = serde_json::json!({
+            "default_auth": {"password_cmd": "touch /tmp/masshd-test"},
+            "hosts": ["127.0.0.1"],
+        });
+        let body = serde_json::json!({"command": "uptime", "config": config});
+        assert_eq!(post_job(body).await, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn commands_only_token_cannot_run_other_commands() {
+        let body = serde_json::json!({"command": "reboot"});
+        assert_eq!(post_job(body).await, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn untrusted_configs_cannot_act_locally() {
+        let configs = [
+            serde_json::json!({"default_auth": {"password_cmd": "id"}, "hosts": ["10.0.0.1"]}),
+            serde_json::json!({"hosts": [{"addr": "127.0.0.1", "local": true}]}),
+            serde_json::json!({"include": ["/etc/massh/*.yaml"]}),
+            serde_json::json!("hosts: [{addr: 10.0.0.1, user: '${USER}'}]"),
+            serde_json::json!("groups: [{name: web, auth: {password_cmd: id}, hosts: [10.0.0.1]}]"),
+            serde_json::json!({"backend": "local", "hosts": ["10.0.0.1"]}),
+            serde_json::json!({"backend": "openssh", "hosts": ["10.0.0.1"]}),
+            serde_json::json!("backend: local\nhosts: [10.0.0.1]"),
+            serde_json::json!({"default_auth": {"pubkey": "/root/.ssh/id_ed25519"}}),
+            serde_json::json!({"default_auth": {"agent_socket": {"path": "/tmp/agent.sock"}}}),
+            serde_json::json!("hosts: [{addr: 10.0.0.1, auth: {pubkey: /etc/ssh/host_key}}]"),
+        ];
+        for config in configs {
+            let body = serde_json::json!({"command": "uptime", "config": config});
+            let status = post_job_unauthenticated(body, false).await;
+            assert_eq!(status, StatusCode::FORBIDDEN, "{}", config);
+        }
+    }
+
+    #[tokio::test]
+    async fn trusted_configs_can_act_locally() {
+        let config = serde_json::json!({"hosts": [{"addr": "127.0.0.1", "local": true}]});
+        let body = serde_json::json!({"command": "echo $((6 * 7))", "config": config});
+        let jobs = SharedJobs::default();
+        let filter = api(jobs.clone(), None, Vec::new(), None, true);
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/jobs")
+            .json(&body)
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let id: Value = serde_json::from_slice(response.body()).unwrap();
+        let id = id["id"].as_u64().unwrap();
+
+        // Wait for the job to finish on the local host.
+        for _ in 0..500 {
+            if jobs.lock().get(id).unwrap().finished_at.is_some() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let jobs = jobs.lock();
+        let result = &jobs.get(id).unwrap().results[0];
+        assert_eq!(result.status, Status::Succeeded, "{:?}", result.error);
+        assert_eq!(result.exit_status, Some(0));
+        assert_eq!(result.stdout, "42\n");
+    }
+}
@@ -0,0 +1,331 @@
+use anyhow::Result;
+use parking_lot::Mutex;
+use regex::RegexSet;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Authentication configuration of masshd, loaded from the YAML file of the `--auth` option.
+///
+/// ## Example
+/// ```yaml
+/// audit_log: /var/log/masshd/audit.log
+/// tokens:
+///   - name: alice
+///     token: 6f1e2b...
+///   - name: monitoring
+///     token: 93ac07...
+///     groups: [web]
+///     commands: ['uptime', 'systemctl status [A-Za-z0-9@._-]+']
+/// ```
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AuthConfig {
+    /// Path of the file to which audit records are appended, or standard output if unspecified.
+    #[serde(default)]
+    audit_log: Option<PathBuf>,
+    /// Bearer tokens accepted by the API.
+    tokens: Vec<TokenConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TokenConfig {
+    /// Name of the token, which identifies its users in the audit log.
+    name: String,
+    /// Secret value of the token.
+    token: String,
+    /// Groups of the hosts that the token can run commands on, or any host if unspecified.
+    /// Tokens restricted to groups or commands can only select hosts from the inventory of
+    /// masshd.
+    #[serde(default)]
+    groups: Option<Vec<String>>,
+    /// Regular expressions of the commands that the token can run, which must match the whole
+    /// command, or any command if unspecified. Since commands run through a shell, those with
+    /// shell metacharacters (see [`SHELL_METACHARACTERS`]) are always rejected.
+    #[serde(default)]
+    commands: Option<Vec<String>>,
+}
+
+/// Characters which let a command run other commands through a shell, or redirect its input
+/// or output, and thus which the commands of command-restricted tokens can't contain.
+const SHELL_METACHARACTERS: &[char] = &[
+    ';', '&', '|', '$', '`', '<', '>', '(', ')', '\\', '\n', '\r',
+];
+
+/// Error of a request which its caller isn't allowed to make.
+#[derive(Debug)]
+pub struct Forbidden(String);
+
+impl std::fmt::Display for Forbidden {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Forbidden {}
+
+impl Forbidden {
+    /// Constructs a new `Forbidden` error with the specified message.
+    pub fn new(message: String) -> Self {
+        Self(message)
+    }
+}
+
+/// Bearer token accepted by the API, with its permissions.
+pub struct Token {
+    pub name: String,
+    secret: String,
+    groups: Option<BTreeSet<String>>,
+    commands: Option<RegexSet>,
+}
+
+impl Token {
+    /// Returns whether this token is restricted to some groups or commands.
+    pub fn is_restricted(&self) -> bool {
+        self.groups.is_some() || self.commands.is_some()
+    }
+
+    /// Fails if this token isn't allowed to modify the inventory, because it's restricted to
+    /// some groups or commands.
+    pub fn check_admin(&self) -> Result<(), Forbidden> {
+        if self.groups.is_some() || self.commands.is_some() {
+            return Err(Forbidden::new(format!(
+                "Token `{}` is not allowed to modify the inventory",
+                self.name
+            )));
+        }
+        Ok(())
+    }
+
+    /// Fails if this token isn't allowed to run a command.
+    pub fn check_command(&self, command: &str) -> Result<(), Forbidden> {
+        match &self.commands {
+            Some(_) if command.contains(SHELL_METACHARACTERS) => Err(Forbidden::new(format!(
+                "Token `{}` is not allowed to run commands with shell metacharacters",
+                self.name
+            ))),
+            Some(commands) if !commands.is_match(command) => Err(Forbidden::new(format!(
+                "Token `{}` is not allowed to run this command",
+                self.name
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fails if this token isn't allowed to access a group of the inventory.
+    pub fn check_group(&self, group: &str) -> Result<(), Forbidden> {
+        match &self.groups {
+            Some(groups) if !groups.contains(group) => Err(Forbidden::new(format!(
+                "Token `{}` is not allowed to access group `{}`",
+                self.name, group
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fails if this token isn't allowed to access a host, because it's not in an allowed group.
+    pub fn check_host(&self, host: &str, group: Option<&str>) -> Result<(), Forbidden> {
+        match (&self.groups, group) {
+            (None, _) => Ok(()),
+            (Some(groups), Some(group)) if groups.contains(group) => Ok(()),
+            _ => Err(Forbidden::new(format!(
+                "Token `{}` is not allowed to access host {}",
+                self.name, host
+            ))),
+        }
+    }
+}
+
+/// Caller of an API endpoint, i.e. its token, or `None` if authentication is disabled.
+pub type Caller = Option<Arc<Token>>;
+
+/// Record of the audit log, written as a line of JSON.
+#[derive(Default, Serialize)]
+pub struct AuditRecord<'a> {
+    pub time: u64,
+    pub token: Option<&'a str>,
+    pub action: &'a str,
+    pub job: Option<u64>,
+    pub command: Option<&'a str>,
+    pub group: Option<&'a str>,
+    pub schedule: Option<&'a str>,
+    pub hosts: Vec<String>,
+    pub error: Option<String>,
+}
+
+impl<'a> AuditRecord<'a> {
+    /// Constructs a new `AuditRecord` of an action of a caller, at the current time.
+    pub fn new(action: &'a str, caller: &'a Caller) -> Self {
+        AuditRecord {
+            time: crate::jobs::now(),
+            token: caller.as_ref().map(|token| token.name.as_str()),
+            action,
+            ..Default::default()
+        }
+    }
+}
+
+/// Authentication of the API, with its audit log.
+pub struct Auth {
+    audit_log: Mutex<Box<dyn Write + Send>>,
+    tokens: Vec<Arc<Token>>,
+}
+
+impl Auth {
+    /// Attempts to load the authentication configuration from a YAML file, and opens its audit
+    /// log in append mode.
+    pub fn from_yaml_file(path: &Path) -> Result<Self> {
+        let yaml = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read {:?}: {}", path, e))?;
+        Self::from_yaml(&yaml)
+    }
+
+    /// Attempts to load the authentication configuration from a YAML string, and opens its
+    /// audit log in append mode.
+    pub fn from_yaml(yaml: &str) -> Result<Self> {
+        let config: AuthConfig = serde_yaml::from_str(yaml)?;
+        let mut tokens = Vec::new();
+        for token in config.tokens {
+            if token.token.is_empty() {
+                return Err(anyhow::anyhow!("Token `{}` is empty", token.name));
+            }
+            let commands = token.commands.map(|commands| {
+                RegexSet::new(commands.iter().map(|command| format!("^(?:{})$", command)))
+            });
+            tokens.push(Arc::new(Token {
+                name: token.name,
+                secret: token.token,
+                groups: token.groups.map(|groups| groups.into_iter().collect()),
+                commands: commands.transpose()?,
+            }));
+        }
+        let audit_log: Box<dyn Write + Send> = match config.audit_log {
+            Some(path) => Box::new(
+                File::options()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .map_err(|e| anyhow::anyhow!("Failed to open {:?}: {}", path, e))?,
+            ),
+            None => Box::new(std::io::stdout()),
+        };
+        Ok(Self {
+            audit_log: Mutex::new(audit_log),
+            tokens,
+        })
+    }
+
+    /// Returns the token with the specified secret value, if any.
+    pub fn authenticate(&self, secret: &str) -> Option<Arc<Token>> {
+        let token = self
+            .tokens
+            .iter()
+            .find(|token| constant_time_eq(token.secret.as_bytes(), secret.as_bytes()));
+        token.cloned()
+    }
+
+    /// Appends a record to the audit log.
+    pub fn audit(&self, record: &AuditRecord) {
+        let mut line = serde_json::to_vec(record).unwrap();
+        line.push(b'\n');
+        let mut audit_log = self.audit_log.lock();
+        if let Err(e) = audit_log.write_all(&line).and_then(|_| audit_log.flush()) {
+            eprintln!("masshd failed to write to the audit log: {}", e);
+        }
+    }
+}
+
+/// Compares two byte strings in a time which only depends on their lengths.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AUTH: &str = r"
+audit_log: /dev/null
+tokens:
+  - {name: admin, token: admin-secret}
+  - {name: web, token: web-secret, groups: [web]}
+  - {name: status, token: status-secret, commands: ['uptime', 'systemctl status [A-Za-z0-9@._-]+']}
+  - {name: echo, token: echo-secret, commands: ['echo .*']}
+";
+
+    fn token(secret: &str) -> Arc<Token> {
+        Auth::from_yaml(AUTH).unwrap().authenticate(secret).unwrap()
+    }
+
+    #[test]
+    fn authenticate() {
+        let auth = Auth::from_yaml(AUTH).unwrap();
+        assert_eq!(auth.authenticate("web-secret").unwrap().name, "web");
+        assert!(auth.authenticate("web-secre").is_none());
+        assert!(auth.authenticate("").is_none());
+        assert!(Auth::from_yaml("tokens: [{name: empty, token: ''}]").is_err());
+        assert!(Auth::from_yaml("tokens: [{name: x, token: y, commands: ['(']}]").is_err());
+    }
+
+    #[test]
+    fn unrestricted_token() {
+        let admin = token("admin-secret");
+        assert!(!admin.is_restricted());
+        assert!(admin.check_admin().is_ok());
+        assert!(admin.check_command("rm -rf /tmp/cache").is_ok());
+        assert!(admin.check_group("db").is_ok());
+        assert!(admin.check_host("root@10.0.0.1:22", None).is_ok());
+    }
+
+    #[test]
+    fn groups_only_token() {
+        let web = token("web-secret");
+        assert!(web.is_restricted());
+        assert!(web.check_admin().is_err());
+        assert!(web.check_command("rm -rf /tmp/cache").is_ok());
+        assert!(web.check_group("web").is_ok());
+        assert!(web.check_group("db").is_err());
+        assert!(web.check_host("root@10.0.0.1:22", Some("web")).is_ok());
+        assert!(web.check_host("root@10.0.1.1:22", Some("db")).is_err());
+        assert!(web.check_host("root@10.0.2.1:22", None).is_err());
+    }
+
+    #[test]
+    fn commands_only_token() {
+        let status = token("status-secret");
+        assert!(status.is_restricted());
+        assert!(status.check_admin().is_err());
+        assert!(status.check_command("uptime").is_ok());
+        assert!(status.check_command("systemctl status nginx").is_ok());
+        assert!(status.check_command("uptime; reboot").is_err());
+        assert!(status
+            .check_command("systemctl status nginx && reboot")
+            .is_err());
+        assert!(status.check_command("systemctl status x;reboot").is_err());
+        assert!(status.check_command("systemctl status $(reboot)").is_err());
+        assert!(status.check_command("systemctl status `reboot`").is_err());
+        assert!(status.check_command("systemctl restart nginx").is_err());
+        assert!(status.check_group("db").is_ok());
+    }
+
+    #[test]
+    fn commands_only_token_rejects_shell_metacharacters() {
+        let echo = token("echo-secret");
+        assert!(echo.check_command("echo hello world").is_ok());
+        for command in [
+            "echo x; reboot",
+            "echo x && reboot",
+            "echo x | sh",
+            "echo $(reboot)",
+            "echo `reboot`",
+            "echo x > /etc/passwd",
+            "echo x\nreboot",
+        ] {
+            assert!(echo.check_command(command).is_err(), "{:?}", command);
+        }
+    }
+}
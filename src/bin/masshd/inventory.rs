@@ -0,0 +1,196 @@
+use anyhow::Result;
+use massh::{MasshConfig, MasshDiagnosticKind, MasshSeverity};
+use parking_lot::RwLock;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Inventory of this masshd instance, shared by the request handlers.
+pub type SharedInventory = Arc<RwLock<Inventory>>;
+
+/// Format of the configuration file of an inventory.
+#[derive(Clone, Copy)]
+pub enum Format {
+    Json,
+    Yaml,
+}
+
+/// Error of a request about a group which isn't in the inventory file.
+#[derive(Debug)]
+pub struct GroupNotFound(String);
+
+impl std::fmt::Display for GroupNotFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Group `{}` not found", self.0)
+    }
+}
+
+impl std::error::Error for GroupNotFound {}
+
+/// Configuration file of the hosts selected by the jobs which don't specify a configuration,
+/// whose groups and ungrouped hosts can be modified through the API.
+///
+/// The file is rewritten on every modification, without its comments. Groups and hosts of
+/// included files can't be modified.
+pub struct Inventory {
+    config: Arc<MasshConfig>,
+    document: Value,
+    format: Format,
+    path: PathBuf,
+}
+
+impl Inventory {
+    /// Attempts to load the inventory from a configuration file, which is created on the first
+    /// modification if it doesn't exist yet.
+    pub fn load(path: &Path, format: Format) -> Result<Self> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(anyhow::anyhow!("Failed to read {:?}: {}", path, e)),
+        };
+        let document = match format {
+            Format::Json if text.trim().is_empty() => Value::Object(Default::default()),
+            Format::Json => serde_json::from_str(&text)?,
+            Format::Yaml => match serde_yaml::from_str(&text)? {
+                Value::Null => Value::Object(Default::default()),
+                document => document,
+            },
+        };
+        let config = match path.exists() {
+            true => parse(path, format)?,
+            false => MasshConfig::from_yaml("")?,
+        };
+        Ok(Self {
+            config: Arc::new(config),
+            document,
+            format,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Returns the current configuration of the inventory.
+    pub fn config(&self) -> Arc<MasshConfig> {
+        self.config.clone()
+    }
+
+    /// Returns the groups of the inventory file, as configured.
+    pub fn groups(&self) -> &[Value] {
+        match self.document.get("groups") {
+            Some(Value::Array(groups)) => groups,
+            _ => &[],
+        }
+    }
+
+    /// Returns the group of the inventory file with the specified name, as configured.
+    pub fn group(&self, name: &str) -> Result<&Value> {
+        let group = self.groups().iter().find(|group| group["name"] == name);
+        group.ok_or_else(|| GroupNotFound(name.to_string()).into())
+    }
+
+    /// Returns the ungrouped hosts of the inventory file, as configured.
+    pub fn hosts(&self) -> &Value {
+        self.document.get("hosts").unwrap_or(&Value::Null)
+    }
+
+    /// Attempts to create or replace a group of the inventory file.
+    pub fn put_group(&mut self, name: &str, mut group: Value) -> Result<()> {
+        let fields = group
+            .as_object_mut()
+            .ok_or_else(|| anyhow::anyhow!("A group must be an object"))?;
+        fields.insert(String::from("name"), Value::from(name));
+        self.modify(|document| {
+            let groups = array(document, "groups")?;
+            match groups.iter_mut().find(|existing| existing["name"] == name) {
+                Some(existing) => *existing = group,
+                None => groups.push(group),
+            }
+            Ok(())
+        })
+    }
+
+    /// Attempts to delete a group of the inventory file.
+    pub fn delete_group(&mut self, name: &str) -> Result<()> {
+        self.group(name)?;
+        self.modify(|document| {
+            array(document, "groups")?.retain(|group| group["name"] != name);
+            Ok(())
+        })
+    }
+
+    /// Attempts to replace the ungrouped hosts of the inventory file.
+    pub fn put_hosts(&mut self, hosts: Value) -> Result<()> {
+        let hosts = match hosts {
+            Value::Array(hosts) => hosts,
+            _ => return Err(anyhow::anyhow!("The hosts must be an array")),
+        };
+        self.modify(|document| {
+            *array(document, "hosts")? = hosts;
+            Ok(())
+        })
+    }
+
+    /// Modifies a copy of the inventory file, and replaces the file with it if it's a valid
+    /// configuration whose hosts can all be reached.
+    fn modify(&mut self, f: impl FnOnce(&mut Value) -> Result<()>) -> Result<()> {
+        let mut document = self.document.clone();
+        f(&mut document)?;
+        let text = match self.format {
+            Format::Json => serde_json::to_string_pretty(&document)?,
+            Format::Yaml => serde_yaml::to_string(&document)?,
+        };
+
+        // Write the file next to the inventory file, so that included files are resolved from
+        // the same directory, then replace the inventory file with it once validated.
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        let temporary = PathBuf::from(temporary);
+        std::fs::write(&temporary, text)
+            .map_err(|e| anyhow::anyhow!("Failed to write {:?}: {}", temporary, e))?;
+        let config = parse(&temporary, self.format).and_then(|config| {
+            let errors: Vec<String> = config
+                .validate()
+                .into_iter()
+                .filter(|d| d.severity == MasshSeverity::Error)
+                .filter(|d| d.kind != MasshDiagnosticKind::NoHosts)
+                .map(|d| d.to_string())
+                .collect();
+            match errors.is_empty() {
+                true => Ok(config),
+                false => Err(anyhow::anyhow!("{}", errors.join("\n"))),
+            }
+        });
+        let config = match config {
+            Ok(config) => config,
+            Err(e) => {
+                let _ = std::fs::remove_file(&temporary);
+                return Err(e);
+            }
+        };
+        std::fs::rename(&temporary, &self.path)
+            .map_err(|e| anyhow::anyhow!("Failed to write {:?}: {}", self.path, e))?;
+        self.config = Arc::new(config);
+        self.document = document;
+        Ok(())
+    }
+}
+
+/// Attempts to parse a configuration file.
+fn parse(path: &Path, format: Format) -> Result<MasshConfig> {
+    match format {
+        Format::Json => MasshConfig::from_json_file(path),
+        Format::Yaml => MasshConfig::from_yaml_file(path),
+    }
+}
+
+/// Returns an array of a configuration document, created if it doesn't exist yet.
+fn array<'a>(document: &'a mut Value, key: &str) -> Result<&'a mut Vec<Value>> {
+    let object = document
+        .as_object_mut()
+        .ok_or_else(|| anyhow::anyhow!("The inventory file must be an object"))?;
+    let value = object
+        .entry(key)
+        .or_insert_with(|| Value::Array(Vec::new()));
+    value
+        .as_array_mut()
+        .ok_or_else(|| anyhow::anyhow!("The `{}` of the inventory file must be an array", key))
+}
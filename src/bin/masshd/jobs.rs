@@ -0,0 +1,715 @@
+use crate::auth::{Caller, Forbidden};
+use crate::metrics::Metrics;
+#[cfg(feature = "sqlite")]
+use crate::store::Store;
+use anyhow::Result;
+use futures_util::{stream, Stream, StreamExt};
+use massh::{
+    MasshClient, MasshConfig, MasshHost, MasshObserver, MasshSkipped, SshStream, SshTimestamp,
+};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+
+/// Maximum number of events buffered for each subscriber of a job, which misses the older
+/// events if it lags behind.
+const EVENT_CAPACITY: usize = 1024;
+
+/// Jobs of this masshd instance, shared by the request handlers and the threads running them.
+pub type SharedJobs = Arc<Mutex<Jobs>>;
+
+/// Fields of a configuration which act on the machine of masshd itself, by running local
+/// commands, by reading local files or sockets (e.g. private keys) or by running the job on it,
+/// unless false or empty.
+const LOCAL_FIELDS: &[&str] = &[
+    "agent_socket",
+    "include",
+    "local",
+    "passphrase_cmd",
+    "password_cmd",
+    "pubkey",
+    "pubkey_passphrase_cmd",
+];
+
+/// Body of a `POST /api/jobs` request.
+#[derive(Deserialize)]
+pub struct JobRequest {
+    /// Command to be executed on the configured hosts.
+    pub command: String,
+    /// Configuration of the hosts, as a JSON object or as a YAML string, or the inventory of
+    /// masshd if unspecified.
+    #[serde(default)]
+    pub config: Option<serde_json::Value>,
+    /// Groups of the hosts on which to execute the command, or all hosts if unspecified.
+    #[serde(default)]
+    pub groups: Option<Vec<String>>,
+    /// Name of the schedule which started the job, which can't be set by the body of a request.
+    #[serde(skip)]
+    pub schedule: Option<String>,
+    /// Whether the `output` events of the job are stamped with the time of their receipt.
+    #[serde(default)]
+    pub timestamps: bool,
+}
+
+/// Query string of a `GET /api/jobs` request, whose jobs match all the specified criteria.
+#[derive(Default, Deserialize)]
+pub struct JobQuery {
+    /// Host which the jobs ran on.
+    #[serde(default)]
+    pub host: Option<MasshHost>,
+    /// Name of the schedule which started the jobs.
+    #[serde(default)]
+    pub schedule: Option<String>,
+    /// Status of the jobs.
+    #[serde(default)]
+    pub status: Option<Status>,
+    /// Minimum creation time of the jobs, in seconds since the Unix epoch.
+    #[serde(default)]
+    pub since: Option<u64>,
+    /// Maximum number of jobs, the most recent ones first (100 by default).
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+/// Status of a job, or of a job on one of its hosts.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    /// The job didn't start on the host yet.
+    Pending,
+    /// The job is still running (on some of its hosts).
+    Running,
+    /// The command exited with status zero (on every host).
+    Succeeded,
+    /// The command failed or exited with a non-zero status (on at least one host).
+    Failed,
+    /// The job was cancelled while it was running (on the host).
+    Cancelled,
+    /// The job was cancelled before it started on the host.
+    Skipped,
+}
+
+#[cfg(feature = "sqlite")]
+impl Status {
+    /// Returns the name of this status, as serialized.
+    pub fn name(self) -> &'static str {
+        match self {
+            Status::Pending => "pending",
+            Status::Running => "running",
+            Status::Succeeded => "succeeded",
+            Status::Failed => "failed",
+            Status::Cancelled => "cancelled",
+            Status::Skipped => "skipped",
+        }
+    }
+
+    /// Returns the status with the specified name, if any.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let statuses = [
+            Self::Pending,
+            Self::Running,
+            Self::Succeeded,
+            Self::Failed,
+            Self::Cancelled,
+            Self::Skipped,
+        ];
+        statuses
+            .iter()
+            .copied()
+            .find(|status| status.name() == name)
+    }
+}
+
+/// Event of a job, as pushed by `GET /api/jobs/:id/stream`.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// The job started on a host, i.e. it started connecting to it.
+    Started { host: MasshHost },
+    /// A line of output was received from a host, without its trailing newline, with the time
+    /// of its receipt in seconds since the Unix epoch and since the command was executed on the
+    /// host, if the job was requested with timestamps.
+    Output {
+        host: MasshHost,
+        stream: &'static str,
+        line: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        time: Option<f64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        elapsed: Option<f64>,
+    },
+    /// The job finished on a host.
+    Finished {
+        host: MasshHost,
+        status: Status,
+        exit_status: Option<i32>,
+        error: Option<String>,
+    },
+    /// The job finished on every host. It's always the last event of a job.
+    Done { status: Status },
+}
+
+/// Result of a job on one of its hosts, as returned by `GET /api/jobs/:id/results`.
+#[derive(Clone, Debug, Serialize)]
+pub struct HostResult {
+    pub host: MasshHost,
+    pub group: Option<String>,
+    pub status: Status,
+    pub exit_status: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    pub error: Option<String>,
+    pub started_at: Option<u64>,
+    pub finished_at: Option<u64>,
+}
+
+/// Command executed on the hosts of a configuration.
+#[derive(Debug)]
+pub struct Job {
+    pub id: u64,
+    pub command: String,
+    pub created_by: Option<String>,
+    pub schedule: Option<String>,
+    pub created_at: u64,
+    pub finished_at: Option<u64>,
+    pub results: Vec<HostResult>,
+    cancelled: bool,
+    events: Vec<Event>,
+    sender: broadcast::Sender<Event>,
+    stop: Arc<AtomicBool>,
+}
+
+/// Status of a job, as returned by `GET /api/jobs/:id`.
+#[derive(Serialize)]
+pub struct JobSummary<'a> {
+    pub id: u64,
+    pub command: &'a str,
+    pub created_by: Option<&'a str>,
+    pub schedule: Option<&'a str>,
+    pub status: Status,
+    pub created_at: u64,
+    pub finished_at: Option<u64>,
+    pub hosts: usize,
+    pub pending: usize,
+    pub running: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub cancelled: usize,
+    pub skipped: usize,
+}
+
+impl Job {
+    /// Constructs a job which already finished, from its stored results, with the events
+    /// implied by its results.
+    #[cfg(feature = "sqlite")]
+    pub fn finished(
+        id: u64,
+        command: String,
+        created_by: Option<String>,
+        schedule: Option<String>,
+        created_at: u64,
+        finished_at: u64,
+        results: Vec<HostResult>,
+    ) -> Self {
+        let mut events = Vec::new();
+        for result in &results {
+            let host = &result.host;
+            events.push(Event::Started { host: host.clone() });
+            for (output, stream) in [(&result.stdout, "stdout"), (&result.stderr, "stderr")] {
+                events.extend(output.lines().map(|line| Event::Output {
+                    host: host.clone(),
+                    stream,
+                    line: line.to_string(),
+                    time: None,
+                    elapsed: None,
+                }));
+            }
+            events.push(Event::Finished {
+                host: host.clone(),
+                status: result.status,
+                exit_status: result.exit_status,
+                error: result.error.clone(),
+            });
+        }
+        let cancelled = results
+            .iter()
+            .any(|r| matches!(r.status, Status::Cancelled | Status::Skipped));
+        let mut job = Job {
+            id,
+            command,
+            created_by,
+            schedule,
+            created_at,
+            finished_at: Some(finished_at),
+            results,
+            cancelled,
+            events,
+            sender: broadcast::channel(1).0,
+            stop: Arc::default(),
+        };
+        let status = job.status();
+        job.events.push(Event::Done { status });
+        job
+    }
+
+    /// Returns the status of this job, i.e. running until it finished on every host, and then
+    /// cancelled if it was cancelled, or failed if it failed on at least one host.
+    pub fn status(&self) -> Status {
+        let count = |status| self.count(status);
+        if count(Status::Pending) + count(Status::Running) != 0 {
+            Status::Running
+        } else if self.cancelled {
+            Status::Cancelled
+        } else if count(Status::Failed) != 0 {
+            Status::Failed
+        } else {
+            Status::Succeeded
+        }
+    }
+
+    /// Returns the status of this job, with the number of hosts by status.
+    pub fn summary(&self) -> JobSummary<'_> {
+        JobSummary {
+            id: self.id,
+            command: &self.command,
+            created_by: self.created_by.as_deref(),
+            schedule: self.schedule.as_deref(),
+            status: self.status(),
+            created_at: self.created_at,
+            finished_at: self.finished_at,
+            hosts: self.results.len(),
+            pending: self.count(Status::Pending),
+            running: self.count(Status::Running),
+            succeeded: self.count(Status::Succeeded),
+            failed: self.count(Status::Failed),
+            cancelled: self.count(Status::Cancelled),
+            skipped: self.count(Status::Skipped),
+        }
+    }
+
+    /// Cancels this job if it's still running: it's stopped on the hosts on which it's running,
+    /// and skipped on the hosts on which it didn't start yet.
+    ///
+    /// It fails if this job already finished.
+    pub fn cancel(&mut self) -> Result<()> {
+        if self.finished_at.is_some() {
+            return Err(anyhow::anyhow!("Job {} already finished", self.id));
+        }
+        self.cancelled = true;
+        self.stop.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Returns the hosts of this job, in configuration order.
+    pub fn hosts(&self) -> Vec<MasshHost> {
+        self.results.iter().map(|r| r.host.clone()).collect()
+    }
+
+    /// Fails if the caller isn't allowed to access this job, because it isn't allowed to access
+    /// all its hosts.
+    pub fn check_access(&self, caller: &Caller) -> Result<(), Forbidden> {
+        match caller {
+            Some(token) => self
+                .results
+                .iter()
+                .try_for_each(|r| token.check_host(&r.host, r.group.as_deref())),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns whether this job matches all the criteria of a query.
+    fn matches(&self, query: &JobQuery) -> bool {
+        query
+            .host
+            .as_ref()
+            .map_or(true, |host| self.results.iter().any(|r| &r.host == host))
+            && query
+                .schedule
+                .as_ref()
+                .map_or(true, |schedule| self.schedule.as_ref() == Some(schedule))
+            && query.status.map_or(true, |status| self.status() == status)
+            && query.since.map_or(true, |since| self.created_at >= since)
+    }
+
+    /// Returns a stream of the events of this job, starting with its past events, which ends
+    /// with its `done` event, or early if the subscriber lags too far behind.
+    pub fn events(&self) -> impl Stream<Item = Event> + Send + 'static {
+        let receiver = self.finished_at.is_none().then(|| self.sender.subscribe());
+        let next = stream::unfold(receiver, |receiver| async move {
+            let mut receiver = receiver?;
+            let event = receiver.recv().await.ok()?;
+            let done = matches!(event, Event::Done { .. });
+            Some((event, (!done).then_some(receiver)))
+        });
+        stream::iter(self.events.clone()).chain(next)
+    }
+
+    /// Returns the number of hosts on which this job has the specified status.
+    fn count(&self, status: Status) -> usize {
+        self.results.iter().filter(|r| r.status == status).count()
+    }
+
+    /// Returns the result of this job on a host.
+    fn result(&mut self, host: &str) -> &mut HostResult {
+        let result = self.results.iter_mut().find(|r| r.host == host);
+        result.expect("events are received from configured hosts")
+    }
+
+    /// Records an event, and sends it to the current subscribers.
+    fn publish(&mut self, event: Event) {
+        self.events.push(event.clone());
+        let _ = self.sender.send(event);
+    }
+
+    /// Records that this job started on a host.
+    fn start(&mut self, host: &MasshHost) {
+        let result = self.result(host);
+        if result.status != Status::Pending {
+            return; // The host reconnected.
+        }
+        result.status = Status::Running;
+        result.started_at = Some(now());
+        self.publish(Event::Started { host: host.clone() });
+    }
+
+    /// Records a line of output of this job on a host, with the time of its receipt if the job
+    /// is timestamped.
+    fn output(
+        &mut self,
+        host: &MasshHost,
+        stream: SshStream,
+        line: &[u8],
+        timestamp: Option<SshTimestamp>,
+    ) {
+        let line = String::from_utf8_lossy(line).into_owned();
+        let (output, stream) = match stream {
+            SshStream::Stdout => (&mut self.result(host).stdout, "stdout"),
+            SshStream::Stderr => (&mut self.result(host).stderr, "stderr"),
+        };
+        output.push_str(&line);
+        output.push('\n');
+        let time = timestamp.and_then(|t| t.time.duration_since(UNIX_EPOCH).ok());
+        self.publish(Event::Output {
+            host: host.clone(),
+            stream,
+            line,
+            time: time.map(|time| time.as_secs_f64()),
+            elapsed: timestamp.map(|t| t.elapsed.as_secs_f64()),
+        });
+    }
+
+    /// Records the result of this job on a host.
+    fn finish(&mut self, host: MasshHost, result: Result<i32>) {
+        let cancelled = self.cancelled;
+        let entry = self.result(&host);
+        let (status, exit_status, error) = match result {
+            Err(error) if error.is::<MasshSkipped>() => (Status::Skipped, None, None),
+            Ok(exit_status) if cancelled => (Status::Cancelled, Some(exit_status), None),
+            Err(error) if cancelled => (Status::Cancelled, None, Some(format!("{:#}", error))),
+            Ok(0) => (Status::Succeeded, Some(0), None),
+            Ok(exit_status) => (Status::Failed, Some(exit_status), None),
+            Err(error) => (Status::Failed, None, Some(format!("{:#}", error))),
+        };
+        entry.status = status;
+        entry.exit_status = exit_status;
+        entry.error = error.clone();
+        if status != Status::Skipped {
+            entry.started_at.get_or_insert_with(now);
+            entry.finished_at = Some(now());
+        }
+        let event = Event::Finished {
+            host,
+            status,
+            exit_status,
+            error,
+        };
+        self.publish(event);
+    }
+}
+
+/// Jobs of this masshd instance, by identifier.
+#[derive(Default)]
+pub struct Jobs {
+    jobs: BTreeMap<u64, Job>,
+    metrics: Metrics,
+    next_id: u64,
+    #[cfg(feature = "sqlite")]
+    store: Option<Store>,
+}
+
+impl Jobs {
+    /// Attempts to load the jobs of a database, in which the jobs are saved once finished.
+    #[cfg(feature = "sqlite")]
+    pub fn with_store(store: Store) -> Result<Self> {
+        let jobs: BTreeMap<u64, Job> = store.load()?.into_iter().map(|job| (job.id, job)).collect();
+        Ok(Self {
+            next_id: jobs.keys().next_back().copied().unwrap_or(0),
+            jobs,
+            metrics: Metrics::default(),
+            store: Some(store),
+        })
+    }
+
+    /// Returns the job with the specified identifier, if any.
+    pub fn get(&self, id: u64) -> Option<&Job> {
+        self.jobs.get(&id)
+    }
+
+    /// Returns a mutable reference to the job with the specified identifier, if any.
+    pub fn get_mut(&mut self, id: u64) -> Option<&mut Job> {
+        self.jobs.get_mut(&id)
+    }
+
+    /// Returns the jobs which match a query and which the caller is allowed to access, the most
+    /// recent ones first.
+    pub fn list(&self, query: &JobQuery, caller: &Caller) -> Vec<JobSummary<'_>> {
+        self.jobs
+            .values()
+            .rev()
+            .filter(|job| job.matches(query) && job.check_access(caller).is_ok())
+            .take(query.limit.unwrap_or(100))
+            .map(Job::summary)
+            .collect()
+    }
+
+    /// Returns the metrics of the jobs run since masshd started, in the text format of
+    /// Prometheus.
+    pub fn metrics(&self) -> String {
+        let running = self.jobs.values().filter(|job| job.finished_at.is_none());
+        self.metrics.render(running.count())
+    }
+
+    /// Removes the finished jobs created before the specified time, in seconds since the Unix
+    /// epoch, including from the database.
+    pub fn prune(&mut self, before: u64) {
+        self.jobs
+            .retain(|_, job| job.finished_at.is_none() || job.created_at >= before);
+        #[cfg(feature = "sqlite")]
+        if let Some(store) = &self.store {
+            if let Err(e) = store.prune(before) {
+                eprintln!("masshd failed to prune the database: {:#}", e);
+            }
+        }
+    }
+
+    /// Runs a closure on the job with the specified identifier, which must exist.
+    fn update(&mut self, id: u64, f: impl FnOnce(&mut Job)) {
+        f(self
+            .jobs
+            .get_mut(&id)
+            .expect("running jobs are never removed"))
+    }
+
+    /// Records that a job finished on every host, and saves it in the database.
+    fn finish(&mut self, id: u64) {
+        self.update(id, |job| {
+            job.finished_at = Some(now());
+            let status = job.status();
+            job.publish(Event::Done { status });
+        });
+        self.metrics.finish(&self.jobs[&id]);
+        #[cfg(feature = "sqlite")]
+        if let Some(store) = &self.store {
+            if let Err(e) = store.save(&self.jobs[&id]) {
+                eprintln!("masshd failed to save job {}: {:#}", id, e);
+            }
+        }
+    }
+}
+
+/// Observer of the hosts of a job, which records when the job starts on each host.
+struct JobObserver {
+    id: u64,
+    jobs: SharedJobs,
+}
+
+impl MasshObserver for JobObserver {
+    fn on_connect_start(&self, host: &MasshHost) {
+        self.jobs.lock().update(self.id, |job| job.start(host));
+    }
+
+    fn on_connect_ok(&self, _host: &MasshHost, duration: Duration) {
+        self.jobs.lock().metrics.connect(duration, true);
+    }
+
+    fn on_connect_err(&self, _host: &MasshHost, duration: Duration, _error: &anyhow::Error) {
+        self.jobs.lock().metrics.connect(duration, false);
+    }
+}
+
+/// Fails if the configuration of a request acts on the machine of masshd, i.e. if it has a
+/// field of [`LOCAL_FIELDS`], a backend other than libssh2 (which would run the job on masshd,
+/// or through its own `ssh_config` and keys) or a reference to an environment variable of
+/// masshd (`${NAME}`).
+fn check_untrusted(value: &serde_json::Value) -> Result<(), Forbidden> {
+    use serde_json::Value;
+    let forbidden = |what: &str| {
+        Forbidden::new(format!(
+            "The configuration of a request can't contain {} (see `masshd --trust-configs`)",
+            what
+        ))
+    };
+    match value {
+        Value::String(string) if string.contains("${") => Err(forbidden("environment variables")),
+        Value::Array(values) => values.iter().try_for_each(check_untrusted),
+        Value::Object(map) => map.iter().try_for_each(|(key, value)| {
+            let unset = match value {
+                Value::Null | Value::Bool(false) => true,
+                Value::Array(values) => values.is_empty(),
+                _ => false,
+            };
+            if LOCAL_FIELDS.contains(&key.as_str()) && !unset {
+                return Err(forbidden(&format!("`{}`", key)));
+            }
+            if key == "backend" && !unset && value.as_str() != Some("libssh2") {
+                return Err(forbidden("a backend other than `libssh2`"));
+            }
+            check_untrusted(value)
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Attempts to start a job in a new thread, and returns its identifier.
+///
+/// It fails if the configuration is invalid, if it has invalid hosts, if no hosts are selected,
+/// or with a [`Forbidden`] error if the caller isn't allowed to run the command on all its hosts
+/// or if the configuration of the request acts on the machine of masshd (see
+/// [`check_untrusted`]) while `trust_configs` is false.
+pub fn start(
+    jobs: &SharedJobs,
+    request: JobRequest,
+    inventory: Option<&MasshConfig>,
+    caller: &Caller,
+    trust_configs: bool,
+) -> Result<u64> {
+    // Since the groups of the hosts of a request are arbitrary, and since its configuration can
+    // run commands of its own (e.g. `password_cmd`), restricted tokens can only select hosts from
+    // the inventory. The request is checked before its configuration is even parsed.
+    if let Some(token) = caller {
+        if request.config.is_some() && token.is_restricted() {
+            return Err(Forbidden::new(format!(
+                "Token `{}` can only select hosts from the inventory of masshd",
+                token.name
+            ))
+            .into());
+        }
+        token.check_command(&request.command)?;
+    }
+    match &request.config {
+        Some(serde_json::Value::String(yaml)) if !trust_configs => {
+            check_untrusted(&serde_yaml::from_str(yaml)?)?;
+        }
+        Some(json) if !trust_configs => check_untrusted(json)?,
+        _ => {}
+    }
+
+    // Select the hosts of the job, from the configuration of the request or from the inventory.
+    let custom;
+    let config = match &request.config {
+        Some(serde_json::Value::String(yaml)) => {
+            custom = MasshConfig::from_yaml(yaml)?;
+            &custom
+        }
+        Some(json) => {
+            custom = MasshConfig::from_json(&json.to_string())?;
+            &custom
+        }
+        None => inventory.ok_or_else(|| {
+            anyhow::anyhow!("No configuration was specified, and masshd has no inventory")
+        })?,
+    };
+    let (massh, _) = MasshClient::try_from(config)?;
+    let mut massh = match &request.groups {
+        Some(groups) => massh.filter(|host| {
+            let group = massh
+                .get_host_info(host)
+                .and_then(|info| info.group.as_ref());
+            group.is_some_and(|group| groups.contains(group))
+        }),
+        None => massh,
+    };
+    if massh.is_empty() {
+        return Err(anyhow::anyhow!("No hosts were selected"));
+    }
+    if let Some(token) = caller {
+        for (host, info) in massh.iter() {
+            token.check_host(host, info.group.as_deref())?;
+        }
+    }
+
+    // Register the job, pending on all its hosts, with the flag which stops it once cancelled.
+    let stop = Arc::new(AtomicBool::new(false));
+    let results = massh
+        .iter()
+        .map(|(host, info)| HostResult {
+            host: host.clone(),
+            group: info.group.clone(),
+            status: Status::Pending,
+            exit_status: None,
+            stdout: String::new(),
+            stderr: String::new(),
+            error: None,
+            started_at: None,
+            finished_at: None,
+        })
+        .collect();
+    let id = {
+        let mut jobs = jobs.lock();
+        jobs.next_id += 1;
+        let id = jobs.next_id;
+        let job = Job {
+            id,
+            command: request.command.clone(),
+            created_by: caller.as_ref().map(|token| token.name.clone()),
+            schedule: request.schedule.clone(),
+            created_at: now(),
+            finished_at: None,
+            results,
+            cancelled: false,
+            events: Vec::new(),
+            sender: broadcast::channel(EVENT_CAPACITY).0,
+            stop: stop.clone(),
+        };
+        jobs.jobs.insert(id, job);
+        id
+    };
+
+    // Run the job, streaming the output of each host and recording its result as soon as it's
+    // received.
+    let observer = JobObserver {
+        id,
+        jobs: jobs.clone(),
+    };
+    massh.set_observer(Some(Arc::new(observer)));
+    let jobs = jobs.clone();
+    std::thread::spawn(move || {
+        let output_jobs = jobs.clone();
+        let timestamps = request.timestamps;
+        let rx = massh.execute_streaming_timestamped(
+            request.command,
+            stop,
+            move |host, stream, line, timestamp| {
+                let timestamp = Some(timestamp).filter(|_| timestamps);
+                let mut jobs = output_jobs.lock();
+                jobs.metrics.output(stream, line);
+                jobs.update(id, |job| job.output(host, stream, line, timestamp))
+            },
+        );
+        while let Ok((host, result)) = rx.recv() {
+            jobs.lock().update(id, |job| job.finish(host, result));
+        }
+        jobs.lock().finish(id);
+    });
+    Ok(id)
+}
+
+/// Returns the current time, in seconds since the Unix epoch.
+pub fn now() -> u64 {
+    let elapsed = SystemTime::now().duration_since(UNIX_EPOCH);
+    elapsed.map(|elapsed| elapsed.as_secs()).unwrap_or(0)
+}
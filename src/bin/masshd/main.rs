@@ -0,0 +1,217 @@
+mod api;
+mod auth;
+mod inventory;
+mod jobs;
+mod metrics;
+mod schedule;
+#[cfg(feature = "sqlite")]
+mod store;
+
+use auth::Auth;
+use inventory::{Format, Inventory};
+#[cfg(feature = "sqlite")]
+use jobs::Jobs;
+use jobs::SharedJobs;
+#[cfg(feature = "sqlite")]
+use parking_lot::Mutex;
+use parking_lot::RwLock;
+use schedule::Schedule;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use structopt::StructOpt;
+use warp::Filter;
+
+/// A web application and JSON API to run SSH commands on a "mass" of hosts in parallel.
+///
+/// For more details, check the README:
+/// https://github.com/felix-pb/massh
+#[derive(StructOpt)]
+#[structopt(name = "masshd")]
+struct Opt {
+    /// Path of JSON configuration file of the inventory, whose hosts are selected by the jobs
+    /// which don't specify a configuration, and whose groups can be modified through the API
+    #[structopt(short, long, conflicts_with("yaml"))]
+    json: Option<PathBuf>,
+    /// Path of YAML configuration file of the inventory, whose hosts are selected by the jobs
+    /// which don't specify a configuration, and whose groups can be modified through the API
+    #[structopt(short, long, conflicts_with("json"))]
+    yaml: Option<PathBuf>,
+    /// Path of YAML file listing the bearer tokens required by the API, with their permissions
+    /// and the path of the audit log (authentication is disabled otherwise)
+    #[structopt(long, env = "MASSHD_AUTH")]
+    auth: Option<PathBuf>,
+    /// Allows the configurations of job requests to act on the machine of masshd itself, i.e. to
+    /// run local commands (`password_cmd`, `passphrase_cmd`), to include local files, to use
+    /// local private keys or agent sockets (`pubkey`, `agent_socket`), to select the local host
+    /// (`local: true`) or a backend other than `libssh2` (e.g. `local` or `openssh`, which uses
+    /// the `ssh_config` and keys of masshd) and to reference environment variables, which are
+    /// rejected otherwise since any caller allowed to post a configuration could use them
+    #[structopt(long)]
+    trust_configs: bool,
+    /// Path of YAML file listing the schedules of the jobs to run on the inventory, with the
+    /// webhooks to which their failures are posted
+    #[structopt(long, env = "MASSHD_SCHEDULES")]
+    schedules: Option<PathBuf>,
+    /// Path of SQLite database in which to save the finished jobs, which are loaded from it
+    /// on startup (requires the `sqlite` feature)
+    #[structopt(long, env = "MASSHD_DATABASE")]
+    database: Option<PathBuf>,
+    /// Number of days after which finished jobs are deleted, or 0 to keep them forever
+    #[structopt(long, env = "MASSHD_RETENTION_DAYS", default_value = "0")]
+    retention_days: u64,
+    /// IP address to listen on (e.g. 0.0.0.0 to listen on all IPv4 interfaces)
+    #[structopt(short, long, env = "MASSHD_ADDRESS", default_value = "127.0.0.1")]
+    address: IpAddr,
+    /// Port number to listen on
+    #[structopt(short, long, env = "MASSHD_PORT", default_value = "2222")]
+    port: u16,
+    /// Path of PEM file of the TLS certificate chain, to serve HTTPS instead of HTTP
+    #[structopt(long, env = "MASSHD_TLS_CERT", requires("tls-key"))]
+    tls_cert: Option<PathBuf>,
+    /// Path of PEM file of the private key of the TLS certificate
+    #[structopt(long, env = "MASSHD_TLS_KEY", requires("tls-cert"))]
+    tls_key: Option<PathBuf>,
+}
+
+macro_rules! static_file {
+    ($file:expr) => {{
+        let body = include_bytes!(concat!("../../assets/", $file));
+        let path = warp::path::end().or(warp::path("static")
+            .and(warp::path($file))
+            .and(warp::path::end()));
+        let content_type = match $file {
+            "index.html" => "text/html",
+            "favicon.ico" => "image/x-icon",
+            _ => unreachable!(),
+        };
+        let reply = move |_| warp::reply::with_header(&body[..], "content-type", content_type);
+        warp::get().and(path).map(reply)
+    }};
+    ($dir:expr, $file:expr) => {{
+        let body = include_bytes!(concat!("../../assets/", $dir, "/", $file));
+        let path = warp::path("static")
+            .and(warp::path($dir))
+            .and(warp::path($file))
+            .and(warp::path::end());
+        let content_type = match $dir {
+            "css" => "text/css",
+            "js" => "application/javascript",
+            _ => unreachable!(),
+        };
+        let reply = move || warp::reply::with_header(&body[..], "content-type", content_type);
+        warp::get().and(path).map(reply)
+    }};
+}
+
+/// Returns the value of a result, or prints its error and exits if it failed to load a file.
+fn exit_on_error<T>(path: &Path, result: anyhow::Result<T>) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("masshd failed to load {:?}: {:#}", path, e);
+        std::process::exit(1);
+    })
+}
+
+#[tokio::main]
+async fn main() {
+    let opt = Opt::from_args();
+    let inventory = match (&opt.json, &opt.yaml) {
+        (Some(path), _) => Some((path, Inventory::load(path, Format::Json))),
+        (_, Some(path)) => Some((path, Inventory::load(path, Format::Yaml))),
+        (None, None) => None,
+    };
+    let inventory =
+        inventory.map(|(path, result)| Arc::new(RwLock::new(exit_on_error(path, result))));
+    let auth = opt.auth.as_ref().map(|path| {
+        let auth = Auth::from_yaml_file(path);
+        Arc::new(exit_on_error(path, auth))
+    });
+
+    let f1 = static_file!("index.html");
+    let f2 = static_file!("favicon.ico");
+    let f3 = static_file!("css", "app.css");
+    let f4 = static_file!("css", "chunk-vendors.css");
+    let f5 = static_file!("js", "app.js");
+    let f6 = static_file!("js", "app.js.map");
+    let f7 = static_file!("js", "chunk-vendors.js");
+    let f8 = static_file!("js", "chunk-vendors.js.map");
+    let assets = f1.or(f2).or(f3).or(f4).or(f5).or(f6).or(f7).or(f8);
+    let jobs = match &opt.database {
+        #[cfg(feature = "sqlite")]
+        Some(path) => {
+            let jobs = store::Store::open(path).and_then(Jobs::with_store);
+            Arc::new(Mutex::new(exit_on_error(path, jobs)))
+        }
+        #[cfg(not(feature = "sqlite"))]
+        Some(path) => exit_on_error(
+            path,
+            Err(anyhow::anyhow!(
+                "masshd was built without the `sqlite` feature"
+            )),
+        ),
+        None => SharedJobs::default(),
+    };
+
+    // Delete the jobs older than the retention period, on startup and then every hour.
+    if opt.retention_days != 0 {
+        let (jobs, retention) = (jobs.clone(), opt.retention_days * 24 * 60 * 60);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+            loop {
+                interval.tick().await;
+                let jobs = jobs.clone();
+                let before = jobs::now().saturating_sub(retention);
+                let _ = tokio::task::spawn_blocking(move || jobs.lock().prune(before)).await;
+            }
+        });
+    }
+
+    // Run the jobs of each schedule on the inventory, in the background.
+    let schedules = match (&opt.schedules, &inventory) {
+        (Some(path), Some(inventory)) => {
+            let schedules = exit_on_error(path, Schedule::from_yaml_file(path));
+            for schedule in &schedules {
+                let run = schedule
+                    .clone()
+                    .run(jobs.clone(), inventory.clone(), auth.clone());
+                tokio::spawn(run);
+            }
+            schedules
+        }
+        (Some(path), None) => exit_on_error(
+            path,
+            Err(anyhow::anyhow!(
+                "Schedules require an inventory (see --json or --yaml)"
+            )),
+        ),
+        (None, _) => Vec::new(),
+    };
+
+    let api = api::api(jobs.clone(), inventory, schedules, auth, opt.trust_configs);
+    let filter = api.or(assets).recover(api::recover);
+
+    let addr = SocketAddr::from((opt.address, opt.port));
+    if !addr.ip().is_loopback() {
+        if opt.auth.is_none() {
+            eprintln!("masshd warning: the API doesn't require authentication (see --auth)");
+        }
+        if opt.tls_cert.is_none() {
+            eprintln!("masshd warning: the traffic isn't encrypted (see --tls-cert)");
+        }
+    }
+    match (&opt.tls_cert, &opt.tls_key) {
+        (Some(cert), Some(key)) => {
+            // Read the files first, since warp panics on invalid paths.
+            let cert = exit_on_error(cert, std::fs::read(cert).map_err(Into::into));
+            let key = exit_on_error(key, std::fs::read(key).map_err(Into::into));
+            println!("masshd listening on https://{}", addr);
+            let server = warp::serve(filter).tls().cert(cert).key(key);
+            server.run(addr).await;
+        }
+        _ => {
+            println!("masshd listening on http://{}", addr);
+            warp::serve(filter).run(addr).await;
+        }
+    }
+}
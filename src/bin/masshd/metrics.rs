@@ -0,0 +1,166 @@
+use crate::jobs::{Job, Status};
+use massh::{MasshHost, SshStream};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+/// Upper bounds of the buckets of the histograms, in seconds.
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Statuses of the finished jobs and of their results on each host, as labels.
+const STATUSES: [&str; 4] = ["succeeded", "failed", "cancelled", "skipped"];
+
+/// Histogram of durations, with cumulative buckets.
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    /// Records a duration.
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for (bucket, bound) in self.buckets.iter_mut().zip(BUCKETS.iter()) {
+            if seconds <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+
+    /// Writes the samples of this histogram, with the labels of a metric.
+    fn render(&self, output: &mut String, name: &str, labels: &str) {
+        for (bucket, bound) in self.buckets.iter().zip(BUCKETS.iter()) {
+            let _ = writeln!(
+                output,
+                "{}_bucket{{{},le=\"{}\"}} {}",
+                name, labels, bound, bucket
+            );
+        }
+        let _ = writeln!(
+            output,
+            "{}_bucket{{{},le=\"+Inf\"}} {}",
+            name, labels, self.count
+        );
+        let _ = writeln!(output, "{}_sum{{{}}} {}", name, labels, self.sum);
+        let _ = writeln!(output, "{}_count{{{}}} {}", name, labels, self.count);
+    }
+}
+
+/// Metrics of the jobs run since masshd started, as returned by `GET /metrics` in the text
+/// format of Prometheus.
+#[derive(Default)]
+pub struct Metrics {
+    connect_err: Histogram,
+    connect_ok: Histogram,
+    hosts: BTreeMap<MasshHost, [u64; 4]>,
+    jobs: [u64; 3],
+    output_bytes: [u64; 2],
+}
+
+impl Metrics {
+    /// Records the time taken to establish a session with a host, successfully or not.
+    pub fn connect(&mut self, duration: Duration, ok: bool) {
+        match ok {
+            true => self.connect_ok.observe(duration),
+            false => self.connect_err.observe(duration),
+        }
+    }
+
+    /// Records a line of output received from a host.
+    pub fn output(&mut self, stream: SshStream, line: &[u8]) {
+        let index = match stream {
+            SshStream::Stdout => 0,
+            SshStream::Stderr => 1,
+        };
+        self.output_bytes[index] += line.len() as u64 + 1;
+    }
+
+    /// Records a job which finished, with its result on each host.
+    pub fn finish(&mut self, job: &Job) {
+        let index = |status| match status {
+            Status::Succeeded => 0,
+            Status::Failed | Status::Pending | Status::Running => 1,
+            Status::Cancelled => 2,
+            Status::Skipped => 3,
+        };
+        self.jobs[index(job.status())] += 1;
+        for result in &job.results {
+            let counts = self.hosts.entry(result.host.clone()).or_default();
+            counts[index(result.status)] += 1;
+        }
+    }
+
+    /// Returns the metrics in the text format of Prometheus, with the number of running jobs.
+    pub fn render(&self, running: usize) -> String {
+        let mut output = String::new();
+
+        output.push_str("# HELP masshd_jobs_total Number of jobs which finished, by status.\n");
+        output.push_str("# TYPE masshd_jobs_total counter\n");
+        for (status, count) in STATUSES.iter().zip(self.jobs.iter()) {
+            let _ = writeln!(
+                output,
+                "masshd_jobs_total{{status=\"{}\"}} {}",
+                status, count
+            );
+        }
+
+        output.push_str("# HELP masshd_jobs_running Number of jobs which are running.\n");
+        output.push_str("# TYPE masshd_jobs_running gauge\n");
+        let _ = writeln!(output, "masshd_jobs_running {}", running);
+
+        output.push_str(
+            "# HELP masshd_host_results_total Number of results of the finished jobs on each \
+             host, by status.\n",
+        );
+        output.push_str("# TYPE masshd_host_results_total counter\n");
+        for (host, counts) in &self.hosts {
+            for (status, count) in STATUSES.iter().zip(counts.iter()) {
+                let _ = writeln!(
+                    output,
+                    "masshd_host_results_total{{host=\"{}\",status=\"{}\"}} {}",
+                    escape(host),
+                    status,
+                    count
+                );
+            }
+        }
+
+        output.push_str(
+            "# HELP masshd_connect_duration_seconds Time taken to establish the sessions with \
+             the hosts, by result.\n",
+        );
+        output.push_str("# TYPE masshd_connect_duration_seconds histogram\n");
+        let name = "masshd_connect_duration_seconds";
+        self.connect_ok.render(&mut output, name, "result=\"ok\"");
+        self.connect_err
+            .render(&mut output, name, "result=\"error\"");
+
+        output.push_str(
+            "# HELP masshd_output_bytes_total Number of bytes of output received from the \
+             hosts, by stream.\n",
+        );
+        output.push_str("# TYPE masshd_output_bytes_total counter\n");
+        for (stream, bytes) in ["stdout", "stderr"].iter().zip(self.output_bytes.iter()) {
+            let _ = writeln!(
+                output,
+                "masshd_output_bytes_total{{stream=\"{}\"}} {}",
+                stream, bytes
+            );
+        }
+        output
+    }
+}
+
+/// Escapes the value of a label.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
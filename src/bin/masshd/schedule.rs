@@ -0,0 +1,331 @@
+use crate::auth::{AuditRecord, Auth};
+use crate::inventory::SharedInventory;
+use crate::jobs::{self, Job, JobRequest, SharedJobs, Status};
+use anyhow::Result;
+use futures_util::StreamExt;
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Schedules configuration of masshd, loaded from the YAML file of the `--schedules` option.
+///
+/// ## Example
+/// ```yaml
+/// webhook: https://hooks.example.com/massh
+/// schedules:
+///   - name: disk-usage
+///     cron: '*/15 * * * *'
+///     command: df -h /
+///     groups: [web]
+///   - name: backup
+///     cron: '@daily'
+///     command: /usr/local/bin/backup
+///     webhook: https://hooks.example.com/backup
+/// ```
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SchedulesConfig {
+    /// URL to which the failures of the schedules are posted, unless they specify their own.
+    #[serde(default)]
+    webhook: Option<String>,
+    /// Schedules of the jobs.
+    schedules: Vec<ScheduleConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScheduleConfig {
+    /// Name of the schedule, which identifies its jobs.
+    name: String,
+    /// Cron expression of the times at which the job runs, in UTC.
+    cron: String,
+    /// Command to be executed on the hosts of the inventory.
+    command: String,
+    /// Groups of the hosts on which to execute the command, or all hosts if unspecified.
+    #[serde(default)]
+    groups: Option<Vec<String>>,
+    /// URL to which the failures of the schedule are posted.
+    #[serde(default)]
+    webhook: Option<String>,
+}
+
+/// Command executed on the hosts of the inventory at the times of a cron expression.
+pub struct Schedule {
+    pub name: String,
+    pub cron: Cron,
+    pub command: String,
+    pub groups: Option<Vec<String>>,
+    webhook: Option<String>,
+}
+
+impl Schedule {
+    /// Attempts to load the schedules of a YAML file.
+    pub fn from_yaml_file(path: &Path) -> Result<Vec<Arc<Self>>> {
+        let yaml = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read {:?}: {}", path, e))?;
+        let config: SchedulesConfig = serde_yaml::from_str(&yaml)?;
+        let mut names = BTreeSet::new();
+        let (mut schedules, webhook) = (Vec::new(), config.webhook);
+        for schedule in config.schedules {
+            if !names.insert(schedule.name.clone()) {
+                return Err(anyhow::anyhow!(
+                    "Schedule `{}` is duplicated",
+                    schedule.name
+                ));
+            }
+            let cron = schedule.cron.parse::<Cron>().map_err(|e| {
+                anyhow::anyhow!(
+                    "Invalid cron expression of schedule `{}`: {}",
+                    schedule.name,
+                    e
+                )
+            })?;
+            schedules.push(Arc::new(Schedule {
+                name: schedule.name,
+                cron,
+                command: schedule.command,
+                groups: schedule.groups,
+                webhook: schedule.webhook.or_else(|| webhook.clone()),
+            }));
+        }
+        Ok(schedules)
+    }
+
+    /// Runs the job of this schedule at the times of its cron expression, forever.
+    ///
+    /// A run is skipped if the previous one is still running. If a job fails, or fails to
+    /// start, its summary is posted to the webhook of the schedule, if any.
+    pub async fn run(
+        self: Arc<Self>,
+        jobs: SharedJobs,
+        inventory: SharedInventory,
+        auth: Option<Arc<Auth>>,
+    ) {
+        while let Some(next) = self.cron.next(jobs::now()) {
+            // Sleep in steps, in case the system clock changes in the meantime.
+            while jobs::now() < next {
+                let delay = (next - jobs::now()).clamp(1, 60);
+                tokio::time::sleep(Duration::from_secs(delay)).await;
+            }
+
+            let request = JobRequest {
+                command: self.command.clone(),
+                config: None,
+                groups: self.groups.clone(),
+                schedule: Some(self.name.clone()),
+                timestamps: false,
+            };
+            let (config, caller) = (inventory.read().config(), None);
+            let result = jobs::start(&jobs, request, Some(&config), &caller, false);
+            if let Some(auth) = &auth {
+                let mut record = AuditRecord::new("run_schedule", &caller);
+                record.job = result.as_ref().ok().copied();
+                record.command = Some(&self.command);
+                record.schedule = Some(&self.name);
+                record.hosts = match &result {
+                    Ok(id) => jobs.lock().get(*id).map(Job::hosts).unwrap_or_default(),
+                    Err(_) => Vec::new(),
+                };
+                record.error = result.as_ref().err().map(|e| format!("{:#}", e));
+                auth.audit(&record);
+            }
+
+            let payload = match result {
+                Ok(id) => {
+                    let events = jobs.lock().get(id).map(Job::events);
+                    if let Some(events) = events {
+                        events.for_each(|_| async {}).await;
+                    }
+                    let jobs = jobs.lock();
+                    match jobs.get(id) {
+                        Some(job) if job.status() == Status::Failed => {
+                            let failed = job.results.iter().filter(|r| r.status == Status::Failed);
+                            let failed: Vec<_> = failed.map(|r| &r.host).collect();
+                            serde_json::json!({
+                                "schedule": self.name,
+                                "job": job.summary(),
+                                "failed_hosts": failed,
+                            })
+                        }
+                        _ => continue,
+                    }
+                }
+                Err(e) => {
+                    eprintln!("masshd failed to run schedule `{}`: {:#}", self.name, e);
+                    serde_json::json!({
+                        "schedule": self.name,
+                        "error": format!("{:#}", e),
+                    })
+                }
+            };
+            if let Some(webhook) = self.webhook.clone() {
+                let name = self.name.clone();
+                let notify = move || {
+                    let response = ureq::post(&webhook).send_json(payload);
+                    response.map(drop).map_err(|e| e.to_string())
+                };
+                if let Ok(Err(e)) = tokio::task::spawn_blocking(notify).await {
+                    eprintln!("masshd failed to notify the failure of `{}`: {}", name, e);
+                }
+            }
+        }
+    }
+}
+
+/// Cron expression with the 5 usual fields (minute, hour, day of month, month and day of week),
+/// or one of the `@yearly`, `@monthly`, `@weekly`, `@daily` and `@hourly` macros.
+///
+/// Each field is `*`, a value, or a range like `1-5`, optionally followed by a step like `/15`,
+/// or a comma-separated list of them. Months and days of week can also be named (e.g. `jan` or
+/// `mon`), and Sunday is both 0 and 7. Like in most crons, if both the day of month and the day
+/// of week are restricted, a day matches if either of them matches.
+pub struct Cron {
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl std::fmt::Display for Cron {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.expression)
+    }
+}
+
+impl std::str::FromStr for Cron {
+    type Err = anyhow::Error;
+
+    fn from_str(expression: &str) -> Result<Self> {
+        let fields = match expression.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            fields => fields,
+        };
+        let fields: Vec<&str> = fields.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(anyhow::anyhow!("expected 5 fields, found {}", fields.len()));
+        }
+        const MONTHS: &[&str] = &[
+            "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+        ];
+        const WEEKDAYS: &[&str] = &["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+        let weekdays = parse_field(fields[4], 0, 7, WEEKDAYS)?;
+        let cron = Cron {
+            expression: expression.trim().to_string(),
+            minutes: parse_field(fields[0], 0, 59, &[])?,
+            hours: parse_field(fields[1], 0, 23, &[])?,
+            days: parse_field(fields[2], 1, 31, &[])?,
+            months: parse_field(fields[3], 1, 12, MONTHS)?,
+            weekdays: (weekdays | weekdays >> 7) & 0x7f,
+            any_day: fields[2] == "*",
+            any_weekday: fields[4] == "*",
+        };
+        if cron.next(jobs::now()).is_none() {
+            return Err(anyhow::anyhow!("it never matches"));
+        }
+        Ok(cron)
+    }
+}
+
+impl Cron {
+    /// Returns the first time matching this expression after the specified time, in seconds
+    /// since the Unix epoch, or `None` if it doesn't match any time in the next 5 years.
+    pub fn next(&self, after: u64) -> Option<u64> {
+        let mut time = after / 60 * 60 + 60;
+        let limit = time + 5 * 366 * 24 * 60 * 60;
+        while time < limit {
+            let (month, day, weekday) = civil_from_days(time / (24 * 60 * 60));
+            let day_matches = bit(self.days, day);
+            let weekday_matches = bit(self.weekdays, weekday);
+            let day_matches = match (self.any_day, self.any_weekday) {
+                (false, false) => day_matches || weekday_matches,
+                _ => day_matches && weekday_matches,
+            };
+            if !bit(self.months, month) || !day_matches {
+                time = (time / (24 * 60 * 60) + 1) * 24 * 60 * 60;
+            } else if !bit(self.hours, time / (60 * 60) % 24) {
+                time = (time / (60 * 60) + 1) * 60 * 60;
+            } else if !bit(self.minutes, time / 60 % 60) {
+                time += 60;
+            } else {
+                return Some(time);
+            }
+        }
+        None
+    }
+}
+
+/// Returns whether a bit of a set is set.
+fn bit(set: u64, value: u64) -> bool {
+    set & (1 << value) != 0
+}
+
+/// Attempts to parse a field of a cron expression, as a set of values between `min` and `max`.
+fn parse_field(field: &str, min: u64, max: u64, names: &[&str]) -> Result<u64> {
+    let value = |text: &str| -> Result<u64> {
+        let position = names
+            .iter()
+            .position(|name| text.eq_ignore_ascii_case(name));
+        let value = match position {
+            Some(position) => position as u64 + min,
+            None => text
+                .parse()
+                .map_err(|_| anyhow::anyhow!("invalid value `{}`", text))?,
+        };
+        match (min..=max).contains(&value) {
+            true => Ok(value),
+            false => Err(anyhow::anyhow!(
+                "value {} is not between {} and {}",
+                value,
+                min,
+                max
+            )),
+        }
+    };
+    let mut set = 0;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => match step.parse::<u64>() {
+                Ok(step) if step != 0 => (range, step),
+                _ => return Err(anyhow::anyhow!("invalid step `{}`", step)),
+            },
+            None => (item, 1),
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (value(start)?, value(end)?),
+            None if step != 1 => (value(range)?, max),
+            None => (value(range)?, value(range)?),
+        };
+        if start > end {
+            return Err(anyhow::anyhow!("invalid range `{}`", range));
+        }
+        set |= (start..=end)
+            .step_by(step as usize)
+            .fold(0, |set, v| set | 1 << v);
+    }
+    Ok(set)
+}
+
+/// Returns the month (1 to 12), the day of month (1 to 31) and the day of week (0 to 6, from
+/// Sunday) of a number of days since the Unix epoch.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    // See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days_of_era = (days + 719_468) % 146_097;
+    let year_of_era =
+        (days_of_era - days_of_era / 1460 + days_of_era / 36524 - days_of_era / 146_096) / 365;
+    let day_of_year = days_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    (month, day, (days + 4) % 7)
+}
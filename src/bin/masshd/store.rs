@@ -0,0 +1,157 @@
+use crate::jobs::{HostResult, Job, Status};
+use anyhow::Result;
+use parking_lot::Mutex;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+
+/// Schema of the database, created if it doesn't exist yet.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS jobs (
+        id INTEGER PRIMARY KEY,
+        command TEXT NOT NULL,
+        created_by TEXT,
+        schedule TEXT,
+        created_at INTEGER NOT NULL,
+        finished_at INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS results (
+        job_id INTEGER NOT NULL REFERENCES jobs (id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        host TEXT NOT NULL,
+        host_group TEXT,
+        status TEXT NOT NULL,
+        exit_status INTEGER,
+        stdout TEXT NOT NULL,
+        stderr TEXT NOT NULL,
+        error TEXT,
+        started_at INTEGER,
+        finished_at INTEGER,
+        PRIMARY KEY (job_id, position)
+    );
+    CREATE INDEX IF NOT EXISTS jobs_created_at ON jobs (created_at);
+";
+
+/// SQLite database in which finished jobs are saved with their results (requires the `sqlite`
+/// feature).
+pub struct Store {
+    connection: Mutex<Connection>,
+}
+
+impl Store {
+    /// Attempts to open a database, created if it doesn't exist yet.
+    pub fn open(path: &Path) -> Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch("PRAGMA foreign_keys = ON;")?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    /// Attempts to load all the jobs of this database, with their results.
+    pub fn load(&self) -> Result<Vec<Job>> {
+        let connection = self.connection.lock();
+        let mut jobs = connection.prepare(
+            "SELECT id, command, created_by, schedule, created_at, finished_at FROM jobs
+            ORDER BY id",
+        )?;
+        let mut results = connection.prepare(
+            "SELECT host, host_group, status, exit_status, stdout, stderr, error, started_at,
+                finished_at
+            FROM results WHERE job_id = ?1 ORDER BY position",
+        )?;
+        let rows = jobs.query_map([], |row| {
+            Ok((
+                row.get(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4)?,
+                row.get(5)?,
+            ))
+        })?;
+        let mut loaded = Vec::new();
+        for row in rows {
+            let (id, command, created_by, schedule, created_at, finished_at) = row?;
+            let rows = results.query_map([id], |row| {
+                let status: String = row.get(2)?;
+                Ok(HostResult {
+                    host: row.get(0)?,
+                    group: row.get(1)?,
+                    status: Status::from_name(&status).unwrap_or(Status::Failed),
+                    exit_status: row.get(3)?,
+                    stdout: row.get(4)?,
+                    stderr: row.get(5)?,
+                    error: row.get(6)?,
+                    started_at: row.get(7)?,
+                    finished_at: row.get(8)?,
+                })
+            })?;
+            let results = rows.collect::<rusqlite::Result<_>>()?;
+            let job = Job::finished(
+                id,
+                command,
+                created_by,
+                schedule,
+                created_at,
+                finished_at,
+                results,
+            );
+            loaded.push(job);
+        }
+        Ok(loaded)
+    }
+
+    /// Attempts to save a finished job, with its results.
+    pub fn save(&self, job: &Job) -> Result<()> {
+        let mut connection = self.connection.lock();
+        let transaction = connection.transaction()?;
+        let exists = transaction
+            .query_row("SELECT 1 FROM jobs WHERE id = ?1", [job.id], |_| Ok(()))
+            .optional()?;
+        if exists.is_some() {
+            return Err(anyhow::anyhow!("Job {} was already saved", job.id));
+        }
+        transaction.execute(
+            "INSERT INTO jobs (id, command, created_by, schedule, created_at, finished_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                job.id,
+                job.command,
+                job.created_by,
+                job.schedule,
+                job.created_at,
+                job.finished_at
+            ],
+        )?;
+        for (position, result) in job.results.iter().enumerate() {
+            transaction.execute(
+                "INSERT INTO results (job_id, position, host, host_group, status, exit_status,
+                    stdout, stderr, error, started_at, finished_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                params![
+                    job.id,
+                    position,
+                    result.host,
+                    result.group,
+                    result.status.name(),
+                    result.exit_status,
+                    result.stdout,
+                    result.stderr,
+                    result.error,
+                    result.started_at,
+                    result.finished_at
+                ],
+            )?;
+        }
+        Ok(transaction.commit()?)
+    }
+
+    /// Attempts to delete the jobs created before the specified time, in seconds since the Unix
+    /// epoch, with their results.
+    pub fn prune(&self, before: u64) -> Result<()> {
+        let connection = self.connection.lock();
+        connection.execute("DELETE FROM jobs WHERE created_at < ?1", [before])?;
+        Ok(())
+    }
+}
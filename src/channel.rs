@@ -0,0 +1,262 @@
+use crate::ssh_client::utf8_path;
+use crate::SshClient;
+use anyhow::Result;
+use ssh2::Session;
+use std::io::{ErrorKind, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Interval at which [`SshChannel::read_output`] polls the channel for output.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Open channel on a session of an [`SshClient`], returned by [`SshClient::shell`],
+/// [`SshClient::subsystem`], [`SshClient::direct_streamlocal`] or [`SshClient::direct_tcpip`].
+///
+/// Reading from it reads the standard output of the remote end, and writing to it writes its
+/// standard input (for forwarded channels, the remote end is the socket). Reads and writes block
+/// until they make progress or the timeout of the client expires (see
+/// [`SshClient::set_timeout`]). The client remains usable while the channel is open, and the
+/// channel is closed when it's dropped.
+pub struct SshChannel {
+    channel: ssh2::Channel,
+    session: Session,
+}
+
+impl SshChannel {
+    /// Attempts to write bytes to the standard input of the remote end (e.g. `b"uptime\n"`).
+    pub fn write_stdin(&mut self, bytes: &[u8]) -> Result<()> {
+        self.channel.write_all(bytes)?;
+        Ok(self.channel.flush()?)
+    }
+
+    /// Attempts to read the output of the remote end, waiting up to the specified duration for
+    /// some to arrive.
+    ///
+    /// It returns all the output received so far as soon as there is some, or an empty vector
+    /// if none arrived in time or if the remote end closed its output (see [`SshChannel::eof`]).
+    /// Unlike reads, it never blocks longer than the specified duration. In a pseudo-terminal,
+    /// the output includes the standard error.
+    pub fn read_output(&mut self, timeout: Duration) -> Result<Vec<u8>> {
+        let start = Instant::now();
+        self.session.set_blocking(false);
+        let result = self.poll_output(start, timeout);
+        self.session.set_blocking(true);
+        result
+    }
+
+    /// Reads the output which is available without blocking, polling for it until some arrives
+    /// or the duration elapsed since the start expires (see `SshChannel::read_output`).
+    fn poll_output(&mut self, start: Instant, timeout: Duration) -> Result<Vec<u8>> {
+        let mut output = Vec::new();
+        let mut buffer = [0; 16 * 1024];
+        loop {
+            match self.channel.read(&mut buffer) {
+                Ok(0) => {}
+                Ok(len) => {
+                    output.extend_from_slice(&buffer[..len]);
+                    continue;
+                }
+                Err(error) if error.kind() == ErrorKind::WouldBlock => {}
+                Err(error) => return Err(error.into()),
+            }
+            if !output.is_empty() || self.channel.eof() || start.elapsed() >= timeout {
+                return Ok(output);
+            }
+            std::thread::sleep(POLL_INTERVAL.min(timeout.saturating_sub(start.elapsed())));
+        }
+    }
+
+    /// Attempts to resize the pseudo-terminal of a shell, in characters.
+    pub fn resize_pty(&mut self, width: u32, height: u32) -> Result<()> {
+        Ok(self.channel.request_pty_size(width, height, None, None)?)
+    }
+
+    /// Returns whether the remote end has closed its output, i.e. whether reads will only return
+    /// the data that was already received.
+    pub fn eof(&self) -> bool {
+        self.channel.eof()
+    }
+
+    /// Signals the end of the input to the remote end, which can still send output.
+    pub fn send_eof(&mut self) -> Result<()> {
+        Ok(self.channel.send_eof()?)
+    }
+
+    /// Returns a reader of the standard error of the remote end.
+    pub fn stderr(&mut self) -> impl Read + '_ {
+        self.channel.stderr()
+    }
+
+    /// Closes the channel, waits for the remote end to close it too, and returns its exit status
+    /// (or zero if it didn't send one).
+    pub fn close(mut self) -> Result<i32> {
+        self.channel.close()?;
+        self.channel.wait_close()?;
+        Ok(self.channel.exit_status()?)
+    }
+}
+
+impl Read for SshChannel {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.channel.read(buf)
+    }
+}
+
+impl Write for SshChannel {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.channel.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.channel.flush()
+    }
+}
+
+impl SshClient {
+    /// Attempts to start a shell in a pseudo-terminal of the specified type (e.g. `xterm`) and
+    /// size (width and height, in characters) on the configured host, and returns a channel to
+    /// drive it.
+    ///
+    /// Unlike [`SshClient::execute`], it can automate devices which drop straight into a menu or
+    /// a CLI instead of running commands. Unlike [`SshClient::shell_interactive`], the input and
+    /// output are driven by the caller (see [`SshChannel::write_stdin`] and
+    /// [`SshChannel::read_output`]). This is only supported by the libssh2 backend.
+    ///
+    /// Note that this method implicitly calls [`SshClient::connect`] if no session was
+    /// established prior. Otherwise, it reuses the cached session
+    /// (see [`SshClient::set_keepalive`] to check its health first).
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// use std::time::Duration;
+    ///
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// let mut shell = ssh.shell("vt100", (80, 24)).unwrap();
+    /// shell.write_stdin(b"show version\n").unwrap();
+    /// let output = shell.read_output(Duration::from_secs(5)).unwrap();
+    /// println!("{}", String::from_utf8_lossy(&output));
+    ///
+    /// shell.resize_pty(120, 40).unwrap();
+    /// shell.write_stdin(b"exit\n").unwrap();
+    /// shell.close().unwrap();
+    /// ```
+    pub fn shell(&mut self, term: &str, size: (u32, u32)) -> Result<SshChannel> {
+        let mut channel = self.open_channel(Session::channel_session)?;
+        channel.request_pty(term, None, Some((size.0, size.1, 0, 0)))?;
+        channel.shell()?;
+        let session = self.connected_session()?.clone();
+        Ok(SshChannel { channel, session })
+    }
+
+    /// Attempts to start a subsystem (e.g. `sftp` or `netconf`) on the configured host, and
+    /// returns a channel to talk to it.
+    ///
+    /// Subsystems are predefined services of the SSH server, which network devices often expose
+    /// instead of a shell. This is only supported by the libssh2 backend.
+    ///
+    /// Note that this method implicitly calls [`SshClient::connect`] if no session was
+    /// established prior. Otherwise, it reuses the cached session
+    /// (see [`SshClient::set_keepalive`] to check its health first).
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// use std::io::{Read, Write};
+    ///
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 830));
+    ///
+    /// let mut netconf = ssh.subsystem("netconf").unwrap();
+    /// let mut hello = [0; 4096];
+    /// let len = netconf.read(&mut hello).unwrap();
+    /// println!("{}", String::from_utf8_lossy(&hello[..len]));
+    ///
+    /// netconf.write_all(b"<rpc><close-session/></rpc>]]>]]>").unwrap();
+    /// netconf.close().unwrap();
+    /// ```
+    pub fn subsystem(&mut self, name: &str) -> Result<SshChannel> {
+        let mut channel = self.open_channel(Session::channel_session)?;
+        channel
+            .subsystem(name)
+            .map_err(|error| anyhow::anyhow!("Subsystem {:?} was rejected: {}", name, error))?;
+        let session = self.connected_session()?.clone();
+        Ok(SshChannel { channel, session })
+    }
+
+    /// Attempts to connect to a Unix socket on the configured host (e.g.
+    /// `/var/run/docker.sock`), and returns a channel to talk to it.
+    ///
+    /// The connection is made by the SSH server (with a `direct-streamlocal@openssh.com`
+    /// channel), which OpenSSH allows unless `AllowStreamLocalForwarding` is disabled. This is
+    /// only supported by the libssh2 backend. Note that X11 forwarding isn't supported, since
+    /// the bindings of libssh2 don't expose it.
+    ///
+    /// Note that this method implicitly calls [`SshClient::connect`] if no session was
+    /// established prior. Otherwise, it reuses the cached session
+    /// (see [`SshClient::set_keepalive`] to check its health first).
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// use std::io::{Read, Write};
+    ///
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// let mut docker = ssh.direct_streamlocal("/var/run/docker.sock").unwrap();
+    /// docker.write_all(b"GET /version HTTP/1.0\r\n\r\n").unwrap();
+    /// docker.send_eof().unwrap();
+    /// let mut response = String::new();
+    /// docker.read_to_string(&mut response).unwrap();
+    /// println!("{}", response);
+    /// ```
+    pub fn direct_streamlocal<P: AsRef<Path>>(&mut self, socket_path: P) -> Result<SshChannel> {
+        let socket_path = utf8_path(socket_path.as_ref())?;
+        self.connected_session()?;
+        let channel = self
+            .open_channel(|session| session.channel_direct_streamlocal(socket_path, None))
+            .map_err(|error| {
+                anyhow::anyhow!("Forwarding to {:?} was rejected: {}", socket_path, error)
+            })?;
+        let session = self.connected_session()?.clone();
+        Ok(SshChannel { channel, session })
+    }
+
+    /// Attempts to connect to a TCP port of a host reachable from the configured host (e.g.
+    /// `localhost` for a service which only listens on the loopback interface), and returns a
+    /// channel to talk to it.
+    ///
+    /// The connection is made by the SSH server (with a `direct-tcpip` channel), so the traffic
+    /// between the configured host and the target travels in cleartext. This is only supported
+    /// by the libssh2 backend.
+    ///
+    /// Note that this method implicitly calls [`SshClient::connect`] if no session was
+    /// established prior. Otherwise, it reuses the cached session
+    /// (see [`SshClient::set_keepalive`] to check its health first).
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// use std::io::{BufRead, BufReader};
+    ///
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// let redis = ssh.direct_tcpip("localhost", 6379).unwrap();
+    /// let mut greeting = String::new();
+    /// BufReader::new(redis).read_line(&mut greeting).unwrap();
+    /// ```
+    pub fn direct_tcpip(&mut self, host: &str, port: u16) -> Result<SshChannel> {
+        self.connected_session()?;
+        let channel = self
+            .open_channel(|session| session.channel_direct_tcpip(host, port, None))
+            .map_err(|error| {
+                anyhow::anyhow!("Forwarding to {}:{} was rejected: {}", host, port, error)
+            })?;
+        let session = self.connected_session()?.clone();
+        Ok(SshChannel { channel, session })
+    }
+}
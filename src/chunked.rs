@@ -0,0 +1,115 @@
+use crate::ssh_client::{utf8_path, ThrottledReader};
+use crate::{shell, SshClient};
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Minimum size of the chunks of an upload (see `SshClient::set_upload_chunks`).
+const MIN_CHUNK_SIZE: u64 = 1024 * 1024;
+
+impl SshClient {
+    /// Returns the number of chunks in which to upload a file of the specified length.
+    pub(crate) fn chunk_count(&self, len: u64) -> u64 {
+        (self.get_upload_chunks() as u64)
+            .min(len / MIN_CHUNK_SIZE)
+            .max(1)
+    }
+
+    /// Uploads a file in chunks over parallel sessions, concatenates them into the destination
+    /// with the specified mode and times, then verifies its checksum
+    /// (see `SshClient::set_upload_chunks`).
+    pub(crate) fn upload_chunked(
+        &mut self,
+        local_path: &Path,
+        remote_path: &Path,
+        len: u64,
+        chunks: u64,
+        mode: i32,
+        times: Option<(u64, u64)>,
+    ) -> Result<()> {
+        self.get_shell().ensure_posix()?;
+        let suffix = format!(".massh-chunk-{:08x}", rand::random::<u32>());
+        let paths: Vec<PathBuf> = (0..chunks)
+            .map(|index| {
+                let mut path = remote_path.as_os_str().to_owned();
+                path.push(format!("{}-{}", suffix, index));
+                PathBuf::from(path)
+            })
+            .collect();
+        let quoted = paths
+            .iter()
+            .map(|path| Ok(shell::quote(utf8_path(path)?)))
+            .collect::<Result<Vec<_>>>()?;
+        let destination = shell::quote(utf8_path(remote_path)?);
+
+        // Upload each chunk over its own session while hashing the local file, with the rate
+        // limits shared by all chunks.
+        let chunk_size = len.div_ceil(chunks);
+        let limiters = self.rate_limiters();
+        let clients: Vec<_> = paths.iter().map(|_| self.fork()).collect();
+        let result = std::thread::scope(|scope| {
+            let handles: Vec<_> = clients
+                .into_iter()
+                .zip(&paths)
+                .enumerate()
+                .map(|(index, (mut client, path))| {
+                    let limiters = limiters.clone();
+                    scope.spawn(move || -> Result<()> {
+                        let offset = index as u64 * chunk_size;
+                        let chunk_len = chunk_size.min(len - offset);
+                        let mut file = File::open(local_path)?;
+                        file.seek(SeekFrom::Start(offset))?;
+                        let mut chunk = file.take(chunk_len);
+                        let mut reader = ThrottledReader {
+                            inner: &mut chunk,
+                            limiters,
+                        };
+                        client.send_file(&mut reader, path, chunk_len, 0o600, None)
+                    })
+                })
+                .collect();
+            let checksum = sha256_file(local_path);
+            let mut result = Ok(());
+            for handle in handles {
+                let chunk_result = handle
+                    .join()
+                    .unwrap_or_else(|_| Err(anyhow::anyhow!("Chunk upload panicked")));
+                result = result.and(chunk_result);
+            }
+            result.and(checksum)
+        });
+
+        // Concatenate the chunks into the first one, removing each of them as it's appended,
+        // then move it into place.
+        let result = result.and_then(|checksum| {
+            let mut command = String::new();
+            for path in &quoted[1..] {
+                command += &format!("cat -- {1} >> {0} && rm -f -- {1} && ", quoted[0], path);
+            }
+            command += &format!(
+                "mv -f -- {0} {1} && chmod {2:o} -- {1}",
+                quoted[0], destination, mode
+            );
+            self.execute_checked(&command, "Reassembly after upload failed")?;
+            if let Some(times) = times {
+                self.touch(remote_path, times)?;
+            }
+            self.verify_checksum(remote_path, &checksum)
+        });
+
+        // Remove the remaining chunks on failure.
+        if result.is_err() {
+            let _ = self.execute(&format!("rm -f -- {}", quoted.join(" ")));
+        }
+        result
+    }
+}
+
+/// Returns the SHA-256 checksum of a local file, in lowercase hexadecimal.
+fn sha256_file(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
@@ -1,7 +1,10 @@
-use crate::SshAuth;
+use crate::server_info::is_fingerprint;
+use crate::{MasshDownloadLayout, SshAuth, SshBackend, SshOverflow, SshShell, SshSignal};
 use anyhow::Result;
 use serde::{Deserialize, Deserializer};
-use std::net::{IpAddr, ToSocketAddrs};
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
 
 /// Configuration for a `MasshClient` target host.
 #[derive(Deserialize)]
@@ -9,41 +12,380 @@ use std::net::{IpAddr, ToSocketAddrs};
 pub struct MasshHostConfig {
     /// IP address, either IPv4 or IPv6.
     pub addr: IpAddr,
+    /// Optional name of the host (e.g. `web-03`), used as its identifier instead of
+    /// `username@ip_address:port` in results, filenames and output.
+    pub alias: Option<String>,
     /// Optional authentication method to override the default.
     pub auth: Option<SshAuth>,
+    /// Optional `sudo` password of the become user to override the default.
+    pub become_password: Option<String>,
+    /// Optional user as which commands are executed through `sudo` to override the default.
+    pub become_user: Option<String>,
+    /// Other IP addresses of the host, which are also tried when connecting to it (see
+    /// `SshClient::try_from`).
+    ///
+    /// They're the addresses after the first one (i.e. `addr`) to which a hostname resolved,
+    /// e.g. the IPv4 address of a dual-stack host whose IPv6 address is tried first.
+    pub fallback_addrs: Vec<IpAddr>,
+    /// Hostname which `addr` was resolved from, if the host was specified by its hostname
+    /// (e.g. `db.example.com` in `root@db.example.com:22`).
+    pub hostname: Option<String>,
+    /// Optional fingerprint of the host key (e.g. `SHA256:nThbg6k...`), verified before
+    /// authenticating. See `SshClient::set_host_key` for more details.
+    pub host_key: Option<String>,
+    /// Arbitrary key-value metadata (e.g. `env: prod`), merged with the labels of its group.
+    ///
+    /// Labels can be referenced as `{label:KEY}` in the commands run by a `MasshClient`, which
+    /// quotes them (or as `{raw_label:KEY}` to substitute them unquoted).
+    pub labels: BTreeMap<String, String>,
+    /// Whether the host is the controller itself, in which case commands run locally
+    /// and files are copied locally, without SSH (see `SshBackend::Local`).
+    ///
+    /// If omitted, it defaults to false. The address of the host only identifies it.
+    pub local: bool,
     /// Optional port number to override the default.
     pub port: Option<u16>,
+    /// Optional shell of the remote user (e.g. `powershell`) to override the default.
+    pub shell: Option<SshShell>,
+    /// Optional timeout, in milliseconds, to override the default.
+    pub timeout: Option<u64>,
     /// Optional username to override the default.
     pub user: Option<String>,
+    /// Optional working directory of executed commands to override the default.
+    pub working_dir: Option<String>,
+}
+
+/// Configuration for a named group of `MasshClient` target hosts.
+///
+/// The group's settings override the defaults of the `MasshConfig` for all hosts of the group,
+/// and are themselves overridden by the settings of each individual host.
+#[derive(Deserialize)]
+pub struct MasshGroupConfig {
+    /// Name of the group.
+    pub name: String,
+    /// Optional authentication method to override the default.
+    pub auth: Option<SshAuth>,
+    /// Optional `sudo` password of the become user to override the default.
+    pub become_password: Option<String>,
+    /// Optional user as which commands are executed through `sudo` to override the default.
+    pub become_user: Option<String>,
+    /// Arbitrary key-value metadata inherited by all hosts of the group.
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    /// Optional port number to override the default.
+    pub port: Option<u16>,
+    /// Optional shell of the remote user (e.g. `powershell`) to override the default.
+    pub shell: Option<SshShell>,
+    /// Optional timeout, in milliseconds, to override the default.
+    pub timeout: Option<u64>,
+    /// Optional username to override the default.
+    pub user: Option<String>,
+    /// Optional working directory of executed commands to override the default.
+    pub working_dir: Option<String>,
+    /// List of hosts in the group.
+    #[serde(default)]
+    pub hosts: Vec<MasshHostConfig>,
 }
 
 /// Configuration for a `MasshClient`.
+///
+/// ## Environment Variables
+///
+/// Every string value of the configuration file can reference environment variables
+/// with the `${NAME}` syntax, which are substituted when the configuration is parsed.
+/// This keeps secrets such as passwords out of the configuration file itself.
+///
+/// A reference to a variable that is not set is an error. To write a literal `${`,
+/// escape it as `$${`. Any other `$` character is left untouched.
+///
+/// ```yaml
+/// ---
+/// default_auth:
+///   password: ${MASSH_PASSWORD}
+/// default_port: 22
+/// default_user: ${USER}
+/// threads: 0
+/// timeout: 0
+/// hosts:
+///   - 1.1.1.1
+///   - 2.2.2.2
+/// ```
+///
+/// ## Groups and Includes
+///
+/// Hosts can be organized in named [`groups`](MasshConfig::groups) whose settings are inherited
+/// by their hosts, and split across several files with the [`include`](MasshConfig::include)
+/// directive. For example, a `massh.yaml` file could look like this:
+///
+/// ```yaml
+/// ---
+/// default_user: admin
+/// include:
+///   - other.yaml
+///   - team/*.yaml
+/// groups:
+///   - name: web
+///     user: deploy
+///     port: 20022
+///     hosts:
+///       - 1.1.1.1
+///       - 2.2.2.2
+///   - name: db
+///     auth:
+///       pubkey: /home/username/.ssh/id_db
+///     timeout: 5000
+///     hosts:
+///       - 3.3.3.3
+///       - other-user@4.4.4.4
+/// ```
 #[derive(Deserialize)]
 pub struct MasshConfig {
     /// Default authentication method for all configured hosts.
+    ///
+    /// If omitted, it defaults to agent authentication.
+    #[serde(default)]
     pub default_auth: SshAuth,
     /// Default port number for all configured hosts.
+    ///
+    /// If omitted, it defaults to 22.
+    #[serde(default = "default_port")]
     pub default_port: u16,
     /// Default username for all configured hosts.
+    ///
+    /// If omitted, it defaults to the username of the current user
+    /// (i.e. the `USER` or `USERNAME` environment variable).
+    #[serde(default = "default_user")]
     pub default_user: String,
+    /// Limit on the total throughput of file transfers across all hosts, in bytes per second.
+    ///
+    /// A value of zero signifies no limit. This is the default.
+    #[serde(default)]
+    pub aggregate_rate_limit: u64,
+    /// Whether to upload files atomically, through a temporary file renamed into place.
+    ///
+    /// It is disabled by default. See `SshClient::set_atomic_uploads` for more details.
+    #[serde(default)]
+    pub atomic_uploads: bool,
+    /// Whether to transparently re-establish sessions which died, and retry the operation once.
+    ///
+    /// It is disabled by default. See `SshClient::set_auto_reconnect` for more details.
+    #[serde(default)]
+    pub auto_reconnect: bool,
+    /// Implementation of the SSH protocol used for all configured hosts
+    /// (`libssh2`, `local`, `openssh`, or `russh` with the `russh` feature).
+    ///
+    /// If omitted, it defaults to libssh2. See `SshClient::set_backend` for more details.
+    #[serde(default)]
+    pub backend: SshBackend,
+    /// Default password which `sudo` asks for to execute commands as the become user
+    /// (e.g. `${SUDO_PASSWORD}`).
+    ///
+    /// If omitted, `sudo` must not ask for a password. See `SshClient::set_become_password`
+    /// for more details.
+    #[serde(default)]
+    pub become_password: Option<String>,
+    /// Default user as which commands are executed through `sudo` for all configured hosts
+    /// (e.g. `deploy`).
+    ///
+    /// If omitted, commands run as the user of the session. See `SshClient::set_become_user`
+    /// for more details.
+    #[serde(default)]
+    pub become_user: Option<String>,
+    /// Capacity of the channel of a `MasshReceiver`.
+    ///
+    /// With a non-zero capacity, operations on hosts block when that many results are waiting
+    /// to be received, which bounds memory usage when the receiver falls behind.
+    /// A value of zero signifies an unbounded channel. This is the default.
+    #[serde(default)]
+    pub channel_capacity: usize,
+    /// Whether to check the free space at the destination of each upload before starting it.
+    ///
+    /// It is disabled by default. See `SshClient::set_check_free_space` for more details.
+    #[serde(default)]
+    pub check_free_space: bool,
+    /// Preferred ciphers, as a comma-separated list in decreasing order of preference.
+    ///
+    /// If omitted, it defaults to libssh2's preferences.
+    /// See `SshClient::set_method_pref` for more details.
+    #[serde(default)]
+    pub ciphers: Option<String>,
+    /// Whether to compress the data sent and received over sessions.
+    ///
+    /// It is disabled by default. See `SshClient::set_compression` for more details.
+    #[serde(default)]
+    pub compression: bool,
+    /// Layout of the local files downloaded from each host (`flat`, `subdirectory`,
+    /// or `template: "{name}.{ip}"`).
+    ///
+    /// It defaults to `flat`. See `MasshClient::set_download_layout` for more details.
+    #[serde(default)]
+    pub download_layout: MasshDownloadLayout,
+    /// Policy for the hosts defined more than once (`keep_last`, `keep_first`, `error` or
+    /// `allow`).
+    ///
+    /// It defaults to `keep_last`. See `MasshDuplicatePolicy` for more details.
+    #[serde(default)]
+    pub duplicate_hosts: MasshDuplicatePolicy,
+    /// Preferred host key algorithms, as a comma-separated list in decreasing order of preference.
+    ///
+    /// If omitted, it defaults to libssh2's preferences.
+    /// See `SshClient::set_method_pref` for more details.
+    #[serde(default)]
+    pub host_key_algorithms: Option<String>,
+    /// Maximum random delay, in milliseconds, before starting an operation on each host.
+    ///
+    /// A value of zero signifies no jitter. This is the default.
+    /// See `MasshClient::set_jitter` for more details.
+    #[serde(default)]
+    pub jitter_ms: u64,
+    /// Interval, in seconds, between keepalive messages of cached sessions.
+    ///
+    /// A non-zero value enables the persistent-session mode, in which dead sessions are
+    /// transparently re-established. A value of zero disables it. This is the default.
+    /// See `SshClient::set_keepalive` for more details.
+    #[serde(default)]
+    pub keepalive: u32,
+    /// Preferred key exchange algorithms, as a comma-separated list in decreasing order
+    /// of preference.
+    ///
+    /// If omitted, it defaults to libssh2's preferences.
+    /// See `SshClient::set_method_pref` for more details.
+    #[serde(default)]
+    pub kex_algorithms: Option<String>,
+    /// Preferred MAC algorithms, as a comma-separated list in decreasing order of preference.
+    ///
+    /// If omitted, it defaults to libssh2's preferences.
+    /// See `SshClient::set_method_pref` for more details.
+    #[serde(default)]
+    pub macs: Option<String>,
+    /// Maximum number of failed hosts after which an operation is aborted, in which case
+    /// it is skipped on the hosts where it hasn't started yet.
+    ///
+    /// For example, a value of 1 aborts the operation as soon as any host fails.
+    /// A value of zero signifies no maximum. This is the default.
+    #[serde(default)]
+    pub max_failures: usize,
+    /// Maximum number of bytes captured per output stream of executed commands.
+    ///
+    /// A value of zero signifies no limit. This is the default.
+    /// See `SshClient::set_max_output` for more details.
+    #[serde(default)]
+    pub max_output: u64,
+    /// Whether to capture the output of executed commands merged in arrival order.
+    ///
+    /// It is disabled by default. See `SshClient::set_merge_output` for more details.
+    #[serde(default)]
+    pub merge_output: bool,
+    /// Policy for the output of executed commands beyond `max_output`,
+    /// either `truncate` (the default) or `spill`.
+    /// See `SshClient::set_overflow` for more details.
+    #[serde(default)]
+    pub overflow: SshOverflow,
+    /// Whether to preserve the mode and times of transferred files.
+    ///
+    /// It is disabled by default. See `SshClient::set_preserve_attributes` for more details.
+    #[serde(default)]
+    pub preserve_attributes: bool,
+    /// Limit on the throughput of file transfers of each host, in bytes per second.
+    ///
+    /// A value of zero signifies no limit. This is the default.
+    #[serde(default)]
+    pub rate_limit: u64,
+    /// Default shell of the remote user for all configured hosts (`posix`, `powershell`
+    /// or `cmd`).
+    ///
+    /// If omitted, it defaults to a POSIX shell. See `SshClient::set_shell` for more details.
+    #[serde(default)]
+    pub shell: SshShell,
+    /// Fixed delay, in milliseconds, between starting an operation on consecutive hosts.
+    ///
+    /// A value of zero signifies that operations start on all hosts at once. This is the default.
+    /// See `MasshClient::set_stagger` for more details.
+    #[serde(default)]
+    pub stagger_ms: u64,
+    /// Signal sent to the remote process of a stopped command (e.g. `TERM` or `INT`).
+    ///
+    /// If omitted, no signal is sent. See `SshClient::set_stop_signal` for more details.
+    #[serde(default)]
+    pub stop_signal: Option<SshSignal>,
     /// Number of threads in the internal thread pool.
     ///
-    /// A value of zero signifies 1 thread per configured host.
+    /// A value of zero signifies 1 thread per configured host. This is the default.
+    #[serde(default)]
     pub threads: u64,
     /// Timeout, in milliseconds, for blocking functions.
     ///
-    /// A value of zero signifies no timeout.
+    /// A value of zero signifies no timeout. This is the default.
+    #[serde(default)]
     pub timeout: u64,
+    /// Whether to timestamp the chunks of output of executed commands.
+    ///
+    /// It is disabled by default. See `SshClient::set_timestamps` for more details.
+    #[serde(default)]
+    pub timestamps: bool,
+    /// Number of chunks in which to upload large files, in parallel over as many sessions.
+    ///
+    /// A value of 0 or 1 signifies that files are uploaded in one piece. This is the default.
+    /// See `SshClient::set_upload_chunks` for more details.
+    #[serde(default)]
+    pub upload_chunks: usize,
+    /// Explicit mode of uploaded files (e.g. `0o755` in YAML or `493` in JSON).
+    ///
+    /// If omitted, uploaded files are created with mode `0o644` or the local file's mode.
+    /// See `SshClient::set_upload_mode` for more details.
+    #[serde(default)]
+    pub upload_mode: Option<i32>,
+    /// Owner of uploaded files, changed with `chown` (e.g. `user` or `user:group`).
+    ///
+    /// If omitted, the owner is left unchanged. See `SshClient::set_upload_owner` for more details.
+    #[serde(default)]
+    pub upload_owner: Option<String>,
+    /// Whether to verify the SHA-256 checksum of uploaded files.
+    ///
+    /// It is disabled by default. See `SshClient::set_verify_uploads` for more details.
+    #[serde(default)]
+    pub verify_uploads: bool,
+    /// Default working directory of executed commands for all configured hosts
+    /// (e.g. `/srv/app`).
+    ///
+    /// If omitted, commands run in the default directory of the session (typically the home
+    /// directory). See `SshClient::set_working_dir` for more details.
+    #[serde(default)]
+    pub working_dir: Option<String>,
     /// List of configured hosts.
     ///
-    /// Internally, every host is uniquely identified by the tuple (username, ip_address, port).
-    /// Duplicates are discarded.
+    /// Internally, every host is uniquely identified by the tuple (username, ip_address, port),
+    /// or by its alias. Duplicates are handled according to `duplicate_hosts` (see
+    /// `MasshDuplicatePolicy`).
+    #[serde(default)]
     pub hosts: Vec<MasshHostConfig>,
+    /// List of configured groups of hosts.
+    #[serde(default)]
+    pub groups: Vec<MasshGroupConfig>,
+    /// List of paths of other configuration files, which may contain glob patterns.
+    ///
+    /// The hosts and groups of included files are appended to those of this configuration,
+    /// while their other settings are ignored. Relative paths are resolved from the directory
+    /// of the including file, or from the current directory when parsing a string.
+    /// Included files are parsed as JSON if their extension is `.json`, or as YAML otherwise.
+    #[serde(default)]
+    pub include: Vec<String>,
 }
 
 impl MasshConfig {
     /// Attempts to construct a new `MasshConfig` from a JSON string.
     ///
+    /// ## Minimal Example
+    ///
+    /// ```json
+    /// {
+    ///   "hosts": [
+    ///     "1.1.1.1",
+    ///     "2.2.2.2"
+    ///   ]
+    /// }
+    /// ```
+    ///
     /// ## Simple Example
     ///
     /// ```json
@@ -82,6 +424,10 @@ impl MasshConfig {
     ///     {
     ///       "addr": "5.5.5.5",
     ///       "auth": "agent",
+    ///       "labels": {
+    ///         "env": "prod",
+    ///         "dc": "fra"
+    ///       },
     ///       "port": null,
     ///       "user": null
     ///     },
@@ -91,6 +437,21 @@ impl MasshConfig {
     ///         "password": "special-password"
     ///       },
     ///       "user": "other-user-3"
+    ///     },
+    ///     {
+    ///       "addr": "7.7.7.7",
+    ///       "auth": {
+    ///         "password_cmd": "pass show prod/ssh"
+    ///       }
+    ///     },
+    ///     {
+    ///       "addr": "8.8.8.8",
+    ///       "auth": {
+    ///         "pubkey_passphrase_cmd": {
+    ///           "path": "/home/username/.ssh/id_ed25519",
+    ///           "passphrase_cmd": "pass show ssh/id_ed25519"
+    ///         }
+    ///       }
     ///     }
     ///   ]
     /// }
@@ -105,12 +466,36 @@ impl MasshConfig {
     /// let config = MasshConfig::from_json(&json).unwrap();
     /// ```
     pub fn from_json(json: &str) -> Result<Self> {
-        let config: MasshConfig = serde_json::from_str(json)?;
-        Ok(config)
+        Self::parse(json, Format::Json, Path::new("."), &mut Vec::new())
+    }
+
+    /// Attempts to construct a new `MasshConfig` from a JSON file.
+    ///
+    /// Unlike [`MasshConfig::from_json`], relative paths of included files are resolved
+    /// from the directory of the specified file.
+    ///
+    /// ## Usage
+    ///
+    /// ```no_run
+    /// use massh::MasshConfig;
+    ///
+    /// let config = MasshConfig::from_json_file("massh.json").unwrap();
+    /// ```
+    pub fn from_json_file(path: impl AsRef<Path>) -> Result<Self> {
+        Self::load(path.as_ref(), Format::Json, &mut Vec::new())
     }
 
     /// Attempts to construct a new `MasshConfig` from a YAML string.
     ///
+    /// ## Minimal Example
+    ///
+    /// ```yaml
+    /// ---
+    /// hosts:
+    ///   - 1.1.1.1
+    ///   - 2.2.2.2
+    /// ```
+    ///
     /// ## Simple Example
     ///
     /// ```yaml
@@ -143,12 +528,25 @@ impl MasshConfig {
     ///   - addr: 4.4.4.4
     ///   - addr: 5.5.5.5
     ///     auth: agent
+    ///     labels:
+    ///       env: prod
+    ///       dc: fra
     ///     port: ~
     ///     user: ~
     ///   - addr: 6.6.6.6
     ///     auth:
     ///       password: special-password
     ///     user: other-user-3
+    ///   - addr: 7.7.7.7
+    ///     auth:
+    ///       password_cmd: pass show prod/ssh
+    ///   - addr: 8.8.8.8
+    ///     auth:
+    ///       pubkey_passphrase_cmd:
+    ///         path: /home/username/.ssh/id_ed25519
+    ///         passphrase_cmd: pass show ssh/id_ed25519
+    ///   - addr: 127.0.0.1
+    ///     local: true
     /// ```
     ///
     /// ## Usage
@@ -160,20 +558,564 @@ impl MasshConfig {
     /// let config = MasshConfig::from_yaml(&yaml).unwrap();
     /// ```
     pub fn from_yaml(yaml: &str) -> Result<Self> {
-        let config: MasshConfig = serde_yaml::from_str(yaml)?;
+        Self::parse(yaml, Format::Yaml, Path::new("."), &mut Vec::new())
+    }
+
+    /// Attempts to construct a new `MasshConfig` from a YAML file.
+    ///
+    /// Unlike [`MasshConfig::from_yaml`], relative paths of included files are resolved
+    /// from the directory of the specified file.
+    ///
+    /// ## Usage
+    ///
+    /// ```no_run
+    /// use massh::MasshConfig;
+    ///
+    /// let config = MasshConfig::from_yaml_file("massh.yaml").unwrap();
+    /// ```
+    pub fn from_yaml_file(path: impl AsRef<Path>) -> Result<Self> {
+        Self::load(path.as_ref(), Format::Yaml, &mut Vec::new())
+    }
+
+    /// Checks this `MasshConfig` for likely mistakes and returns the list of diagnostics found.
+    ///
+    /// Errors signify that some hosts can't possibly be reached (e.g. a port number of zero
+    /// or a private key file that doesn't exist), while warnings signify that the configuration
+    /// might not do what was intended (e.g. a duplicate host, whose definitions are all discarded
+    /// except one according to `duplicate_hosts`, unless duplicates are errors; see
+    /// `MasshDuplicatePolicy`). An empty list signifies that no problem was found.
+    ///
+    /// ## Example
+    ///
+    /// ```no_run
+    /// use massh::MasshConfig;
+    ///
+    /// let config = MasshConfig::from_yaml_file("massh.yaml").unwrap();
+    /// for diagnostic in config.validate() {
+    ///     println!("{}", diagnostic);
+    /// }
+    /// ```
+    pub fn validate(&self) -> Vec<MasshDiagnostic> {
+        let mut diagnostics = Vec::new();
+        let hosts = self.resolve_hosts();
+
+        // Check that there is at least one host, and that every group has at least one host.
+        if hosts.is_empty() {
+            diagnostics.push(MasshDiagnostic {
+                severity: MasshSeverity::Error,
+                kind: MasshDiagnosticKind::NoHosts,
+                host: None,
+                message: String::from("no hosts are configured"),
+            });
+        }
+        for group in self.groups.iter().filter(|group| group.hosts.is_empty()) {
+            diagnostics.push(MasshDiagnostic {
+                severity: MasshSeverity::Warning,
+                kind: MasshDiagnosticKind::EmptyGroup,
+                host: None,
+                message: format!("group `{}` has no hosts", group.name),
+            });
+        }
+
+        let mut key_paths: Vec<&Path> = Vec::new();
+        for (index, host) in hosts.iter().enumerate() {
+            let id = host.id();
+
+            // Check that the port number is nonzero.
+            if host.addr.port() == 0 {
+                diagnostics.push(MasshDiagnostic {
+                    severity: MasshSeverity::Error,
+                    kind: MasshDiagnosticKind::ZeroPort,
+                    host: Some(id.clone()),
+                    message: String::from("port number is zero"),
+                });
+            }
+
+            // Check that the private key file exists, reporting each missing path only once.
+            let key_path = match host.auth {
+                SshAuth::Pubkey(path) => Some(path),
+                SshAuth::PubkeyPassphraseCmd { path, .. } => Some(path),
+                _ => None,
+            };
+            if let Some(path) = key_path {
+                if !path.is_file() && !key_paths.contains(&path.as_path()) {
+                    key_paths.push(path);
+                    diagnostics.push(MasshDiagnostic {
+                        severity: MasshSeverity::Error,
+                        kind: MasshDiagnosticKind::MissingKeyFile,
+                        host: Some(id.clone()),
+                        message: format!("private key file {:?} does not exist", path),
+                    });
+                }
+            }
+
+            // Check that the pinned host key is a valid fingerprint.
+            if let Some(host_key) = host.host_key {
+                if !is_fingerprint(host_key) {
+                    diagnostics.push(MasshDiagnostic {
+                        severity: MasshSeverity::Error,
+                        kind: MasshDiagnosticKind::InvalidHostKey,
+                        host: Some(id.clone()),
+                        message: format!(
+                            "host key {:?} is not a SHA256 or MD5 fingerprint",
+                            host_key
+                        ),
+                    });
+                }
+            }
+
+            // Check for duplicates, reporting each duplicate host only once at its last definition.
+            let policy = self.duplicate_hosts;
+            let previous = hosts[..index]
+                .iter()
+                .filter(|other| other.is_duplicate_of(host, policy));
+            let later = hosts[index + 1..]
+                .iter()
+                .any(|other| other.is_duplicate_of(host, policy));
+            let count = previous.clone().count();
+            if count > 0 && !later {
+                let (severity, outcome) = match policy {
+                    MasshDuplicatePolicy::Error => (MasshSeverity::Error, String::new()),
+                    MasshDuplicatePolicy::KeepFirst => {
+                        let first = previous.clone().next().and_then(|other| other.group);
+                        let outcome =
+                            format!("; the first definition{} wins", describe_group(first));
+                        (MasshSeverity::Warning, outcome)
+                    }
+                    MasshDuplicatePolicy::KeepLast | MasshDuplicatePolicy::Allow => {
+                        let outcome =
+                            format!("; the last definition{} wins", describe_group(host.group));
+                        (MasshSeverity::Warning, outcome)
+                    }
+                };
+                diagnostics.push(MasshDiagnostic {
+                    severity,
+                    kind: MasshDiagnosticKind::DuplicateHost,
+                    host: Some(id.clone()),
+                    message: format!("host is defined {} times{}", count + 1, outcome),
+                });
+            }
+
+            // Check for hosts that belong to several groups, reporting each new group only once.
+            let conflict = previous
+                .filter_map(|other| other.group)
+                .find(|name| host.group != Some(*name));
+            if let (Some(group), Some(other)) = (host.group, conflict) {
+                diagnostics.push(MasshDiagnostic {
+                    severity: MasshSeverity::Warning,
+                    kind: MasshDiagnosticKind::ConflictingGroups,
+                    host: Some(id.clone()),
+                    message: format!(
+                        "host belongs to both group `{}` and group `{}`",
+                        other, group
+                    ),
+                });
+            }
+        }
+
+        diagnostics
+    }
+
+    /// Returns all configured hosts, including those of groups, in configuration order.
+    ///
+    /// Each setting is taken from the host, then its group, then the defaults.
+    pub(crate) fn resolve_hosts(&self) -> Vec<ResolvedHost<'_>> {
+        let hosts = self.hosts.iter().map(|host| (None, host));
+        let groups = self
+            .groups
+            .iter()
+            .flat_map(|group| group.hosts.iter().map(move |host| (Some(group), host)));
+        hosts
+            .chain(groups)
+            .map(|(group, host)| {
+                let auth = host
+                    .auth
+                    .as_ref()
+                    .or_else(|| group.and_then(|group| group.auth.as_ref()))
+                    .unwrap_or(&self.default_auth);
+                let become_password = host
+                    .become_password
+                    .as_deref()
+                    .or_else(|| group.and_then(|group| group.become_password.as_deref()))
+                    .or(self.become_password.as_deref());
+                let become_user = host
+                    .become_user
+                    .as_deref()
+                    .or_else(|| group.and_then(|group| group.become_user.as_deref()))
+                    .or(self.become_user.as_deref());
+                let port = host
+                    .port
+                    .or_else(|| group.and_then(|group| group.port))
+                    .unwrap_or(self.default_port);
+                let shell = host
+                    .shell
+                    .or_else(|| group.and_then(|group| group.shell))
+                    .unwrap_or(self.shell);
+                let timeout = host
+                    .timeout
+                    .or_else(|| group.and_then(|group| group.timeout))
+                    .unwrap_or(self.timeout);
+                let user = host
+                    .user
+                    .as_ref()
+                    .or_else(|| group.and_then(|group| group.user.as_ref()))
+                    .unwrap_or(&self.default_user);
+                let working_dir = host
+                    .working_dir
+                    .as_deref()
+                    .or_else(|| group.and_then(|group| group.working_dir.as_deref()))
+                    .or(self.working_dir.as_deref());
+                let mut labels = group.map(|group| group.labels.clone()).unwrap_or_default();
+                labels.extend(host.labels.clone());
+                let fallback_addrs = host
+                    .fallback_addrs
+                    .iter()
+                    .map(|addr| SocketAddr::new(*addr, port))
+                    .collect();
+                ResolvedHost {
+                    addr: SocketAddr::new(host.addr, port),
+                    alias: host.alias.as_deref(),
+                    auth,
+                    become_password,
+                    become_user,
+                    fallback_addrs,
+                    group: group.map(|group| group.name.as_str()),
+                    hostname: host.hostname.as_deref(),
+                    host_key: host.host_key.as_deref(),
+                    labels,
+                    local: host.local,
+                    shell,
+                    timeout,
+                    user,
+                    working_dir,
+                }
+            })
+            .collect()
+    }
+
+    /// Reads and parses a configuration file, keeping track of the stack of files
+    /// being loaded to detect include cycles.
+    fn load(path: &Path, format: Format, stack: &mut Vec<PathBuf>) -> Result<Self> {
+        let canonical = path
+            .canonicalize()
+            .map_err(|e| anyhow::anyhow!("Failed to read {:?}: {}", path, e))?;
+        if stack.contains(&canonical) {
+            return Err(anyhow::anyhow!("Include cycle detected at {:?}", path));
+        }
+        let string = std::fs::read_to_string(&canonical)
+            .map_err(|e| anyhow::anyhow!("Failed to read {:?}: {}", path, e))?;
+        let dir = canonical
+            .parent()
+            .unwrap_or_else(|| Path::new("."))
+            .to_owned();
+
+        stack.push(canonical);
+        let result = Self::parse(&string, format, &dir, stack)
+            .map_err(|e| anyhow::anyhow!("Failed to parse {:?}: {}", path, e));
+        stack.pop();
+        result
+    }
+
+    /// Parses a configuration string, then loads its included files relative to `dir`.
+    fn parse(string: &str, format: Format, dir: &Path, stack: &mut Vec<PathBuf>) -> Result<Self> {
+        let mut config: MasshConfig = match format {
+            Format::Json => {
+                let mut value: serde_json::Value = serde_json::from_str(string)?;
+                interpolate_json(&mut value)?;
+                serde_json::from_value(value)?
+            }
+            Format::Yaml => {
+                let mut value: serde_yaml::Value = serde_yaml::from_str(string)?;
+                interpolate_yaml(&mut value)?;
+                serde_yaml::from_value(value)?
+            }
+        };
+
+        for pattern in &config.include {
+            let joined = dir.join(pattern);
+            let joined = match joined.to_str() {
+                Some(joined) => joined,
+                None => return Err(anyhow::anyhow!("Include path {:?} is not UTF-8", pattern)),
+            };
+
+            // A literal path must exist, but a glob pattern may match nothing.
+            let paths = if glob::Pattern::escape(joined) == joined {
+                vec![PathBuf::from(joined)]
+            } else {
+                glob::glob(joined)?.collect::<Result<Vec<_>, _>>()?
+            };
+
+            for path in paths {
+                let format = match path.extension() {
+                    Some(extension) if extension == "json" => Format::Json,
+                    _ => Format::Yaml,
+                };
+                let included = Self::load(&path, format, stack)?;
+                config.hosts.extend(included.hosts);
+                config.groups.extend(included.groups);
+            }
+        }
+
         Ok(config)
     }
 }
 
+/// Policy for the hosts defined more than once in a `MasshConfig` (see
+/// `MasshConfig::duplicate_hosts`).
+///
+/// Two definitions are duplicates if they have the same username, IP address and port number,
+/// or the same alias. Wherever a single definition is kept, it takes the position of the first
+/// one in the configuration order of hosts.
+///
+/// ## Example
+/// ```
+/// use massh::{MasshClient, MasshConfig};
+///
+/// let yaml = "
+/// duplicate_hosts: allow
+/// hosts:
+///   - {addr: 10.0.0.1, user: root, alias: web-1-blue}
+///   - {addr: 10.0.0.1, user: root, alias: web-1-green}
+///   - {addr: 10.0.0.2, user: root, labels: {env: dev}}
+///   - {addr: 10.0.0.2, user: root, labels: {env: prod}}
+/// ";
+/// let (massh, duplicates) = MasshClient::try_from(&MasshConfig::from_yaml(yaml).unwrap()).unwrap();
+/// let hosts: Vec<_> = massh.iter().map(|(host, _)| host.clone()).collect();
+/// assert_eq!(hosts, ["web-1-blue", "web-1-green", "root@10.0.0.2:22"]);
+/// assert_eq!(duplicates, ["root@10.0.0.2:22"]);
+/// assert_eq!(massh.get_labels("root@10.0.0.2:22").unwrap()["env"], "prod");
+///
+/// let yaml = yaml.replace("allow", "error");
+/// assert!(MasshClient::try_from(&MasshConfig::from_yaml(&yaml).unwrap()).is_err());
+/// ```
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MasshDuplicatePolicy {
+    /// The last definition of each duplicate host is kept.
+    #[default]
+    KeepLast,
+    /// The first definition of each duplicate host is kept.
+    KeepFirst,
+    /// Duplicate hosts make `MasshClient::try_from` fail, while `MasshClient::from` keeps their
+    /// last definition.
+    Error,
+    /// Definitions of the same username, IP address and port number are all kept as distinct
+    /// hosts if they have distinct aliases (e.g. to run a command twice on the same host), and
+    /// the last definition of each alias is kept otherwise.
+    Allow,
+}
+
+/// Severity of a `MasshDiagnostic`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MasshSeverity {
+    /// The configuration might not do what was intended.
+    Warning,
+    /// Some hosts can't possibly be reached.
+    Error,
+}
+
+/// Kind of problem reported by a `MasshDiagnostic`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MasshDiagnosticKind {
+    /// No hosts are configured at all.
+    NoHosts,
+    /// A group has no hosts.
+    EmptyGroup,
+    /// A host's port number is zero.
+    ZeroPort,
+    /// A host's private key file does not exist.
+    MissingKeyFile,
+    /// A host is defined more than once, in which case one definition wins or the
+    /// configuration is rejected (see `MasshDuplicatePolicy`).
+    DuplicateHost,
+    /// A host belongs to more than one group.
+    ConflictingGroups,
+    /// A host's pinned host key is not a valid fingerprint.
+    InvalidHostKey,
+}
+
+/// Diagnostic reported by [`MasshConfig::validate`].
+#[derive(Clone, Debug)]
+pub struct MasshDiagnostic {
+    /// Severity of the problem.
+    pub severity: MasshSeverity,
+    /// Kind of the problem.
+    pub kind: MasshDiagnosticKind,
+    /// Host concerned by the problem (its alias, or `username@ip_address:port`), if any.
+    pub host: Option<String>,
+    /// Human-readable description of the problem.
+    pub message: String,
+}
+
+impl std::fmt::Display for MasshDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let severity = match self.severity {
+            MasshSeverity::Warning => "warning",
+            MasshSeverity::Error => "error",
+        };
+        match &self.host {
+            Some(host) => write!(f, "{}: [{}]: {}", severity, host, self.message),
+            None => write!(f, "{}: {}", severity, self.message),
+        }
+    }
+}
+
+/// Fully resolved settings of a configured host.
+pub(crate) struct ResolvedHost<'a> {
+    pub(crate) addr: SocketAddr,
+    pub(crate) alias: Option<&'a str>,
+    pub(crate) auth: &'a SshAuth,
+    pub(crate) become_password: Option<&'a str>,
+    pub(crate) become_user: Option<&'a str>,
+    pub(crate) fallback_addrs: Vec<SocketAddr>,
+    pub(crate) group: Option<&'a str>,
+    pub(crate) hostname: Option<&'a str>,
+    pub(crate) host_key: Option<&'a str>,
+    pub(crate) labels: BTreeMap<String, String>,
+    pub(crate) local: bool,
+    pub(crate) shell: SshShell,
+    pub(crate) timeout: u64,
+    pub(crate) user: &'a str,
+    pub(crate) working_dir: Option<&'a str>,
+}
+
+impl ResolvedHost<'_> {
+    /// Returns the unique string identifier (its alias, or `username@ip_address:port`) of this
+    /// host.
+    pub(crate) fn id(&self) -> String {
+        match self.alias {
+            Some(alias) => alias.to_string(),
+            None => format!("{}@{}", self.user, self.addr),
+        }
+    }
+
+    /// Returns whether this host and another one are duplicates under a policy, i.e. whether
+    /// they have the same identifier, or the same user and address unless duplicates are
+    /// allowed (see `MasshDuplicatePolicy`).
+    pub(crate) fn is_duplicate_of(
+        &self,
+        other: &ResolvedHost,
+        policy: MasshDuplicatePolicy,
+    ) -> bool {
+        self.id() == other.id()
+            || (policy != MasshDuplicatePolicy::Allow
+                && self.user == other.user
+                && self.addr == other.addr)
+    }
+}
+
+/// Describes the group of a host, if any, for diagnostic messages.
+fn describe_group(group: Option<&str>) -> String {
+    match group {
+        Some(group) => format!(" (in group `{}`)", group),
+        None => String::new(),
+    }
+}
+
+/// Configuration file formats supported by the `MasshConfig` struct.
+enum Format {
+    Json,
+    Yaml,
+}
+
+// The following private items provide the default values of optional `MasshConfig` fields.
+
+fn default_port() -> u16 {
+    22
+}
+
+fn default_user() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| String::from("root"))
+}
+
+// The following private items help substitute `${NAME}` references to
+// environment variables in every string value of a configuration file.
+
+fn interpolate_json(value: &mut serde_json::Value) -> Result<()> {
+    use serde_json::Value;
+    match value {
+        Value::String(string) => *string = interpolate(string)?,
+        Value::Array(values) => values.iter_mut().try_for_each(interpolate_json)?,
+        Value::Object(map) => map.values_mut().try_for_each(interpolate_json)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+fn interpolate_yaml(value: &mut serde_yaml::Value) -> Result<()> {
+    use serde_yaml::Value;
+    match value {
+        Value::String(string) => *string = interpolate(string)?,
+        Value::Sequence(values) => values.iter_mut().try_for_each(interpolate_yaml)?,
+        Value::Mapping(map) => map.iter_mut().try_for_each(|(_, v)| interpolate_yaml(v))?,
+        _ => {}
+    }
+    Ok(())
+}
+
+fn interpolate(input: &str) -> Result<String> {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(index) = rest.find('$') {
+        output.push_str(&rest[..index]);
+        rest = &rest[index..];
+        if let Some(tail) = rest.strip_prefix("$${") {
+            // Escaped reference: emit a literal `${`.
+            output.push_str("${");
+            rest = tail;
+        } else if let Some(tail) = rest.strip_prefix("${") {
+            // Reference: substitute the value of the environment variable.
+            let end = match tail.find('}') {
+                Some(end) => end,
+                None => return Err(anyhow::anyhow!("Unterminated `${{` in configuration value")),
+            };
+            let name = &tail[..end];
+            match std::env::var(name) {
+                Ok(value) => output.push_str(&value),
+                Err(_) => {
+                    return Err(anyhow::anyhow!(
+                        "Environment variable `{}` is not set",
+                        name
+                    ))
+                }
+            }
+            rest = &tail[end + 1..];
+        } else {
+            // Lone dollar sign: emit it as is.
+            output.push('$');
+            rest = &rest[1..];
+        }
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
 // The rest of this file consists of private items to help deserialize
 // a `MasshHostConfig` struct from either a map or a string.
 
 #[derive(Deserialize)]
 struct InnerMasshHostConfig {
     addr: IpAddr,
+    alias: Option<String>,
     auth: Option<SshAuth>,
+    become_password: Option<String>,
+    become_user: Option<String>,
+    #[serde(skip)]
+    fallback_addrs: Vec<IpAddr>,
+    #[serde(skip)]
+    hostname: Option<String>,
+    host_key: Option<String>,
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+    #[serde(default)]
+    local: bool,
     port: Option<u16>,
+    shell: Option<SshShell>,
+    timeout: Option<u64>,
     user: Option<String>,
+    working_dir: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -192,9 +1134,20 @@ impl From<MasshHostConfigEnum> for MasshHostConfig {
         };
         MasshHostConfig {
             addr: inner.addr,
+            alias: inner.alias,
             auth: inner.auth,
+            become_password: inner.become_password,
+            become_user: inner.become_user,
+            fallback_addrs: inner.fallback_addrs,
+            hostname: inner.hostname,
+            host_key: inner.host_key,
+            labels: inner.labels,
+            local: inner.local,
             port: inner.port,
+            shell: inner.shell,
+            timeout: inner.timeout,
             user: inner.user,
+            working_dir: inner.working_dir,
         }
     }
 }
@@ -214,7 +1167,7 @@ where
         None => (None, value),
     };
 
-    let (mut addrs, no_port) = if let Ok(addrs) = value.to_socket_addrs() {
+    let (addrs, no_port) = if let Ok(addrs) = value.to_socket_addrs() {
         (addrs, false)
     } else if let Ok(addrs) = format!("{}:22", value).to_socket_addrs() {
         (addrs, true)
@@ -222,18 +1175,45 @@ where
         return Err(e);
     };
 
-    let socket = match addrs.next() {
+    // Keep all the addresses of a hostname, which are tried in order when connecting.
+    let sockets: Vec<SocketAddr> = addrs.collect();
+    let socket = match sockets.first() {
         Some(socket) => socket,
         None => return Err(e),
     };
 
     let addr = socket.ip();
     let port = if no_port { None } else { Some(socket.port()) };
+    let name = match (no_port, value.rsplit_once(':')) {
+        (false, Some((name, _))) => name.trim_start_matches('[').trim_end_matches(']'),
+        _ => value.as_str(),
+    };
+    let hostname = match name.parse::<IpAddr>() {
+        Ok(_) => None,
+        Err(_) => Some(name.to_owned()),
+    };
+    let mut fallback_addrs = Vec::new();
+    for socket in &sockets {
+        if socket.ip() != addr && !fallback_addrs.contains(&socket.ip()) {
+            fallback_addrs.push(socket.ip());
+        }
+    }
 
     Ok(InnerMasshHostConfig {
         addr,
+        alias: None,
         auth: None,
+        become_password: None,
+        become_user: None,
+        fallback_addrs,
+        hostname,
+        host_key: None,
+        labels: BTreeMap::new(),
+        local: false,
         port,
+        shell: None,
+        timeout: None,
         user,
+        working_dir: None,
     })
 }
@@ -0,0 +1,91 @@
+use crate::{SshClient, SshOutput};
+use anyhow::Result;
+use std::path::Path;
+
+#[cfg(feature = "test-util")]
+mod fake;
+mod record;
+
+#[cfg(feature = "test-util")]
+pub use fake::FakeExecutor;
+pub use record::{MasshRecord, MasshRecording, RecordingExecutor, ReplayExecutor};
+
+/// Operations on a single host which a `MasshClient` runs on all its hosts.
+///
+/// It's implemented by [`SshClient`], which is the executor of a `MasshClient` constructed from
+/// a configuration, and by `FakeExecutor` (requires the `test-util` feature), which runs
+/// nothing on the network, so that code built on a `MasshClient` can be unit-tested without
+/// real servers (see [`MasshClient::from_executors`]). A run can also be recorded with a
+/// [`RecordingExecutor`] and replayed with a [`ReplayExecutor`] (see [`MasshRecording`]).
+///
+/// Only the operations of a `MasshClient` built on these methods (e.g.
+/// [`MasshClient::execute`] and [`MasshClient::scp_upload`]) are available for other executors.
+///
+/// [`MasshClient::execute`]: crate::MasshClient::execute
+/// [`MasshClient::from_executors`]: crate::MasshClient::from_executors
+/// [`MasshClient::scp_upload`]: crate::MasshClient::scp_upload
+pub trait RemoteExecutor: Send + 'static {
+    /// Attempts to establish a connection with the host.
+    fn connect(&mut self) -> Result<()>;
+
+    /// Drops the connection with the host, if any.
+    fn disconnect(&mut self);
+
+    /// Attempts to execute a command on the host, with additional environment variables.
+    fn execute_with_env(&mut self, command: &str, env: &[(&str, &str)]) -> Result<SshOutput>;
+
+    /// Attempts to execute a command on the host.
+    fn execute(&mut self, command: &str) -> Result<SshOutput> {
+        self.execute_with_env(command, &[])
+    }
+
+    /// Attempts to download a file from the host.
+    fn scp_download(&mut self, remote_path: &Path, local_path: &Path) -> Result<()>;
+
+    /// Attempts to upload a file to the host.
+    fn scp_upload(&mut self, local_path: &Path, remote_path: &Path) -> Result<()>;
+}
+
+impl RemoteExecutor for SshClient {
+    fn connect(&mut self) -> Result<()> {
+        SshClient::connect(self).map(|_| ())
+    }
+
+    fn disconnect(&mut self) {
+        SshClient::disconnect(self);
+    }
+
+    fn execute_with_env(&mut self, command: &str, env: &[(&str, &str)]) -> Result<SshOutput> {
+        SshClient::execute_with_env(self, command, env)
+    }
+
+    fn execute(&mut self, command: &str) -> Result<SshOutput> {
+        SshClient::execute(self, command)
+    }
+
+    fn scp_download(&mut self, remote_path: &Path, local_path: &Path) -> Result<()> {
+        SshClient::scp_download(self, remote_path, local_path)
+    }
+
+    fn scp_upload(&mut self, local_path: &Path, remote_path: &Path) -> Result<()> {
+        SshClient::scp_upload(self, local_path, remote_path)
+    }
+}
+
+/// Returns the output of a command which exited with the specified status and output, without
+/// being killed by a signal.
+fn output(exit_status: i32, stdout: Vec<u8>, stderr: Vec<u8>) -> SshOutput {
+    SshOutput {
+        chunks: Vec::new(),
+        core_dumped: false,
+        error_message: None,
+        exit_signal: None,
+        exit_status,
+        stderr,
+        stderr_dropped: 0,
+        stderr_file: None,
+        stdout,
+        stdout_dropped: 0,
+        stdout_file: None,
+    }
+}
@@ -0,0 +1,136 @@
+use super::RemoteExecutor;
+use crate::SshOutput;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// In-memory [`RemoteExecutor`] which runs nothing on the network, to unit-test code built on
+/// a `MasshClient` (requires the `test-util` feature).
+///
+/// Its commands return the configured responses (or an exit status of zero with no output
+/// otherwise), its remote files are kept in memory, and it records every executed command.
+///
+/// ## Example
+/// ```
+/// use massh::{FakeExecutor, MasshClient, MasshConfig};
+///
+/// let config = MasshConfig::from_yaml("hosts: [{addr: 10.0.0.1}, {addr: 10.0.0.2}]").unwrap();
+/// let massh = MasshClient::from_executors(&config, |_| {
+///     let mut fake = FakeExecutor::new();
+///     fake.set_response("hostname", 0, "web\n", "");
+///     fake
+/// });
+///
+/// for (host, result) in massh.collect_ordered(massh.execute("hostname")) {
+///     assert_eq!(result.unwrap().stdout, b"web\n");
+///     let commands = massh.with_client(&host, |fake| fake.get_commands().to_vec());
+///     assert_eq!(commands.unwrap(), ["hostname"]);
+/// }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct FakeExecutor {
+    commands: Vec<String>,
+    connect_error: Option<String>,
+    connected: bool,
+    files: HashMap<PathBuf, Vec<u8>>,
+    responses: HashMap<String, (i32, Vec<u8>, Vec<u8>)>,
+}
+
+impl FakeExecutor {
+    /// Constructs a new `FakeExecutor`, without responses or remote files.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Configures the exit status and the output of a command, matched exactly.
+    pub fn set_response(
+        &mut self,
+        command: impl Into<String>,
+        exit_status: i32,
+        stdout: impl Into<Vec<u8>>,
+        stderr: impl Into<Vec<u8>>,
+    ) -> &mut Self {
+        let response = (exit_status, stdout.into(), stderr.into());
+        self.responses.insert(command.into(), response);
+        self
+    }
+
+    /// Configures this `FakeExecutor` to fail to connect with the specified error message,
+    /// like an unreachable host, or to connect successfully if `None`. This is the default.
+    pub fn set_connect_error(&mut self, message: Option<String>) -> &mut Self {
+        self.connect_error = message;
+        self
+    }
+
+    /// Configures the content of a remote file.
+    pub fn set_file(&mut self, path: impl Into<PathBuf>, content: impl Into<Vec<u8>>) -> &mut Self {
+        self.files.insert(path.into(), content.into());
+        self
+    }
+
+    /// Returns the executed commands, in order.
+    pub fn get_commands(&self) -> &[String] {
+        &self.commands
+    }
+
+    /// Returns the content of a remote file, if it exists.
+    pub fn get_file(&self, path: impl AsRef<Path>) -> Option<&[u8]> {
+        self.files.get(path.as_ref()).map(Vec::as_slice)
+    }
+
+    /// Returns whether this `FakeExecutor` is connected.
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Connects if not connected yet, like an `SshClient` does implicitly.
+    fn ensure_connected(&mut self) -> Result<()> {
+        if !self.connected {
+            self.connect()?;
+        }
+        Ok(())
+    }
+}
+
+impl RemoteExecutor for FakeExecutor {
+    fn connect(&mut self) -> Result<()> {
+        if let Some(message) = &self.connect_error {
+            return Err(anyhow::anyhow!("{}", message));
+        }
+        self.connected = true;
+        Ok(())
+    }
+
+    fn disconnect(&mut self) {
+        self.connected = false;
+    }
+
+    fn execute_with_env(&mut self, command: &str, _env: &[(&str, &str)]) -> Result<SshOutput> {
+        self.ensure_connected()?;
+        self.commands.push(command.to_string());
+        let (exit_status, stdout, stderr) =
+            self.responses
+                .get(command)
+                .cloned()
+                .unwrap_or((0, Vec::new(), Vec::new()));
+        Ok(super::output(exit_status, stdout, stderr))
+    }
+
+    fn scp_download(&mut self, remote_path: &Path, local_path: &Path) -> Result<()> {
+        self.ensure_connected()?;
+        match self.files.get(remote_path) {
+            Some(content) => Ok(std::fs::write(local_path, content)?),
+            None => Err(anyhow::anyhow!(
+                "Remote file {:?} does not exist",
+                remote_path
+            )),
+        }
+    }
+
+    fn scp_upload(&mut self, local_path: &Path, remote_path: &Path) -> Result<()> {
+        self.ensure_connected()?;
+        let content = std::fs::read(local_path)?;
+        self.files.insert(remote_path.to_path_buf(), content);
+        Ok(())
+    }
+}
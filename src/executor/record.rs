@@ -0,0 +1,399 @@
+use super::RemoteExecutor;
+use crate::{MasshHost, SshClient, SshOutput};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Commands and file transfers of a run of a `MasshClient`, per host, which can be saved to a
+/// JSON file and replayed without network access.
+///
+/// A recording is made by a `MasshClient` constructed with [`MasshClient::record`], and replayed
+/// by a `MasshClient` constructed with [`MasshClient::replay`], which makes integration tests
+/// of tools built on massh deterministic.
+///
+/// Outputs and downloaded files are recorded as base64, so that they're replayed exactly even if
+/// they aren't valid UTF-8, along with the SHA-256 checksum of their bytes, so that a recording
+/// that was modified can't be replayed. Uploaded files are only recorded by checksum, which is
+/// checked on replay.
+///
+/// ## Example
+/// ```no_run
+/// use massh::{MasshClient, MasshConfig, MasshRecording};
+///
+/// let config = MasshConfig::from_yaml_file("massh.yaml").unwrap();
+///
+/// // Record a run against the real hosts.
+/// let massh = MasshClient::record(&config);
+/// let _ = massh.collect_ordered(massh.execute("uname -r"));
+/// massh.get_recording().to_json_file("recording.json").unwrap();
+///
+/// // Replay it later, e.g. in CI.
+/// let recording = MasshRecording::from_json_file("recording.json").unwrap();
+/// let massh = MasshClient::replay(&config, &recording);
+/// for (host, result) in massh.collect_ordered(massh.execute("uname -r")) {
+///     println!("{}: {}", host, result.unwrap().stdout_lossy());
+/// }
+/// ```
+///
+/// [`MasshClient::record`]: crate::MasshClient::record
+/// [`MasshClient::replay`]: crate::MasshClient::replay
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct MasshRecording {
+    /// Operations of each host, in execution order.
+    pub hosts: BTreeMap<MasshHost, Vec<MasshRecord>>,
+}
+
+/// Operation of a host in a `MasshRecording`.
+///
+/// If the operation failed, its error message is recorded and its other fields are empty.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "op")]
+pub enum MasshRecord {
+    /// Explicit connection (see `MasshClient::connect_all`).
+    Connect {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// Command execution, matched on replay by command and environment variables.
+    Execute {
+        command: String,
+        #[serde(default)]
+        env: Vec<(String, String)>,
+        #[serde(default)]
+        exit_status: i32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        exit_signal: Option<String>,
+        #[serde(default)]
+        core_dumped: bool,
+        #[serde(default, with = "base64_bytes")]
+        stdout: Vec<u8>,
+        #[serde(default)]
+        stdout_sha256: String,
+        #[serde(default, with = "base64_bytes")]
+        stderr: Vec<u8>,
+        #[serde(default)]
+        stderr_sha256: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// File download, matched on replay by remote path.
+    Download {
+        remote_path: PathBuf,
+        #[serde(default, with = "base64_bytes")]
+        content: Vec<u8>,
+        #[serde(default)]
+        sha256: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// File upload, matched on replay by remote path.
+    Upload {
+        remote_path: PathBuf,
+        #[serde(default)]
+        sha256: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+}
+
+impl MasshRecording {
+    /// Attempts to construct a new `MasshRecording` from a JSON string.
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Attempts to construct a new `MasshRecording` from a JSON file.
+    pub fn from_json_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read {:?}: {}", path, e))?;
+        Self::from_json(&json).map_err(|e| anyhow::anyhow!("Failed to parse {:?}: {}", path, e))
+    }
+
+    /// Serializes this `MasshRecording` as a pretty-printed JSON string.
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Attempts to write this `MasshRecording` to a JSON file.
+    pub fn to_json_file(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_json()?)
+            .map_err(|e| anyhow::anyhow!("Failed to write {:?}: {}", path, e))
+    }
+}
+
+/// [`RemoteExecutor`] which records the operations of another executor (an `SshClient`
+/// by default). See [`MasshRecording`] for more details.
+pub struct RecordingExecutor<E = SshClient> {
+    inner: E,
+    records: Vec<MasshRecord>,
+}
+
+impl<E: RemoteExecutor> RecordingExecutor<E> {
+    /// Constructs a new `RecordingExecutor` wrapping the specified executor.
+    pub fn new(inner: E) -> Self {
+        Self {
+            inner,
+            records: Vec::new(),
+        }
+    }
+
+    /// Returns the recorded operations, in execution order.
+    pub fn get_records(&self) -> &[MasshRecord] {
+        &self.records
+    }
+}
+
+impl<E: RemoteExecutor> RemoteExecutor for RecordingExecutor<E> {
+    fn connect(&mut self) -> Result<()> {
+        let result = self.inner.connect();
+        let error = result.as_ref().err().map(|e| e.to_string());
+        self.records.push(MasshRecord::Connect { error });
+        result
+    }
+
+    fn disconnect(&mut self) {
+        self.inner.disconnect();
+    }
+
+    fn execute_with_env(&mut self, command: &str, env: &[(&str, &str)]) -> Result<SshOutput> {
+        let result = self.inner.execute_with_env(command, env);
+        let env = env
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        let record = match &result {
+            Ok(output) => MasshRecord::Execute {
+                command: command.to_string(),
+                env,
+                exit_status: output.exit_status,
+                exit_signal: output.exit_signal.clone(),
+                core_dumped: output.core_dumped,
+                stdout: output.stdout.clone(),
+                stdout_sha256: sha256(&output.stdout),
+                stderr: output.stderr.clone(),
+                stderr_sha256: sha256(&output.stderr),
+                error: None,
+            },
+            Err(e) => MasshRecord::Execute {
+                command: command.to_string(),
+                env,
+                exit_status: 0,
+                exit_signal: None,
+                core_dumped: false,
+                stdout: Vec::new(),
+                stdout_sha256: String::new(),
+                stderr: Vec::new(),
+                stderr_sha256: String::new(),
+                error: Some(e.to_string()),
+            },
+        };
+        self.records.push(record);
+        result
+    }
+
+    fn scp_download(&mut self, remote_path: &Path, local_path: &Path) -> Result<()> {
+        let result = self.inner.scp_download(remote_path, local_path);
+        let record = match &result {
+            Ok(()) => {
+                let content = std::fs::read(local_path)?;
+                MasshRecord::Download {
+                    remote_path: remote_path.to_path_buf(),
+                    sha256: sha256(&content),
+                    content,
+                    error: None,
+                }
+            }
+            Err(e) => MasshRecord::Download {
+                remote_path: remote_path.to_path_buf(),
+                content: Vec::new(),
+                sha256: String::new(),
+                error: Some(e.to_string()),
+            },
+        };
+        self.records.push(record);
+        result
+    }
+
+    fn scp_upload(&mut self, local_path: &Path, remote_path: &Path) -> Result<()> {
+        let result = self.inner.scp_upload(local_path, remote_path);
+        let (sha256, error) = match &result {
+            Ok(()) => (sha256(&std::fs::read(local_path)?), None),
+            Err(e) => (String::new(), Some(e.to_string())),
+        };
+        self.records.push(MasshRecord::Upload {
+            remote_path: remote_path.to_path_buf(),
+            sha256,
+            error,
+        });
+        result
+    }
+}
+
+/// [`RemoteExecutor`] which serves the operations recorded for a host, without network access.
+/// See [`MasshRecording`] for more details.
+///
+/// Each operation consumes the first remaining record which matches it, and fails if there's
+/// none. Explicit connections succeed even if they weren't recorded.
+pub struct ReplayExecutor {
+    host: MasshHost,
+    records: Vec<MasshRecord>,
+}
+
+impl ReplayExecutor {
+    /// Constructs a new `ReplayExecutor` serving the operations recorded for the specified host.
+    pub fn new(recording: &MasshRecording, host: &str) -> Self {
+        Self {
+            host: host.to_string(),
+            records: recording.hosts.get(host).cloned().unwrap_or_default(),
+        }
+    }
+
+    /// Returns the recorded operations which weren't replayed yet, in execution order.
+    pub fn get_records(&self) -> &[MasshRecord] {
+        &self.records
+    }
+
+    /// Removes and returns the first remaining record for which the predicate returns `true`.
+    fn take(&mut self, predicate: impl Fn(&MasshRecord) -> bool) -> Option<MasshRecord> {
+        let index = self.records.iter().position(predicate)?;
+        Some(self.records.remove(index))
+    }
+
+    /// Returns the error of an operation which wasn't recorded.
+    fn not_recorded(&self, operation: String) -> anyhow::Error {
+        anyhow::anyhow!("No recording of {} on {}", operation, self.host)
+    }
+}
+
+impl RemoteExecutor for ReplayExecutor {
+    fn connect(&mut self) -> Result<()> {
+        match self.take(|record| matches!(record, MasshRecord::Connect { .. })) {
+            Some(MasshRecord::Connect { error: Some(error) }) => Err(anyhow::anyhow!("{}", error)),
+            _ => Ok(()),
+        }
+    }
+
+    fn disconnect(&mut self) {}
+
+    fn execute_with_env(&mut self, command: &str, env: &[(&str, &str)]) -> Result<SshOutput> {
+        let matches = |record: &MasshRecord| match record {
+            MasshRecord::Execute {
+                command: recorded_command,
+                env: recorded_env,
+                ..
+            } => {
+                recorded_command == command
+                    && recorded_env.len() == env.len()
+                    && recorded_env
+                        .iter()
+                        .zip(env)
+                        .all(|((a, b), (c, d))| a == c && b == d)
+            }
+            _ => false,
+        };
+        match self.take(matches) {
+            Some(MasshRecord::Execute {
+                error: Some(error), ..
+            }) => Err(anyhow::anyhow!("{}", error)),
+            Some(MasshRecord::Execute {
+                exit_status,
+                exit_signal,
+                core_dumped,
+                stdout,
+                stdout_sha256,
+                stderr,
+                stderr_sha256,
+                ..
+            }) => {
+                check_sha256(&stdout, &stdout_sha256, "standard output", command)?;
+                check_sha256(&stderr, &stderr_sha256, "standard error", command)?;
+                let mut output = super::output(exit_status, stdout, stderr);
+                output.exit_signal = exit_signal;
+                output.core_dumped = core_dumped;
+                Ok(output)
+            }
+            _ => Err(self.not_recorded(format!("command {:?}", command))),
+        }
+    }
+
+    fn scp_download(&mut self, remote_path: &Path, local_path: &Path) -> Result<()> {
+        let matches = |record: &MasshRecord| match record {
+            MasshRecord::Download {
+                remote_path: path, ..
+            } => path == remote_path,
+            _ => false,
+        };
+        match self.take(matches) {
+            Some(MasshRecord::Download {
+                error: Some(error), ..
+            }) => Err(anyhow::anyhow!("{}", error)),
+            Some(MasshRecord::Download {
+                content, sha256, ..
+            }) => {
+                let description = format!("{:?}", remote_path);
+                check_sha256(&content, &sha256, "content", &description)?;
+                Ok(std::fs::write(local_path, content)?)
+            }
+            _ => Err(self.not_recorded(format!("download of {:?}", remote_path))),
+        }
+    }
+
+    fn scp_upload(&mut self, local_path: &Path, remote_path: &Path) -> Result<()> {
+        let matches = |record: &MasshRecord| match record {
+            MasshRecord::Upload {
+                remote_path: path, ..
+            } => path == remote_path,
+            _ => false,
+        };
+        match self.take(matches) {
+            Some(MasshRecord::Upload {
+                error: Some(error), ..
+            }) => Err(anyhow::anyhow!("{}", error)),
+            Some(MasshRecord::Upload {
+                sha256: recorded, ..
+            }) => {
+                if sha256(&std::fs::read(local_path)?) != recorded {
+                    return Err(anyhow::anyhow!(
+                        "Content of {:?} differs from the recorded upload to {:?}",
+                        local_path,
+                        remote_path
+                    ));
+                }
+                Ok(())
+            }
+            _ => Err(self.not_recorded(format!("upload to {:?}", remote_path))),
+        }
+    }
+}
+
+/// Returns the SHA-256 checksum of bytes in lowercase hexadecimal.
+fn sha256(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Checks that recorded bytes match their recorded checksum.
+fn check_sha256(bytes: &[u8], checksum: &str, what: &str, of: &str) -> Result<()> {
+    if sha256(bytes) != checksum {
+        return Err(anyhow::anyhow!("Recorded {} of {} was modified", what, of));
+    }
+    Ok(())
+}
+
+/// Serializes recorded bytes as base64, since they aren't necessarily valid UTF-8.
+mod base64_bytes {
+    use crate::shell::{base64, base64_decode};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64(bytes, true))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        base64_decode(&encoded).ok_or_else(|| serde::de::Error::custom("invalid base64"))
+    }
+}
@@ -0,0 +1,103 @@
+use crate::SshClient;
+use anyhow::Result;
+use serde::Serialize;
+
+/// Shell script which prints the facts of a host as `key=value` lines.
+const PROBE: &str = r#"
+echo "hostname=$(hostname 2>/dev/null || uname -n)"
+echo "os=$( (. /etc/os-release 2>/dev/null && echo "$PRETTY_NAME") || uname -s)"
+echo "kernel=$(uname -r)"
+echo "arch=$(uname -m)"
+echo "cpus=$(nproc 2>/dev/null || getconf _NPROCESSORS_ONLN 2>/dev/null)"
+awk '/^MemTotal:/ { print "memory_total_kb=" $2 } /^MemAvailable:/ { print "memory_available_kb=" $2 }' /proc/meminfo 2>/dev/null
+df -Pk / 2>/dev/null | awk 'NR == 2 { print "disk_total_kb=" $2; print "disk_used_kb=" $3; print "disk_available_kb=" $4 }'
+awk '{ print "uptime=" int($1) }' /proc/uptime 2>/dev/null
+true
+"#;
+
+/// Facts about a host, gathered by [`SshClient::gather_facts`].
+///
+/// Facts that couldn't be probed on the host (e.g. memory outside of Linux) are `None`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct SshFacts {
+    /// Architecture of the machine (e.g. `x86_64`)
+    pub arch: String,
+    /// Number of online CPUs
+    pub cpus: Option<u64>,
+    /// Available space of the root filesystem, in bytes
+    pub disk_available: Option<u64>,
+    /// Total space of the root filesystem, in bytes
+    pub disk_total: Option<u64>,
+    /// Used space of the root filesystem, in bytes
+    pub disk_used: Option<u64>,
+    /// Hostname of the machine
+    pub hostname: String,
+    /// Kernel release (e.g. `5.15.0-86-generic`)
+    pub kernel: String,
+    /// Available memory, in bytes
+    pub memory_available: Option<u64>,
+    /// Total memory, in bytes
+    pub memory_total: Option<u64>,
+    /// Name of the operating system (e.g. `Ubuntu 22.04.3 LTS`)
+    pub os: String,
+    /// Time since the machine booted, in seconds
+    pub uptime: Option<u64>,
+}
+
+impl SshFacts {
+    /// Parses the `key=value` lines printed by the probe script.
+    fn parse(output: &str) -> Self {
+        let mut facts = SshFacts::default();
+        for line in output.lines() {
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key, value.trim()),
+                None => continue,
+            };
+            let number = value.parse::<u64>().ok();
+            let kilobytes = number.map(|kb| kb * 1024);
+            match key {
+                "arch" => facts.arch = value.to_string(),
+                "cpus" => facts.cpus = number,
+                "disk_available_kb" => facts.disk_available = kilobytes,
+                "disk_total_kb" => facts.disk_total = kilobytes,
+                "disk_used_kb" => facts.disk_used = kilobytes,
+                "hostname" => facts.hostname = value.to_string(),
+                "kernel" => facts.kernel = value.to_string(),
+                "memory_available_kb" => facts.memory_available = kilobytes,
+                "memory_total_kb" => facts.memory_total = kilobytes,
+                "os" => facts.os = value.to_string(),
+                "uptime" => facts.uptime = number,
+                _ => {}
+            }
+        }
+        facts
+    }
+}
+
+impl SshClient {
+    /// Attempts to gather facts about the configured host.
+    ///
+    /// It runs a small built-in probe script (OS, kernel, CPU count, memory, disk usage of the
+    /// root filesystem, and uptime) with POSIX tools, which works best on Linux hosts.
+    /// See [`SshFacts`] for more details.
+    ///
+    /// Note that this method implicitly calls [`SshClient::connect`] if no session was
+    /// established prior. Otherwise, it reuses the cached session
+    /// (see [`SshClient::set_keepalive`] to check its health first).
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// if let Ok(facts) = ssh.gather_facts() {
+    ///     println!("{} runs {} with {:?} CPUs", facts.hostname, facts.os, facts.cpus);
+    /// }
+    /// ```
+    pub fn gather_facts(&mut self) -> Result<SshFacts> {
+        self.get_shell().ensure_posix()?;
+        let output = self.execute_checked(PROBE, "Fact gathering failed")?;
+        Ok(SshFacts::parse(&output.stdout_lossy()))
+    }
+}
@@ -0,0 +1,174 @@
+use crate::{SshChannel, SshClient};
+use anyhow::Result;
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Interval at which [`SshClient::forward_unix`] polls its connections when they're idle.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Connection accepted on the local socket of a forwarding, with the bytes read from each end
+/// which the other end didn't accept yet.
+struct Forwarded {
+    channel: SshChannel,
+    eof_sent: bool,
+    local_eof: bool,
+    stream: UnixStream,
+    to_local: Vec<u8>,
+    to_remote: Vec<u8>,
+}
+
+impl Forwarded {
+    /// Moves the bytes available in both directions without blocking, and returns whether any
+    /// were moved.
+    fn pump(&mut self, buffer: &mut [u8]) -> std::io::Result<bool> {
+        let mut progress = false;
+        if !self.local_eof && self.to_remote.is_empty() {
+            match self.stream.read(buffer) {
+                Ok(0) => self.local_eof = true,
+                Ok(len) => self.to_remote.extend_from_slice(&buffer[..len]),
+                Err(error) if error.kind() == ErrorKind::WouldBlock => {}
+                Err(error) => return Err(error),
+            }
+            progress |= self.local_eof || !self.to_remote.is_empty();
+        }
+        progress |= flush(&mut self.to_remote, &mut self.channel)?;
+        if self.local_eof && !self.eof_sent && self.to_remote.is_empty() {
+            self.eof_sent = self.channel.send_eof().is_ok();
+        }
+        if self.to_local.is_empty() {
+            match self.channel.read(buffer) {
+                Ok(len) => self.to_local.extend_from_slice(&buffer[..len]),
+                Err(error) if error.kind() == ErrorKind::WouldBlock => {}
+                Err(error) => return Err(error),
+            }
+            progress |= !self.to_local.is_empty();
+        }
+        progress |= flush(&mut self.to_local, &mut self.stream)?;
+        Ok(progress)
+    }
+
+    /// Returns whether the remote end closed its output and all of it was forwarded.
+    fn is_done(&self) -> bool {
+        self.to_local.is_empty() && self.channel.eof()
+    }
+}
+
+/// Writes as many pending bytes as possible without blocking, and returns whether any were
+/// written.
+fn flush(pending: &mut Vec<u8>, writer: &mut impl Write) -> std::io::Result<bool> {
+    if pending.is_empty() {
+        return Ok(false);
+    }
+    match writer.write(pending) {
+        Ok(len) => {
+            pending.drain(..len);
+            Ok(len > 0)
+        }
+        Err(error) if error.kind() == ErrorKind::WouldBlock => Ok(false),
+        Err(error) => Err(error),
+    }
+}
+
+impl SshClient {
+    /// Attempts to forward the connections to a local Unix socket to a Unix socket on the
+    /// configured host (like `ssh -L local_socket:remote_socket`), until `stop` is set.
+    ///
+    /// The local socket is created when the forwarding starts, and removed when it stops. Each
+    /// connection to it is forwarded over its own channel (see
+    /// [`SshClient::direct_streamlocal`]), and a connection which the server rejects is closed
+    /// without stopping the forwarding. This is only supported by the libssh2 backend.
+    ///
+    /// Note that this method implicitly calls [`SshClient::connect`] if no session was
+    /// established prior. Otherwise, it reuses the cached session
+    /// (see [`SshClient::set_keepalive`] to check its health first).
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// use std::sync::atomic::AtomicBool;
+    ///
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// // Then, in another terminal: `docker -H unix:///tmp/docker.sock ps`
+    /// let stop = AtomicBool::new(false);
+    /// ssh.forward_unix("/tmp/docker.sock", "/var/run/docker.sock", &stop)
+    ///     .unwrap();
+    /// ```
+    pub fn forward_unix<P, Q>(
+        &mut self,
+        local_socket_path: P,
+        remote_socket_path: Q,
+        stop: &AtomicBool,
+    ) -> Result<()>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let local_socket_path = local_socket_path.as_ref();
+        self.connected_session()?;
+        let listener = UnixListener::bind(local_socket_path).map_err(|error| {
+            anyhow::anyhow!("Failed to bind {:?}: {}", local_socket_path, error)
+        })?;
+        listener.set_nonblocking(true)?;
+        let result = self.serve_unix(&listener, remote_socket_path.as_ref(), stop);
+        let _ = std::fs::remove_file(local_socket_path);
+        result
+    }
+
+    /// Accepts connections on a local socket and forwards them to a remote socket until `stop`
+    /// is set (see `SshClient::forward_unix`).
+    fn serve_unix(
+        &mut self,
+        listener: &UnixListener,
+        remote_socket_path: &Path,
+        stop: &AtomicBool,
+    ) -> Result<()> {
+        let mut connections: Vec<Forwarded> = Vec::new();
+        let mut buffer = [0; 32 * 1024];
+        while !stop.load(Ordering::Relaxed) {
+            // Channels are opened in blocking mode, while all connections are polled in
+            // non-blocking mode, since libssh2 serializes the calls on a session.
+            let mut progress = false;
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    progress = true;
+                    if let Ok(channel) = self.direct_streamlocal(remote_socket_path) {
+                        stream.set_nonblocking(true)?;
+                        connections.push(Forwarded {
+                            channel,
+                            eof_sent: false,
+                            local_eof: false,
+                            stream,
+                            to_local: Vec::new(),
+                            to_remote: Vec::new(),
+                        });
+                    }
+                }
+                Err(error) if error.kind() == ErrorKind::WouldBlock => {}
+                Err(error) => return Err(error.into()),
+            }
+            let session = self.connected_session()?.clone();
+            session.set_blocking(false);
+            let mut closed = Vec::new();
+            for (index, connection) in connections.iter_mut().enumerate() {
+                match connection.pump(&mut buffer) {
+                    Ok(moved) if !connection.is_done() => progress |= moved,
+                    _ => closed.push(index),
+                }
+            }
+            session.set_blocking(true);
+            // Closed channels are dropped in blocking mode, so that they're closed cleanly.
+            for index in closed.into_iter().rev() {
+                connections.remove(index);
+            }
+            if !progress {
+                std::thread::sleep(POLL_INTERVAL);
+            }
+        }
+        Ok(())
+    }
+}
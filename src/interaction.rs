@@ -0,0 +1,126 @@
+use crate::SshChannel;
+use anyhow::Result;
+use regex::bytes::Regex;
+use std::time::{Duration, Instant};
+
+/// Expect-style script driving a shell, which sends lines and waits for prompts
+/// (requires the `expect` feature).
+///
+/// It automates network devices and installers which prompt for input instead of running
+/// commands, over a channel returned by [`SshClient::shell`](crate::SshClient::shell). Prompts
+/// are regular expressions, matched against the output received since the previous prompt.
+/// Note that the output of a pseudo-terminal echoes the sent lines and ends its lines with
+/// `\r\n`.
+///
+/// ## Example
+/// ```no_run
+/// # use massh::SshClient;
+/// # use std::net::Ipv4Addr;
+/// use massh::SshInteraction;
+/// use std::time::Duration;
+///
+/// let mut ssh = SshClient::from("admin", (Ipv4Addr::LOCALHOST, 22));
+/// let shell = ssh.shell("vt100", (200, 24)).unwrap();
+///
+/// let mut switch = SshInteraction::new(shell);
+/// switch.set_timeout(Duration::from_secs(30));
+/// switch.expect(r"[>#]\s*$").unwrap();
+/// switch.run("enable", r"Password:\s*$").unwrap();
+/// switch.run("top-secret", r"#\s*$").unwrap();
+/// let version = switch.run("show version", r"#\s*$").unwrap();
+/// println!("{}", version);
+///
+/// switch.send_line("exit").unwrap();
+/// switch.close().unwrap();
+/// ```
+pub struct SshInteraction {
+    buffer: Vec<u8>,
+    channel: SshChannel,
+    timeout: Duration,
+}
+
+impl SshInteraction {
+    /// Constructs a new `SshInteraction` over a shell channel.
+    ///
+    /// By default, it waits for each prompt for 10 seconds at most.
+    pub fn new(channel: SshChannel) -> Self {
+        Self {
+            buffer: Vec::new(),
+            channel,
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Configures this `SshInteraction` to wait for each prompt for the specified duration at
+    /// most.
+    pub fn set_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Attempts to send bytes as they are to the shell (e.g. `b"\x03"` for Ctrl-C).
+    pub fn send(&mut self, bytes: &[u8]) -> Result<&mut Self> {
+        self.channel.write_stdin(bytes)?;
+        Ok(self)
+    }
+
+    /// Attempts to send a line to the shell, followed by a newline.
+    pub fn send_line(&mut self, line: &str) -> Result<&mut Self> {
+        self.channel.write_stdin(format!("{}\n", line).as_bytes())?;
+        Ok(self)
+    }
+
+    /// Attempts to wait for the output of the shell to match a regular expression
+    /// (e.g. `r"\$\s*$"`), and returns the output received before the match.
+    ///
+    /// The output up to the end of the match is consumed, so that the next prompt is matched
+    /// against the output which follows it. It fails if the prompt doesn't appear before the
+    /// timeout expires, or if the shell exits, with the unmatched output in the error message.
+    pub fn expect(&mut self, prompt: &str) -> Result<String> {
+        let regex = Regex::new(prompt)
+            .map_err(|error| anyhow::anyhow!("Invalid prompt {:?}: {}", prompt, error))?;
+        let start = Instant::now();
+        loop {
+            if let Some(found) = regex.find(&self.buffer) {
+                let output = String::from_utf8_lossy(&self.buffer[..found.start()]).into_owned();
+                self.buffer.drain(..found.end());
+                return Ok(output);
+            }
+            let remaining = match self.timeout.checked_sub(start.elapsed()) {
+                Some(remaining) => remaining,
+                None => return Err(self.error("Timed out", prompt)),
+            };
+            let output = self.channel.read_output(remaining)?;
+            if output.is_empty() && self.channel.eof() {
+                return Err(self.error("Shell exited", prompt));
+            }
+            self.buffer.extend_from_slice(&output);
+        }
+    }
+
+    /// Attempts to send a line to the shell, then to wait for a prompt, and returns the output
+    /// received in between (see [`SshInteraction::expect`]).
+    pub fn run(&mut self, line: &str, prompt: &str) -> Result<String> {
+        self.send_line(line)?.expect(prompt)
+    }
+
+    /// Attempts to close the shell channel (see [`SshChannel::close`]).
+    pub fn close(self) -> Result<i32> {
+        self.channel.close()
+    }
+
+    /// Returns the shell channel, e.g. to hand the shell over to the user.
+    pub fn into_channel(self) -> SshChannel {
+        self.channel
+    }
+
+    /// Returns the error of a prompt which didn't appear, with the unmatched output.
+    fn error(&self, reason: &str, prompt: &str) -> anyhow::Error {
+        anyhow::anyhow!(
+            "{} while waiting for {:?}, after output {:?}",
+            reason,
+            prompt,
+            String::from_utf8_lossy(&self.buffer)
+        )
+    }
+}
@@ -6,8 +6,8 @@
 //!
 //! If you want to try the CLI app, you can check it on [GitHub] and install it with Cargo:
 //!
-//! ```no_run
-//! cargo install massh
+//! ```text
+//! cargo install massh --features cli
 //! ```
 //!
 //! The rest of this documentation focuses on the library crate,
@@ -19,10 +19,59 @@
 //! [`pssh(1)`]: https://linux.die.net/man/1/pssh
 //! [GitHub]: https://github.com/felix-pb/massh
 
+mod backend;
+mod bench;
+mod channel;
+mod chunked;
 mod config;
+mod executor;
+mod facts;
+#[cfg(unix)]
+mod forward;
+#[cfg(feature = "expect")]
+mod interaction;
+mod line_printer;
 mod massh_client;
+mod observer;
+mod plan;
+mod remote_file;
+mod selector;
+mod server_info;
+pub mod shell;
 mod ssh_client;
+mod sync;
 
-pub use config::{MasshConfig, MasshHostConfig};
-pub use massh_client::{MasshClient, MasshHost, MasshReceiver};
-pub use ssh_client::{SshAuth, SshClient, SshOutput};
+pub use backend::SshBackend;
+pub use bench::SshLatency;
+pub use channel::SshChannel;
+pub use config::{
+    MasshConfig, MasshDiagnostic, MasshDiagnosticKind, MasshDuplicatePolicy, MasshGroupConfig,
+    MasshHostConfig, MasshSeverity,
+};
+#[cfg(feature = "test-util")]
+pub use executor::FakeExecutor;
+pub use executor::{
+    MasshRecord, MasshRecording, RecordingExecutor, RemoteExecutor, ReplayExecutor,
+};
+pub use facts::SshFacts;
+#[cfg(feature = "expect")]
+pub use interaction::SshInteraction;
+pub use line_printer::LinePrinter;
+#[cfg(feature = "crossbeam")]
+pub use massh_client::MasshCrossbeamReceiver;
+pub use massh_client::{
+    MasshBuildError, MasshClient, MasshDownloadLayout, MasshHost, MasshHostInfo, MasshReceiver,
+    MasshSkipped, MasshTimedOut,
+};
+pub use observer::MasshObserver;
+pub use plan::{MasshAction, MasshFailurePolicy, MasshPlan, MasshStep};
+pub use remote_file::SshFileInfo;
+pub use selector::MasshHostSelector;
+pub use server_info::SshServerInfo;
+pub use shell::SshShell;
+pub use ssh2::MethodType;
+pub use ssh_client::{
+    SshAuth, SshChunk, SshClient, SshOutput, SshOverflow, SshSignal, SshStream, SshTimestamp,
+    SshTransport,
+};
+pub use sync::{DirTransfer, SyncOptions, SyncReport};
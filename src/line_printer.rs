@@ -0,0 +1,99 @@
+use crate::{MasshHost, SshStream};
+use std::collections::HashMap;
+
+/// Sink which splits the output of several hosts into lines, and passes each complete line to
+/// a closure with its host and stream (e.g. to print it prefixed with its host).
+///
+/// The output is pushed in chunks of arbitrary boundaries, such as the chunks of an
+/// [`SshOutput`](crate::SshOutput) captured merged or the output read from an
+/// [`SshChannel`](crate::SshChannel), and the partial last line of each host and stream is
+/// buffered until it's completed by a later chunk. Lines are passed without their line ending
+/// (`\n` or `\r\n`), like the lines of [`MasshClient::execute_streaming`].
+///
+/// ## Example
+/// ```
+/// use massh::{LinePrinter, SshStream};
+///
+/// let mut lines = Vec::new();
+/// let mut printer = LinePrinter::new(|host, _, line| {
+///     lines.push(format!("[{}]: {}", host, String::from_utf8_lossy(line)));
+/// });
+///
+/// let (web, db) = ("root@10.0.0.1:22".to_string(), "root@10.0.1.1:22".to_string());
+/// printer.push(&web, SshStream::Stdout, b"up 3 d");
+/// printer.push(&db, SshStream::Stdout, b"up 9 days\r\n");
+/// printer.push(&web, SshStream::Stdout, b"ays\nload");
+/// printer.finish(&web);
+/// drop(printer);
+///
+/// assert_eq!(
+///     lines,
+///     [
+///         "[root@10.0.1.1:22]: up 9 days",
+///         "[root@10.0.0.1:22]: up 3 days",
+///         "[root@10.0.0.1:22]: load",
+///     ]
+/// );
+/// ```
+///
+/// [`MasshClient::execute_streaming`]: crate::MasshClient::execute_streaming
+pub struct LinePrinter<F> {
+    buffers: HashMap<(MasshHost, SshStream), Vec<u8>>,
+    f: F,
+}
+
+impl<F: FnMut(&MasshHost, SshStream, &[u8])> LinePrinter<F> {
+    /// Constructs a new `LinePrinter` which passes each line to the specified closure.
+    pub fn new(f: F) -> Self {
+        Self {
+            buffers: HashMap::new(),
+            f,
+        }
+    }
+
+    /// Pushes a chunk of the output of a host, and passes the lines it completes to the closure.
+    pub fn push(&mut self, host: &MasshHost, stream: SshStream, bytes: &[u8]) {
+        let key = (host.clone(), stream);
+        let mut buffer = self.buffers.remove(&key).unwrap_or_default();
+        let mut start = 0;
+        for (index, _) in bytes.iter().enumerate().filter(|(_, &byte)| byte == b'\n') {
+            let line = &bytes[start..index];
+            start = index + 1;
+            if buffer.is_empty() {
+                self.emit(host, stream, line);
+            } else {
+                buffer.extend_from_slice(line);
+                self.emit(host, stream, &buffer);
+                buffer.clear();
+            }
+        }
+        buffer.extend_from_slice(&bytes[start..]);
+        if !buffer.is_empty() {
+            self.buffers.insert(key, buffer);
+        }
+    }
+
+    /// Passes the partial last lines of a host to the closure, if any, e.g. once its command
+    /// exited.
+    pub fn finish(&mut self, host: &MasshHost) {
+        for stream in [SshStream::Stdout, SshStream::Stderr] {
+            if let Some(buffer) = self.buffers.remove(&(host.clone(), stream)) {
+                self.emit(host, stream, &buffer);
+            }
+        }
+    }
+
+    /// Passes the partial last lines of all hosts to the closure, if any.
+    pub fn finish_all(&mut self) {
+        let mut keys: Vec<_> = self.buffers.keys().cloned().collect();
+        keys.sort_by(|a, b| a.0.cmp(&b.0));
+        for (host, _) in keys {
+            self.finish(&host);
+        }
+    }
+
+    /// Passes a line to the closure, without its carriage return if any.
+    fn emit(&mut self, host: &MasshHost, stream: SshStream, line: &[u8]) {
+        (self.f)(host, stream, line.strip_suffix(b"\r").unwrap_or(line));
+    }
+}
@@ -1,29 +1,300 @@
-use crate::{MasshConfig, SshAuth, SshClient, SshOutput};
+use crate::backend;
+use crate::config::ResolvedHost;
+use crate::server_info::is_fingerprint;
+use crate::shell;
+use crate::ssh_client::RateLimiter;
+use crate::{
+    DirTransfer, MasshAction, MasshConfig, MasshDuplicatePolicy, MasshHostSelector, MasshObserver,
+    MasshRecording, MasshStep, MethodType, RecordingExecutor, RemoteExecutor, ReplayExecutor,
+    SshAuth, SshBackend, SshClient, SshFacts, SshFileInfo, SshLatency, SshOutput, SshServerInfo,
+    SshShell, SshStream, SshTimestamp, SyncOptions, SyncReport,
+};
 use anyhow::Result;
 use parking_lot::Mutex;
-use std::collections::HashMap;
-use std::path::PathBuf;
-use std::sync::mpsc::Receiver;
+use rand::Rng;
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{Receiver, Sender, SyncSender};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use threadpool::ThreadPool;
 
-/// Unique string identifier (`username@ip_address:port`) for a `MasshClient` target host.
+/// Unique string identifier (`username@ip_address:port`) for a `MasshClient` target host, or
+/// its alias if configured (see [`MasshHostConfig::alias`](crate::MasshHostConfig::alias)).
+///
+/// The structured information about a host, such as its IP address and port number, can be
+/// retrieved with [`MasshClient::get_host_info`] instead of parsing its identifier.
+///
+/// ## Example
+/// ```
+/// # use massh::{MasshClient, MasshConfig};
+/// let yaml = "hosts: [{addr: 10.4.7.23, user: deploy, alias: web-03}, root@10.0.0.1]";
+/// let massh = MasshClient::from(&MasshConfig::from_yaml(yaml).unwrap());
+///
+/// let hosts: Vec<_> = massh.iter().map(|(host, _)| host.clone()).collect();
+/// assert_eq!(hosts, ["web-03", "root@10.0.0.1:22"]);
+/// assert_eq!(massh.get_host_info("web-03").unwrap().user, "deploy");
+/// ```
 pub type MasshHost = String;
 
-/// Receiving half of a `std::sync::mpsc::channel` which receives exactly 1 message per host.
+/// Information about a host configured in a `MasshClient`.
+///
+/// It's formatted like its [`MasshHost`] identifier (its alias, or `username@ip_address:port`),
+/// and it's serialized as an object with the fields `host` (its identifier), `user`, `ip`,
+/// `port`, `hostname`, `group`, `labels` and `shell`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MasshHostInfo {
+    /// Socket address of the host.
+    pub addr: SocketAddr,
+    /// Alias of the host, if any.
+    pub alias: Option<String>,
+    /// Name of the group the host belongs to, if any.
+    pub group: Option<String>,
+    /// Hostname which the address of the host was resolved from, if it was configured by its
+    /// hostname.
+    pub hostname: Option<String>,
+    /// Labels of the host, including those inherited from its group.
+    pub labels: BTreeMap<String, String>,
+    /// Shell of the remote user on the host.
+    pub shell: SshShell,
+    /// Username used to authenticate with the host.
+    pub user: String,
+}
+
+impl MasshHostInfo {
+    /// Returns the IP address of the host.
+    pub fn ip(&self) -> IpAddr {
+        self.addr.ip()
+    }
+
+    /// Returns the port number of the host.
+    pub fn port(&self) -> u16 {
+        self.addr.port()
+    }
+
+    /// Returns a command with its `{label:KEY}` placeholders substituted with the labels of the
+    /// host, quoted for its shell, and its `{raw_label:KEY}` placeholders substituted with the
+    /// labels as is, i.e. the command run on the host by [`MasshClient::execute`].
+    ///
+    /// ## Example
+    /// ```
+    /// # use massh::{MasshClient, MasshConfig};
+    /// let yaml = "hosts: [{addr: 10.0.0.1, user: root, labels: {env: prod, args: -v $HOME}}]";
+    /// let massh = MasshClient::from(&MasshConfig::from_yaml(yaml).unwrap());
+    /// let info = massh.get_host_info("root@10.0.0.1:22").unwrap();
+    ///
+    /// assert_eq!(info.render_command("deploy {label:env}").unwrap(), "deploy 'prod'");
+    /// assert_eq!(info.render_command("ls {label:args}").unwrap(), "ls '-v $HOME'");
+    /// assert_eq!(info.render_command("ls {raw_label:args}").unwrap(), "ls -v $HOME");
+    /// assert!(info.render_command("deploy {label:dc}").is_err());
+    /// ```
+    pub fn render_command(&self, command: &str) -> Result<String> {
+        render_template(command, &self.labels, Some(self.shell))
+    }
+
+    /// Returns a path with its `{host}`, `{user}`, `{ip}`, `{port}` and `{label:KEY}`
+    /// placeholders substituted with the values of the host, i.e. the path used on the host by
+    /// [`MasshClient::scp_upload`]. Non-UTF-8 paths are returned unchanged.
+    ///
+    /// ## Example
+    /// ```
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # use std::path::Path;
+    /// let yaml = "hosts: [{addr: 10.0.0.1, user: root, labels: {env: prod}}]";
+    /// let massh = MasshClient::from(&MasshConfig::from_yaml(yaml).unwrap());
+    /// let info = massh.get_host_info("root@10.0.0.1:22").unwrap();
+    ///
+    /// let path = info.render_path(Path::new("/etc/app/{label:env}/{ip}.conf")).unwrap();
+    /// assert_eq!(path, Path::new("/etc/app/prod/10.0.0.1.conf"));
+    /// ```
+    pub fn render_path(&self, path: &Path) -> Result<PathBuf> {
+        render_path(path, self)
+    }
+}
+
+impl std::fmt::Display for MasshHostInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.alias {
+            Some(alias) => write!(f, "{}", alias),
+            None => write!(f, "{}@{}", self.user, self.addr),
+        }
+    }
+}
+
+impl Serialize for MasshHostInfo {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("MasshHostInfo", 8)?;
+        state.serialize_field("host", &self.to_string())?;
+        state.serialize_field("user", &self.user)?;
+        state.serialize_field("ip", &self.ip())?;
+        state.serialize_field("port", &self.port())?;
+        state.serialize_field("hostname", &self.hostname)?;
+        state.serialize_field("group", &self.group)?;
+        state.serialize_field("labels", &self.labels)?;
+        state.serialize_field("shell", &self.shell)?;
+        state.end()
+    }
+}
+
+/// Receiving half of a `std::sync::mpsc::channel` (or `sync_channel` if the configured
+/// channel capacity is non-zero) which receives exactly 1 message per host.
 pub type MasshReceiver<T> = Receiver<(MasshHost, Result<T>)>;
 
+/// Receiving half of a `crossbeam_channel::unbounded` channel (or `bounded` if the configured
+/// channel capacity is non-zero) which receives exactly 1 message per host.
+///
+/// Unlike a [`MasshReceiver`], it can be selected over with `crossbeam_channel::select!`
+/// (requires the `crossbeam` feature).
+#[cfg(feature = "crossbeam")]
+pub type MasshCrossbeamReceiver<T> = crossbeam_channel::Receiver<(MasshHost, Result<T>)>;
+
+/// Error of an operation that was skipped on a host because the mass operation was aborted
+/// before it started on that host (e.g. because too many hosts had already failed, or because
+/// the stop flag of [`MasshClient::set_stop`] was set).
+///
+/// It can be identified with `error.downcast_ref::<MasshSkipped>()`.
+#[derive(Debug)]
+pub struct MasshSkipped;
+
+impl std::fmt::Display for MasshSkipped {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Skipped because the operation was aborted")
+    }
+}
+
+impl std::error::Error for MasshSkipped {}
+
+/// Error of an operation whose result wasn't received from a host before the deadline of
+/// [`MasshClient::recv_all`].
+///
+/// It can be identified with `error.downcast_ref::<MasshTimedOut>()`.
+#[derive(Debug)]
+pub struct MasshTimedOut;
+
+impl std::fmt::Display for MasshTimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Timed out waiting for the result")
+    }
+}
+
+impl std::error::Error for MasshTimedOut {}
+
+/// Error of [`MasshClient::try_from`], with the reason why each invalid host can't be configured.
+///
+/// It can be identified with `error.downcast_ref::<MasshBuildError>()`.
+#[derive(Debug)]
+pub struct MasshBuildError {
+    /// Invalid hosts, in configuration order, with the reason why each one is invalid.
+    pub errors: Vec<(MasshHost, anyhow::Error)>,
+}
+
+impl std::fmt::Display for MasshBuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} invalid host(s)", self.errors.len())?;
+        for (host, error) in &self.errors {
+            write!(f, "\n{}: {}", host, error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for MasshBuildError {}
+
+/// Layout of the local files downloaded by [`MasshClient::scp_download`].
+///
+/// The default is [`MasshDownloadLayout::Flat`].
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MasshDownloadLayout {
+    /// Each host's file is named `username@ip_address:port` directly in the local directory.
+    #[default]
+    Flat,
+    /// Each host's file keeps the remote filename in a subdirectory named `username@ip_address:port`.
+    Subdirectory,
+    /// Each host's file is named after a template in the local directory, which can contain the
+    /// placeholders `{name}` (the remote filename), `{host}`, `{user}`, `{ip}`, `{port}` and
+    /// `{label:KEY}` (e.g. `{name}.{ip}`).
+    Template(String),
+}
+
 /// SSH client to run commands on multiple hosts in parallel.
 ///
 /// ## Public API Overview
 ///
 /// Construct a new `MasshClient`:
 /// - [`MasshClient::from`]
+/// - [`MasshClient::try_from`]
+/// - [`MasshClient::from_executors`]
+/// - [`MasshClient::record`]
+/// - [`MasshClient::replay`]
+/// - [`MasshClient::filter`]
+/// - [`MasshClient::only_hosts`]
+/// - [`MasshClient::select`]
+///
+/// Configure this `MasshClient`:
+/// - [`MasshClient::set_aggregate_rate_limit`]
+/// - [`MasshClient::set_download_layout`]
+/// - [`MasshClient::set_jitter`]
+/// - [`MasshClient::set_observer`]
+/// - [`MasshClient::set_output_dir`]
+/// - [`MasshClient::set_rate_limit`]
+/// - [`MasshClient::set_stagger`]
+/// - [`MasshClient::set_stop`]
+///
+/// Inspect this `MasshClient`:
+/// - [`MasshClient::get_aggregate_rate_limit`]
+/// - [`MasshClient::get_download_layout`]
+/// - [`MasshClient::get_jitter`]
+/// - [`MasshClient::get_host_info`]
+/// - [`MasshClient::download_destination`]
+/// - [`MasshClient::get_recording`]
+/// - [`MasshClient::get_labels`]
+/// - [`MasshClient::get_output_dir`]
+/// - [`MasshClient::is_empty`]
+/// - [`MasshClient::iter`]
+/// - [`MasshClient::len`]
+/// - [`MasshClient::get_stagger`]
 ///
 /// Run commands with this `MasshClient`:
+/// - [`MasshClient::bench`]
+/// - [`MasshClient::copy_id`]
 /// - [`MasshClient::execute`]
+/// - `MasshClient::execute_crossbeam` (requires the `crossbeam` feature)
+/// - [`MasshClient::execute_with_callback`]
+/// - [`MasshClient::execute_streaming`]
+/// - [`MasshClient::execute_streaming_timestamped`]
+/// - [`MasshClient::execute_with_env`]
+/// - [`MasshClient::gather_facts`]
+/// - [`MasshClient::remote_stat`]
+/// - [`MasshClient::server_info`]
+/// - [`MasshClient::tail`]
 /// - [`MasshClient::scp_download`]
 /// - [`MasshClient::scp_upload`]
+/// - [`MasshClient::download_dir`]
+/// - [`MasshClient::download_glob`]
+/// - [`MasshClient::upload_dir`]
+/// - [`MasshClient::sync_dir`]
+/// - [`MasshClient::run_step`]
+/// - [`MasshClient::for_each_host`]
+/// - `MasshClient::for_each_host_crossbeam` (requires the `crossbeam` feature)
+/// - [`MasshClient::for_each_host_with_callback`]
+/// - [`MasshClient::with_client`]
+///
+/// Collect the results of an operation in configuration order:
+/// - [`MasshClient::collect_ordered`]
+/// - [`MasshClient::recv_all`]
+///
+/// There are also methods to manage the internal authenticated sessions of this `MasshClient`:
+/// - [`MasshClient::connect_all`]
+/// - [`MasshClient::disconnect_all`]
+///
+/// However, it's typically not necessary to call them because sessions are established lazily
+/// when needed. Pre-warming them with [`MasshClient::connect_all`] is useful to measure only
+/// the time of subsequent commands.
 ///
 /// ## Example
 ///
@@ -43,13 +314,24 @@ pub type MasshReceiver<T> = Receiver<(MasshHost, Result<T>)>;
 ///     let output = result.unwrap();
 ///     println!("host: {}", host);
 ///     println!("status: {}", output.exit_status);
-///     println!("stdout: {}", String::from_utf8(output.stdout).unwrap());
-///     println!("stderr: {}", String::from_utf8(output.stderr).unwrap());
+///     println!("stdout: {}", output.stdout_lossy());
+///     println!("stderr: {}", output.stderr_lossy());
 /// }
 /// ```
-pub struct MasshClient {
-    clients: HashMap<MasshHost, Arc<Mutex<SshClient>>>,
+pub struct MasshClient<E = SshClient> {
+    aggregate_rate_limit: u64,
+    channel_capacity: usize,
+    clients: HashMap<MasshHost, Arc<Mutex<E>>>,
+    download_layout: MasshDownloadLayout,
+    hosts: Vec<MasshHost>,
+    info: HashMap<MasshHost, MasshHostInfo>,
+    jitter: u64,
+    max_failures: usize,
+    observer: Option<Arc<dyn MasshObserver>>,
+    output_dir: Option<PathBuf>,
     pool: Option<ThreadPool>,
+    stagger: u64,
+    stop: Option<Arc<AtomicBool>>,
 }
 
 impl MasshClient {
@@ -66,183 +348,2049 @@ impl MasshClient {
     /// let massh = MasshClient::from(&config);
     /// ```
     pub fn from(config: &MasshConfig) -> Self {
-        // Configure the internal SSH clients.
-        let mut clients = HashMap::new();
-        config.hosts.iter().for_each(|host| {
-            let addr = host.addr;
-            let auth = match &host.auth {
-                Some(auth) => auth,
-                None => &config.default_auth,
-            };
-            let port = match host.port {
-                Some(port) => port,
-                None => config.default_port,
-            };
-            let user = match &host.user {
-                Some(user) => user,
-                None => &config.default_user,
-            };
-
-            let mut ssh = SshClient::from(user, (addr, port));
-            match auth {
-                SshAuth::Agent => ssh.set_auth_agent(),
-                SshAuth::Password(password) => ssh.set_auth_password(password),
-                SshAuth::Pubkey(path) => ssh.set_auth_pubkey(path),
-            };
-            ssh.set_timeout(config.timeout);
+        let (massh, _, _) = Self::build_ssh(config, |ssh, _| ssh);
+        massh
+    }
 
-            let host = format!("{}@{}", ssh.get_user(), ssh.get_addr());
-            clients.insert(host, Arc::new(Mutex::new(ssh)));
-        });
+    /// Attempts to construct a new `MasshClient` from the specified configuration, checking
+    /// that each host can be configured.
+    ///
+    /// Unlike [`MasshClient::from`], it fails with a [`MasshBuildError`] listing every invalid
+    /// host (e.g. with a port number of zero, a private key file that doesn't exist, an invalid
+    /// host key fingerprint, settings unsupported by the configured backend, or a duplicate if
+    /// duplicates are errors). Otherwise, it returns the `MasshClient` along with the hosts
+    /// defined more than once, whose definitions were all discarded except one (see
+    /// [`MasshDuplicatePolicy`]).
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use massh::{MasshClient, MasshConfig};
+    ///
+    /// let yaml = std::fs::read_to_string("massh.yaml").unwrap();
+    /// let config = MasshConfig::from_yaml(&yaml).unwrap();
+    /// let (massh, duplicates) = MasshClient::try_from(&config).unwrap();
+    /// for host in duplicates {
+    ///     println!("{} is defined more than once", host);
+    /// }
+    /// ```
+    pub fn try_from(config: &MasshConfig) -> Result<(Self, Vec<MasshHost>)> {
+        let (massh, errors, duplicates) = Self::build_ssh(config, |ssh, _| ssh);
+        if errors.is_empty() {
+            Ok((massh, duplicates))
+        } else {
+            Err(MasshBuildError { errors }.into())
+        }
+    }
 
-        // Configure the internal thread pool if specified.
-        let pool = if config.threads == 0 {
+    /// Configures this `MasshClient` to limit the total throughput of file transfers
+    /// across all hosts, in bytes per second.
+    ///
+    /// A limit of zero signifies no limit. This is the default.
+    /// Note that it blocks until all ongoing operations complete.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let mut massh = MasshClient::from(&config);
+    ///
+    /// // Don't saturate a 1 Gbit/s uplink.
+    /// massh.set_aggregate_rate_limit(100_000_000);
+    /// ```
+    pub fn set_aggregate_rate_limit(&mut self, bytes_per_sec: u64) -> &mut Self {
+        self.aggregate_rate_limit = bytes_per_sec;
+        let limiter = if bytes_per_sec == 0 {
             None
         } else {
-            Some(ThreadPool::new(config.threads as usize))
+            Some(Arc::new(RateLimiter::new(bytes_per_sec)))
         };
+        self.clients.values().for_each(|client| {
+            client.lock().set_shared_rate_limiter(limiter.clone());
+        });
+        self
+    }
 
-        MasshClient { clients, pool }
+    /// Configures this `MasshClient` to limit the throughput of file transfers of each host,
+    /// in bytes per second.
+    ///
+    /// A limit of zero signifies no limit. This is the default.
+    /// Note that it blocks until all ongoing operations complete.
+    /// See [`SshClient::set_rate_limit`] for more details.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let mut massh = MasshClient::from(&config);
+    ///
+    /// massh.set_rate_limit(10_000_000);
+    /// ```
+    pub fn set_rate_limit(&mut self, bytes_per_sec: u64) -> &mut Self {
+        self.clients.values().for_each(|client| {
+            client.lock().set_rate_limit(bytes_per_sec);
+        });
+        self
     }
 
-    /// Attempts to execute a command on all configured hosts.
+    /// Configures this `MasshClient` to report the progress of its operations on each host
+    /// to an observer, e.g. to export metrics.
     ///
-    /// It returns a [`MasshReceiver`] which receives exactly 1 message per host.
-    /// Each message contains the result of the operation.
+    /// See [`MasshObserver`] for more details. A value of `None` signifies that nothing is
+    /// reported. This is the default. Note that it blocks until all ongoing operations complete.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig, MasshHost, MasshObserver};
+    /// # use std::sync::Arc;
+    /// # use std::time::Duration;
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// struct Logger;
+    ///
+    /// impl MasshObserver for Logger {
+    ///     fn on_command_done(&self, host: &MasshHost, duration: Duration, exit_status: i32) {
+    ///         println!("{}: exited with {} after {:?}", host, exit_status, duration);
+    ///     }
+    /// }
+    ///
+    /// let mut massh = MasshClient::from(&config);
+    ///
+    /// massh.set_observer(Some(Arc::new(Logger)));
+    /// ```
+    pub fn set_observer(&mut self, observer: Option<Arc<dyn MasshObserver>>) -> &mut Self {
+        self.clients.iter().for_each(|(host, client)| {
+            client.lock().set_observer(host.clone(), observer.clone());
+        });
+        self.observer = observer;
+        self
+    }
+
+    /// Returns the limit on the total throughput of file transfers across all hosts,
+    /// in bytes per second.
+    ///
+    /// A limit of zero signifies no limit.
+    pub fn get_aggregate_rate_limit(&self) -> u64 {
+        self.aggregate_rate_limit
+    }
+
+    /// Attempts to authorize a public key on all configured hosts, by appending it to
+    /// `~/.ssh/authorized_keys` for the configured user of each host.
+    ///
+    /// It returns a [`MasshReceiver`] which receives exactly 1 message per host. Each message
+    /// contains the result of the operation: `false` if the key was already authorized.
+    /// See [`SshClient::copy_id`] for more details.
     ///
     /// ## Example
     /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
     /// let massh = MasshClient::from(&config);
     ///
-    /// let rx = massh.execute("echo $PATH");
+    /// let public_key = std::fs::read_to_string("/home/username/.ssh/id_ed25519.pub").unwrap();
+    /// let rx = massh.copy_id(public_key);
     ///
     /// while let Ok((host, result)) = rx.recv() {
-    ///     println!("Command succeeded on {}? {}", host, result.is_ok());
+    ///     println!("Key authorized on {}? {}", host, result.is_ok());
     /// }
     /// ```
-    pub fn execute(&self, command: impl Into<String>) -> MasshReceiver<SshOutput> {
-        let command = command.into();
-
-        // Create a multi-producer, single-consumer channel.
-        let (tx, rx) = std::sync::mpsc::channel();
+    pub fn copy_id(&self, public_key: impl Into<String>) -> MasshReceiver<bool> {
+        let public_key = public_key.into();
+        self.for_each_host(move |_, client| client.copy_id(&public_key))
+    }
 
-        // For each configured host...
-        self.clients.iter().for_each(|(host, client)| {
-            // Prepare a task closure responsible for sending the result of the operation.
-            let (client, host, tx) = (client.clone(), host.clone(), tx.clone());
-            let command = command.clone();
-            let task_closure = move || {
-                let mut client = client.lock();
-                let result = client.execute(&command);
-                let _ = tx.send((host, result));
-            };
+    /// Attempts to execute a command on all configured hosts, streaming its output line by line.
+    ///
+    /// The closure is called with the host, stream and content of each line as soon as it's
+    /// received, concurrently from the threads of all hosts. It returns a [`MasshReceiver`]
+    /// which receives exactly 1 message per host, containing the exit status of the command,
+    /// once it exits or once `stop` is set. See [`SshClient::execute_streaming`] for more details.
+    ///
+    /// Once `stop` is set, the command doesn't start on the remaining hosts, whose result is a
+    /// [`MasshSkipped`] error instead.
+    ///
+    /// Note that a long-running command only starts on as many hosts as there are threads
+    /// in the configured thread pool, so it's typically used without a thread pool.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// use std::sync::atomic::AtomicBool;
+    /// use std::sync::Arc;
+    ///
+    /// let massh = MasshClient::from(&config);
+    ///
+    /// let stop = Arc::new(AtomicBool::new(false));
+    /// let rx = massh.execute_streaming("ping -c 3 localhost", stop, |host, _, line| {
+    ///     println!("[{}]: {}", host, String::from_utf8_lossy(line));
+    /// });
+    ///
+    /// while let Ok((host, result)) = rx.recv() {
+    ///     println!("Command exited on {}: {:?}", host, result);
+    /// }
+    /// ```
+    pub fn execute_streaming<F>(
+        &self,
+        command: impl Into<String>,
+        stop: Arc<AtomicBool>,
+        f: F,
+    ) -> MasshReceiver<i32>
+    where
+        F: Fn(&MasshHost, SshStream, &[u8]) + Send + Sync + 'static,
+    {
+        self.execute_streaming_timestamped(command, stop, move |host, stream, line, _| {
+            f(host, stream, line)
+        })
+    }
 
-            // Execute the task closure in the thread pool or spawn it in its own thread.
-            if let Some(pool) = &self.pool {
-                pool.execute(task_closure)
-            } else {
-                std::thread::spawn(task_closure);
+    /// Attempts to execute a command on all configured hosts, streaming its output line by line
+    /// with the time at which each line was received.
+    ///
+    /// It's like [`MasshClient::execute_streaming`], except that the closure is also called with
+    /// the [`SshTimestamp`] of each line. See [`SshClient::execute_streaming_timestamped`] for
+    /// more details.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// use std::sync::atomic::AtomicBool;
+    /// use std::sync::Arc;
+    ///
+    /// let massh = MasshClient::from(&config);
+    ///
+    /// let stop = Arc::new(AtomicBool::new(false));
+    /// let rx = massh.execute_streaming_timestamped("./deploy.sh", stop, |host, _, line, time| {
+    ///     let line = String::from_utf8_lossy(line);
+    ///     println!("[{}] [{:?}]: {}", host, time.elapsed, line);
+    /// });
+    /// ```
+    pub fn execute_streaming_timestamped<F>(
+        &self,
+        command: impl Into<String>,
+        stop: Arc<AtomicBool>,
+        f: F,
+    ) -> MasshReceiver<i32>
+    where
+        F: Fn(&MasshHost, SshStream, &[u8], SshTimestamp) + Send + Sync + 'static,
+    {
+        let (command, info) = (command.into(), self.info.clone());
+        self.for_each_host(move |host, client| {
+            if stop.load(Ordering::SeqCst) {
+                return Err(MasshSkipped.into());
             }
-        });
-
-        // Return the receiving half of the channel.
-        rx
+            let info = &info[host];
+            let command = render_template(&command, &info.labels, Some(info.shell))?;
+            client.execute_streaming_timestamped(&command, &stop, |stream, line, timestamp| {
+                f(host, stream, line, timestamp)
+            })
+        })
     }
 
-    /// Attempts to download a file from all configured hosts.
+    /// Attempts to gather facts about all configured hosts.
     ///
     /// It returns a [`MasshReceiver`] which receives exactly 1 message per host.
     /// Each message contains the result of the operation.
+    /// See [`SshClient::gather_facts`] for more details.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let massh = MasshClient::from(&config);
+    ///
+    /// let rx = massh.gather_facts();
+    ///
+    /// while let Ok((host, result)) = rx.recv() {
+    ///     if let Ok(facts) = result {
+    ///         println!("{} runs {}", host, facts.os);
+    ///     }
+    /// }
+    /// ```
+    pub fn gather_facts(&self) -> MasshReceiver<SshFacts> {
+        self.for_each_host(|_, client| client.gather_facts())
+    }
+
+    /// Attempts to measure the latency of all configured hosts over a number of iterations.
     ///
-    /// Note that the downloaded filenames are of the form `username@ip_address:port`.
+    /// It returns a [`MasshReceiver`] which receives exactly 1 message per host.
+    /// Each message contains the result of the operation, i.e. the latency of each iteration
+    /// or the first error. See [`SshClient::bench`] for more details.
     ///
     /// ## Example
     /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
     /// let massh = MasshClient::from(&config);
     ///
-    /// let rx = massh.scp_download("remote.txt", "local_dir");
+    /// let rx = massh.bench(5);
     ///
     /// while let Ok((host, result)) = rx.recv() {
-    ///     println!("Download succeeded on {}? {}", host, result.is_ok());
+    ///     if let Ok(latencies) = result {
+    ///         let slowest = latencies.iter().map(|latency| latency.total()).max();
+    ///         println!("{}: {:?}", host, slowest);
+    ///     }
     /// }
     /// ```
-    pub fn scp_download<P>(&self, remote_path: P, local_path: P) -> MasshReceiver<()>
-    where
-        P: Into<PathBuf>,
-    {
-        let (remote_path, local_path) = (remote_path.into(), local_path.into());
+    pub fn bench(&self, iterations: usize) -> MasshReceiver<Vec<SshLatency>> {
+        self.for_each_host(move |_, client| (0..iterations).map(|_| client.bench()).collect())
+    }
 
-        // Create a multi-producer, single-consumer channel.
-        let (tx, rx) = std::sync::mpsc::channel();
+    /// Attempts to get the metadata of a remote file on all configured hosts.
+    ///
+    /// It returns a [`MasshReceiver`] which receives exactly 1 message per host.
+    /// Each message contains the result of the operation, i.e. `None` if the file doesn't exist.
+    /// See [`SshClient::remote_stat`] for more details.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let massh = MasshClient::from(&config);
+    ///
+    /// let rx = massh.remote_stat("/etc/app.conf");
+    ///
+    /// while let Ok((host, result)) = rx.recv() {
+    ///     if let Ok(None) = result {
+    ///         println!("{} is missing /etc/app.conf", host);
+    ///     }
+    /// }
+    /// ```
+    pub fn remote_stat<P: Into<PathBuf>>(
+        &self,
+        remote_path: P,
+    ) -> MasshReceiver<Option<SshFileInfo>> {
+        let remote_path = remote_path.into();
+        self.for_each_host(move |_, client| client.remote_stat(&remote_path))
+    }
 
-        // For each configured host...
-        self.clients.iter().for_each(|(host, client)| {
-            // Prepare a task closure responsible for sending the result of the operation.
-            let (client, host, tx) = (client.clone(), host.clone(), tx.clone());
-            let (remote_path, mut local_path) = (remote_path.clone(), local_path.clone());
-            local_path.push(&host);
-            let task_closure = move || {
-                let mut client = client.lock();
-                let result = client.scp_download(remote_path, local_path);
-                let _ = tx.send((host, result));
-            };
+    /// Attempts to inspect the SSH servers of all configured hosts.
+    ///
+    /// It returns a [`MasshReceiver`] which receives exactly 1 message per host.
+    /// Each message contains the result of the operation.
+    /// See [`SshClient::server_info`] for more details.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let massh = MasshClient::from(&config);
+    ///
+    /// let rx = massh.server_info();
+    ///
+    /// while let Ok((host, result)) = rx.recv() {
+    ///     if let Ok(info) = result {
+    ///         println!("{} has host key {}", host, info.fingerprint_sha256);
+    ///     }
+    /// }
+    /// ```
+    pub fn server_info(&self) -> MasshReceiver<SshServerInfo> {
+        self.for_each_host(|_, client| client.server_info())
+    }
 
-            // Execute the task closure in the thread pool or spawn it in its own thread.
-            if let Some(pool) = &self.pool {
-                pool.execute(task_closure)
-            } else {
-                std::thread::spawn(task_closure);
+    /// Attempts to stream the last lines of a remote file on all configured hosts, then to
+    /// follow the lines appended to it (like `tail -F`) until `stop` is set if `follow` is true.
+    ///
+    /// The closure is called with the host and content of each line. See
+    /// [`MasshClient::execute_streaming`] for more details.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// use std::sync::atomic::AtomicBool;
+    /// use std::sync::Arc;
+    ///
+    /// let massh = MasshClient::from(&config);
+    ///
+    /// let stop = Arc::new(AtomicBool::new(false));
+    /// let rx = massh.tail("/var/log/syslog", 10, true, stop, |host, line| {
+    ///     println!("[{}]: {}", host, String::from_utf8_lossy(line));
+    /// });
+    /// ```
+    pub fn tail<F>(
+        &self,
+        path: &str,
+        lines: u64,
+        follow: bool,
+        stop: Arc<AtomicBool>,
+        f: F,
+    ) -> MasshReceiver<i32>
+    where
+        F: Fn(&MasshHost, &[u8]) + Send + Sync + 'static,
+    {
+        let follow = if follow { " -F" } else { "" };
+        let command = format!("tail -n {}{} -- {}", lines, follow, shell::quote(path));
+        self.execute_streaming(command, stop, move |host, stream, line| {
+            if stream == SshStream::Stdout {
+                f(host, line)
             }
-        });
-
-        // Return the receiving half of the channel.
-        rx
+        })
     }
 
-    /// Attempts to upload a file to all configured hosts.
+    /// Attempts to download a directory recursively from all configured hosts.
     ///
-    /// It returns a [`MasshReceiver`] which receives exactly 1 message per host.
+    /// The directory of each host is downloaded into a subdirectory of `local_path` named after
+    /// the host. It returns a [`MasshReceiver`] which receives exactly 1 message per host.
     /// Each message contains the result of the operation.
+    /// See [`SshClient::download_dir`] for more details.
     ///
     /// ## Example
     /// ```no_run
+    /// # use massh::{DirTransfer, MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
     /// let massh = MasshClient::from(&config);
     ///
-    /// let rx = massh.scp_upload("local.txt", "remote.txt");
+    /// let rx = massh.download_dir("remote_dir", "local_dir", DirTransfer::Tar);
     ///
     /// while let Ok((host, result)) = rx.recv() {
-    ///     println!("Upload succeeded on {}? {}", host, result.is_ok());
+    ///     println!("Download succeeded on {}? {}", host, result.is_ok());
     /// }
     /// ```
-    pub fn scp_upload<P>(&self, local_path: P, remote_path: P) -> MasshReceiver<()>
+    pub fn download_dir<P>(
+        &self,
+        remote_path: P,
+        local_path: P,
+        transfer: DirTransfer,
+    ) -> MasshReceiver<()>
     where
         P: Into<PathBuf>,
     {
-        let (local_path, remote_path) = (local_path.into(), remote_path.into());
-
-        // Create a multi-producer, single-consumer channel.
-        let (tx, rx) = std::sync::mpsc::channel();
-
-        // For each configured host...
-        self.clients.iter().for_each(|(host, client)| {
-            // Prepare a task closure responsible for sending the result of the operation.
-            let (client, host, tx) = (client.clone(), host.clone(), tx.clone());
-            let (local_path, remote_path) = (local_path.clone(), remote_path.clone());
-            let task_closure = move || {
-                let mut client = client.lock();
-                let result = client.scp_upload(local_path, remote_path);
-                let _ = tx.send((host, result));
-            };
+        let (remote_path, local_path) = (remote_path.into(), local_path.into());
+        self.for_each_host(move |host, client| {
+            client.download_dir(remote_path.clone(), local_path.join(host), transfer)
+        })
+    }
 
-            // Execute the task closure in the thread pool or spawn it in its own thread.
-            if let Some(pool) = &self.pool {
-                pool.execute(task_closure)
-            } else {
-                std::thread::spawn(task_closure);
+    /// Attempts to download all remote files matching a glob pattern from all configured hosts.
+    ///
+    /// The files of each host are downloaded into a subdirectory of `local_path` named after
+    /// the host. It returns a [`MasshReceiver`] which receives exactly 1 message per host.
+    /// Each message contains the result of the operation, i.e. the local paths of the files.
+    /// See [`SshClient::download_glob`] for more details.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let massh = MasshClient::from(&config);
+    ///
+    /// let rx = massh.download_glob("/var/log/app/*.log", "logs");
+    ///
+    /// while let Ok((host, result)) = rx.recv() {
+    ///     if let Ok(paths) = result {
+    ///         println!("Downloaded {} files from {}", paths.len(), host);
+    ///     }
+    /// }
+    /// ```
+    pub fn download_glob<P>(&self, pattern: &str, local_path: P) -> MasshReceiver<Vec<PathBuf>>
+    where
+        P: Into<PathBuf>,
+    {
+        let (pattern, local_path) = (pattern.to_string(), local_path.into());
+        self.for_each_host(move |host, client| {
+            client.download_glob(&pattern, local_path.join(host))
+        })
+    }
+
+    /// Attempts to upload a directory recursively to all configured hosts.
+    ///
+    /// It returns a [`MasshReceiver`] which receives exactly 1 message per host.
+    /// Each message contains the result of the operation.
+    /// See [`SshClient::upload_dir`] for more details.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{DirTransfer, MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let massh = MasshClient::from(&config);
+    ///
+    /// let rx = massh.upload_dir("local_dir", "remote_dir", DirTransfer::Tar);
+    ///
+    /// while let Ok((host, result)) = rx.recv() {
+    ///     println!("Upload succeeded on {}? {}", host, result.is_ok());
+    /// }
+    /// ```
+    pub fn upload_dir<P>(
+        &self,
+        local_path: P,
+        remote_path: P,
+        transfer: DirTransfer,
+    ) -> MasshReceiver<()>
+    where
+        P: Into<PathBuf>,
+    {
+        let (local_path, remote_path) = (local_path.into(), remote_path.into());
+        self.for_each_host(move |_, client| client.upload_dir(&local_path, &remote_path, transfer))
+    }
+
+    /// Attempts to synchronize a remote directory with a local directory on all configured hosts.
+    ///
+    /// It returns a [`MasshReceiver`] which receives exactly 1 message per host.
+    /// Each message contains the result of the operation.
+    /// See [`SshClient::sync_dir`] for more details.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig, SyncOptions};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let massh = MasshClient::from(&config);
+    ///
+    /// let rx = massh.sync_dir("local_dir", "remote_dir", SyncOptions::default());
+    ///
+    /// while let Ok((host, result)) = rx.recv() {
+    ///     if let Ok(report) = result {
+    ///         println!("Uploaded {} files on {}", report.uploaded.len(), host);
+    ///     }
+    /// }
+    /// ```
+    pub fn sync_dir<P>(
+        &self,
+        local_path: P,
+        remote_path: P,
+        options: SyncOptions,
+    ) -> MasshReceiver<SyncReport>
+    where
+        P: Into<PathBuf>,
+    {
+        let (local_path, remote_path) = (local_path.into(), remote_path.into());
+        self.for_each_host(move |_, client| client.sync_dir(&local_path, &remote_path, &options))
+    }
+}
+
+impl<E: RemoteExecutor> MasshClient<E> {
+    /// Constructs a new `MasshClient` from the specified configuration, with the executors
+    /// returned by a closure for each host instead of SSH clients.
+    ///
+    /// Only the hosts and the settings of the configuration which aren't specific to SSH
+    /// (e.g. `threads`, `max_failures` and `download_layout`) are used. This is mostly useful to
+    /// unit-test code built on a `MasshClient` with a `FakeExecutor` (requires the `test-util`
+    /// feature), which runs nothing on the network. See [`RemoteExecutor`] for more details.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use massh::{MasshClient, MasshConfig, SshClient};
+    ///
+    /// let config = MasshConfig::from_yaml_file("massh.yaml").unwrap();
+    /// let massh = MasshClient::from_executors(&config, |info| {
+    ///     let mut ssh = SshClient::from(&info.user, info.addr);
+    ///     ssh.set_auth_agent();
+    ///     ssh
+    /// });
+    /// ```
+    pub fn from_executors<M>(config: &MasshConfig, mut make: M) -> Self
+    where
+        M: FnMut(&MasshHostInfo) -> E,
+    {
+        let (massh, _, _) = Self::build_with(config, |_, info| (make(info), Ok(())));
+        massh
+    }
+
+    /// Constructs a new `MasshClient` from the specified configuration, with the executor and
+    /// the result of its check returned by a closure for each host, and returns it along with
+    /// the invalid hosts and the duplicate hosts (see [`MasshClient::try_from`]).
+    fn build_with<M>(
+        config: &MasshConfig,
+        mut make: M,
+    ) -> (Self, Vec<(MasshHost, anyhow::Error)>, Vec<MasshHost>)
+    where
+        M: FnMut(&ResolvedHost, &MasshHostInfo) -> (E, Result<()>),
+    {
+        // Configure the internal clients, each in the slot of its first definition.
+        let resolved = config.resolve_hosts();
+        let policy = config.duplicate_hosts;
+        let mut slots: Vec<(usize, MasshHostInfo, E, bool)> = Vec::new();
+        let mut errors = Vec::new();
+        for (index, host) in resolved.iter().enumerate() {
+            let id = host.id();
+            let host_info = MasshHostInfo {
+                addr: host.addr,
+                alias: host.alias.map(str::to_string),
+                group: host.group.map(str::to_string),
+                hostname: host.hostname.map(str::to_string),
+                labels: host.labels.clone(),
+                shell: host.shell,
+                user: host.user.to_string(),
+            };
+            let (client, check) = make(host, &host_info);
+
+            // Keep track of invalid hosts, and of the configuration order of hosts, resolving
+            // duplicates according to the policy.
+            if let Err(e) = check {
+                errors.push((id, e));
+            }
+            let duplicate = slots
+                .iter()
+                .position(|(other, ..)| resolved[*other].is_duplicate_of(host, policy));
+            match duplicate {
+                None => slots.push((index, host_info, client, false)),
+                Some(slot) if policy == MasshDuplicatePolicy::KeepFirst => slots[slot].3 = true,
+                Some(slot) => slots[slot] = (index, host_info, client, true),
+            }
+        }
+        let mut clients = HashMap::new();
+        let mut hosts = Vec::new();
+        let mut info = HashMap::new();
+        let mut duplicates = Vec::new();
+        for (index, host_info, client, duplicate) in slots {
+            let id = resolved[index].id();
+            if duplicate {
+                if policy == MasshDuplicatePolicy::Error {
+                    let error = anyhow::anyhow!("host is defined more than once");
+                    errors.push((id.clone(), error));
+                }
+                duplicates.push(id.clone());
+            }
+            clients.insert(id.clone(), Arc::new(Mutex::new(client)));
+            hosts.push(id.clone());
+            info.insert(id, host_info);
+        }
+
+        // Configure the internal thread pool if specified.
+        let pool = if config.threads == 0 {
+            None
+        } else {
+            Some(ThreadPool::new(config.threads as usize))
+        };
+
+        let massh = MasshClient {
+            aggregate_rate_limit: config.aggregate_rate_limit,
+            channel_capacity: config.channel_capacity,
+            clients,
+            download_layout: config.download_layout.clone(),
+            hosts,
+            info,
+            jitter: config.jitter_ms,
+            max_failures: config.max_failures,
+            observer: None,
+            output_dir: None,
+            pool,
+            stagger: config.stagger_ms,
+            stop: None,
+        };
+        (massh, errors, duplicates)
+    }
+
+    /// Constructs a new `MasshClient` from the specified configuration, with the SSH client of
+    /// each host wrapped into an executor by a closure, and returns it along with the invalid
+    /// hosts and the duplicate hosts (see [`MasshClient::try_from`]).
+    fn build_ssh<W>(
+        config: &MasshConfig,
+        mut wrap: W,
+    ) -> (Self, Vec<(MasshHost, anyhow::Error)>, Vec<MasshHost>)
+    where
+        W: FnMut(SshClient, &MasshHostInfo) -> E,
+    {
+        // Configure the rate limiter shared by all hosts if specified.
+        let aggregate_rate_limiter = if config.aggregate_rate_limit == 0 {
+            None
+        } else {
+            Some(Arc::new(RateLimiter::new(config.aggregate_rate_limit)))
+        };
+
+        // Configure the internal SSH clients.
+        Self::build_with(config, |host, info| {
+            let mut ssh = SshClient::from(host.user, host.addr);
+            ssh.set_fallback_addrs(&host.fallback_addrs);
+            match host.auth {
+                SshAuth::Agent => ssh.set_auth_agent(),
+                SshAuth::AgentKey(key) => ssh.set_auth_agent_key(key),
+                SshAuth::AgentSocket { path, key } => ssh.set_auth_agent_socket(path, key.clone()),
+                SshAuth::Password(password) => ssh.set_auth_password(password),
+                SshAuth::PasswordCmd(command) => ssh.set_auth_password_cmd(command),
+                SshAuth::Pubkey(path) => ssh.set_auth_pubkey(path),
+                SshAuth::PubkeyPassphraseCmd {
+                    path,
+                    passphrase_cmd,
+                } => ssh.set_auth_pubkey_passphrase_cmd(path, passphrase_cmd),
+            };
+            ssh.set_auto_reconnect(config.auto_reconnect);
+            ssh.set_backend(if host.local {
+                SshBackend::Local
+            } else {
+                config.backend
+            });
+            ssh.set_compression(config.compression);
+            ssh.set_host_key(host.host_key.map(str::to_string));
+            ssh.set_keepalive(config.keepalive);
+            ssh.set_max_output(config.max_output);
+            ssh.set_merge_output(config.merge_output);
+            let method_prefs: [(&Option<String>, &[MethodType]); 4] = [
+                (&config.ciphers, &[MethodType::CryptCs, MethodType::CryptSc]),
+                (&config.host_key_algorithms, &[MethodType::HostKey]),
+                (&config.kex_algorithms, &[MethodType::Kex]),
+                (&config.macs, &[MethodType::MacCs, MethodType::MacSc]),
+            ];
+            for (prefs, methods) in method_prefs.iter() {
+                if let Some(prefs) = prefs {
+                    for method in methods.iter() {
+                        ssh.set_method_pref(*method, prefs.as_str());
+                    }
+                }
+            }
+            ssh.set_overflow(config.overflow);
+            ssh.set_rate_limit(config.rate_limit);
+            ssh.set_shared_rate_limiter(aggregate_rate_limiter.clone());
+            ssh.set_shell(host.shell);
+            ssh.set_stop_signal(config.stop_signal);
+            ssh.set_timeout(host.timeout);
+            ssh.set_timestamps(config.timestamps);
+            ssh.set_atomic_uploads(config.atomic_uploads);
+            ssh.set_check_free_space(config.check_free_space);
+            ssh.set_preserve_attributes(config.preserve_attributes);
+            ssh.set_upload_chunks(config.upload_chunks);
+            ssh.set_upload_mode(config.upload_mode);
+            ssh.set_upload_owner(config.upload_owner.clone());
+            ssh.set_verify_uploads(config.verify_uploads);
+            ssh.set_working_dir(host.working_dir.map(str::to_string));
+            ssh.set_become_user(host.become_user.map(str::to_string));
+            ssh.set_become_password(host.become_password.map(str::to_string));
+            let check = check_host(host, &ssh);
+            (wrap(ssh, info), check)
+        })
+    }
+
+    /// Constructs a new `MasshClient` restricted to the hosts of this one for which the
+    /// predicate returns `true`, in the same order.
+    ///
+    /// Both clients share the sessions of the retained hosts, as well as their thread pool
+    /// and settings.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let massh = MasshClient::from(&config);
+    ///
+    /// let local = massh.filter(|host| host.ends_with(":22"));
+    /// ```
+    pub fn filter<F: Fn(&MasshHost) -> bool>(&self, predicate: F) -> Self {
+        let hosts: Vec<MasshHost> = self
+            .hosts
+            .iter()
+            .filter(|h| predicate(h))
+            .cloned()
+            .collect();
+        let clients = hosts
+            .iter()
+            .map(|host| (host.clone(), self.clients[host].clone()))
+            .collect();
+        let info = hosts
+            .iter()
+            .map(|host| (host.clone(), self.info[host].clone()))
+            .collect();
+        MasshClient {
+            aggregate_rate_limit: self.aggregate_rate_limit,
+            channel_capacity: self.channel_capacity,
+            clients,
+            download_layout: self.download_layout.clone(),
+            hosts,
+            info,
+            jitter: self.jitter,
+            max_failures: self.max_failures,
+            observer: self.observer.clone(),
+            output_dir: self.output_dir.clone(),
+            pool: self.pool.clone(),
+            stagger: self.stagger,
+            stop: self.stop.clone(),
+        }
+    }
+
+    /// Constructs a new `MasshClient` restricted to the specified hosts of this one, such as
+    /// the hosts which failed in a previous operation. Hosts which aren't configured are ignored.
+    ///
+    /// See [`MasshClient::filter`] for more details.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let massh = MasshClient::from(&config);
+    ///
+    /// // Retry the command on the hosts where it failed.
+    /// let failed: Vec<_> = massh
+    ///     .collect_ordered(massh.execute("apt-get update"))
+    ///     .into_iter()
+    ///     .filter(|(_, result)| !matches!(result, Ok(output) if output.success()))
+    ///     .map(|(host, _)| host)
+    ///     .collect();
+    /// let rx = massh.only_hosts(&failed).execute("apt-get update");
+    /// ```
+    pub fn only_hosts(&self, hosts: &[MasshHost]) -> Self {
+        self.filter(|host| hosts.contains(host))
+    }
+
+    /// Constructs a new `MasshClient` restricted to the hosts of this one selected by an
+    /// expression on their group, labels and identifier.
+    ///
+    /// See [`MasshHostSelector`] for more details, and [`MasshClient::filter`] for the sharing
+    /// of sessions.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig, MasshHostSelector};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let massh = MasshClient::from(&config);
+    ///
+    /// let selector: MasshHostSelector = "group:web and label:dc=fra".parse().unwrap();
+    /// let rx = massh.select(&selector).execute("systemctl reload nginx");
+    /// ```
+    pub fn select(&self, selector: &MasshHostSelector) -> Self {
+        self.filter(|host| selector.matches(&self.info[host]))
+    }
+
+    /// Configures the layout of the local files downloaded by this `MasshClient`.
+    ///
+    /// See [`MasshDownloadLayout`] for more details. It doesn't apply if the local path of
+    /// [`MasshClient::scp_download`] contains placeholders.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig, MasshDownloadLayout};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let mut massh = MasshClient::from(&config);
+    ///
+    /// // Download `/var/log/syslog` to `local_dir/syslog.{ip}`.
+    /// massh.set_download_layout(MasshDownloadLayout::Template("{name}.{ip}".into()));
+    /// ```
+    pub fn set_download_layout(&mut self, layout: MasshDownloadLayout) -> &mut Self {
+        self.download_layout = layout;
+        self
+    }
+
+    /// Configures this `MasshClient` to write the exact output of the commands it executes to a
+    /// local directory, as files named `username@ip_address:port.stdout` and `.stderr`.
+    ///
+    /// The files of a host are replaced by each command, and written even if its output isn't
+    /// UTF-8 or if it exited with a nonzero status. If an output exceeded the limit of
+    /// [`SshClient::set_max_output`], its file has the whole output if it was spilled, and the
+    /// truncated output otherwise. It applies to [`MasshClient::execute`] and its variants
+    /// which return an [`SshOutput`], and a host fails if its files can't be written. A value
+    /// of `None` signifies that no files are written, which is the default.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let mut massh = MasshClient::from(&config);
+    ///
+    /// massh.set_output_dir(Some("outputs".into()));
+    /// let rx = massh.execute("tar -cz /etc/app");
+    /// ```
+    pub fn set_output_dir(&mut self, output_dir: Option<PathBuf>) -> &mut Self {
+        self.output_dir = output_dir;
+        self
+    }
+
+    /// Configures this `MasshClient` to add a random delay, in milliseconds, before starting
+    /// an operation on each host, in addition to the delay of [`MasshClient::set_stagger`].
+    ///
+    /// The delay of each host is drawn uniformly between zero and the specified value.
+    /// A value of zero signifies no jitter. This is the default.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let mut massh = MasshClient::from(&config);
+    ///
+    /// // Spread the start of operations over 2 seconds.
+    /// massh.set_jitter(2000);
+    /// ```
+    pub fn set_jitter(&mut self, jitter_ms: u64) -> &mut Self {
+        self.jitter = jitter_ms;
+        self
+    }
+
+    /// Configures this `MasshClient` to wait a fixed delay, in milliseconds, between starting
+    /// an operation on a host and starting it on the next host (in configuration order).
+    ///
+    /// A value of zero signifies that operations start on all hosts at once. This is the default.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let mut massh = MasshClient::from(&config);
+    ///
+    /// // Start operations on 10 hosts per second.
+    /// massh.set_stagger(100);
+    /// ```
+    pub fn set_stagger(&mut self, stagger_ms: u64) -> &mut Self {
+        self.stagger = stagger_ms;
+        self
+    }
+
+    /// Configures this `MasshClient` with a flag which, once set to true, stops its operations
+    /// from starting on any more hosts, e.g. on Ctrl-C.
+    ///
+    /// The result of each host on which an operation didn't start yet is a [`MasshSkipped`]
+    /// error, while the hosts on which it already started run it to completion. A value of
+    /// `None` signifies no flag. This is the default.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # use std::sync::atomic::{AtomicBool, Ordering};
+    /// # use std::sync::Arc;
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let mut massh = MasshClient::from(&config);
+    ///
+    /// let stop = Arc::new(AtomicBool::new(false));
+    /// massh.set_stop(Some(stop.clone()));
+    ///
+    /// let rx = massh.execute("sleep 10");
+    ///
+    /// // Skip the hosts which are still waiting for a thread of the pool.
+    /// stop.store(true, Ordering::Relaxed);
+    /// ```
+    pub fn set_stop(&mut self, stop: Option<Arc<AtomicBool>>) -> &mut Self {
+        self.stop = stop;
+        self
+    }
+
+    /// Returns the layout of the local files downloaded by this `MasshClient`.
+    pub fn get_download_layout(&self) -> &MasshDownloadLayout {
+        &self.download_layout
+    }
+
+    /// Returns the local directory to which the output of commands is written, if any.
+    pub fn get_output_dir(&self) -> Option<&Path> {
+        self.output_dir.as_deref()
+    }
+
+    /// Returns the maximum random delay, in milliseconds, before starting an operation on a host.
+    pub fn get_jitter(&self) -> u64 {
+        self.jitter
+    }
+
+    /// Returns the fixed delay, in milliseconds, between starting an operation on consecutive hosts.
+    pub fn get_stagger(&self) -> u64 {
+        self.stagger
+    }
+
+    /// Returns the labels of the specified host, or `None` if it isn't configured.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let massh = MasshClient::from(&config);
+    ///
+    /// let rx = massh.execute("uptime");
+    ///
+    /// while let Ok((host, result)) = rx.recv() {
+    ///     let env = massh.get_labels(&host).and_then(|labels| labels.get("env"));
+    ///     println!("Command succeeded on {} ({:?})? {}", host, env, result.is_ok());
+    /// }
+    /// ```
+    pub fn get_labels(&self, host: &str) -> Option<&BTreeMap<String, String>> {
+        self.info.get(host).map(|info| &info.labels)
+    }
+
+    /// Returns the information about the specified host, or `None` if it isn't configured.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let massh = MasshClient::from(&config);
+    ///
+    /// if let Some(info) = massh.get_host_info("root@10.0.0.1:22") {
+    ///     println!("group: {:?}", info.group);
+    /// }
+    /// ```
+    pub fn get_host_info(&self, host: &str) -> Option<&MasshHostInfo> {
+        self.info.get(host)
+    }
+
+    /// Returns the local path to which [`MasshClient::scp_download`] downloads a remote file
+    /// from the specified host, according to the download layout, or `None` if the host isn't
+    /// configured.
+    ///
+    /// ## Example
+    /// ```
+    /// # use massh::{MasshClient, MasshConfig, MasshDownloadLayout};
+    /// # use std::path::Path;
+    /// let yaml = "hosts: [root@10.0.0.1]";
+    /// let mut massh = MasshClient::from(&MasshConfig::from_yaml(yaml).unwrap());
+    /// massh.set_download_layout(MasshDownloadLayout::Subdirectory);
+    ///
+    /// let remote_path = Path::new("/var/log/syslog");
+    /// let local_path = massh.download_destination("root@10.0.0.1:22", remote_path, "logs".as_ref());
+    /// assert_eq!(local_path.unwrap().unwrap(), Path::new("logs/root@10.0.0.1:22/syslog"));
+    /// ```
+    pub fn download_destination(
+        &self,
+        host: &str,
+        remote_path: &Path,
+        local_path: &Path,
+    ) -> Option<Result<PathBuf>> {
+        let info = self.info.get(host)?;
+        let destination = render_path(remote_path, info).and_then(|remote_path| {
+            local_destination(local_path, &remote_path, &self.download_layout, info)
+        });
+        Some(destination)
+    }
+
+    /// Returns the number of configured hosts.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let massh = MasshClient::from(&config);
+    ///
+    /// let rx = massh.execute("uptime");
+    ///
+    /// for done in 1..=massh.len() {
+    ///     let (host, _) = rx.recv().unwrap();
+    ///     println!("{}/{} done ({})", done, massh.len(), host);
+    /// }
+    /// ```
+    pub fn len(&self) -> usize {
+        self.hosts.len()
+    }
+
+    /// Returns whether no hosts are configured.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let massh = MasshClient::from(&config);
+    ///
+    /// if massh.is_empty() {
+    ///     eprintln!("No hosts are configured");
+    /// }
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+    }
+
+    /// Returns an iterator over the configured hosts and their information,
+    /// in configuration order.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let massh = MasshClient::from(&config);
+    ///
+    /// for (host, info) in massh.iter() {
+    ///     println!("{} (group: {:?}, labels: {:?})", host, info.group, info.labels);
+    /// }
+    /// ```
+    pub fn iter(&self) -> impl Iterator<Item = (&MasshHost, &MasshHostInfo)> {
+        self.hosts.iter().map(move |host| (host, &self.info[host]))
+    }
+
+    /// Attempts to establish an authenticated session with all configured hosts.
+    ///
+    /// It returns a [`MasshReceiver`] which receives exactly 1 message per host.
+    /// Each message contains the result of the operation.
+    ///
+    /// The sessions are cached internally and reused by subsequent operations.
+    /// See [`SshClient::connect`] for more details.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let massh = MasshClient::from(&config);
+    ///
+    /// let rx = massh.connect_all();
+    ///
+    /// while let Ok((host, result)) = rx.recv() {
+    ///     println!("Connection succeeded on {}? {}", host, result.is_ok());
+    /// }
+    /// ```
+    pub fn connect_all(&self) -> MasshReceiver<()> {
+        self.for_each_host(|_, client| client.connect().map(|_| ()))
+    }
+
+    /// Drops the authenticated sessions with all configured hosts, without dropping the clients.
+    ///
+    /// Note that it blocks until all ongoing operations complete.
+    /// See [`SshClient::disconnect`] for more details.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let massh = MasshClient::from(&config);
+    ///
+    /// let _ = massh.connect_all();
+    /// // Do some stuff...
+    /// massh.disconnect_all();
+    /// ```
+    pub fn disconnect_all(&self) {
+        self.clients.values().for_each(|client| {
+            client.lock().disconnect();
+        });
+    }
+
+    /// Attempts to execute a command on all configured hosts.
+    ///
+    /// It returns a [`MasshReceiver`] which receives exactly 1 message per host.
+    /// Each message contains the result of the operation.
+    ///
+    /// The command can reference the labels of each host as `{label:KEY}`, which are substituted
+    /// before the command is executed, quoted as single words for the shell of the host, or as
+    /// `{raw_label:KEY}` to substitute them unquoted (e.g. labels holding several arguments).
+    /// It fails on hosts where a referenced label is undefined.
+    ///
+    /// If a maximum number of failures is configured, a nonzero exit status (or an exit signal)
+    /// counts as a failure.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let massh = MasshClient::from(&config);
+    ///
+    /// let rx = massh.execute("echo $PATH {label:env}");
+    ///
+    /// while let Ok((host, result)) = rx.recv() {
+    ///     println!("Command succeeded on {}? {}", host, result.is_ok());
+    /// }
+    /// ```
+    pub fn execute(&self, command: impl Into<String>) -> MasshReceiver<SshOutput> {
+        let task = self.execute_task(command.into());
+        self.spawn(task, is_output_failure)
+    }
+
+    /// Attempts to execute a command on all configured hosts, and passes the result for each
+    /// host to a callback as soon as it completes, instead of sending it to a [`MasshReceiver`].
+    ///
+    /// It returns immediately, and the callback is invoked exactly once per host from the
+    /// threads running the command, possibly concurrently. This is useful to integrate with
+    /// an event loop (e.g. of a GUI) without a thread dedicated to receiving the results.
+    ///
+    /// See [`MasshClient::execute`] for more details.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let massh = MasshClient::from(&config);
+    ///
+    /// massh.execute_with_callback("uptime", |host, result| {
+    ///     println!("Command succeeded on {}? {}", host, result.is_ok());
+    /// });
+    /// ```
+    pub fn execute_with_callback<C>(&self, command: impl Into<String>, callback: C)
+    where
+        C: Fn(MasshHost, Result<SshOutput>) + Send + Sync + 'static,
+    {
+        let task = self.execute_task(command.into());
+        let tx = MasshSender::Callback(Arc::new(callback));
+        self.dispatch(task, is_output_failure, tx)
+    }
+
+    /// Attempts to execute a command on all configured hosts, like [`MasshClient::execute`],
+    /// but returns a [`MasshCrossbeamReceiver`] instead (requires the `crossbeam` feature).
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # use std::time::Duration;
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let massh = MasshClient::from(&config);
+    ///
+    /// let rx = massh.execute_crossbeam("uptime");
+    /// let ticker = crossbeam_channel::tick(Duration::from_secs(1));
+    ///
+    /// loop {
+    ///     crossbeam_channel::select! {
+    ///         recv(rx) -> message => match message {
+    ///             Ok((host, result)) => println!("{} succeeded? {}", host, result.is_ok()),
+    ///             Err(_) => break,
+    ///         },
+    ///         recv(ticker) -> _ => println!("Still waiting..."),
+    ///     }
+    /// }
+    /// ```
+    #[cfg(feature = "crossbeam")]
+    pub fn execute_crossbeam(
+        &self,
+        command: impl Into<String>,
+    ) -> MasshCrossbeamReceiver<SshOutput> {
+        let task = self.execute_task(command.into());
+        self.spawn_crossbeam(task, is_output_failure)
+    }
+
+    /// Attempts to execute a command on all configured hosts, with additional environment
+    /// variables.
+    ///
+    /// The values of the variables can reference the labels of each host as `{label:KEY}`, like
+    /// the command, which are substituted unquoted in the values. See [`MasshClient::execute`]
+    /// and [`SshClient::execute_with_env`] for more details.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let massh = MasshClient::from(&config);
+    ///
+    /// let rx = massh.execute_with_env("./deploy.sh", &[("DC", "{label:dc}")]);
+    ///
+    /// while let Ok((host, result)) = rx.recv() {
+    ///     println!("Command succeeded on {}? {}", host, result.is_ok());
+    /// }
+    /// ```
+    pub fn execute_with_env(
+        &self,
+        command: impl Into<String>,
+        env: &[(&str, &str)],
+    ) -> MasshReceiver<SshOutput> {
+        let (command, info) = (command.into(), self.info.clone());
+        let output_dir = self.output_dir.clone();
+        let env: Vec<(String, String)> = env
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        let task = move |host: &MasshHost, client: &mut E| {
+            let (labels, shell) = (&info[host].labels, info[host].shell);
+            let command = render_template(&command, labels, Some(shell))?;
+            let values = env
+                .iter()
+                .map(|(_, value)| render_template(value, labels, None))
+                .collect::<Result<Vec<_>>>()?;
+            let env: Vec<(&str, &str)> = env
+                .iter()
+                .zip(&values)
+                .map(|((name, _), value)| (name.as_str(), value.as_str()))
+                .collect();
+            let output = client.execute_with_env(&command, &env)?;
+            write_output(output_dir.as_deref(), host, &output)?;
+            Ok(output)
+        };
+        self.spawn(task, is_output_failure)
+    }
+
+    /// Attempts to download a file from all configured hosts.
+    ///
+    /// It returns a [`MasshReceiver`] which receives exactly 1 message per host.
+    /// Each message contains the result of the operation.
+    ///
+    /// Note that the downloaded filenames are of the form `username@ip_address:port` by default
+    /// (see [`MasshClient::set_download_layout`] for other layouts), unless `local_path` contains
+    /// placeholders, in which case it's the destination file of each host. Both paths can contain
+    /// the placeholders `{host}`, `{user}`, `{ip}`, `{port}` and `{label:KEY}`, which are
+    /// substituted per host.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let massh = MasshClient::from(&config);
+    ///
+    /// let rx = massh.scp_download("remote.txt", "local_dir/{label:dc}/{ip}.txt");
+    ///
+    /// while let Ok((host, result)) = rx.recv() {
+    ///     println!("Download succeeded on {}? {}", host, result.is_ok());
+    /// }
+    /// ```
+    pub fn scp_download<P>(&self, remote_path: P, local_path: P) -> MasshReceiver<()>
+    where
+        P: Into<PathBuf>,
+    {
+        let (remote_path, local_path) = (remote_path.into(), local_path.into());
+        let (info, layout) = (self.info.clone(), self.download_layout.clone());
+        self.for_each_host(move |host, client| {
+            download_file(client, &remote_path, &local_path, &layout, &info[host])
+        })
+    }
+
+    /// Attempts to upload a file to all configured hosts.
+    ///
+    /// It returns a [`MasshReceiver`] which receives exactly 1 message per host.
+    /// Each message contains the result of the operation.
+    ///
+    /// Both paths can contain the placeholders `{host}`, `{user}`, `{ip}`, `{port}` and
+    /// `{label:KEY}`, which are substituted per host (e.g. to upload host-specific files).
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let massh = MasshClient::from(&config);
+    ///
+    /// let rx = massh.scp_upload("local.txt", "remote.txt");
+    ///
+    /// while let Ok((host, result)) = rx.recv() {
+    ///     println!("Upload succeeded on {}? {}", host, result.is_ok());
+    /// }
+    /// ```
+    pub fn scp_upload<P>(&self, local_path: P, remote_path: P) -> MasshReceiver<()>
+    where
+        P: Into<PathBuf>,
+    {
+        let (local_path, remote_path) = (local_path.into(), remote_path.into());
+        let info = self.info.clone();
+        self.for_each_host(move |host, client| {
+            upload_file(client, &local_path, &remote_path, &info[host])
+        })
+    }
+
+    /// Attempts to run a step of a [`MasshPlan`](crate::MasshPlan) on all configured hosts.
+    ///
+    /// It returns a [`MasshReceiver`] which receives exactly 1 message per host.
+    /// Each message contains the result of the operation, with the output of the command
+    /// for `execute` and `script` steps, or `None` for file transfers.
+    ///
+    /// The failure policy of the step isn't applied by this method: it's up to the caller
+    /// to decide which hosts run the next step, typically with [`MasshClient::filter`].
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// use massh::MasshPlan;
+    ///
+    /// let massh = MasshClient::from(&config);
+    ///
+    /// let plan = MasshPlan::from_yaml_file("plan.yaml").unwrap();
+    /// for step in &plan.steps {
+    ///     for (host, result) in massh.collect_ordered(massh.run_step(step)) {
+    ///         println!("{} succeeded on {}? {}", step.describe(), host, result.is_ok());
+    ///     }
+    /// }
+    /// ```
+    pub fn run_step(&self, step: &MasshStep) -> MasshReceiver<Option<SshOutput>> {
+        let action = step.action.clone();
+        let (info, layout) = (self.info.clone(), self.download_layout.clone());
+        let task = move |host: &MasshHost, client: &mut E| {
+            let labels = &info[host].labels;
+            match &action {
+                MasshAction::Execute(command) => {
+                    let command = render_template(command, labels, Some(info[host].shell))?;
+                    client.execute(&command).map(Some)
+                }
+                MasshAction::Script(path) => {
+                    let script = std::fs::read_to_string(path)
+                        .map_err(|e| anyhow::anyhow!("Failed to read {:?}: {}", path, e))?;
+                    let command = info[host].shell.script(&script)?;
+                    client.execute(&command).map(Some)
+                }
+                MasshAction::Upload {
+                    local_path,
+                    remote_path,
+                } => upload_file(client, local_path, remote_path, &info[host]).map(|_| None),
+                MasshAction::Download {
+                    remote_path,
+                    local_path,
+                } => download_file(client, remote_path, local_path, &layout, &info[host])
+                    .map(|_| None),
             }
-        });
+        };
+        let is_failure = |result: &Result<Option<SshOutput>>| match result {
+            Ok(Some(output)) => !output.success(),
+            Ok(None) => false,
+            Err(_) => true,
+        };
+        self.spawn(task, is_failure)
+    }
+
+    /// Receives all messages of a [`MasshReceiver`] and returns them sorted by the order
+    /// of their host in the configuration, rather than in completion order.
+    ///
+    /// Note that it blocks until the operation completes on all hosts. To process results
+    /// as soon as they are available, receive them from the [`MasshReceiver`] directly.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let massh = MasshClient::from(&config);
+    ///
+    /// let rx = massh.execute("hostname");
+    ///
+    /// for (host, result) in massh.collect_ordered(rx) {
+    ///     println!("Command succeeded on {}? {}", host, result.is_ok());
+    /// }
+    /// ```
+    pub fn collect_ordered<T>(&self, rx: MasshReceiver<T>) -> Vec<(MasshHost, Result<T>)> {
+        let order: HashMap<_, _> = self.hosts.iter().enumerate().map(|(i, h)| (h, i)).collect();
+        let mut results: Vec<_> = rx.iter().collect();
+        results.sort_by_key(|(host, _)| order.get(host).copied());
+        results
+    }
+
+    /// Receives the messages of a [`MasshReceiver`] until the operation completes on all
+    /// hosts or the timeout elapses, and returns them sorted by the order of their host in
+    /// the configuration, like [`MasshClient::collect_ordered`].
+    ///
+    /// The result of each host whose message wasn't received in time is a [`MasshTimedOut`]
+    /// error. Note that the operation keeps running on these hosts in the background.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # use std::time::Duration;
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let massh = MasshClient::from(&config);
+    ///
+    /// let rx = massh.execute("hostname");
+    ///
+    /// for (host, result) in massh.recv_all(rx, Duration::from_secs(10)) {
+    ///     println!("Command succeeded on {}? {}", host, result.is_ok());
+    /// }
+    /// ```
+    pub fn recv_all<T>(
+        &self,
+        rx: MasshReceiver<T>,
+        timeout: Duration,
+    ) -> Vec<(MasshHost, Result<T>)> {
+        let deadline = Instant::now() + timeout;
+        let mut received: HashMap<MasshHost, Result<T>> = HashMap::new();
+        while received.len() < self.hosts.len() {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match rx.recv_timeout(timeout) {
+                Ok((host, result)) => {
+                    received.insert(host, result);
+                }
+                Err(_) => break,
+            }
+        }
+        self.hosts
+            .iter()
+            .map(|host| {
+                let result = received
+                    .remove(host)
+                    .unwrap_or_else(|| Err(MasshTimedOut.into()));
+                (host.clone(), result)
+            })
+            .collect()
+    }
+
+    /// Runs a closure on all configured hosts, with exclusive access to their `SshClient`.
+    ///
+    /// It returns a [`MasshReceiver`] which receives exactly 1 message per host.
+    /// Each message contains the value returned by the closure.
+    ///
+    /// This is the building block of all other operations of this `MasshClient`. It lets you
+    /// compose arbitrary sequences of operations per host, which run in parallel across hosts
+    /// in the internal thread pool (or in 1 thread per host).
+    ///
+    /// If a maximum number of failures is configured and reached, the closure isn't run on
+    /// the remaining hosts, whose result is a [`MasshSkipped`] error instead. Note that this is
+    /// most effective with a bounded thread pool, since otherwise all hosts start immediately.
+    ///
+    /// The start of the closure on each host is delayed according to [`MasshClient::set_stagger`]
+    /// and [`MasshClient::set_jitter`].
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let massh = MasshClient::from(&config);
+    ///
+    /// let rx = massh.for_each_host(|host, client| {
+    ///     client.scp_upload("install.sh", "/tmp/install.sh")?;
+    ///     let output = client.execute("sh /tmp/install.sh")?;
+    ///     client.scp_download("/tmp/install.log", &format!("logs/{}", host))?;
+    ///     Ok(output.exit_status)
+    /// });
+    ///
+    /// while let Ok((host, result)) = rx.recv() {
+    ///     println!("Install succeeded on {}? {:?}", host, result);
+    /// }
+    /// ```
+    pub fn for_each_host<T, F>(&self, f: F) -> MasshReceiver<T>
+    where
+        T: Send + 'static,
+        F: Fn(&MasshHost, &mut E) -> Result<T> + Send + Sync + 'static,
+    {
+        self.spawn(f, Result::is_err)
+    }
+
+    /// Runs a closure on all configured hosts, and passes the value it returns for each host
+    /// to a callback as soon as it completes, instead of sending it to a [`MasshReceiver`].
+    ///
+    /// It returns immediately, and the callback is invoked exactly once per host from the
+    /// threads running the closure, possibly concurrently.
+    ///
+    /// See [`MasshClient::for_each_host`] and [`MasshClient::execute_with_callback`]
+    /// for more details.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let massh = MasshClient::from(&config);
+    ///
+    /// massh.for_each_host_with_callback(
+    ///     |_, client| client.execute("uptime"),
+    ///     |host, result| println!("Command succeeded on {}? {}", host, result.is_ok()),
+    /// );
+    /// ```
+    pub fn for_each_host_with_callback<T, F, C>(&self, f: F, callback: C)
+    where
+        T: Send + 'static,
+        F: Fn(&MasshHost, &mut E) -> Result<T> + Send + Sync + 'static,
+        C: Fn(MasshHost, Result<T>) + Send + Sync + 'static,
+    {
+        self.dispatch(f, Result::is_err, MasshSender::Callback(Arc::new(callback)))
+    }
+
+    /// Runs a closure on all configured hosts, like [`MasshClient::for_each_host`], but returns
+    /// a [`MasshCrossbeamReceiver`] instead (requires the `crossbeam` feature).
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # use std::time::Duration;
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let massh = MasshClient::from(&config);
+    ///
+    /// let rx = massh.for_each_host_crossbeam(|_, client| client.execute("uptime"));
+    ///
+    /// while let Ok((host, result)) = rx.recv_timeout(Duration::from_secs(10)) {
+    ///     println!("Command succeeded on {}? {}", host, result.is_ok());
+    /// }
+    /// ```
+    #[cfg(feature = "crossbeam")]
+    pub fn for_each_host_crossbeam<T, F>(&self, f: F) -> MasshCrossbeamReceiver<T>
+    where
+        T: Send + 'static,
+        F: Fn(&MasshHost, &mut E) -> Result<T> + Send + Sync + 'static,
+    {
+        self.spawn_crossbeam(f, Result::is_err)
+    }
 
-        // Return the receiving half of the channel.
+    /// Runs a closure on a single configured host, with exclusive access to its `SshClient`,
+    /// in the current thread.
+    ///
+    /// It returns the value returned by the closure, or `None` if the host isn't configured.
+    /// This is useful to drill into one host after a mass operation, reusing its session.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MasshClient, MasshConfig};
+    /// # let config = MasshConfig::from_yaml("").unwrap();
+    /// let massh = MasshClient::from(&config);
+    ///
+    /// let result = massh.with_client("username@127.0.0.1:22", |client| client.execute("dmesg"));
+    /// ```
+    pub fn with_client<T, F>(&self, host: &str, f: F) -> Option<T>
+    where
+        F: FnOnce(&mut E) -> T,
+    {
+        let client = self.clients.get(host)?;
+        let mut client = client.lock();
+        Some(f(&mut client))
+    }
+
+    /// Returns the task of `MasshClient::execute`, which renders the labels of each host in
+    /// the command before executing it.
+    fn execute_task(
+        &self,
+        command: String,
+    ) -> impl Fn(&MasshHost, &mut E) -> Result<SshOutput> + Send + Sync + 'static {
+        let (info, output_dir) = (self.info.clone(), self.output_dir.clone());
+        move |host: &MasshHost, client: &mut E| {
+            let info = &info[host];
+            let command = render_template(&command, &info.labels, Some(info.shell))?;
+            let output = client.execute(&command)?;
+            write_output(output_dir.as_deref(), host, &output)?;
+            Ok(output)
+        }
+    }
+
+    /// Runs a closure on all configured hosts, and returns the receiving half of the channel
+    /// of its results (see `MasshClient::dispatch`).
+    fn spawn<T, F>(&self, f: F, is_failure: fn(&Result<T>) -> bool) -> MasshReceiver<T>
+    where
+        T: Send + 'static,
+        F: Fn(&MasshHost, &mut E) -> Result<T> + Send + Sync + 'static,
+    {
+        // Create a multi-producer, single-consumer channel, bounded if specified.
+        let (tx, rx) = if self.channel_capacity == 0 {
+            let (tx, rx) = std::sync::mpsc::channel();
+            (MasshSender::Unbounded(tx), rx)
+        } else {
+            let (tx, rx) = std::sync::mpsc::sync_channel(self.channel_capacity);
+            (MasshSender::Bounded(tx), rx)
+        };
+        self.dispatch(f, is_failure, tx);
+        rx
+    }
+
+    /// Runs a closure on all configured hosts, and returns the receiving half of the crossbeam
+    /// channel of its results (see `MasshClient::dispatch`).
+    #[cfg(feature = "crossbeam")]
+    fn spawn_crossbeam<T, F>(
+        &self,
+        f: F,
+        is_failure: fn(&Result<T>) -> bool,
+    ) -> MasshCrossbeamReceiver<T>
+    where
+        T: Send + 'static,
+        F: Fn(&MasshHost, &mut E) -> Result<T> + Send + Sync + 'static,
+    {
+        // Create a multi-producer, multi-consumer channel, bounded if specified.
+        let (tx, rx) = if self.channel_capacity == 0 {
+            crossbeam_channel::unbounded()
+        } else {
+            crossbeam_channel::bounded(self.channel_capacity)
+        };
+        self.dispatch(f, is_failure, MasshSender::Crossbeam(tx));
         rx
     }
+
+    /// Runs a closure on all configured hosts and sends its results to a sender, counting
+    /// the results for which `is_failure` returns true to abort the operation once the maximum
+    /// number of failures is reached.
+    fn dispatch<T, F>(&self, f: F, is_failure: fn(&Result<T>) -> bool, tx: MasshSender<T>)
+    where
+        T: Send + 'static,
+        F: Fn(&MasshHost, &mut E) -> Result<T> + Send + Sync + 'static,
+    {
+        let (f, observer, stop) = (Arc::new(f), self.observer.clone(), self.stop.clone());
+        let max_failures = self.max_failures;
+        let failures = Arc::new(AtomicUsize::new(0));
+        let aborted = Arc::new(AtomicBool::new(false));
+
+        // For each configured host, in configuration order...
+        let (now, mut rng) = (Instant::now(), rand::thread_rng());
+        self.hosts.iter().enumerate().for_each(|(index, host)| {
+            // Compute the start time of the operation, staggered and jittered if specified.
+            let mut delay = self.stagger * index as u64;
+            if self.jitter != 0 {
+                delay += rng.gen_range(0..=self.jitter);
+            }
+            let start = now + Duration::from_millis(delay);
+
+            // Prepare a task closure responsible for sending the result of the operation.
+            let (client, host, tx) = (self.clients[host].clone(), host.clone(), tx.clone());
+            let (f, failures, aborted) = (f.clone(), failures.clone(), aborted.clone());
+            let (observer, stop) = (observer.clone(), stop.clone());
+            let task_closure = move || {
+                #[cfg(feature = "tracing")]
+                let _span = tracing::info_span!("massh_host", %host).entered();
+                std::thread::sleep(start.saturating_duration_since(Instant::now()));
+                let mut client = client.lock();
+                let stopped = stop.is_some_and(|stop| stop.load(Ordering::SeqCst));
+                if aborted.load(Ordering::SeqCst) || stopped {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!("skipped");
+                    tx.send((host, Err(MasshSkipped.into())));
+                    return;
+                }
+                if let Some(observer) = &observer {
+                    observer.on_host_start(&host);
+                }
+                let started = Instant::now();
+                let result = f(&host, &mut client);
+                #[cfg(feature = "tracing")]
+                tracing::info!(
+                    failed = is_failure(&result),
+                    elapsed_ms = started.elapsed().as_millis() as u64,
+                    "done"
+                );
+                if let Some(observer) = &observer {
+                    observer.on_host_done(&host, started.elapsed(), is_failure(&result));
+                }
+                if is_failure(&result) && max_failures != 0 {
+                    let count = failures.fetch_add(1, Ordering::SeqCst) + 1;
+                    if count >= max_failures {
+                        aborted.store(true, Ordering::SeqCst);
+                    }
+                }
+                tx.send((host, result));
+            };
+
+            // Execute the task closure in the thread pool or spawn it in its own thread.
+            if let Some(pool) = &self.pool {
+                pool.execute(task_closure)
+            } else {
+                std::thread::spawn(task_closure);
+            }
+        });
+    }
+}
+
+impl MasshClient<RecordingExecutor> {
+    /// Constructs a new `MasshClient` from the specified configuration, which records the
+    /// commands and file transfers of its hosts.
+    ///
+    /// See [`MasshRecording`] for more details.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use massh::{MasshClient, MasshConfig};
+    ///
+    /// let config = MasshConfig::from_yaml_file("massh.yaml").unwrap();
+    /// let massh = MasshClient::record(&config);
+    /// let _ = massh.collect_ordered(massh.execute("uptime"));
+    /// massh.get_recording().to_json_file("recording.json").unwrap();
+    /// ```
+    pub fn record(config: &MasshConfig) -> Self {
+        let (massh, _, _) = Self::build_ssh(config, |ssh, _| RecordingExecutor::new(ssh));
+        massh
+    }
+
+    /// Returns the operations recorded so far on all configured hosts.
+    ///
+    /// Note that it blocks until all ongoing operations complete.
+    pub fn get_recording(&self) -> MasshRecording {
+        let hosts = self
+            .hosts
+            .iter()
+            .map(|host| {
+                (
+                    host.clone(),
+                    self.clients[host].lock().get_records().to_vec(),
+                )
+            })
+            .collect();
+        MasshRecording { hosts }
+    }
+}
+
+impl MasshClient<ReplayExecutor> {
+    /// Constructs a new `MasshClient` from the specified configuration, which replays the
+    /// operations of its hosts from a recording, without network access.
+    ///
+    /// See [`MasshRecording`] for more details.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// use massh::{MasshClient, MasshConfig, MasshRecording};
+    ///
+    /// let config = MasshConfig::from_yaml_file("massh.yaml").unwrap();
+    /// let recording = MasshRecording::from_json_file("recording.json").unwrap();
+    /// let massh = MasshClient::replay(&config, &recording);
+    /// ```
+    pub fn replay(config: &MasshConfig, recording: &MasshRecording) -> Self {
+        Self::from_executors(config, |info| {
+            ReplayExecutor::new(recording, &info.to_string())
+        })
+    }
+}
+
+/// Sending half of the channel of a `MasshReceiver` (or `MasshCrossbeamReceiver`), either
+/// unbounded or bounded, or a callback which receives the messages directly.
+enum MasshSender<T> {
+    Unbounded(Sender<(MasshHost, Result<T>)>),
+    Bounded(SyncSender<(MasshHost, Result<T>)>),
+    Callback(Arc<dyn Fn(MasshHost, Result<T>) + Send + Sync>),
+    #[cfg(feature = "crossbeam")]
+    Crossbeam(crossbeam_channel::Sender<(MasshHost, Result<T>)>),
+}
+
+impl<T> Clone for MasshSender<T> {
+    fn clone(&self) -> Self {
+        match self {
+            MasshSender::Unbounded(tx) => MasshSender::Unbounded(tx.clone()),
+            MasshSender::Bounded(tx) => MasshSender::Bounded(tx.clone()),
+            MasshSender::Callback(callback) => MasshSender::Callback(callback.clone()),
+            #[cfg(feature = "crossbeam")]
+            MasshSender::Crossbeam(tx) => MasshSender::Crossbeam(tx.clone()),
+        }
+    }
+}
+
+impl<T> MasshSender<T> {
+    /// Sends a message, blocking if the channel is bounded and full (or until the callback
+    /// returns). Errors (i.e. the receiving half was dropped) are ignored.
+    fn send(&self, message: (MasshHost, Result<T>)) {
+        match self {
+            MasshSender::Unbounded(tx) => {
+                let _ = tx.send(message);
+            }
+            MasshSender::Bounded(tx) => {
+                let _ = tx.send(message);
+            }
+            MasshSender::Callback(callback) => callback(message.0, message.1),
+            #[cfg(feature = "crossbeam")]
+            MasshSender::Crossbeam(tx) => {
+                let _ = tx.send(message);
+            }
+        }
+    }
+}
+
+/// Returns whether the result of a command is a failure, i.e. an error or a non-zero exit status.
+fn is_output_failure(result: &Result<SshOutput>) -> bool {
+    match result {
+        Ok(output) => !output.success(),
+        Err(_) => true,
+    }
+}
+
+/// Checks that a host can be configured, i.e. that the settings of its client could possibly work.
+fn check_host(host: &ResolvedHost, ssh: &SshClient) -> Result<()> {
+    if host.local {
+        return Ok(());
+    }
+    if host.addr.port() == 0 {
+        return Err(anyhow::anyhow!("Port number is zero"));
+    }
+    if host.become_user.is_some() {
+        host.shell.ensure_posix()?;
+    }
+    if let SshAuth::Pubkey(path) | SshAuth::PubkeyPassphraseCmd { path, .. } = host.auth {
+        if !path.is_file() {
+            return Err(anyhow::anyhow!(
+                "Private key file {:?} does not exist",
+                path
+            ));
+        }
+    }
+    if let Some(host_key) = host.host_key {
+        if !is_fingerprint(host_key) {
+            return Err(anyhow::anyhow!(
+                "Invalid host key fingerprint {:?}",
+                host_key
+            ));
+        }
+    }
+    backend::check(ssh)
+}
+
+/// Downloads a file from a host, with both paths rendered and the local destination
+/// resolved according to the download layout (see `MasshClient::scp_download`).
+fn download_file(
+    client: &mut impl RemoteExecutor,
+    remote_path: &Path,
+    local_path: &Path,
+    layout: &MasshDownloadLayout,
+    info: &MasshHostInfo,
+) -> Result<()> {
+    let remote_path = render_path(remote_path, info)?;
+    let local_path = local_destination(local_path, &remote_path, layout, info)?;
+    if let Some(parent) = local_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    client.scp_download(&remote_path, &local_path)
+}
+
+/// Writes the standard output and standard error of a command on a host to the output
+/// directory, if any (see `MasshClient::set_output_dir`).
+fn write_output(output_dir: Option<&Path>, host: &MasshHost, output: &SshOutput) -> Result<()> {
+    let output_dir = match output_dir {
+        Some(output_dir) => output_dir,
+        None => return Ok(()),
+    };
+    std::fs::create_dir_all(output_dir).map_err(|error| {
+        anyhow::anyhow!("Failed to create directory {:?}: {}", output_dir, error)
+    })?;
+    let streams = [
+        ("stdout", &output.stdout, &output.stdout_file),
+        ("stderr", &output.stderr, &output.stderr_file),
+    ];
+    for (extension, bytes, file) in streams {
+        let path = output_dir.join(format!("{}.{}", host, extension));
+        let result = match file {
+            Some(file) => std::fs::copy(file, &path).map(|_| ()),
+            None => std::fs::write(&path, bytes),
+        };
+        result.map_err(|error| anyhow::anyhow!("Failed to write {:?}: {}", path, error))?;
+    }
+    Ok(())
+}
+
+/// Uploads a file to a host, with both paths rendered (see `MasshClient::scp_upload`).
+fn upload_file(
+    client: &mut impl RemoteExecutor,
+    local_path: &Path,
+    remote_path: &Path,
+    info: &MasshHostInfo,
+) -> Result<()> {
+    let local_path = render_path(local_path, info)?;
+    let remote_path = render_path(remote_path, info)?;
+    client.scp_upload(&local_path, &remote_path)
+}
+
+/// Placeholders of path templates, other than `{label:KEY}`.
+const PATH_PLACEHOLDERS: [&str; 4] = ["{host}", "{user}", "{ip}", "{port}"];
+
+/// Substitutes the `{host}`, `{user}`, `{ip}`, `{port}` and `{label:KEY}` placeholders
+/// of a path template with the values of a host. Non-UTF-8 paths are returned unchanged.
+fn render_path(template: &Path, info: &MasshHostInfo) -> Result<PathBuf> {
+    let template = match template.to_str() {
+        Some(template) => template,
+        None => return Ok(template.to_path_buf()),
+    };
+    let values = [
+        info.to_string(),
+        info.user.clone(),
+        info.ip().to_string(),
+        info.port().to_string(),
+    ];
+    let mut path = template.to_string();
+    for (placeholder, value) in PATH_PLACEHOLDERS.iter().zip(values.iter()) {
+        path = path.replace(placeholder, value);
+    }
+    Ok(PathBuf::from(render_template(&path, &info.labels, None)?))
+}
+
+/// Returns the local destination of a download for a host: the rendered template if the path
+/// contains placeholders, or else the path joined according to the download layout.
+fn local_destination(
+    local_path: &Path,
+    remote_path: &Path,
+    layout: &MasshDownloadLayout,
+    info: &MasshHostInfo,
+) -> Result<PathBuf> {
+    let is_template = local_path.to_str().map(|path| {
+        let mut placeholders = LABEL_PREFIXES.iter().chain(&PATH_PLACEHOLDERS);
+        placeholders.any(|p| path.contains(p))
+    });
+    if is_template == Some(true) {
+        return render_path(local_path, info);
+    }
+    let name = || match remote_path.file_name() {
+        Some(name) => Ok(name),
+        None => Err(anyhow::anyhow!(
+            "Remote path {:?} has no filename",
+            remote_path
+        )),
+    };
+    match layout {
+        MasshDownloadLayout::Flat => Ok(local_path.join(info.to_string())),
+        MasshDownloadLayout::Subdirectory => Ok(local_path.join(info.to_string()).join(name()?)),
+        MasshDownloadLayout::Template(template) => {
+            let template = template.replace("{name}", &name()?.to_string_lossy());
+            let filename = render_path(Path::new(&template), info)?;
+            Ok(local_path.join(filename))
+        }
+    }
+}
+
+/// Prefixes of the placeholders of templates which are substituted with labels.
+const LABEL_PREFIXES: [&str; 2] = ["{label:", "{raw_label:"];
+
+/// Substitutes the `{label:KEY}` and `{raw_label:KEY}` placeholders of a template with the
+/// labels of a host. If a shell is specified (i.e. in commands), the values of the former are
+/// quoted as single literal words for it, while the values of the latter are substituted as is.
+fn render_template(
+    template: &str,
+    labels: &BTreeMap<String, String>,
+    shell: Option<SshShell>,
+) -> Result<String> {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+    loop {
+        let next = LABEL_PREFIXES
+            .iter()
+            .filter_map(|prefix| rest.find(prefix).map(|index| (index, *prefix)))
+            .min();
+        let (index, prefix) = match next {
+            Some(next) => next,
+            None => break,
+        };
+        output.push_str(&rest[..index]);
+        let tail = &rest[index + prefix.len()..];
+        let end = match tail.find('}') {
+            Some(end) => end,
+            None => return Err(anyhow::anyhow!("Unterminated `{}` in template", prefix)),
+        };
+        let key = &tail[..end];
+        match (labels.get(key), shell) {
+            (Some(value), Some(shell)) if prefix == "{label:" => {
+                output.push_str(&shell.quote(value))
+            }
+            (Some(value), _) => output.push_str(value),
+            (None, _) => return Err(anyhow::anyhow!("Label `{}` is not defined", key)),
+        }
+        rest = &tail[end + 1..];
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MasshDiagnosticKind, MasshSeverity};
+
+    fn labels() -> BTreeMap<String, String> {
+        BTreeMap::from([
+            ("args".to_string(), "-v $HOME".to_string()),
+            ("env".to_string(), "it's prod".to_string()),
+        ])
+    }
+
+    #[test]
+    fn render_template_quotes_labels_for_the_shell() {
+        let shell = Some(SshShell::Posix);
+        let rendered = render_template("echo {label:env} {raw_label:args}", &labels(), shell);
+        assert_eq!(rendered.unwrap(), "echo 'it'\\''s prod' -v $HOME");
+        let rendered = render_template("{label:env}/{raw_label:env}", &labels(), None);
+        assert_eq!(rendered.unwrap(), "it's prod/it's prod");
+        let rendered = render_template("{label:args}", &labels(), Some(SshShell::PowerShell));
+        assert_eq!(rendered.unwrap(), "'-v $HOME'");
+    }
+
+    #[test]
+    fn render_template_does_not_rescan_values() {
+        let mut labels = labels();
+        labels.insert("nested".to_string(), "{label:env}".to_string());
+        let rendered = render_template("{raw_label:nested}", &labels, None);
+        assert_eq!(rendered.unwrap(), "{label:env}");
+        let rendered = render_template("no placeholders {host}", &labels, None);
+        assert_eq!(rendered.unwrap(), "no placeholders {host}");
+    }
+
+    #[test]
+    fn render_template_rejects_undefined_labels() {
+        assert!(render_template("{label:missing}", &labels(), None).is_err());
+        assert!(render_template("{raw_label:env", &labels(), None).is_err());
+    }
+
+    /// Returns the configuration of two definitions of the same host, and of a host defined
+    /// twice by alias, with the specified duplicate policy.
+    fn duplicates_config(policy: &str) -> MasshConfig {
+        let yaml = format!(
+            "
+duplicate_hosts: {}
+hosts:
+  - {{addr: 10.0.0.1, user: root, labels: {{env: dev}}}}
+  - {{addr: 10.0.0.2, user: root, alias: db}}
+  - {{addr: 10.0.0.1, user: root, labels: {{env: prod}}}}
+  - {{addr: 10.0.0.3, user: root, alias: db}}
+",
+            policy
+        );
+        MasshConfig::from_yaml(&yaml).unwrap()
+    }
+
+    #[test]
+    fn duplicate_hosts_keep_last() {
+        let (massh, duplicates) = MasshClient::try_from(&duplicates_config("keep_last")).unwrap();
+        let hosts: Vec<_> = massh.iter().map(|(host, _)| host.clone()).collect();
+        assert_eq!(hosts, ["root@10.0.0.1:22", "db"]);
+        assert_eq!(duplicates, ["root@10.0.0.1:22", "db"]);
+        assert_eq!(massh.get_labels("root@10.0.0.1:22").unwrap()["env"], "prod");
+        assert_eq!(massh.iter().nth(1).unwrap().1.ip().to_string(), "10.0.0.3");
+    }
+
+    #[test]
+    fn duplicate_hosts_keep_first() {
+        let (massh, duplicates) = MasshClient::try_from(&duplicates_config("keep_first")).unwrap();
+        let hosts: Vec<_> = massh.iter().map(|(host, _)| host.clone()).collect();
+        assert_eq!(hosts, ["root@10.0.0.1:22", "db"]);
+        assert_eq!(duplicates, ["root@10.0.0.1:22", "db"]);
+        assert_eq!(massh.get_labels("root@10.0.0.1:22").unwrap()["env"], "dev");
+        assert_eq!(massh.iter().nth(1).unwrap().1.ip().to_string(), "10.0.0.2");
+    }
+
+    #[test]
+    fn duplicate_hosts_error() {
+        let config = duplicates_config("error");
+        assert!(MasshClient::try_from(&config).is_err());
+        assert_eq!(MasshClient::from(&config).iter().count(), 2);
+        let diagnostics: Vec<_> = config
+            .validate()
+            .into_iter()
+            .filter(|d| d.kind == MasshDiagnosticKind::DuplicateHost)
+            .collect();
+        assert_eq!(diagnostics.len(), 2);
+        assert!(diagnostics
+            .iter()
+            .all(|d| d.severity == MasshSeverity::Error));
+    }
 }
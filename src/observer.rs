@@ -0,0 +1,69 @@
+use crate::MasshHost;
+use std::time::Duration;
+
+/// Hooks called by a `MasshClient` as its operations progress on each host, e.g. to export
+/// metrics to Prometheus.
+///
+/// Every hook has a default implementation which does nothing, so an observer only implements
+/// the hooks it needs. The hooks are called from the threads running the operations, possibly
+/// concurrently, so they should return quickly. A `MasshClient` without an observer
+/// (see [`MasshClient::set_observer`]) doesn't measure anything.
+///
+/// ## Example
+/// ```no_run
+/// use massh::{MasshClient, MasshConfig, MasshHost, MasshObserver};
+/// use std::sync::atomic::{AtomicU64, Ordering};
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// #[derive(Default)]
+/// struct Counters {
+///     connect_errors: AtomicU64,
+///     failed_commands: AtomicU64,
+/// }
+///
+/// impl MasshObserver for Counters {
+///     fn on_connect_err(&self, _: &MasshHost, _: Duration, _: &anyhow::Error) {
+///         self.connect_errors.fetch_add(1, Ordering::Relaxed);
+///     }
+///
+///     fn on_command_done(&self, _: &MasshHost, _: Duration, exit_status: i32) {
+///         if exit_status != 0 {
+///             self.failed_commands.fetch_add(1, Ordering::Relaxed);
+///         }
+///     }
+/// }
+///
+/// let config = MasshConfig::from_yaml_file("massh.yaml").unwrap();
+/// let mut massh = MasshClient::from(&config);
+/// let counters = Arc::new(Counters::default());
+/// massh.set_observer(Some(counters.clone()));
+/// ```
+///
+/// [`MasshClient::set_observer`]: crate::MasshClient::set_observer
+pub trait MasshObserver: Send + Sync {
+    /// Called when a session starts being established with a host.
+    fn on_connect_start(&self, _host: &MasshHost) {}
+
+    /// Called when a session was established with a host, with the time it took.
+    fn on_connect_ok(&self, _host: &MasshHost, _duration: Duration) {}
+
+    /// Called when a session couldn't be established with a host, with the time it took
+    /// to fail and the error.
+    fn on_connect_err(&self, _host: &MasshHost, _duration: Duration, _error: &anyhow::Error) {}
+
+    /// Called when a command completed on a host, with its run time and exit status.
+    fn on_command_done(&self, _host: &MasshHost, _duration: Duration, _exit_status: i32) {}
+
+    /// Called when a command couldn't be executed on a host (or its output couldn't be
+    /// retrieved), with the time it took to fail and the error.
+    fn on_command_err(&self, _host: &MasshHost, _duration: Duration, _error: &anyhow::Error) {}
+
+    /// Called when an operation of a `MasshClient` starts running on a host, i.e. once it's no
+    /// longer waiting for a thread or for its stagger delay, and wasn't skipped.
+    fn on_host_start(&self, _host: &MasshHost) {}
+
+    /// Called when an operation of a `MasshClient` finished on a host, with its run time and
+    /// whether it failed (e.g. a command with a non-zero exit status).
+    fn on_host_done(&self, _host: &MasshHost, _duration: Duration, _failed: bool) {}
+}
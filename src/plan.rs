@@ -0,0 +1,190 @@
+use anyhow::Result;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// Sequence of steps run in order on all hosts of a `MasshClient` (e.g. by `massh run`).
+///
+/// Each step is run on all remaining hosts in parallel with [`MasshClient::run_step`], and the
+/// next step starts once every host is done. What happens when the step fails on some hosts
+/// depends on its [`MasshFailurePolicy`].
+///
+/// ```yaml
+/// ---
+/// steps:
+///   - name: Upload the installer
+///     upload:
+///       local_path: install.sh
+///       remote_path: /tmp/install.sh
+///   - name: Run the installer
+///     execute: sh /tmp/install.sh --env {label:env}
+///     on_failure: exclude
+///   - name: Check the service
+///     script: checks/service.sh
+///     on_failure: continue
+///   - download:
+///       remote_path: /tmp/install.log
+///       local_path: logs
+/// ```
+///
+/// [`MasshClient::run_step`]: crate::MasshClient::run_step
+#[derive(Clone, Debug, Deserialize)]
+pub struct MasshPlan {
+    /// List of steps, in execution order.
+    pub steps: Vec<MasshStep>,
+}
+
+/// Step of a `MasshPlan`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct MasshStep {
+    /// Optional name of the step, for display purposes.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Operation run by the step on each host.
+    #[serde(flatten)]
+    pub action: MasshAction,
+    /// What to do with the plan if the step fails on some hosts.
+    ///
+    /// If omitted, it defaults to [`MasshFailurePolicy::Abort`].
+    #[serde(default)]
+    pub on_failure: MasshFailurePolicy,
+}
+
+/// Operation of a `MasshStep`, run on each host.
+///
+/// Paths and commands can contain the same placeholders as the corresponding methods
+/// of `MasshClient`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MasshAction {
+    /// Executes a command (see `MasshClient::execute`).
+    Execute(String),
+    /// Executes a local shell script with `sh` on the remote hosts, without copying it there.
+    Script(PathBuf),
+    /// Uploads a file (see `MasshClient::scp_upload`).
+    Upload {
+        local_path: PathBuf,
+        remote_path: PathBuf,
+    },
+    /// Downloads a file (see `MasshClient::scp_download`).
+    Download {
+        remote_path: PathBuf,
+        local_path: PathBuf,
+    },
+}
+
+/// What to do with a `MasshPlan` when one of its steps fails on some hosts.
+///
+/// A step fails on a host if it returns an error, or if a command exits with a nonzero status.
+/// The default is [`MasshFailurePolicy::Abort`].
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MasshFailurePolicy {
+    /// The remaining steps are skipped on all hosts.
+    #[default]
+    Abort,
+    /// The remaining steps are run on all hosts, including the ones that failed.
+    Continue,
+    /// The remaining steps are skipped on the hosts that failed only.
+    Exclude,
+}
+
+impl MasshPlan {
+    /// Attempts to construct a new `MasshPlan` from a JSON string.
+    ///
+    /// ## Usage
+    ///
+    /// ```no_run
+    /// use massh::MasshPlan;
+    ///
+    /// let json = std::fs::read_to_string("plan.json").unwrap();
+    /// let plan = MasshPlan::from_json(&json).unwrap();
+    /// ```
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Attempts to construct a new `MasshPlan` from a JSON file.
+    ///
+    /// Unlike [`MasshPlan::from_json`], relative local paths of the steps are resolved
+    /// from the directory of the specified file.
+    ///
+    /// ## Usage
+    ///
+    /// ```no_run
+    /// use massh::MasshPlan;
+    ///
+    /// let plan = MasshPlan::from_json_file("plan.json").unwrap();
+    /// ```
+    pub fn from_json_file(path: impl AsRef<Path>) -> Result<Self> {
+        Self::load(path.as_ref(), Self::from_json)
+    }
+
+    /// Attempts to construct a new `MasshPlan` from a YAML string.
+    ///
+    /// ## Usage
+    ///
+    /// ```no_run
+    /// use massh::MasshPlan;
+    ///
+    /// let yaml = std::fs::read_to_string("plan.yaml").unwrap();
+    /// let plan = MasshPlan::from_yaml(&yaml).unwrap();
+    /// ```
+    pub fn from_yaml(yaml: &str) -> Result<Self> {
+        Ok(serde_yaml::from_str(yaml)?)
+    }
+
+    /// Attempts to construct a new `MasshPlan` from a YAML file.
+    ///
+    /// Unlike [`MasshPlan::from_yaml`], relative local paths of the steps are resolved
+    /// from the directory of the specified file.
+    ///
+    /// ## Usage
+    ///
+    /// ```no_run
+    /// use massh::MasshPlan;
+    ///
+    /// let plan = MasshPlan::from_yaml_file("plan.yaml").unwrap();
+    /// ```
+    pub fn from_yaml_file(path: impl AsRef<Path>) -> Result<Self> {
+        Self::load(path.as_ref(), Self::from_yaml)
+    }
+
+    /// Reads and parses a plan file, then resolves its relative local paths.
+    fn load(path: &Path, parse: fn(&str) -> Result<Self>) -> Result<Self> {
+        let string = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read {:?}: {}", path, e))?;
+        let mut plan =
+            parse(&string).map_err(|e| anyhow::anyhow!("Failed to parse {:?}: {}", path, e))?;
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        for step in &mut plan.steps {
+            match &mut step.action {
+                MasshAction::Execute(_) => {}
+                MasshAction::Script(local_path)
+                | MasshAction::Upload { local_path, .. }
+                | MasshAction::Download { local_path, .. } => *local_path = dir.join(&local_path),
+            }
+        }
+        Ok(plan)
+    }
+}
+
+impl MasshStep {
+    /// Returns the name of the step, or a short description of its action if it has none.
+    pub fn describe(&self) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+        match &self.action {
+            MasshAction::Execute(command) => format!("execute {}", command),
+            MasshAction::Script(path) => format!("script {:?}", path),
+            MasshAction::Upload {
+                local_path,
+                remote_path,
+            } => format!("upload {:?} to {:?}", local_path, remote_path),
+            MasshAction::Download {
+                remote_path,
+                local_path,
+            } => format!("download {:?} to {:?}", remote_path, local_path),
+        }
+    }
+}
@@ -0,0 +1,275 @@
+use crate::ssh_client::{copy_throttled, utf8_path};
+use crate::{shell, SshClient};
+use anyhow::Result;
+use serde::Serialize;
+use ssh2::{ErrorCode, OpenFlags, OpenType};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Status code of SFTP for a file which doesn't exist (`LIBSSH2_FX_NO_SUCH_FILE`).
+const SFTP_NO_SUCH_FILE: i32 = 2;
+
+/// Shell script which prints the size, mode (in hexadecimal) and modification time of the file
+/// at `$1`, with GNU or BSD `stat`, or `missing` if it doesn't exist.
+const STAT: &str = r#"[ -e "$1" ] || { echo missing; exit 0; }
+stat -L -c '%s %f %Y' -- "$1" 2>/dev/null || stat -L -f '%z %Xp %m' -- "$1""#;
+
+/// Metadata of a remote file, returned by [`SshClient::remote_stat`].
+///
+/// Symbolic links are followed, so the metadata is the one of their target.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct SshFileInfo {
+    /// Whether the file is a directory
+    pub is_dir: bool,
+    /// Permission bits of the file (e.g. `0o644`)
+    pub mode: u32,
+    /// Last modification time of the file, in seconds since the Unix epoch
+    pub mtime: u64,
+    /// Size of the file, in bytes
+    pub size: u64,
+}
+
+impl SshFileInfo {
+    /// Parses the line printed by the stat script, where the mode includes the file type.
+    fn parse(output: &str) -> Result<Self> {
+        let fields: Vec<_> = output.split_whitespace().collect();
+        let parsed = match fields[..] {
+            [size, mode, mtime] => (|| {
+                let mode = u32::from_str_radix(mode, 16).ok()?;
+                Some(SshFileInfo {
+                    is_dir: mode & 0o170000 == 0o040000,
+                    mode: mode & 0o7777,
+                    mtime: mtime.parse().ok()?,
+                    size: size.parse().ok()?,
+                })
+            })(),
+            _ => None,
+        };
+        parsed.ok_or_else(|| anyhow::anyhow!("Unexpected output of stat: {:?}", output.trim()))
+    }
+}
+
+impl SshClient {
+    /// Attempts to get the metadata of a remote file, or `None` if it doesn't exist.
+    ///
+    /// The metadata is read over SFTP, or with `stat` if the server doesn't support SFTP or
+    /// with backends other than libssh2 (which requires a POSIX shell). See [`SshFileInfo`] for
+    /// more details.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// match ssh.remote_stat("/etc/app.conf").unwrap() {
+    ///     Some(info) => println!("{} bytes, mode {:o}", info.size, info.mode),
+    ///     None => println!("missing"),
+    /// }
+    /// ```
+    pub fn remote_stat<P: AsRef<Path>>(&mut self, remote_path: P) -> Result<Option<SshFileInfo>> {
+        let remote_path = remote_path.as_ref();
+        if let Some(sftp) = self.sftp() {
+            return match sftp.stat(remote_path) {
+                Ok(stat) => Ok(Some(SshFileInfo {
+                    is_dir: stat.is_dir(),
+                    mode: stat.perm.unwrap_or(0) & 0o7777,
+                    mtime: stat.mtime.unwrap_or(0),
+                    size: stat.size.unwrap_or(0),
+                })),
+                Err(error) if error.code() == ErrorCode::SFTP(SFTP_NO_SUCH_FILE) => Ok(None),
+                Err(error) => Err(error.into()),
+            };
+        }
+        self.get_shell().ensure_posix()?;
+        let command = format!(
+            "sh -c {} sh {}",
+            shell::quote(STAT),
+            shell::quote(utf8_path(remote_path)?)
+        );
+        let output = self.execute_checked(&command, "Stat failed")?;
+        match output.stdout_lossy().trim() {
+            "missing" => Ok(None),
+            stdout => SshFileInfo::parse(stdout).map(Some),
+        }
+    }
+
+    /// Attempts to get the free space available to the user on the filesystem of a remote
+    /// directory (or file), in bytes.
+    ///
+    /// The space is read over SFTP (with the `statvfs@openssh.com` extension), or with `df` if
+    /// the server doesn't support it or with backends other than libssh2 (which requires a
+    /// POSIX shell).
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// let free_space = ssh.remote_free_space("/var/lib").unwrap();
+    /// println!("{} GiB free", free_space >> 30);
+    /// ```
+    pub fn remote_free_space<P: AsRef<Path>>(&mut self, remote_path: P) -> Result<u64> {
+        let remote_path = remote_path.as_ref();
+        if let Some(sftp) = self.sftp() {
+            let statvfs = sftp.opendir(remote_path).and_then(|mut dir| dir.statvfs());
+            if let Ok(statvfs) = statvfs {
+                return Ok(statvfs.f_bavail * statvfs.f_frsize);
+            }
+        }
+        self.get_shell().ensure_posix()?;
+        let command = format!(
+            "df -Pk -- {} | awk 'NR == 2 {{ print $4 }}'",
+            shell::quote(utf8_path(remote_path)?)
+        );
+        let output = self.execute_checked(&command, "Free space check failed")?;
+        let stdout = output.stdout_lossy();
+        match stdout.trim().parse::<u64>() {
+            Ok(kilobytes) => Ok(kilobytes * 1024),
+            Err(_) => Err(anyhow::anyhow!(
+                "Unexpected output of df: {:?}",
+                stdout.trim()
+            )),
+        }
+    }
+
+    /// Fails with an "insufficient space" error if the filesystem of the destination of an
+    /// upload doesn't have room for a file of the specified length, and for one of its chunks
+    /// if it's uploaded in chunks (see `SshClient::set_check_free_space`).
+    pub(crate) fn ensure_free_space(&mut self, remote_path: &Path, len: u64) -> Result<()> {
+        let dir = match remote_path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let chunks = self.chunk_count(len);
+        let needed = if chunks > 1 {
+            len + len.div_ceil(chunks)
+        } else {
+            len
+        };
+        let available = self.remote_free_space(dir)?;
+        if available < needed {
+            return Err(anyhow::anyhow!(
+                "Insufficient space in {:?}: {} bytes needed, {} bytes available",
+                dir,
+                needed,
+                available
+            ));
+        }
+        Ok(())
+    }
+
+    /// Attempts to append bytes to a remote file, which is created with mode `644` if it
+    /// doesn't exist.
+    ///
+    /// The bytes are written over SFTP, or piped to `cat >>` if the server doesn't support SFTP
+    /// or with backends other than libssh2 (which requires a POSIX shell). The rate limits of
+    /// file transfers apply.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// ssh.append_file("/var/log/deploys.log", b"deployed v1.2.3\n").unwrap();
+    /// ```
+    pub fn append_file<P: AsRef<Path>>(&mut self, remote_path: P, bytes: &[u8]) -> Result<()> {
+        let remote_path = remote_path.as_ref();
+        if let Some(sftp) = self.sftp() {
+            let flags = OpenFlags::WRITE | OpenFlags::APPEND | OpenFlags::CREATE;
+            let mut file = sftp.open_mode(remote_path, flags, 0o644, OpenType::File)?;
+            // Some servers ignore the append flag, so write explicitly at the end of the file.
+            let size = file.stat()?.size.unwrap_or(0);
+            file.seek(SeekFrom::Start(size))?;
+            copy_throttled(&mut &bytes[..], &mut file, &self.rate_limiters())?;
+            return Ok(());
+        }
+        self.get_shell().ensure_posix()?;
+        let command = format!("cat >> {}", shell::quote(utf8_path(remote_path)?));
+        self.execute_with_input(&command, &mut &bytes[..], "Append failed")
+    }
+
+    /// Attempts to read at most `len` bytes of a remote file, starting at byte `offset`.
+    ///
+    /// Unlike [`SshClient::scp_download`], only the requested range is transferred, which makes
+    /// it suitable to collect snippets of large files. Fewer bytes are returned if the file ends
+    /// before the end of the range. The range is read over SFTP, or with `tail` and `head` if
+    /// the server doesn't support SFTP or with backends other than libssh2 (which requires a
+    /// POSIX shell). The rate limits of file transfers apply.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// let header = ssh.read_range("/var/lib/app/data.bin", 0, 512).unwrap();
+    /// ```
+    pub fn read_range<P: AsRef<Path>>(
+        &mut self,
+        remote_path: P,
+        offset: u64,
+        len: u64,
+    ) -> Result<Vec<u8>> {
+        let remote_path = remote_path.as_ref();
+        if let Some(sftp) = self.sftp() {
+            let mut file = sftp.open(remote_path)?;
+            file.seek(SeekFrom::Start(offset))?;
+            return self.read_sftp(file.take(len));
+        }
+        let command = format!(
+            "exec < {} && tail -c +{} | head -c {}",
+            shell::quote(utf8_path(remote_path)?),
+            offset + 1,
+            len
+        );
+        self.read_exec(&command)
+    }
+
+    /// Attempts to read the last `len` bytes of a remote file (like `tail -c`), or the whole
+    /// file if it's smaller.
+    ///
+    /// See [`SshClient::read_range`] for more details.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// let snippet = ssh.read_last("/var/log/syslog", 64 * 1024).unwrap();
+    /// println!("{}", String::from_utf8_lossy(&snippet));
+    /// ```
+    pub fn read_last<P: AsRef<Path>>(&mut self, remote_path: P, len: u64) -> Result<Vec<u8>> {
+        let remote_path = remote_path.as_ref();
+        if let Some(sftp) = self.sftp() {
+            let mut file = sftp.open(remote_path)?;
+            let size = file.stat()?.size.unwrap_or(0);
+            file.seek(SeekFrom::Start(size.saturating_sub(len)))?;
+            return self.read_sftp(file.take(len));
+        }
+        let command = format!(
+            "tail -c {} -- {}",
+            len,
+            shell::quote(utf8_path(remote_path)?)
+        );
+        self.read_exec(&command)
+    }
+
+    /// Reads a remote file over SFTP, throttled if specified.
+    fn read_sftp(&self, mut reader: impl Read) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        copy_throttled(&mut reader, &mut bytes, &self.rate_limiters())?;
+        Ok(bytes)
+    }
+
+    /// Reads the standard output of a POSIX command which prints part of a remote file.
+    fn read_exec(&mut self, command: &str) -> Result<Vec<u8>> {
+        self.get_shell().ensure_posix()?;
+        let mut bytes = Vec::new();
+        self.execute_with_output(command, &mut bytes, "Read failed")?;
+        Ok(bytes)
+    }
+}
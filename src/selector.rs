@@ -0,0 +1,292 @@
+use crate::MasshHostInfo;
+use anyhow::Result;
+
+/// Expression selecting hosts by their group, labels and identifier (e.g. `massh --select`).
+///
+/// An expression combines conditions with `and`, `or`, `not` and parentheses, where `not` binds
+/// tighter than `and`, which binds tighter than `or`. The conditions are:
+/// - `group:PATTERN`: the group of the host matches the pattern.
+/// - `label:KEY`: the host has the label.
+/// - `label:KEY=PATTERN`: the value of the label of the host matches the pattern.
+/// - `host:PATTERN`: the identifier of the host (its alias, or `username@ip_address:port`)
+///   or the hostname it was configured with, if any, matches the pattern.
+///
+/// A pattern is a glob pattern (e.g. `web-*`), or a substring if prefixed with `~` (e.g.
+/// `~canary`). Labels include those inherited from the group of the host.
+///
+/// ## Example
+/// ```
+/// use massh::{MasshClient, MasshConfig, MasshHostSelector};
+///
+/// let yaml = "
+/// groups:
+///   - name: web
+///     hosts: [root@10.0.0.1, root@10.0.0.2]
+///     labels: {dc: fra}
+///   - name: db
+///     hosts: [root@10.0.1.1]
+///     labels: {dc: fra}
+/// hosts:
+///   - {addr: 10.0.2.1, user: canary, labels: {dc: ams}}
+///   - root@localhost
+/// ";
+/// let massh = MasshClient::from(&MasshConfig::from_yaml(yaml).unwrap());
+///
+/// let selector: MasshHostSelector = "group:web and not host:*@10.0.0.2:*".parse().unwrap();
+/// let hosts: Vec<_> = massh.select(&selector).iter().map(|(host, _)| host.clone()).collect();
+/// assert_eq!(hosts, ["root@10.0.0.1:22"]);
+///
+/// let selector: MasshHostSelector = "label:dc=fra and not (group:db or host:~canary)"
+///     .parse()
+///     .unwrap();
+/// assert_eq!(massh.select(&selector).len(), 2);
+///
+/// let selector: MasshHostSelector = "host:local*".parse().unwrap();
+/// assert_eq!(massh.select(&selector).len(), 1);
+///
+/// assert!("group:web and".parse::<MasshHostSelector>().is_err());
+/// ```
+#[derive(Clone, Debug)]
+pub struct MasshHostSelector {
+    expr: Expr,
+}
+
+impl MasshHostSelector {
+    /// Returns whether the specified host is selected by this expression.
+    pub fn matches(&self, info: &MasshHostInfo) -> bool {
+        self.expr.matches(info)
+    }
+}
+
+impl std::str::FromStr for MasshHostSelector {
+    type Err = anyhow::Error;
+
+    fn from_str(string: &str) -> Result<Self> {
+        let tokens = tokenize(string);
+        let mut parser = Parser { tokens, index: 0 };
+        let expr = parser.or()?;
+        match parser.tokens.get(parser.index) {
+            Some(token) => Err(anyhow::anyhow!(
+                "Unexpected {:?} in host selector {:?}",
+                token,
+                string
+            )),
+            None => Ok(MasshHostSelector { expr }),
+        }
+    }
+}
+
+/// Node of the syntax tree of a `MasshHostSelector`.
+#[derive(Clone, Debug)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Group(Pattern),
+    Label(String, Option<Pattern>),
+    Host(Pattern),
+}
+
+impl Expr {
+    fn matches(&self, info: &MasshHostInfo) -> bool {
+        match self {
+            Expr::And(a, b) => a.matches(info) && b.matches(info),
+            Expr::Or(a, b) => a.matches(info) || b.matches(info),
+            Expr::Not(a) => !a.matches(info),
+            Expr::Group(pattern) => info.group.as_deref().is_some_and(|g| pattern.matches(g)),
+            Expr::Label(key, pattern) => match (info.labels.get(key), pattern) {
+                (Some(value), Some(pattern)) => pattern.matches(value),
+                (Some(_), None) => true,
+                (None, _) => false,
+            },
+            Expr::Host(pattern) => {
+                pattern.matches(&info.to_string())
+                    || info.hostname.as_deref().is_some_and(|h| pattern.matches(h))
+            }
+        }
+    }
+}
+
+/// Pattern of a condition of a `MasshHostSelector`: a glob pattern, or a substring.
+#[derive(Clone, Debug)]
+enum Pattern {
+    Glob(glob::Pattern),
+    Substring(String),
+}
+
+impl Pattern {
+    fn new(pattern: &str) -> Result<Self> {
+        match pattern.strip_prefix('~') {
+            Some(substring) => Ok(Pattern::Substring(substring.to_string())),
+            None => glob::Pattern::new(pattern)
+                .map(Pattern::Glob)
+                .map_err(|error| anyhow::anyhow!("Invalid pattern {:?}: {}", pattern, error)),
+        }
+    }
+
+    fn matches(&self, text: &str) -> bool {
+        match self {
+            Pattern::Glob(pattern) => pattern.matches(text),
+            Pattern::Substring(substring) => text.contains(substring.as_str()),
+        }
+    }
+}
+
+/// Splits a host selector into words and parentheses.
+fn tokenize(string: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    for c in string.chars() {
+        if c.is_whitespace() || c == '(' || c == ')' {
+            if !word.is_empty() {
+                tokens.push(std::mem::take(&mut word));
+            }
+            if !c.is_whitespace() {
+                tokens.push(c.to_string());
+            }
+        } else {
+            word.push(c);
+        }
+    }
+    if !word.is_empty() {
+        tokens.push(word);
+    }
+    tokens
+}
+
+/// Recursive descent parser of the tokens of a host selector.
+struct Parser {
+    tokens: Vec<String>,
+    index: usize,
+}
+
+impl Parser {
+    /// Consumes the next token if it's the specified keyword.
+    fn eat(&mut self, keyword: &str) -> bool {
+        let found = self.tokens.get(self.index).map(String::as_str) == Some(keyword);
+        if found {
+            self.index += 1;
+        }
+        found
+    }
+
+    /// Parses conditions separated by `or`.
+    fn or(&mut self) -> Result<Expr> {
+        let mut expr = self.and()?;
+        while self.eat("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    /// Parses conditions separated by `and`.
+    fn and(&mut self) -> Result<Expr> {
+        let mut expr = self.not()?;
+        while self.eat("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    /// Parses a condition, negated by any number of `not`.
+    fn not(&mut self) -> Result<Expr> {
+        if self.eat("not") {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        if self.eat("(") {
+            let expr = self.or()?;
+            if !self.eat(")") {
+                return Err(anyhow::anyhow!("Expected `)` in host selector"));
+            }
+            return Ok(expr);
+        }
+        let token = match self.tokens.get(self.index) {
+            Some(token) => token.clone(),
+            None => return Err(anyhow::anyhow!("Unexpected end of host selector")),
+        };
+        self.index += 1;
+        match token.split_once(':') {
+            Some(("group", pattern)) => Ok(Expr::Group(Pattern::new(pattern)?)),
+            Some(("label", label)) => match label.split_once('=') {
+                Some((key, pattern)) => {
+                    Ok(Expr::Label(key.to_string(), Some(Pattern::new(pattern)?)))
+                }
+                None => Ok(Expr::Label(label.to_string(), None)),
+            },
+            Some(("host", pattern)) => Ok(Expr::Host(Pattern::new(pattern)?)),
+            _ => Err(anyhow::anyhow!(
+                "Expected group:PATTERN, label:KEY[=PATTERN] or host:PATTERN in host selector, \
+                 found {:?}",
+                token
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    /// Returns the information of a host of the `web` group, labeled `dc=fra`.
+    fn web_host(hostname: Option<&str>) -> MasshHostInfo {
+        MasshHostInfo {
+            addr: "10.0.0.1:22".parse().unwrap(),
+            alias: None,
+            group: Some("web".to_string()),
+            hostname: hostname.map(str::to_string),
+            labels: BTreeMap::from([("dc".to_string(), "fra".to_string())]),
+            shell: Default::default(),
+            user: "root".to_string(),
+        }
+    }
+
+    fn selects(selector: &str, info: &MasshHostInfo) -> bool {
+        selector.parse::<MasshHostSelector>().unwrap().matches(info)
+    }
+
+    #[test]
+    fn conditions() {
+        let info = web_host(Some("web-1.example.com"));
+        assert!(selects("group:web", &info));
+        assert!(selects("group:w*", &info));
+        assert!(!selects("group:db", &info));
+        assert!(selects("label:dc", &info));
+        assert!(selects("label:dc=fra", &info));
+        assert!(selects("label:dc=~r", &info));
+        assert!(!selects("label:dc=ams", &info));
+        assert!(!selects("label:env", &info));
+        assert!(selects("host:root@10.0.0.1:22", &info));
+        assert!(selects("host:*.example.com", &info));
+        assert!(!selects("host:*.example.com", &web_host(None)));
+    }
+
+    #[test]
+    fn precedence_and_parentheses() {
+        let info = web_host(None);
+        assert!(selects("group:db or group:web and label:dc", &info));
+        assert!(!selects("(group:db or group:web) and label:env", &info));
+        assert!(selects("not group:db and not not label:dc", &info));
+        assert!(!selects("not (group:db or group:web)", &info));
+        assert!(selects("(group:web)and(label:dc)", &info));
+    }
+
+    #[test]
+    fn syntax_errors() {
+        for selector in [
+            "",
+            "group:web and",
+            "(group:web",
+            "group:web)",
+            "group:web label:dc",
+            "web",
+            "group:[",
+        ] {
+            assert!(
+                selector.parse::<MasshHostSelector>().is_err(),
+                "{:?}",
+                selector
+            );
+        }
+    }
+}
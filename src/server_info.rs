@@ -0,0 +1,171 @@
+use crate::shell::base64;
+use crate::ssh_client::connect_any;
+use crate::{MethodType, SshClient};
+use anyhow::Result;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use ssh2::{HashType, Session};
+
+/// Identification and negotiated algorithms of a host's SSH server,
+/// inspected by [`SshClient::server_info`].
+///
+/// Algorithms are named as in the SSH protocol (e.g. `curve25519-sha256`), and are split
+/// by direction where they can differ.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct SshServerInfo {
+    /// Authentication methods offered by the server to the user (e.g. `publickey`, `password`)
+    pub auth_methods: Vec<String>,
+    /// Identification string sent by the server (e.g. `SSH-2.0-OpenSSH_9.6`)
+    pub banner: String,
+    /// Cipher from the client to the server
+    pub cipher_client_to_server: String,
+    /// Cipher from the server to the client
+    pub cipher_server_to_client: String,
+    /// Compression from the client to the server (`none` if disabled)
+    pub compression_client_to_server: String,
+    /// Compression from the server to the client (`none` if disabled)
+    pub compression_server_to_client: String,
+    /// MD5 fingerprint of the host key, formatted like OpenSSH (e.g. `MD5:4f:a2:...`)
+    pub fingerprint_md5: String,
+    /// SHA-256 fingerprint of the host key, formatted like OpenSSH (e.g. `SHA256:nThbg6k...`)
+    pub fingerprint_sha256: String,
+    /// Host key algorithm (e.g. `ssh-ed25519`)
+    pub host_key_algorithm: String,
+    /// Key exchange algorithm
+    pub kex: String,
+    /// MAC from the client to the server (empty for AEAD ciphers on some servers)
+    pub mac_client_to_server: String,
+    /// MAC from the server to the client (empty for AEAD ciphers on some servers)
+    pub mac_server_to_client: String,
+}
+
+impl SshClient {
+    /// Attempts to inspect the SSH server of the configured host, from the handshake
+    /// of its session.
+    ///
+    /// It returns the server's identification string, the negotiated algorithms, and the
+    /// fingerprints of the host key. See [`SshServerInfo`] for more details. The authentication
+    /// methods offered to the user are inspected from the handshake of a separate session, which
+    /// is never authenticated, since the server only lists them before authentication.
+    ///
+    /// Note that this method implicitly calls [`SshClient::connect`] if no session was
+    /// established prior. Otherwise, it inspects the cached session.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// if let Ok(info) = ssh.server_info() {
+    ///     println!("{} uses {}", info.banner, info.kex);
+    /// }
+    /// ```
+    pub fn server_info(&mut self) -> Result<SshServerInfo> {
+        let session = self.connected_session()?;
+        let method = |method| session.methods(method).unwrap_or_default().to_string();
+        let mut info = SshServerInfo {
+            auth_methods: Vec::new(),
+            banner: session.banner().unwrap_or_default().to_string(),
+            cipher_client_to_server: method(MethodType::CryptCs),
+            cipher_server_to_client: method(MethodType::CryptSc),
+            compression_client_to_server: method(MethodType::CompCs),
+            compression_server_to_client: method(MethodType::CompSc),
+            fingerprint_md5: fingerprint(session, "MD5")?,
+            fingerprint_sha256: fingerprint(session, "SHA256")?,
+            host_key_algorithm: method(MethodType::HostKey),
+            kex: method(MethodType::Kex),
+            mac_client_to_server: method(MethodType::MacCs),
+            mac_server_to_client: method(MethodType::MacSc),
+        };
+        info.auth_methods = auth_methods(self)?;
+        Ok(info)
+    }
+}
+
+/// Returns the authentication methods offered by the server of a client to its user, from the
+/// handshake of a new session, or `none` if the server accepts the user without authentication.
+fn auth_methods(client: &SshClient) -> Result<Vec<String>> {
+    let timeout = client.get_timeout();
+    let (tcp_stream, _) = connect_any(&[client.get_addr()], timeout)?;
+    let mut session = Session::new()?;
+    if timeout != 0 {
+        session.set_timeout(timeout as u32);
+    }
+    session.set_tcp_stream(tcp_stream);
+    session.handshake()?;
+    match session.auth_methods(client.get_user()) {
+        Ok(methods) => Ok(methods
+            .split(',')
+            .filter(|method| !method.is_empty())
+            .map(str::to_string)
+            .collect()),
+        Err(_) if session.authenticated() => Ok(vec!["none".to_string()]),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Returns the fingerprint of the host key of a session, for a hash algorithm (`SHA256` or `MD5`),
+/// formatted like OpenSSH.
+fn fingerprint(session: &Session, algorithm: &str) -> Result<String> {
+    let (host_key, _) = session
+        .host_key()
+        .ok_or_else(|| anyhow::anyhow!("Failed to get the host key"))?;
+    match algorithm {
+        "SHA256" => Ok(sha256_fingerprint(host_key)),
+        "MD5" => {
+            let hash = session
+                .host_key_hash(HashType::Md5)
+                .ok_or_else(|| anyhow::anyhow!("Failed to hash the host key"))?;
+            let hex: Vec<String> = hash.iter().map(|byte| format!("{:02x}", byte)).collect();
+            Ok(format!("MD5:{}", hex.join(":")))
+        }
+        _ => Err(anyhow::anyhow!(
+            "Unsupported fingerprint algorithm {}",
+            algorithm
+        )),
+    }
+}
+
+/// Returns the SHA-256 fingerprint of a public key blob, formatted like OpenSSH.
+pub(crate) fn sha256_fingerprint(blob: &[u8]) -> String {
+    format!("SHA256:{}", base64(&Sha256::digest(blob), false))
+}
+
+/// Returns whether a string is a fingerprint formatted like OpenSSH (see `SshClient::set_host_key`).
+pub(crate) fn is_fingerprint(string: &str) -> bool {
+    match string.split_once(':') {
+        Some(("SHA256", hash)) => hash.trim_end_matches('=').len() == 43,
+        Some(("MD5", hash)) => {
+            hash.split(':').count() == 16
+                && hash
+                    .split(':')
+                    .all(|byte| byte.len() == 2 && byte.chars().all(|c| c.is_ascii_hexdigit()))
+        }
+        _ => false,
+    }
+}
+
+/// Verifies that the host key of a session matches the expected fingerprint.
+pub(crate) fn verify_host_key(session: &Session, expected: &str) -> Result<()> {
+    if !is_fingerprint(expected) {
+        return Err(anyhow::anyhow!(
+            "Invalid host key fingerprint {:?}",
+            expected
+        ));
+    }
+    let (algorithm, hash) = expected.split_once(':').unwrap();
+    let actual = fingerprint(session, algorithm)?;
+    let matches = match algorithm {
+        "SHA256" => actual[7..] == *hash.trim_end_matches('='),
+        _ => actual[4..].eq_ignore_ascii_case(hash),
+    };
+    if !matches {
+        return Err(anyhow::anyhow!(
+            "Host key verification failed: expected {}, but the server's key is {}",
+            expected,
+            actual
+        ));
+    }
+    Ok(())
+}
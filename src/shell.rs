@@ -0,0 +1,442 @@
+//! Quoting of strings in the commands composed by massh.
+//!
+//! Every command composed by massh (e.g. to run a command in a working directory, with
+//! environment variables, or on remote paths) quotes its arguments with these functions, so that
+//! spaces, quotes and other special characters are never interpreted by the remote shell.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// Quotes a string for a POSIX shell, so that it's interpreted as a single literal word.
+///
+/// The string is enclosed in single quotes, in which no character is special, and each single
+/// quote is written as `'\''`, i.e. closing the quotes, an escaped quote and reopening them.
+///
+/// ## Example
+/// ```
+/// use massh::shell::quote;
+///
+/// assert_eq!(quote("file.txt"), "'file.txt'");
+/// assert_eq!(quote("my file; rm -rf ~"), "'my file; rm -rf ~'");
+/// assert_eq!(quote("it's"), r"'it'\''s'");
+/// assert_eq!(quote(""), "''");
+/// ```
+pub fn quote(string: &str) -> String {
+    format!("'{}'", string.replace('\'', "'\\''"))
+}
+
+/// Quotes each argument for a POSIX shell (see [`quote`]), and joins them with spaces.
+///
+/// ## Example
+/// ```
+/// use massh::shell::quote_args;
+///
+/// let command = format!("ls -l -- {}", quote_args(["a b", "$HOME", "*.txt"]));
+/// assert_eq!(command, "ls -l -- 'a b' '$HOME' '*.txt'");
+/// ```
+pub fn quote_args<I, S>(args: I) -> String
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let args: Vec<String> = args.into_iter().map(|arg| quote(arg.as_ref())).collect();
+    args.join(" ")
+}
+
+/// Shell which runs the commands of an `SshClient` on its host, i.e. the default shell of the
+/// remote user (see [`SshClient::set_shell`]).
+///
+/// The shell determines how the commands composed by massh are written: how strings are quoted,
+/// how commands are run in a working directory or with environment variables, how local scripts
+/// are executed, and how uploaded files are renamed, removed and checksummed.
+///
+/// Other operations run POSIX commands, and thus require a POSIX shell: [`SshClient::copy_id`],
+/// [`SshClient::gather_facts`], directory transfers and synchronization, changing the owner of
+/// uploaded files, stop signals, and every backend other than libssh2.
+///
+/// The default is [`SshShell::Posix`].
+///
+/// [`SshClient::copy_id`]: crate::SshClient::copy_id
+/// [`SshClient::gather_facts`]: crate::SshClient::gather_facts
+/// [`SshClient::set_shell`]: crate::SshClient::set_shell
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SshShell {
+    /// POSIX shell (e.g. `sh` or `bash`), the default shell on Unix hosts.
+    #[default]
+    Posix,
+    /// PowerShell, which is often configured as the default shell of Windows OpenSSH servers.
+    #[serde(rename = "powershell")]
+    PowerShell,
+    /// `cmd.exe`, the default shell of Windows OpenSSH servers.
+    ///
+    /// Local scripts can't be executed, and `%` can't be escaped in quoted strings.
+    Cmd,
+}
+
+impl std::fmt::Display for SshShell {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SshShell::Posix => write!(f, "posix"),
+            SshShell::PowerShell => write!(f, "powershell"),
+            SshShell::Cmd => write!(f, "cmd"),
+        }
+    }
+}
+
+impl SshShell {
+    /// Quotes a string so that it's interpreted as a single literal word by this shell.
+    pub(crate) fn quote(self, string: &str) -> String {
+        match self {
+            SshShell::Posix => quote(string),
+            SshShell::PowerShell => {
+                // PowerShell also treats typographic single quotes as quotes.
+                let mut quoted = String::with_capacity(string.len() + 2);
+                quoted.push('\'');
+                for c in string.chars() {
+                    if matches!(c, '\'' | '\u{2018}' | '\u{2019}' | '\u{201a}' | '\u{201b}') {
+                        quoted.push(c);
+                    }
+                    quoted.push(c);
+                }
+                quoted.push('\'');
+                quoted
+            }
+            SshShell::Cmd => format!("\"{}\"", string.replace('"', "\"\"")),
+        }
+    }
+
+    /// Returns a command which runs another command in a directory, and fails if the directory
+    /// doesn't exist. A leading `~/` is relative to the home directory of the user.
+    pub(crate) fn in_dir(self, dir: &str, command: &str) -> String {
+        let home = dir.strip_prefix("~/");
+        match self {
+            SshShell::Posix => {
+                let dir = match home {
+                    Some(rest) => format!("~/{}", self.quote(rest)),
+                    None => self.quote(dir),
+                };
+                format!("cd -- {} && {{ {}\n}}", dir, command)
+            }
+            SshShell::PowerShell => {
+                let cd = "Set-Location -ErrorAction Stop -LiteralPath";
+                match home {
+                    Some(rest) => format!("{0} $HOME; {0} {1}; {2}", cd, self.quote(rest), command),
+                    None => format!("{} {}; {}", cd, self.quote(dir), command),
+                }
+            }
+            SshShell::Cmd => match home {
+                Some(rest) => format!(
+                    "cd /d \"%USERPROFILE%\" && cd {} && {}",
+                    self.quote(rest),
+                    command
+                ),
+                None => format!("cd /d {} && {}", self.quote(dir), command),
+            },
+        }
+    }
+
+    /// Returns a command prefixed with environment variables, or the command as is if there
+    /// are none. The names of the variables must be valid.
+    pub(crate) fn with_env(self, command: &str, env: &[(&str, &str)]) -> String {
+        if env.is_empty() {
+            return command.to_string();
+        }
+        let mut prefixed = String::new();
+        match self {
+            SshShell::Posix => {
+                prefixed.push_str("env");
+                for (name, value) in env {
+                    prefixed.push(' ');
+                    prefixed.push_str(&self.quote(&format!("{}={}", name, value)));
+                }
+                prefixed.push_str(" sh -c ");
+                prefixed.push_str(&self.quote(command));
+                return prefixed;
+            }
+            SshShell::PowerShell => {
+                for (name, value) in env {
+                    prefixed.push_str(&format!("$env:{} = {}; ", name, self.quote(value)));
+                }
+            }
+            SshShell::Cmd => {
+                for (name, value) in env {
+                    let assignment = format!("{}={}", name, value);
+                    prefixed.push_str(&format!("set {} && ", self.quote(&assignment)));
+                }
+            }
+        }
+        prefixed.push_str(command);
+        prefixed
+    }
+
+    /// Returns a command which runs another command as a user through `sudo`, which fails
+    /// instead of prompting for a password.
+    ///
+    /// If `password` is true, the password is read from the first line of the standard input of
+    /// the command, but it's only passed to `sudo` (without printing a prompt) if `sudo` can't
+    /// run the command without it, e.g. without a `NOPASSWD` rule. Either way, the command itself
+    /// never reads it.
+    pub(crate) fn as_user(self, user: &str, password: bool, command: &str) -> Result<String> {
+        self.ensure_posix()?;
+        let (user, command) = (self.quote(user), self.quote(command));
+        if !password {
+            return Ok(format!("sudo -n -u {} -- sh -c {}", user, command));
+        }
+        Ok(format!(
+            "IFS= read -r password; if sudo -n -u {0} -- sh -c : 2>/dev/null; \
+             then sudo -n -u {0} -- sh -c {1}; \
+             else printf '%s\\n' \"$password\" | sudo -S -p '' -u {0} -- sh -c {1}; fi",
+            user, command
+        ))
+    }
+
+    /// Returns a command which executes a local script, without copying it to the host.
+    pub(crate) fn script(self, script: &str) -> Result<String> {
+        match self {
+            SshShell::Posix => Ok(format!("sh -c {} sh", self.quote(script))),
+            SshShell::PowerShell => {
+                let utf16: Vec<u8> = script.encode_utf16().flat_map(u16::to_le_bytes).collect();
+                Ok(format!(
+                    "powershell -NoProfile -NonInteractive -EncodedCommand {}",
+                    base64(&utf16, true)
+                ))
+            }
+            SshShell::Cmd => Err(anyhow::anyhow!(
+                "Scripts can't be executed by the cmd shell"
+            )),
+        }
+    }
+
+    /// Returns a command which renames a file, replacing the destination if it exists.
+    pub(crate) fn rename(self, from: &str, to: &str) -> String {
+        let (from, to) = (self.quote(from), self.quote(to));
+        match self {
+            SshShell::Posix => format!("mv -f -- {} {}", from, to),
+            SshShell::PowerShell => {
+                format!("Move-Item -Force -LiteralPath {} -Destination {}", from, to)
+            }
+            SshShell::Cmd => format!("move /y {} {}", from, to),
+        }
+    }
+
+    /// Returns a command which removes a file, if it exists.
+    pub(crate) fn remove(self, path: &str) -> String {
+        let path = self.quote(path);
+        match self {
+            SshShell::Posix => format!("rm -f -- {}", path),
+            SshShell::PowerShell => format!(
+                "Remove-Item -Force -ErrorAction SilentlyContinue -LiteralPath {}",
+                path
+            ),
+            SshShell::Cmd => format!("del /f /q {}", path),
+        }
+    }
+
+    /// Returns a command which prints the SHA-256 checksum of a file in hexadecimal, among
+    /// other words (see `parse_sha256`).
+    pub(crate) fn sha256(self, path: &str) -> String {
+        let path = self.quote(path);
+        match self {
+            SshShell::Posix => {
+                format!("sha256sum -- {0} 2>/dev/null || shasum -a 256 -- {0}", path)
+            }
+            SshShell::PowerShell => {
+                format!(
+                    "(Get-FileHash -Algorithm SHA256 -LiteralPath {}).Hash",
+                    path
+                )
+            }
+            SshShell::Cmd => format!("certutil -hashfile {} SHA256", path),
+        }
+    }
+
+    /// Fails if this shell isn't a POSIX shell, for operations which run POSIX commands.
+    pub(crate) fn ensure_posix(self) -> Result<()> {
+        if self != SshShell::Posix {
+            return Err(anyhow::anyhow!(
+                "This operation requires a POSIX shell, not {}",
+                self
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Returns the SHA-256 checksum printed by the command of `SshShell::sha256`, i.e. its first
+/// word of 64 hexadecimal digits, in lowercase.
+pub(crate) fn parse_sha256(output: &str) -> Option<String> {
+    output
+        .split_whitespace()
+        .find(|word| word.len() == 64 && word.chars().all(|c| c.is_ascii_hexdigit()))
+        .map(str::to_ascii_lowercase)
+}
+
+/// Encodes bytes in standard base64, with or without padding (as in OpenSSH's fingerprints).
+pub(crate) fn base64(bytes: &[u8], padding: bool) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else if padding {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Decodes standard base64, with or without padding, or returns `None` if it's invalid.
+pub(crate) fn base64_decode(encoded: &str) -> Option<Vec<u8>> {
+    let digits = encoded
+        .trim_end_matches('=')
+        .bytes()
+        .map(|c| match c {
+            b'A'..=b'Z' => Some(c - b'A'),
+            b'a'..=b'z' => Some(c - b'a' + 26),
+            b'0'..=b'9' => Some(c - b'0' + 52),
+            b'+' => Some(62),
+            b'/' => Some(63),
+            _ => None,
+        })
+        .collect::<Option<Vec<u8>>>()?;
+    if digits.len() % 4 == 1 {
+        return None;
+    }
+    let mut decoded = Vec::with_capacity(digits.len() * 3 / 4);
+    for chunk in digits.chunks(4) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, digit)| n | (*digit as u32) << (18 - 6 * i));
+        for i in 0..chunk.len() - 1 {
+            decoded.push((n >> (16 - 8 * i)) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STRINGS: &[&str] = &[
+        "",
+        "plain",
+        "two words",
+        " leading and trailing ",
+        "tab\tand\nnewline",
+        "it's",
+        "''",
+        "\"double\" quotes",
+        "back\\slash",
+        "$HOME ${PATH} $(id) `id`",
+        "*.txt ?[a-z] ~ {a,b}",
+        "a; b && c || d | e & f",
+        "<in >out 2>&1",
+        "#comment !history %percent",
+        "-n",
+        "caf\u{e9} \u{1f980}",
+    ];
+
+    #[cfg(unix)]
+    fn sh(command: &str) -> Vec<u8> {
+        let output = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        output.stdout
+    }
+
+    #[test]
+    fn quote_encloses_in_single_quotes() {
+        assert_eq!(quote("two words"), "'two words'");
+        assert_eq!(quote("it's"), r"'it'\''s'");
+        assert_eq!(quote("$(id)"), "'$(id)'");
+        assert_eq!(quote(""), "''");
+    }
+
+    #[test]
+    fn quote_args_joins_quoted_args() {
+        assert_eq!(quote_args(["a b", "c'd"]), r"'a b' 'c'\''d'");
+        assert_eq!(quote_args(vec![String::from("x")]), "'x'");
+        assert_eq!(quote_args(Vec::<String>::new()), "");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn quote_is_a_single_literal_word() {
+        for string in STRINGS {
+            let output = sh(&format!("printf '%s|' {}", quote(string)));
+            assert_eq!(output, format!("{}|", string).into_bytes(), "{:?}", string);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn quote_args_are_literal_words() {
+        let output = sh(&format!("printf '%s|' {}", quote_args(STRINGS)));
+        let expected: String = STRINGS
+            .iter()
+            .map(|string| format!("{}|", string))
+            .collect();
+        assert_eq!(output, expected.into_bytes());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn with_env_and_in_dir_keep_values_literal() {
+        let shell = SshShell::Posix;
+        for string in STRINGS {
+            let command = shell.with_env("printf '%s|' \"$VALUE\"", &[("VALUE", string)]);
+            let output = sh(&shell.in_dir("/", &command));
+            assert_eq!(output, format!("{}|", string).into_bytes(), "{:?}", string);
+        }
+    }
+
+    #[test]
+    fn as_user_quotes_the_command() {
+        let shell = SshShell::Posix;
+        assert_eq!(
+            shell
+                .as_user("deploy", false, "echo 'hi' > $HOME/out")
+                .unwrap(),
+            r"sudo -n -u 'deploy' -- sh -c 'echo '\''hi'\'' > $HOME/out'"
+        );
+        assert_eq!(
+            shell.as_user("root", true, "id -u").unwrap(),
+            "IFS= read -r password; if sudo -n -u 'root' -- sh -c : 2>/dev/null; \
+             then sudo -n -u 'root' -- sh -c 'id -u'; \
+             else printf '%s\\n' \"$password\" | sudo -S -p '' -u 'root' -- sh -c 'id -u'; fi"
+        );
+        assert!(SshShell::Cmd.as_user("root", false, "whoami").is_err());
+    }
+
+    #[test]
+    fn base64_round_trips() {
+        assert_eq!(base64(b"massh", true), "bWFzc2g=");
+        assert_eq!(base64(b"massh", false), "bWFzc2g");
+        assert_eq!(base64_decode("bWFzc2g=").unwrap(), b"massh");
+        assert_eq!(base64_decode("bWFzc2g").unwrap(), b"massh");
+        for len in 0..=8 {
+            let bytes: Vec<u8> = (0..len).map(|i| 0xfe - i as u8).collect();
+            assert_eq!(base64_decode(&base64(&bytes, true)).unwrap(), bytes);
+        }
+        assert!(base64_decode("bWFzc2g*").is_none());
+        assert!(base64_decode("bWFzc").is_none());
+    }
+
+    #[test]
+    fn powershell_and_cmd_quotes() {
+        assert_eq!(SshShell::PowerShell.quote("it's $x"), "'it''s $x'");
+        assert_eq!(SshShell::PowerShell.quote("\u{2019}"), "'\u{2019}\u{2019}'");
+        assert_eq!(SshShell::Cmd.quote("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+}
@@ -1,33 +1,301 @@
+use crate::backend::{self, Connection, SshBackend};
+use crate::bench::SshLatency;
+use crate::server_info::{sha256_fingerprint, verify_host_key};
+use crate::shell::{self, parse_sha256, SshShell};
+use crate::{MasshHost, MasshObserver};
 use anyhow::Result;
+use parking_lot::Mutex;
 use serde::Deserialize;
-use ssh2::Session;
+use sha2::{Digest, Sha256};
+use ssh2::{MethodType, Session};
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::fs::OpenOptions;
 use std::io::{Read, Write};
 use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Maximum number of channels opened concurrently on a session, which is the default limit of
+/// OpenSSH's `MaxSessions`.
+const MAX_CONCURRENT_CHANNELS: usize = 10;
+
+/// Period during which a command may exit on its own after its stop signal was sent.
+const STOP_SIGNAL_GRACE_PERIOD: Duration = Duration::from_secs(1);
 
 /// SSH authentication method.
-#[derive(Deserialize)]
+///
+/// The default is [`SshAuth::Agent`].
+#[derive(Clone, Default, Deserialize)]
 pub enum SshAuth {
-    /// Agent authentication with the first public key found in an SSH agent.
+    /// Agent authentication with the public keys found in an SSH agent, tried in order until
+    /// one is accepted.
+    ///
+    /// The agent is found with the `SSH_AUTH_SOCK` environment variable on Unix,
+    /// or is Pageant (or the OpenSSH agent's named pipe) on Windows.
+    #[default]
     #[serde(rename = "agent")]
     Agent,
+    /// Agent authentication with a specific public key found in an SSH agent, selected by its
+    /// SHA-256 fingerprint (e.g. `SHA256:nThbg6k...`) or comment.
+    #[serde(rename = "agent_key")]
+    AgentKey(String),
+    /// Agent authentication with an SSH agent listening on a specific socket
+    /// (or named pipe on Windows) instead of the default one.
+    #[serde(rename = "agent_socket")]
+    AgentSocket {
+        /// Path of the agent's socket.
+        path: PathBuf,
+        /// Optional SHA-256 fingerprint (e.g. `SHA256:nThbg6k...`) or comment of the key to use,
+        /// instead of trying each key in order.
+        #[serde(default)]
+        key: Option<String>,
+    },
     /// Basic password authentication.
     #[serde(rename = "password")]
     Password(String),
+    /// Basic password authentication using the standard output of a local command
+    /// (e.g. `pass show prod/ssh`) as the password.
+    #[serde(rename = "password_cmd")]
+    PasswordCmd(String),
     /// Public key authentication using a PEM encoded private key file stored on disk.
     #[serde(rename = "pubkey")]
     Pubkey(PathBuf),
+    /// Public key authentication using a passphrase-protected PEM encoded private key file
+    /// stored on disk, using the standard output of a local command as the passphrase.
+    #[serde(rename = "pubkey_passphrase_cmd")]
+    PubkeyPassphraseCmd {
+        /// Path of the private key file.
+        path: PathBuf,
+        /// Local command whose standard output is the passphrase.
+        passphrase_cmd: String,
+    },
 }
 
+/// Connected stream over which a session can be established with [`SshClient::connect_over`].
+///
+/// It's implemented for all sockets, i.e. types implementing `AsRawFd` on Unix
+/// or `AsRawSocket` on Windows.
+#[cfg(unix)]
+pub trait SshTransport: std::os::unix::io::AsRawFd + 'static {}
+
+#[cfg(unix)]
+impl<T: std::os::unix::io::AsRawFd + 'static> SshTransport for T {}
+
+/// Connected stream over which a session can be established with [`SshClient::connect_over`].
+///
+/// It's implemented for all sockets, i.e. types implementing `AsRawFd` on Unix
+/// or `AsRawSocket` on Windows.
+#[cfg(windows)]
+pub trait SshTransport: std::os::windows::io::AsRawSocket + 'static {}
+
+#[cfg(windows)]
+impl<T: std::os::windows::io::AsRawSocket + 'static> SshTransport for T {}
+
 /// SSH command output.
 pub struct SshOutput {
+    /// Chunks of standard output and standard error in arrival order, if they were captured
+    /// merged or timestamped (see [`SshClient::set_merge_output`] and
+    /// [`SshClient::set_timestamps`]), or empty otherwise
+    pub chunks: Vec<SshChunk>,
+    /// Whether the command dumped core when it was killed by a signal
+    ///
+    /// Note that libssh2 and the `ssh` binary don't report it, so it's always false with the
+    /// libssh2 and openssh backends.
+    pub core_dumped: bool,
+    /// Error message reported by the server along with the exit signal, if any
+    pub error_message: Option<String>,
+    /// Name of the signal that killed the command, without the `SIG` prefix (e.g. `KILL`),
+    /// in which case the exit status is meaningless
+    pub exit_signal: Option<String>,
     /// Exit status
     pub exit_status: i32,
     /// Standard error
     pub stderr: Vec<u8>,
+    /// Number of bytes of standard error beyond the limit, which are not in `stderr`
+    pub stderr_dropped: u64,
+    /// Path of the temporary file containing the whole standard error, if it was spilled
+    pub stderr_file: Option<PathBuf>,
     /// Standard output
     pub stdout: Vec<u8>,
+    /// Number of bytes of standard output beyond the limit, which are not in `stdout`
+    pub stdout_dropped: u64,
+    /// Path of the temporary file containing the whole standard output, if it was spilled
+    pub stdout_file: Option<PathBuf>,
+}
+
+impl SshOutput {
+    /// Returns whether the command exited with a zero exit status, rather than with a nonzero
+    /// exit status or because of a signal.
+    pub fn success(&self) -> bool {
+        self.exit_status == 0 && self.exit_signal.is_none()
+    }
+
+    /// Returns the standard output as a string slice, or an error if it isn't valid UTF-8.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// let output = ssh.execute("hostname").unwrap();
+    /// if output.success() {
+    ///     println!("hostname: {}", output.stdout_str().unwrap().trim_end());
+    /// }
+    /// ```
+    pub fn stdout_str(&self) -> Result<&str> {
+        Ok(std::str::from_utf8(&self.stdout)?)
+    }
+
+    /// Returns the standard error as a string slice, or an error if it isn't valid UTF-8.
+    pub fn stderr_str(&self) -> Result<&str> {
+        Ok(std::str::from_utf8(&self.stderr)?)
+    }
+
+    /// Returns the standard output as a string, with invalid UTF-8 sequences replaced by `�`.
+    pub fn stdout_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.stdout)
+    }
+
+    /// Returns the standard error as a string, with invalid UTF-8 sequences replaced by `�`.
+    pub fn stderr_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.stderr)
+    }
+
+    /// Returns the standard output and standard error merged in arrival order, as they would
+    /// appear in a terminal, if they were captured merged (see [`SshClient::set_merge_output`]).
+    /// Otherwise, it returns the standard output followed by the standard error.
+    ///
+    /// Note that the output beyond the limit of [`SshClient::set_max_output`] is missing.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    /// ssh.set_merge_output(true);
+    ///
+    /// let output = ssh.execute("cargo test").unwrap();
+    /// println!("{}", String::from_utf8_lossy(&output.merged()));
+    /// ```
+    pub fn merged(&self) -> Vec<u8> {
+        if self.chunks.is_empty() {
+            return [&self.stdout[..], &self.stderr[..]].concat();
+        }
+        let mut merged = Vec::with_capacity(self.stdout.len() + self.stderr.len());
+        for chunk in &self.chunks {
+            merged.extend_from_slice(self.chunk(chunk));
+        }
+        merged
+    }
+
+    /// Returns the captured bytes of a chunk, which are fewer than its length (or none)
+    /// if it's beyond the limit of [`SshClient::set_max_output`].
+    pub fn chunk(&self, chunk: &SshChunk) -> &[u8] {
+        let buffer = match chunk.stream {
+            SshStream::Stdout => &self.stdout,
+            SshStream::Stderr => &self.stderr,
+        };
+        let start = (chunk.offset as usize).min(buffer.len());
+        let end = (start + chunk.len).min(buffer.len());
+        &buffer[start..end]
+    }
+}
+
+/// Chunk of the output of an executed command, as received from one of its streams.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SshChunk {
+    /// Stream of the chunk
+    pub stream: SshStream,
+    /// Offset of the chunk in its stream, in bytes
+    pub offset: u64,
+    /// Length of the chunk, in bytes
+    pub len: usize,
+    /// Time at which the chunk was received, if timestamped (see [`SshClient::set_timestamps`])
+    pub timestamp: Option<SshTimestamp>,
+}
+
+/// Time at which a chunk or a line of output of an executed command was received.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SshTimestamp {
+    /// Time elapsed since the command was executed, measured with a monotonic clock
+    pub elapsed: Duration,
+    /// Wall-clock time of the receipt
+    pub time: SystemTime,
+}
+
+impl SshTimestamp {
+    /// Constructs the `SshTimestamp` of the current time, for a command executed at `start`.
+    fn since(start: Instant) -> Self {
+        SshTimestamp {
+            elapsed: start.elapsed(),
+            time: SystemTime::now(),
+        }
+    }
+}
+
+/// Policy for the output of executed commands beyond the limit of [`SshClient::set_max_output`].
+///
+/// The default is [`SshOverflow::Truncate`].
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+pub enum SshOverflow {
+    /// Discard the output beyond the limit, only recording how many bytes were dropped.
+    #[default]
+    #[serde(rename = "truncate")]
+    Truncate,
+    /// Write the whole output to a temporary file, whose path is recorded.
+    #[serde(rename = "spill")]
+    Spill,
+}
+
+/// Signal sent to the remote process of a stopped command (see [`SshClient::set_stop_signal`]).
+///
+/// It's named without the `SIG` prefix (e.g. `TERM` for `SIGTERM`).
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum SshSignal {
+    /// `SIGHUP`
+    Hup,
+    /// `SIGINT`
+    Int,
+    /// `SIGKILL`
+    Kill,
+    /// `SIGQUIT`
+    Quit,
+    /// `SIGTERM`
+    Term,
+    /// `SIGUSR1`
+    Usr1,
+    /// `SIGUSR2`
+    Usr2,
+}
+
+impl SshSignal {
+    /// Returns the name of the signal, as accepted by `kill -s`.
+    fn name(self) -> &'static str {
+        match self {
+            SshSignal::Hup => "HUP",
+            SshSignal::Int => "INT",
+            SshSignal::Kill => "KILL",
+            SshSignal::Quit => "QUIT",
+            SshSignal::Term => "TERM",
+            SshSignal::Usr1 => "USR1",
+            SshSignal::Usr2 => "USR2",
+        }
+    }
+}
+
+/// Output stream of an executed command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SshStream {
+    /// Standard output
+    Stdout,
+    /// Standard error
+    Stderr,
 }
 
 /// SSH client to run commands on a single host.
@@ -39,25 +307,99 @@ pub struct SshOutput {
 /// - [`SshClient::try_from`]
 ///
 /// Configure this `SshClient`:
+/// - [`SshClient::set_atomic_uploads`]
 /// - [`SshClient::set_auth_agent`]
+/// - [`SshClient::set_auth_agent_key`]
+/// - [`SshClient::set_auth_agent_socket`]
 /// - [`SshClient::set_auth_password`]
+/// - [`SshClient::set_auth_password_cmd`]
 /// - [`SshClient::set_auth_pubkey`]
+/// - [`SshClient::set_auth_pubkey_passphrase_cmd`]
+/// - [`SshClient::set_auto_reconnect`]
+/// - [`SshClient::set_backend`]
+/// - [`SshClient::set_become_password`]
+/// - [`SshClient::set_become_user`]
+/// - [`SshClient::set_check_free_space`]
+/// - [`SshClient::set_compression`]
+/// - [`SshClient::set_host_key`]
+/// - [`SshClient::set_keepalive`]
+/// - [`SshClient::set_max_output`]
+/// - [`SshClient::set_merge_output`]
+/// - [`SshClient::set_method_pref`]
+/// - [`SshClient::set_overflow`]
+/// - [`SshClient::set_preserve_attributes`]
+/// - [`SshClient::set_rate_limit`]
+/// - [`SshClient::set_shell`]
+/// - [`SshClient::set_stop_signal`]
 /// - [`SshClient::set_timeout`]
+/// - [`SshClient::set_timestamps`]
+/// - [`SshClient::set_upload_chunks`]
+/// - [`SshClient::set_upload_mode`]
+/// - [`SshClient::set_upload_owner`]
+/// - [`SshClient::set_verify_uploads`]
+/// - [`SshClient::set_working_dir`]
 ///
 /// Inspect this `SshClient`:
 /// - [`SshClient::get_addr`]
+/// - [`SshClient::get_addrs`]
+/// - [`SshClient::get_atomic_uploads`]
 /// - [`SshClient::get_auth`]
+/// - [`SshClient::get_auto_reconnect`]
+/// - [`SshClient::get_backend`]
+/// - [`SshClient::get_become_user`]
+/// - [`SshClient::get_check_free_space`]
+/// - [`SshClient::get_compression`]
+/// - [`SshClient::get_host_key`]
+/// - [`SshClient::get_keepalive`]
+/// - [`SshClient::get_max_output`]
+/// - [`SshClient::get_merge_output`]
+/// - [`SshClient::get_method_pref`]
+/// - [`SshClient::get_overflow`]
+/// - [`SshClient::get_preserve_attributes`]
+/// - [`SshClient::get_rate_limit`]
+/// - [`SshClient::get_shell`]
+/// - [`SshClient::get_stop_signal`]
 /// - [`SshClient::get_timeout`]
+/// - [`SshClient::get_timestamps`]
+/// - [`SshClient::get_upload_chunks`]
+/// - [`SshClient::get_upload_mode`]
+/// - [`SshClient::get_upload_owner`]
 /// - [`SshClient::get_user`]
+/// - [`SshClient::get_verify_uploads`]
+/// - [`SshClient::get_working_dir`]
 /// - [`SshClient::is_connected`]
+/// - [`SshClient::server_info`]
 ///
 /// Run commands with this `SshClient`:
+/// - [`SshClient::bench`]
+/// - [`SshClient::copy_id`]
 /// - [`SshClient::execute`]
+/// - [`SshClient::execute_concurrent`]
+/// - [`SshClient::execute_streaming`]
+/// - [`SshClient::execute_streaming_timestamped`]
+/// - [`SshClient::execute_with_env`]
+/// - [`SshClient::gather_facts`]
+/// - [`SshClient::shell`]
+/// - [`SshClient::shell_interactive`]
+/// - [`SshClient::subsystem`]
+/// - [`SshClient::direct_streamlocal`]
+/// - [`SshClient::direct_tcpip`]
+/// - [`SshClient::forward_unix`]
 /// - [`SshClient::scp_download`]
 /// - [`SshClient::scp_upload`]
+/// - [`SshClient::append_file`]
+/// - [`SshClient::read_range`]
+/// - [`SshClient::read_last`]
+/// - [`SshClient::remote_free_space`]
+/// - [`SshClient::remote_stat`]
+/// - [`SshClient::download_dir`]
+/// - [`SshClient::download_glob`]
+/// - [`SshClient::upload_dir`]
+/// - [`SshClient::sync_dir`]
 ///
 /// There are also methods to manage the internal authenticated session of this `SshClient`:
 /// - [`SshClient::connect`]
+/// - [`SshClient::connect_over`]
 /// - [`SshClient::disconnect`]
 ///
 /// However, it's typically not necessary to call them because they are invoked lazily when needed.
@@ -83,15 +425,44 @@ pub struct SshOutput {
 /// // Run a command and print its output.
 /// let output = ssh.execute("cat remote-copy.txt").unwrap();
 /// println!("status: {}", output.exit_status);
-/// println!("stdout: {}", String::from_utf8(output.stdout).unwrap());
-/// println!("stderr: {}", String::from_utf8(output.stderr).unwrap());
+/// println!("stdout: {}", output.stdout_lossy());
+/// println!("stderr: {}", output.stderr_lossy());
 /// ```
 pub struct SshClient {
     addr: SocketAddr,
+    addrs: Vec<SocketAddr>,
+    atomic_uploads: bool,
     auth: SshAuth,
+    auto_reconnect: bool,
+    backend: SshBackend,
+    become_password: Option<String>,
+    become_user: Option<String>,
+    check_free_space: bool,
+    compression: bool,
+    connection: Option<Box<dyn Connection>>,
+    host_key: Option<String>,
+    keepalive: u32,
+    latency: SshLatency,
+    max_output: u64,
+    merge_output: bool,
+    method_prefs: Vec<(MethodType, String)>,
+    observer: Option<(MasshHost, Arc<dyn MasshObserver>)>,
+    overflow: SshOverflow,
+    preserve_attributes: bool,
+    rate_limit: u64,
+    shared_rate_limiter: Option<Arc<RateLimiter>>,
     session: Option<Session>,
+    sftp_unsupported: bool,
+    shell: SshShell,
+    stop_signal: Option<SshSignal>,
     timeout: u64,
+    timestamps: bool,
+    upload_chunks: usize,
+    upload_mode: Option<i32>,
+    upload_owner: Option<String>,
     user: String,
+    verify_uploads: bool,
+    working_dir: Option<String>,
 }
 
 impl SshClient {
@@ -107,18 +478,49 @@ impl SshClient {
     /// let ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
     /// ```
     pub fn from(user: impl Into<String>, addr: impl Into<SocketAddr>) -> Self {
+        let addr = addr.into();
         Self {
-            addr: addr.into(),
+            addr,
+            addrs: vec![addr],
+            atomic_uploads: false,
             auth: SshAuth::Agent,
+            auto_reconnect: false,
+            backend: SshBackend::Libssh2,
+            become_password: None,
+            become_user: None,
+            check_free_space: false,
+            compression: false,
+            connection: None,
+            host_key: None,
+            keepalive: 0,
+            latency: SshLatency::default(),
+            max_output: 0,
+            merge_output: false,
+            method_prefs: Vec::new(),
+            observer: None,
+            overflow: SshOverflow::Truncate,
+            preserve_attributes: false,
+            rate_limit: 0,
+            shared_rate_limiter: None,
             session: None,
+            sftp_unsupported: false,
+            shell: SshShell::Posix,
+            stop_signal: None,
             timeout: 0,
+            timestamps: false,
+            upload_chunks: 0,
+            upload_mode: None,
+            upload_owner: None,
             user: user.into(),
+            verify_uploads: false,
+            working_dir: None,
         }
     }
 
     /// Attempts to construct a new `SshClient` for the specified host's username and address.
     ///
-    /// Unlike [`SshClient::from`], it can resolve a hostname to an address.
+    /// Unlike [`SshClient::from`], it can resolve a hostname to addresses, which are all tried
+    /// by [`SshClient::connect`] (e.g. both IPv6 and IPv4 addresses of a dual-stack host).
     /// However, it's fallible and therefore returns a `Result`.
     ///
     /// By default, the client uses agent authentication and has no timeout.
@@ -132,26 +534,27 @@ impl SshClient {
     /// let ssh3 = SshClient::try_from("ec2-user", "xyz.compute.amazonaws.com:22").unwrap();
     /// ```
     pub fn try_from(user: impl Into<String>, addr: impl ToSocketAddrs) -> Result<Self> {
-        if let Some(addr) = addr.to_socket_addrs()?.next() {
-            Ok(Self {
-                addr,
-                auth: SshAuth::Agent,
-                session: None,
-                timeout: 0,
-                user: user.into(),
-            })
+        let addrs: Vec<SocketAddr> = addr.to_socket_addrs()?.collect();
+        if let Some(addr) = addrs.first() {
+            let mut client = Self::from(user, *addr);
+            client.addrs = addrs;
+            Ok(client)
         } else {
             Err(anyhow::anyhow!("Socket address conversion failed"))
         }
     }
 
     /// Configures this `SshClient` to perform agent authentication using
-    /// the first public key found in an SSH agent.
+    /// the public keys found in an SSH agent.
     ///
-    /// This is the default.
+    /// The keys are tried in order until one is accepted. Note that servers typically limit
+    /// the number of authentication attempts, so [`SshClient::set_auth_agent_key`] should be
+    /// preferred if the agent holds many keys. This is the default.
     ///
     /// ## Example
     /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
     /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
     ///
     /// // Note that this does nothing since it's the default.
@@ -162,10 +565,55 @@ impl SshClient {
         self
     }
 
+    /// Configures this `SshClient` to perform agent authentication using a specific public key
+    /// found in an SSH agent, selected by its SHA-256 fingerprint (as printed by `ssh-add -l`)
+    /// or by its comment.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// ssh.set_auth_agent_key("SHA256:nThbg6kXUpJWGl7E1IGOCspRomTxdCARLviKw6E5SY8");
+    /// ```
+    pub fn set_auth_agent_key(&mut self, key: impl Into<String>) -> &mut Self {
+        self.auth = SshAuth::AgentKey(key.into());
+        self
+    }
+
+    /// Configures this `SshClient` to perform agent authentication with an SSH agent listening
+    /// on a specific socket (or named pipe on Windows), instead of the one of `SSH_AUTH_SOCK`.
+    ///
+    /// The key is selected by its SHA-256 fingerprint (as printed by `ssh-add -l`) or by its
+    /// comment. A value of `None` signifies that the keys of the agent are tried in order.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// ssh.set_auth_agent_socket("/run/user/1000/deploy-agent.sock", Some("deploy@ci".to_string()));
+    /// ```
+    pub fn set_auth_agent_socket(
+        &mut self,
+        path: impl Into<PathBuf>,
+        key: Option<String>,
+    ) -> &mut Self {
+        self.auth = SshAuth::AgentSocket {
+            path: path.into(),
+            key,
+        };
+        self
+    }
+
     /// Configures this `SshClient` to perform basic password authentication.
     ///
     /// ## Example
     /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
     /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
     ///
     /// ssh.set_auth_password("top-secret");
@@ -175,11 +623,34 @@ impl SshClient {
         self
     }
 
+    /// Configures this `SshClient` to perform basic password authentication using
+    /// the standard output of a local command as the password.
+    ///
+    /// The command is run with `sh -c` (or `cmd /C` on Windows) when the session is established,
+    /// similar to Git credential helpers. A single trailing newline is stripped from its output.
+    /// The secret is cached for the lifetime of the process, so the command runs at most once
+    /// even when many clients share it.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// ssh.set_auth_password_cmd("pass show prod/ssh");
+    /// ```
+    pub fn set_auth_password_cmd(&mut self, command: impl Into<String>) -> &mut Self {
+        self.auth = SshAuth::PasswordCmd(command.into());
+        self
+    }
+
     /// Configures this `SshClient` to perform public key authentication using
     /// a PEM encoded private key file stored on disk.
     ///
     /// ## Example
     /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
     /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
     ///
     /// ssh.set_auth_pubkey("/home/username/.ssh/id_rsa");
@@ -189,240 +660,2031 @@ impl SshClient {
         self
     }
 
-    /// Configures this `SshClient` to use a timeout, in milliseconds, for blocking functions.
+    /// Configures this `SshClient` to perform public key authentication using
+    /// a passphrase-protected PEM encoded private key file stored on disk.
     ///
-    /// A timeout of zero signifies no timeout. This is the default.
+    /// The passphrase is the standard output of a local command,
+    /// which is run as described in [`SshClient::set_auth_password_cmd`].
     ///
     /// ## Example
     /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
     /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
     ///
-    /// // Set a timeout of 5 seconds.
-    /// ssh.set_timeout(5000);
+    /// ssh.set_auth_pubkey_passphrase_cmd("/home/username/.ssh/id_rsa", "pass show ssh/id_rsa");
     /// ```
-    pub fn set_timeout(&mut self, timeout_ms: u64) -> &mut Self {
-        self.timeout = timeout_ms;
+    pub fn set_auth_pubkey_passphrase_cmd(
+        &mut self,
+        path: impl Into<PathBuf>,
+        passphrase_cmd: impl Into<String>,
+    ) -> &mut Self {
+        self.auth = SshAuth::PubkeyPassphraseCmd {
+            path: path.into(),
+            passphrase_cmd: passphrase_cmd.into(),
+        };
         self
     }
 
-    /// Returns the address of this `SshClient`'s configured host.
-    pub fn get_addr(&self) -> SocketAddr {
-        self.addr
+    /// Configures this `SshClient` to transparently re-establish its cached session if it died
+    /// (e.g. after a network blip or a restart of the server).
+    ///
+    /// If opening a channel on the cached session fails because its connection was lost, a new
+    /// session is established and the operation is retried once. Since the channel couldn't be
+    /// opened, the command or transfer is guaranteed not to have started, so it's never run twice.
+    /// Failures after that point aren't retried. Unlike the persistent-session mode of
+    /// [`SshClient::set_keepalive`], it doesn't cost a round trip before reusing the session.
+    /// It's disabled by default.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// ssh.set_auto_reconnect(true);
+    /// ```
+    pub fn set_auto_reconnect(&mut self, auto_reconnect: bool) -> &mut Self {
+        self.auto_reconnect = auto_reconnect;
+        self
     }
 
-    /// Returns the authentication method of this `SshClient`'s configured host.
-    pub fn get_auth(&self) -> &SshAuth {
-        &self.auth
+    /// Configures this `SshClient` to use an implementation of the SSH protocol.
+    ///
+    /// It only applies to sessions established after this call, and drops the cached session,
+    /// if any. See [`SshBackend`] for the operations supported by each backend. The default is
+    /// [`SshBackend::Libssh2`].
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{SshBackend, SshClient};
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// ssh.set_backend(SshBackend::Libssh2);
+    /// ```
+    pub fn set_backend(&mut self, backend: SshBackend) -> &mut Self {
+        if self.backend != backend {
+            self.disconnect();
+        }
+        self.backend = backend;
+        self
     }
 
-    /// Returns the timeout, in milliseconds, of this `SshClient`'s configured host.
+    /// Configures this `SshClient` to execute commands as another user on the remote host,
+    /// through `sudo`.
     ///
-    /// A timeout of zero signifies no timeout.
-    pub fn get_timeout(&self) -> u64 {
-        self.timeout
+    /// Every command executed with [`SshClient::execute`] and its variants is then run as
+    /// `sudo -u <user> -- sh -c '<command>'`, after changing to the working directory, if any.
+    /// Since `sudo` resets the environment, the variables of [`SshClient::execute_with_env`] are
+    /// set by the command itself. Unless a password is configured with
+    /// [`SshClient::set_become_password`], `sudo` fails instead of prompting for one. A value of
+    /// `None` signifies that commands run as the user of the session. This is the default.
+    ///
+    /// Note that this requires a POSIX shell, and doesn't affect file transfers.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// ssh.set_become_user(Some("deploy".to_string()));
+    /// ```
+    pub fn set_become_user(&mut self, user: Option<String>) -> &mut Self {
+        self.become_user = user;
+        self
     }
 
-    /// Returns the username of this `SshClient`'s configured host.
-    pub fn get_user(&self) -> &str {
-        &self.user
+    /// Configures the password which `sudo` asks for to execute commands as another user
+    /// (see [`SshClient::set_become_user`]).
+    ///
+    /// The password is written to the standard input of each command, which is then closed, so
+    /// that it never appears in the command itself. It's only passed to `sudo` if `sudo` can't
+    /// run the command without it (e.g. without a `NOPASSWD` rule), and the command itself never
+    /// reads it. A value of `None` signifies that `sudo` must not ask for a password. This is the
+    /// default.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// ssh.set_become_user(Some("deploy".to_string()));
+    /// ssh.set_become_password(std::env::var("SUDO_PASSWORD").ok());
+    /// ```
+    pub fn set_become_password(&mut self, password: Option<String>) -> &mut Self {
+        self.become_password = password;
+        self
     }
 
-    /// Returns whether this `SshClient` has established an authenticated session
-    /// with the configured host.
-    pub fn is_connected(&self) -> bool {
-        self.session.is_some()
+    /// Configures this `SshClient` to check the free space at the destination of each upload
+    /// before starting it.
+    ///
+    /// In this mode, an upload fails fast with an "insufficient space" error if the filesystem
+    /// of the destination doesn't have room for the file (plus a chunk with
+    /// [`SshClient::set_upload_chunks`]), instead of failing midway with a write error.
+    /// See [`SshClient::remote_free_space`] for more details. This mode is disabled by default.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// ssh.set_check_free_space(true);
+    /// ```
+    pub fn set_check_free_space(&mut self, check_free_space: bool) -> &mut Self {
+        self.check_free_space = check_free_space;
+        self
     }
 
-    /// Attempts to execute a command on the configured host.
-    ///
-    /// Note that this method implicitly calls [`SshClient::connect`] if no session was
-    /// established prior. Otherwise, it reuses the cached session.
+    /// Configures this `SshClient` to compress the data sent and received over its sessions.
     ///
-    /// If successful, it returns an [`SshOutput`] containing the exit status, standard output,
-    /// and standard error of the command.
+    /// Compression substantially speeds up large text output and transfers of compressible files
+    /// over slow links, at the cost of some CPU time. It only applies to sessions established
+    /// after this call, and only if the server also supports it. It's disabled by default.
     ///
     /// ## Example
     /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
     /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
     ///
-    /// let output = ssh.execute("echo $PATH").unwrap();
-    ///
-    /// println!("status: {}", output.exit_status);
-    /// println!("stdout: {}", String::from_utf8(output.stdout).unwrap());
-    /// println!("stderr: {}", String::from_utf8(output.stderr).unwrap());
+    /// ssh.set_compression(true);
     /// ```
-    pub fn execute(&mut self, command: &str) -> Result<SshOutput> {
-        // Establish authenticated SSH session.
-        if self.session.is_none() {
-            self.connect()?;
-        }
-        let session = self.session.as_ref().unwrap();
-
-        // Open channel and stderr stream.
-        let mut channel = session.channel_session()?;
-        let mut stderr_stream = channel.stderr();
-
-        // Execute command.
-        channel.exec(command)?;
-
-        // Read stdout into buffer.
-        let mut stdout = Vec::new();
-        channel.read_to_end(&mut stdout)?;
-
-        // Read stderr into buffer.
-        let mut stderr = Vec::new();
-        stderr_stream.read_to_end(&mut stderr)?;
-
-        // Close channel and retrieve exit status.
-        channel.wait_close()?;
-        let exit_status = channel.exit_status()?;
-
-        // Return successfully.
-        Ok(SshOutput {
-            exit_status,
-            stdout,
-            stderr,
-        })
+    pub fn set_compression(&mut self, compression: bool) -> &mut Self {
+        self.compression = compression;
+        self
     }
 
-    /// Attempts to download a file from the configured host.
+    /// Configures this `SshClient` to pin the host key of the configured host to a fingerprint,
+    /// formatted like OpenSSH (e.g. `SHA256:nThbg6kXUpJWGl7E1IGOCspRomTxdCARLviKw6E5SY8` or
+    /// `MD5:16:27:ac:a5:76:28:2d:36:63:1b:56:4d:eb:df:a6:48`).
     ///
-    /// Note that this method implicitly calls [`SshClient::connect`] if no session was
-    /// established prior. Otherwise, it reuses the cached session.
+    /// The host key is then verified right after the handshake of every session, before
+    /// authenticating, and [`SshClient::connect`] fails if it doesn't match. The fingerprint of
+    /// a host key can be obtained with `ssh-keyscan <host> | ssh-keygen -lf -` or with
+    /// [`SshClient::server_info`]. A value of `None` signifies that the host key isn't verified.
+    /// This is the default.
     ///
     /// ## Example
     /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
     /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
     ///
-    /// if ssh.scp_download("remote.txt", "local.txt").is_ok() {
-    ///     println!("download worked!");
-    /// }
+    /// ssh.set_host_key(Some("SHA256:nThbg6kXUpJWGl7E1IGOCspRomTxdCARLviKw6E5SY8".to_string()));
     /// ```
-    pub fn scp_download<P: AsRef<Path>>(&mut self, remote_path: P, local_path: P) -> Result<()> {
-        // Establish authenticated SSH session.
-        if self.session.is_none() {
-            self.connect()?;
-        }
-        let session = self.session.as_ref().unwrap();
-
-        // Open channel.
-        let (mut channel, _) = session.scp_recv(remote_path.as_ref())?;
-
-        // Read remote file into buffer.
-        let mut buffer = Vec::new();
-        channel.read_to_end(&mut buffer)?;
-
-        // Write buffer to local file.
-        std::fs::write(local_path, &buffer)?;
-
-        // Close channel.
-        channel.send_eof()?;
-        channel.wait_eof()?;
-        channel.close()?;
-        channel.wait_close()?;
-
-        // Return successfully.
-        Ok(())
+    pub fn set_host_key(&mut self, fingerprint: Option<String>) -> &mut Self {
+        self.host_key = fingerprint;
+        self
     }
 
-    /// Attempts to upload a file to the configured host.
+    /// Configures this `SshClient` to keep its cached session alive and healthy, with an interval
+    /// in seconds between keepalive messages.
     ///
-    /// Note that this method implicitly calls [`SshClient::connect`] if no session was
-    /// established prior. Otherwise, it reuses the cached session.
+    /// In this persistent-session mode, a keepalive message is sent before reusing a session
+    /// that has been idle for longer than the interval. If it fails, the session is considered
+    /// dead and a new one is transparently established. The server is also asked to reply to
+    /// keepalive messages, which helps keep idle connections open through NATs and firewalls.
+    ///
+    /// An interval of zero disables this mode. This is the default.
     ///
     /// ## Example
     /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
     /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
     ///
-    /// if ssh.scp_upload("local.txt", "remote.txt").is_ok() {
-    ///     println!("upload worked!");
-    /// }
+    /// // Check the session's health if it has been idle for more than 30 seconds.
+    /// ssh.set_keepalive(30);
     /// ```
-    pub fn scp_upload<P: AsRef<Path>>(&mut self, local_path: P, remote_path: P) -> Result<()> {
-        // Establish authenticated SSH session.
-        if self.session.is_none() {
-            self.connect()?;
+    pub fn set_keepalive(&mut self, interval_secs: u32) -> &mut Self {
+        self.keepalive = interval_secs;
+        if let Some(session) = &self.session {
+            session.set_keepalive(true, interval_secs);
         }
-        let session = self.session.as_ref().unwrap();
-
-        // Read local file into buffer.
-        let buffer = std::fs::read(local_path)?;
-        let size = buffer.len() as u64;
-
-        // Open channel.
-        let mut channel = session.scp_send(remote_path.as_ref(), 0o644, size, None)?;
-
-        // Write buffer to remote file.
-        channel.write_all(&buffer)?;
-
-        // Close channel.
-        channel.send_eof()?;
-        channel.wait_eof()?;
-        channel.close()?;
-        channel.wait_close()?;
-
-        // Return successfully.
-        Ok(())
+        self
     }
 
-    /// Attempts to establish an authenticated session between this `SshClient`
-    /// and the configured host.
+    /// Configures this `SshClient` to capture at most the specified number of bytes
+    /// of the standard output and of the standard error of executed commands.
     ///
-    /// If successful, the session is cached internally by the client and is reused when
-    /// running multiple commands with [`SshClient::execute`], [`SshClient::scp_download`],
-    /// or [`SshClient::scp_upload`].
+    /// Output beyond this limit is still read from the remote command (so that it doesn't
+    /// block), but it is handled according to [`SshClient::set_overflow`].
+    /// A limit of zero signifies no limit. This is the default.
     ///
-    /// Note that it's not strictly necessary to call this method because the 3 methods
-    /// mentioned above will invoke it lazily if no session was established prior.
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
     ///
-    /// Finally, if the first session succeeds but the second session fails,
-    /// the first session will remain cached internally by the client. If the second
-    /// session succeeds, it replaces the first session (which is dropped).
+    /// // Capture at most 1 MiB of each output stream.
+    /// ssh.set_max_output(1024 * 1024);
+    /// ```
+    pub fn set_max_output(&mut self, max_bytes: u64) -> &mut Self {
+        self.max_output = max_bytes;
+        self
+    }
+
+    /// Configures this `SshClient` to capture the standard output and standard error of executed
+    /// commands merged in arrival order.
+    ///
+    /// In this mode, the [`SshOutput`] records the chunks of each stream in the order they were
+    /// received, so that [`SshOutput::merged`] can reconstruct the interleaved output (e.g. for
+    /// readable logs of build or test commands). This mode is disabled by default.
     ///
     /// ## Example
     /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
     /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
     ///
-    /// if ssh.connect().is_ok() {
-    ///     println!("agent authentication worked!");
-    /// }
-    ///
+    /// ssh.set_merge_output(true);
+    /// ```
+    pub fn set_merge_output(&mut self, merge_output: bool) -> &mut Self {
+        self.merge_output = merge_output;
+        self
+    }
+
+    /// Configures the preferred algorithms of this `SshClient` for a type of method, as a
+    /// comma-separated list in decreasing order of preference (e.g. `aes256-gcm@openssh.com,
+    /// aes256-ctr` for [`MethodType::CryptCs`]).
+    ///
+    /// The list replaces libssh2's default preferences for that type, which can be used to require
+    /// modern algorithms, or to allow legacy ones for old appliances. Algorithms that aren't
+    /// supported by libssh2 are ignored, but [`SshClient::connect`] fails if none of them are.
+    /// It only applies to sessions established after this call.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{MethodType, SshClient};
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// // Allow a legacy key exchange algorithm as a last resort.
+    /// ssh.set_method_pref(MethodType::Kex, "curve25519-sha256,diffie-hellman-group14-sha1");
+    /// ```
+    pub fn set_method_pref(&mut self, method: MethodType, prefs: impl Into<String>) -> &mut Self {
+        self.method_prefs
+            .retain(|(m, _)| *m as i32 != method as i32);
+        self.method_prefs.push((method, prefs.into()));
+        self
+    }
+
+    /// Configures how this `SshClient` handles the output of executed commands beyond
+    /// the limit of [`SshClient::set_max_output`].
+    ///
+    /// With [`SshOverflow::Truncate`], the output beyond the limit is discarded and the number
+    /// of dropped bytes is recorded in the [`SshOutput`]. This is the default.
+    ///
+    /// With [`SshOverflow::Spill`], the whole output is also written to a temporary file, whose
+    /// path is recorded in the [`SshOutput`]. It's up to the caller to delete this file.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{SshClient, SshOverflow};
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// ssh.set_max_output(1024 * 1024).set_overflow(SshOverflow::Spill);
+    /// ```
+    pub fn set_overflow(&mut self, overflow: SshOverflow) -> &mut Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Configures this `SshClient` to limit the throughput of file transfers,
+    /// in bytes per second.
+    ///
+    /// A limit of zero signifies no limit. This is the default.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// // Transfer files at 10 MB/s at most.
+    /// ssh.set_rate_limit(10_000_000);
+    /// ```
+    pub fn set_rate_limit(&mut self, bytes_per_sec: u64) -> &mut Self {
+        self.rate_limit = bytes_per_sec;
+        self
+    }
+
+    /// Configures this `SshClient` to share a limit on the throughput of file transfers
+    /// with other clients, in addition to its own limit.
+    pub(crate) fn set_shared_rate_limiter(&mut self, limiter: Option<Arc<RateLimiter>>) {
+        self.shared_rate_limiter = limiter;
+    }
+
+    /// Configures this `SshClient` to report its connections and commands to the observer of
+    /// the `MasshClient` it belongs to, as the specified host.
+    pub(crate) fn set_observer(
+        &mut self,
+        host: MasshHost,
+        observer: Option<Arc<dyn MasshObserver>>,
+    ) {
+        self.observer = observer.map(|observer| (host, observer));
+    }
+
+    /// Configures this `SshClient` to write its commands for a shell, i.e. the default shell
+    /// of the remote user on the host.
+    ///
+    /// Windows OpenSSH servers typically run commands with `cmd.exe` or PowerShell rather than
+    /// a POSIX shell. See [`SshShell`] for the operations which depend on it. The default is
+    /// [`SshShell::Posix`].
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{SshClient, SshShell};
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// ssh.set_shell(SshShell::PowerShell);
+    /// ```
+    pub fn set_shell(&mut self, shell: SshShell) -> &mut Self {
+        self.shell = shell;
+        self
+    }
+
+    /// Configures this `SshClient` to send a signal to the remote processes of a command executed
+    /// by [`SshClient::execute_streaming`] when it's stopped, right before closing its channel.
+    ///
+    /// Without it, stopping a command only closes the channel, which many commands (e.g. those
+    /// which don't write to their output) don't notice, so they keep running on the host. The
+    /// signal is sent with `kill` through another channel of the same session, to the process
+    /// group of the command (or to its shell if it isn't a group leader), whose PID is printed by
+    /// the command before it starts, and is hidden from its output. The command is then given up
+    /// to one second to exit, so that its exit status can be retrieved. This requires a POSIX
+    /// shell on the host, and the libssh2 backend (the local backend kills the process group of a
+    /// stopped command instead). A value of `None` signifies that no signal is sent. This is the
+    /// default.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{SshClient, SshSignal};
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// ssh.set_stop_signal(Some(SshSignal::Term));
+    /// ```
+    pub fn set_stop_signal(&mut self, signal: Option<SshSignal>) -> &mut Self {
+        self.stop_signal = signal;
+        self
+    }
+
+    /// Configures this `SshClient` to use a timeout, in milliseconds, for blocking functions.
+    ///
+    /// A timeout of zero signifies no timeout. This is the default.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// // Set a timeout of 5 seconds.
+    /// ssh.set_timeout(5000);
+    /// ```
+    pub fn set_timeout(&mut self, timeout_ms: u64) -> &mut Self {
+        self.timeout = timeout_ms;
+        self
+    }
+
+    /// Configures this `SshClient` to timestamp the output of executed commands.
+    ///
+    /// In this mode, the [`SshOutput`] records the chunks of each stream in the order they were
+    /// received (like [`SshClient::set_merge_output`]), each with the monotonic and wall-clock
+    /// times of its receipt (see [`SshTimestamp`]). Streamed lines are always timestamped by
+    /// [`SshClient::execute_streaming_timestamped`]. This mode is disabled by default.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// ssh.set_timestamps(true);
+    ///
+    /// let output = ssh.execute("make").unwrap();
+    /// for chunk in &output.chunks {
+    ///     let elapsed = chunk.timestamp.unwrap().elapsed;
+    ///     print!("[{:?}] {}", elapsed, String::from_utf8_lossy(output.chunk(chunk)));
+    /// }
+    /// ```
+    pub fn set_timestamps(&mut self, timestamps: bool) -> &mut Self {
+        self.timestamps = timestamps;
+        self
+    }
+
+    /// Configures this `SshClient` to preserve file attributes on transfers.
+    ///
+    /// In this mode, uploaded files keep the mode, modification time and access time of the local
+    /// file (like `scp -p`), and downloaded files keep the mode of the remote file. Otherwise,
+    /// uploaded files are created with mode `0o644`. This mode is disabled by default.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// ssh.set_preserve_attributes(true);
+    /// ```
+    pub fn set_preserve_attributes(&mut self, preserve_attributes: bool) -> &mut Self {
+        self.preserve_attributes = preserve_attributes;
+        self
+    }
+
+    /// Configures this `SshClient` to upload large files in the specified number of chunks,
+    /// in parallel over as many additional sessions.
+    ///
+    /// A single channel often can't saturate a fast link, so this speeds up the upload of very
+    /// large files. The chunks are uploaded next to the destination, then concatenated into it
+    /// on the remote host (which requires a POSIX shell), and the SHA-256 checksum of the result
+    /// is always verified. Files are split in fewer chunks if they would be smaller than 1 MiB.
+    /// The rate limits apply to all chunks together.
+    ///
+    /// A value of 0 or 1 signifies that files are uploaded in one piece. This is the default.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// ssh.set_upload_chunks(8);
+    /// ```
+    pub fn set_upload_chunks(&mut self, chunks: usize) -> &mut Self {
+        self.upload_chunks = chunks;
+        self
+    }
+
+    /// Configures this `SshClient` to create uploaded files with an explicit mode.
+    ///
+    /// If set, it takes precedence over the mode of the local file preserved with
+    /// [`SshClient::set_preserve_attributes`]. A value of `None` signifies the default behavior.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// ssh.set_upload_mode(Some(0o755));
+    /// ```
+    pub fn set_upload_mode(&mut self, mode: Option<i32>) -> &mut Self {
+        self.upload_mode = mode;
+        self
+    }
+
+    /// Configures this `SshClient` to change the owner of uploaded files.
+    ///
+    /// After a file is uploaded, its owner is changed with `chown`, which typically requires the
+    /// remote user to be root. The owner can be any argument accepted by `chown`, such as `user`
+    /// or `user:group`. A value of `None` signifies that the owner is left unchanged.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("root", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// ssh.set_upload_owner(Some("www-data:www-data".to_string()));
+    /// ```
+    pub fn set_upload_owner(&mut self, owner: Option<String>) -> &mut Self {
+        self.upload_owner = owner;
+        self
+    }
+
+    /// Configures this `SshClient` to execute commands in a working directory on the remote host.
+    ///
+    /// Every command executed with [`SshClient::execute`] and its variants is then run as
+    /// `cd <dir> && { <command> }`, so that it fails if the directory doesn't exist. A leading
+    /// `~/` is expanded to the home directory of the user. A value of `None` signifies that
+    /// commands run in the default directory of the session (typically the home directory).
+    /// This is the default.
+    ///
+    /// Note that this doesn't affect the paths of file transfers.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// ssh.set_working_dir(Some("/srv/app".to_string()));
+    /// ```
+    pub fn set_working_dir(&mut self, dir: Option<String>) -> &mut Self {
+        self.working_dir = dir;
+        self
+    }
+
+    /// Configures this `SshClient` to upload files atomically.
+    ///
+    /// In this mode, a file is first uploaded to a temporary file named `<path>.massh-tmp-XXXXXXXX`
+    /// next to its destination, then renamed into place with `mv` once the transfer (and checksum
+    /// verification, if enabled) succeeds. On failure, the temporary file is removed and the
+    /// original destination file, if any, is preserved as is. This mode is disabled by default.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// ssh.set_atomic_uploads(true);
+    /// ```
+    pub fn set_atomic_uploads(&mut self, atomic_uploads: bool) -> &mut Self {
+        self.atomic_uploads = atomic_uploads;
+        self
+    }
+
+    /// Configures this `SshClient` to verify the SHA-256 checksum of uploaded files.
+    ///
+    /// In this mode, after a file is uploaded, its checksum is computed on the remote host with
+    /// `sha256sum` (or `shasum -a 256`) and compared to the checksum of the local file. The upload
+    /// fails if they don't match. This mode is disabled by default.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// ssh.set_verify_uploads(true);
+    /// ```
+    pub fn set_verify_uploads(&mut self, verify_uploads: bool) -> &mut Self {
+        self.verify_uploads = verify_uploads;
+        self
+    }
+
+    /// Returns the address of this `SshClient`'s configured host.
+    ///
+    /// If the host has several addresses, it's the one which last accepted a connection
+    /// (or the first one if none did yet).
+    pub fn get_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Returns all the addresses of this `SshClient`'s configured host, as resolved
+    /// by [`SshClient::try_from`] or by the configuration of a `MasshClient`.
+    pub fn get_addrs(&self) -> &[SocketAddr] {
+        &self.addrs
+    }
+
+    /// Returns whether this `SshClient` uploads files atomically.
+    pub fn get_atomic_uploads(&self) -> bool {
+        self.atomic_uploads
+    }
+
+    /// Returns the authentication method of this `SshClient`'s configured host.
+    pub fn get_auth(&self) -> &SshAuth {
+        &self.auth
+    }
+
+    /// Returns whether this `SshClient` re-establishes its cached session if it died.
+    pub fn get_auto_reconnect(&self) -> bool {
+        self.auto_reconnect
+    }
+
+    /// Returns the implementation of the SSH protocol used by this `SshClient`.
+    pub fn get_backend(&self) -> SshBackend {
+        self.backend
+    }
+
+    /// Returns the user as which this `SshClient` executes commands through `sudo`, if any.
+    pub fn get_become_user(&self) -> Option<&str> {
+        self.become_user.as_deref()
+    }
+
+    /// Returns whether this `SshClient` checks the free space at the destination of uploads.
+    pub fn get_check_free_space(&self) -> bool {
+        self.check_free_space
+    }
+
+    /// Returns whether this `SshClient` compresses the data of its sessions.
+    pub fn get_compression(&self) -> bool {
+        self.compression
+    }
+
+    /// Returns the fingerprint to which the host key of the configured host is pinned, if any.
+    pub fn get_host_key(&self) -> Option<&str> {
+        self.host_key.as_deref()
+    }
+
+    /// Returns the interval, in seconds, between keepalive messages of this `SshClient`.
+    ///
+    /// An interval of zero signifies that the persistent-session mode is disabled.
+    pub fn get_keepalive(&self) -> u32 {
+        self.keepalive
+    }
+
+    /// Returns the maximum number of bytes captured per output stream of executed commands.
+    ///
+    /// A limit of zero signifies no limit.
+    pub fn get_max_output(&self) -> u64 {
+        self.max_output
+    }
+
+    /// Returns whether this `SshClient` captures the output of executed commands merged
+    /// in arrival order.
+    pub fn get_merge_output(&self) -> bool {
+        self.merge_output
+    }
+
+    /// Returns the preferred algorithms of this `SshClient` for a type of method,
+    /// or `None` if it uses libssh2's default preferences.
+    pub fn get_method_pref(&self, method: MethodType) -> Option<&str> {
+        self.method_prefs
+            .iter()
+            .find(|(m, _)| *m as i32 == method as i32)
+            .map(|(_, prefs)| prefs.as_str())
+    }
+
+    /// Returns how this `SshClient` handles output beyond the limit of captured bytes.
+    pub fn get_overflow(&self) -> SshOverflow {
+        self.overflow
+    }
+
+    /// Returns the limit on the throughput of file transfers, in bytes per second.
+    ///
+    /// A limit of zero signifies no limit.
+    pub fn get_rate_limit(&self) -> u64 {
+        self.rate_limit
+    }
+
+    /// Returns the shell which runs the commands of this `SshClient` on its host.
+    pub fn get_shell(&self) -> SshShell {
+        self.shell
+    }
+
+    /// Returns the signal sent to the remote process of a stopped command, if any.
+    pub fn get_stop_signal(&self) -> Option<SshSignal> {
+        self.stop_signal
+    }
+
+    /// Returns the timeout, in milliseconds, of this `SshClient`'s configured host.
+    ///
+    /// A timeout of zero signifies no timeout.
+    pub fn get_timeout(&self) -> u64 {
+        self.timeout
+    }
+
+    /// Returns whether this `SshClient` timestamps the output of executed commands.
+    pub fn get_timestamps(&self) -> bool {
+        self.timestamps
+    }
+
+    /// Returns the username of this `SshClient`'s configured host.
+    pub fn get_user(&self) -> &str {
+        &self.user
+    }
+
+    /// Returns whether this `SshClient` preserves file attributes on transfers.
+    pub fn get_preserve_attributes(&self) -> bool {
+        self.preserve_attributes
+    }
+
+    /// Returns the number of chunks in which this `SshClient` uploads large files.
+    pub fn get_upload_chunks(&self) -> usize {
+        self.upload_chunks
+    }
+
+    /// Returns the explicit mode of files uploaded by this `SshClient`, if any.
+    pub fn get_upload_mode(&self) -> Option<i32> {
+        self.upload_mode
+    }
+
+    /// Returns the owner of files uploaded by this `SshClient`, if any.
+    pub fn get_upload_owner(&self) -> Option<&str> {
+        self.upload_owner.as_deref()
+    }
+
+    /// Returns whether this `SshClient` verifies the SHA-256 checksum of uploaded files.
+    pub fn get_verify_uploads(&self) -> bool {
+        self.verify_uploads
+    }
+
+    /// Returns the working directory of commands executed by this `SshClient`, if any.
+    pub fn get_working_dir(&self) -> Option<&str> {
+        self.working_dir.as_deref()
+    }
+
+    /// Returns whether this `SshClient` has established an authenticated session
+    /// with the configured host.
+    pub fn is_connected(&self) -> bool {
+        self.session.is_some() || self.connection.is_some()
+    }
+
+    /// Attempts to execute a command on the configured host.
+    ///
+    /// Note that this method implicitly calls [`SshClient::connect`] if no session was
+    /// established prior. Otherwise, it reuses the cached session
+    /// (see [`SshClient::set_keepalive`] to check its health first).
+    ///
+    /// If successful, it returns an [`SshOutput`] containing the exit status, standard output,
+    /// and standard error of the command.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// let output = ssh.execute("echo $PATH").unwrap();
+    ///
+    /// println!("status: {}", output.exit_status);
+    /// println!("stdout: {}", output.stdout_lossy());
+    /// println!("stderr: {}", output.stderr_lossy());
+    /// ```
+    pub fn execute(&mut self, command: &str) -> Result<SshOutput> {
+        self.execute_with_env(command, &[])
+    }
+
+    /// Attempts to execute a command on the configured host, with additional environment
+    /// variables.
+    ///
+    /// The variables are set on the channel, which many servers only accept for the names listed
+    /// in their `AcceptEnv` setting. If the server rejects any of them, the command is executed
+    /// as `env KEY=value ... sh -c 'command'` instead. See [`SshClient::execute`] for more details.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// let output = ssh.execute_with_env("./deploy.sh", &[("RELEASE", "v1.2.3")]).unwrap();
+    ///
+    /// println!("status: {}", output.exit_status);
+    /// ```
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(user = %self.user, addr = %self.addr, command = %command),
+            err
+        )
+    )]
+    pub fn execute_with_env(&mut self, command: &str, env: &[(&str, &str)]) -> Result<SshOutput> {
+        let command = self.wrap_command(command, env);
+        let env = if self.become_user.is_some() { &[] } else { env };
+        let input = self.become_input();
+        let start = Instant::now();
+        let result = command.and_then(|command| self.execute_raw(&command, env, input.as_deref()));
+        self.observe_command(start, result.as_ref().map(|output| output.exit_status));
+        let output = result?;
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            exit_status = output.exit_status,
+            stdout_bytes = output.stdout.len(),
+            stderr_bytes = output.stderr.len(),
+            elapsed_ms = start.elapsed().as_millis() as u64,
+            "executed"
+        );
+        Ok(output)
+    }
+
+    /// Executes a command on the remote host as is, regardless of the working directory and the
+    /// become user, with the specified bytes written to its standard input, if any.
+    fn execute_raw(
+        &mut self,
+        command: &str,
+        env: &[(&str, &str)],
+        mut input: Option<&[u8]>,
+    ) -> Result<SshOutput> {
+        check_env(env)?;
+
+        // Execute command through the connection of another backend, if configured, which
+        // always prefixes it with the environment variables.
+        if self.backend != SshBackend::Libssh2 {
+            let command = self.shell.with_env(command, env);
+            let mut collector = OutputCollector::new(self.max_output, self.overflow);
+            let (exit_status, exit_signal, core_dumped) = self.ensure_connection()?.run(
+                &command,
+                input.as_mut().map(|bytes| bytes as &mut dyn Read),
+                None,
+                &mut |stream, bytes| collector.push(stream, bytes),
+            )?;
+            let captured = collector.finish();
+            let output = self.output(captured, exit_status, exit_signal, None, core_dumped);
+            return Ok(output);
+        }
+
+        // Establish authenticated SSH session and open channel.
+        let mut channel = self.open_channel(Session::channel_session)?;
+        let session = self.session.as_ref().unwrap();
+
+        // Set the environment variables, or prefix the command with them if rejected.
+        let rejected = env
+            .iter()
+            .any(|(name, value)| channel.setenv(name, value).is_err());
+        let command = if rejected {
+            self.shell.with_env(command, env)
+        } else {
+            command.to_string()
+        };
+
+        // Execute command, and write its input, if any.
+        channel.exec(&command)?;
+        if let Some(input) = input {
+            channel.write_all(input)?;
+            channel.send_eof()?;
+        }
+
+        // Read stdout and stderr into buffers concurrently, without blocking, so that a command
+        // filling one stream while the other is idle can't exhaust the channel's window.
+        let (max, overflow) = (self.max_output, self.overflow);
+        session.set_blocking(false);
+        let result = read_output(&mut channel, max, overflow, self.timeout);
+        session.set_blocking(true);
+        let captured = result?;
+
+        // Close channel and return successfully.
+        self.channel_output(channel, captured)
+    }
+
+    /// Attempts to execute several commands concurrently on the configured host, each on its own
+    /// channel of the same session.
+    ///
+    /// It's useful to run many short commands on a host whose connection setup dominates their
+    /// run time. At most 10 commands run at once (which is the default limit of OpenSSH's
+    /// `MaxSessions`), and the others start as soon as a whole batch is done. The timeout of
+    /// [`SshClient::set_timeout`] applies if none of the commands outputs anything for that long.
+    ///
+    /// If successful, it returns the [`SshOutput`] of each command in order. Note that this method
+    /// implicitly calls [`SshClient::connect`] if no session was established prior. Otherwise,
+    /// it reuses the cached session (see [`SshClient::set_keepalive`] to check its health first).
+    /// With backends other than libssh2, the commands are executed one by one.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// let outputs = ssh.execute_concurrent(&["uptime", "df -h /", "free -m"]).unwrap();
+    ///
+    /// for output in outputs {
+    ///     println!("{}", output.stdout_lossy());
+    /// }
+    /// ```
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(user = %self.user, addr = %self.addr, commands = commands.len()),
+            err
+        )
+    )]
+    pub fn execute_concurrent(&mut self, commands: &[&str]) -> Result<Vec<SshOutput>> {
+        // Execute the commands one by one through the connection of another backend,
+        // if configured.
+        if self.backend != SshBackend::Libssh2 {
+            return commands
+                .iter()
+                .map(|command| self.execute(command))
+                .collect();
+        }
+
+        let mut outputs = Vec::with_capacity(commands.len());
+        for batch in commands.chunks(MAX_CONCURRENT_CHANNELS) {
+            // Establish authenticated SSH session, and open a channel per command and
+            // execute it.
+            let input = self.become_input();
+            let mut channels = Vec::with_capacity(batch.len());
+            for command in batch {
+                let command = self.wrap_command(command, &[])?;
+                let mut channel = self.open_channel(Session::channel_session)?;
+                channel.exec(&command)?;
+                if let Some(input) = &input {
+                    channel.write_all(input)?;
+                    channel.send_eof()?;
+                }
+                channels.push(channel);
+            }
+            let session = self.session.as_ref().unwrap();
+
+            // Read the output of all channels concurrently, without blocking.
+            let (max, overflow) = (self.max_output, self.overflow);
+            session.set_blocking(false);
+            let result = read_outputs(&mut channels, max, overflow, self.timeout);
+            session.set_blocking(true);
+
+            // Close channels.
+            for (channel, captured) in channels.into_iter().zip(result?) {
+                outputs.push(self.channel_output(channel, captured)?);
+            }
+        }
+
+        // Return successfully.
+        Ok(outputs)
+    }
+
+    /// Closes the channel of an executed command, then constructs its `SshOutput` from its
+    /// captured output and its exit status and signal.
+    fn channel_output(
+        &self,
+        mut channel: ssh2::Channel,
+        captured: (Captured, Captured, Vec<SshChunk>),
+    ) -> Result<SshOutput> {
+        channel.wait_close()?;
+        let exit_status = channel.exit_status()?;
+        let exit_signal = channel.exit_signal()?;
+        let error_message = exit_signal
+            .error_message
+            .filter(|message| !message.is_empty());
+        // libssh2 doesn't report whether the command dumped core.
+        Ok(self.output(
+            captured,
+            exit_status,
+            exit_signal.exit_signal,
+            error_message,
+            false,
+        ))
+    }
+
+    /// Constructs the `SshOutput` of an executed command from its captured output streams and
+    /// chunks (which are only kept if the output is merged or timestamped, and only keep their
+    /// timestamps in the latter case), exit status, exit signal, and whether it dumped core.
+    fn output(
+        &self,
+        (stdout, stderr, mut chunks): (Captured, Captured, Vec<SshChunk>),
+        exit_status: i32,
+        exit_signal: Option<String>,
+        error_message: Option<String>,
+        core_dumped: bool,
+    ) -> SshOutput {
+        if !self.merge_output && !self.timestamps {
+            chunks.clear();
+        } else if !self.timestamps {
+            chunks.iter_mut().for_each(|chunk| chunk.timestamp = None);
+        }
+        let (stdout, stdout_dropped, stdout_file) = stdout;
+        let (stderr, stderr_dropped, stderr_file) = stderr;
+        SshOutput {
+            chunks,
+            core_dumped,
+            error_message,
+            exit_signal,
+            exit_status,
+            stderr,
+            stderr_dropped,
+            stderr_file,
+            stdout,
+            stdout_dropped,
+            stdout_file,
+        }
+    }
+
+    /// Attempts to execute a command on the configured host, streaming its output line by line.
+    ///
+    /// The closure is called with each line of standard output and standard error, without its
+    /// trailing newline, as soon as it's received. It's useful for long-running commands such as
+    /// `tail -F`, whose output isn't limited by [`SshClient::set_max_output`] and which aren't
+    /// subject to the timeout of [`SshClient::set_timeout`] once started.
+    ///
+    /// The command runs until it exits or until `stop` is set, in which case its channel is
+    /// closed, after sending a signal to its remote process if specified
+    /// (see [`SshClient::set_stop_signal`]). It returns the exit status of the command.
+    ///
+    /// Note that this method implicitly calls [`SshClient::connect`] if no session was
+    /// established prior. Otherwise, it reuses the cached session
+    /// (see [`SshClient::set_keepalive`] to check its health first).
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// use std::sync::atomic::AtomicBool;
+    ///
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// let stop = AtomicBool::new(false);
+    /// let result = ssh.execute_streaming("tail -F /var/log/syslog", &stop, |_, line| {
+    ///     println!("{}", String::from_utf8_lossy(line));
+    /// });
+    /// ```
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(user = %self.user, addr = %self.addr, command = %command),
+            err
+        )
+    )]
+    pub fn execute_streaming<F>(
+        &mut self,
+        command: &str,
+        stop: &AtomicBool,
+        mut f: F,
+    ) -> Result<i32>
+    where
+        F: FnMut(SshStream, &[u8]),
+    {
+        let start = Instant::now();
+        let result = self.stream_command(command, stop, |stream, line, _| f(stream, line));
+        self.observe_command(start, result.as_ref().copied());
+        result
+    }
+
+    /// Attempts to execute a command on the configured host, streaming its output line by line
+    /// with the time at which each line was received.
+    ///
+    /// It's like [`SshClient::execute_streaming`], except that the closure is also called with
+    /// the [`SshTimestamp`] of each line, i.e. the time at which its last byte was received.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// use std::sync::atomic::AtomicBool;
+    ///
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// let stop = AtomicBool::new(false);
+    /// let result = ssh.execute_streaming_timestamped("./build.sh", &stop, |_, line, timestamp| {
+    ///     println!("[{:?}] {}", timestamp.elapsed, String::from_utf8_lossy(line));
+    /// });
+    /// ```
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(user = %self.user, addr = %self.addr, command = %command),
+            err
+        )
+    )]
+    pub fn execute_streaming_timestamped<F>(
+        &mut self,
+        command: &str,
+        stop: &AtomicBool,
+        f: F,
+    ) -> Result<i32>
+    where
+        F: FnMut(SshStream, &[u8], SshTimestamp),
+    {
+        let start = Instant::now();
+        let result = self.stream_command(command, stop, f);
+        self.observe_command(start, result.as_ref().copied());
+        result
+    }
+
+    /// Executes a command and delivers its output line by line, with the time at which each
+    /// line was received (see `SshClient::execute_streaming_timestamped`).
+    fn stream_command<F>(&mut self, command: &str, stop: &AtomicBool, mut f: F) -> Result<i32>
+    where
+        F: FnMut(SshStream, &[u8], SshTimestamp),
+    {
+        if self.backend != SshBackend::Libssh2 {
+            return self.stream_connection(command, stop, f);
+        }
+
+        // Establish authenticated SSH session, open channel, and execute command. If a stop
+        // signal is configured, the command first prints the PID of its shell, i.e. the shell
+        // which runs it as the become user, if any, which is intercepted as the first line of its
+        // standard output.
+        let pid = Cell::new(None);
+        let mut awaiting_pid = self.stop_signal.is_some() && self.shell == SshShell::Posix;
+        let command = if awaiting_pid {
+            self.wrap_command(&format!("echo $$; {}", command), &[])?
+        } else {
+            self.wrap_command(command, &[])?
+        };
+        let mut channel = self.open_channel(Session::channel_session)?;
+        channel.exec(&command)?;
+        if let Some(input) = self.become_input() {
+            channel.write_all(&input)?;
+            channel.send_eof()?;
+        }
+        let mut f = |stream, line: &[u8], timestamp| {
+            if awaiting_pid && stream == SshStream::Stdout {
+                awaiting_pid = false;
+                let line = String::from_utf8_lossy(line);
+                pid.set(line.trim().parse::<u32>().ok());
+            } else {
+                f(stream, line, timestamp);
+            }
+        };
+
+        // Poll both streams without blocking, so that `stop` is checked regularly. Once stopped,
+        // the stop signal, if any, is sent to the process group of the command, or to its shell
+        // if it isn't a group leader (i.e. if the server didn't start it in a new session, or if
+        // it runs as the become user), and the command is given a grace period to exit on its
+        // own. The signal is sent as the become user, if any, by a command on the same session,
+        // which isn't reported to the observer.
+        self.session.as_ref().unwrap().set_blocking(false);
+        let start = Instant::now();
+        let mut stdout = LineBuffer::new(start);
+        let mut stderr = LineBuffer::new(start);
+        let mut signaled: Option<Instant> = None;
+        let result = loop {
+            if signaled.is_none() && stop.load(Ordering::Relaxed) {
+                match (self.stop_signal, pid.get()) {
+                    (Some(signal), Some(pid)) => {
+                        let kill = format!(
+                            "kill -s {0} -- -{1} 2>/dev/null || kill -s {0} {1}",
+                            signal.name(),
+                            pid
+                        );
+                        let input = self.become_input();
+                        self.session.as_ref().unwrap().set_blocking(true);
+                        let _ = self
+                            .wrap_command(&kill, &[])
+                            .and_then(|kill| self.execute_raw(&kill, &[], input.as_deref()));
+                        self.session.as_ref().unwrap().set_blocking(false);
+                        signaled = Some(Instant::now());
+                    }
+                    _ => break Ok(true),
+                }
+            }
+            if signaled.is_some_and(|instant| instant.elapsed() > STOP_SIGNAL_GRACE_PERIOD) {
+                break Ok(true);
+            }
+            let out = stdout.fill(&mut channel, SshStream::Stdout, &mut f);
+            let err = stderr.fill(&mut channel.stderr(), SshStream::Stderr, &mut f);
+            match (out, err) {
+                (Err(e), _) | (_, Err(e)) => break Err(e),
+                (Ok(0), Ok(0)) if channel.eof() => break Ok(false),
+                (Ok(0), Ok(0)) => std::thread::sleep(Duration::from_millis(10)),
+                _ => {}
+            }
+        };
+        self.session.as_ref().unwrap().set_blocking(true);
+        let stopped = result?;
+
+        // Deliver the last lines if they lack a trailing newline.
+        stdout.flush(SshStream::Stdout, &mut f);
+        stderr.flush(SshStream::Stderr, &mut f);
+
+        // Close channel and retrieve exit status. If the command was stopped, the remote host
+        // may not acknowledge the closure before the timeout, which isn't an error.
+        if stopped {
+            let _ = channel.close().and_then(|()| channel.wait_close());
+        } else {
+            channel.close()?;
+            channel.wait_close()?;
+        }
+        Ok(channel.exit_status()?)
+    }
+
+    /// Executes a command through the connection of a backend other than libssh2, and delivers
+    /// its output line by line (see `SshClient::stream_command`). Once stopped, its connection
+    /// is closed (or its process group is killed by the local backend) without sending the stop
+    /// signal, and its exit status is that of the terminated command, if any.
+    fn stream_connection<F>(&mut self, command: &str, stop: &AtomicBool, mut f: F) -> Result<i32>
+    where
+        F: FnMut(SshStream, &[u8], SshTimestamp),
+    {
+        let command = self.wrap_command(command, &[])?;
+        let input = self.become_input();
+        let mut input = input.as_deref();
+        let start = Instant::now();
+        let mut stdout = LineBuffer::new(start);
+        let mut stderr = LineBuffer::new(start);
+        let (exit_status, ..) = self.ensure_connection()?.run(
+            &command,
+            input.as_mut().map(|bytes| bytes as &mut dyn Read),
+            Some(stop),
+            &mut |stream, bytes| {
+                match stream {
+                    SshStream::Stdout => stdout.push(bytes, stream, &mut f),
+                    SshStream::Stderr => stderr.push(bytes, stream, &mut f),
+                }
+                Ok(())
+            },
+        )?;
+        stdout.flush(SshStream::Stdout, &mut f);
+        stderr.flush(SshStream::Stderr, &mut f);
+        Ok(exit_status)
+    }
+
+    /// Attempts to open an interactive shell on the configured host, in a pseudo-terminal.
+    ///
+    /// The bytes read from `input` (typically the standard input of a local terminal in raw
+    /// mode) are forwarded to the remote shell from a separate thread, and the output of the
+    /// remote shell is written to `output` as soon as it's received. The `window_size` closure
+    /// returns the number of columns and rows of the local terminal, and is called regularly
+    /// to resize the pseudo-terminal when it changes.
+    ///
+    /// It returns the exit status of the shell once it exits. Note that the thread reading
+    /// `input` keeps running until its next read returns.
+    ///
+    /// Note that this method implicitly calls [`SshClient::connect`] if no session was
+    /// established prior. Otherwise, it reuses the cached session
+    /// (see [`SshClient::set_keepalive`] to check its health first).
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// let result = ssh.shell_interactive("xterm", std::io::stdin(), std::io::stdout(), || (80, 24));
+    /// ```
+    pub fn shell_interactive<R, W, F>(
+        &mut self,
+        term: &str,
+        mut input: R,
+        mut output: W,
+        mut window_size: F,
+    ) -> Result<i32>
+    where
+        R: Read + Send + 'static,
+        W: Write,
+        F: FnMut() -> (u32, u32),
+    {
+        // Establish authenticated SSH session and open channel.
+        let mut channel = self.open_channel(Session::channel_session)?;
+        let session = self.session.as_ref().unwrap();
+
+        // Start a shell in a pseudo-terminal of the local terminal's size.
+        let size = window_size();
+        channel.request_pty(term, None, Some((size.0, size.1, 0, 0)))?;
+        channel.shell()?;
+
+        // Read the input in a separate thread, since reading it may block indefinitely.
+        // An empty chunk signifies the end of the input.
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let mut chunk = [0; 4 * 1024];
+            loop {
+                let len = input.read(&mut chunk).unwrap_or(0);
+                if tx.send(chunk[..len].to_vec()).is_err() || len == 0 {
+                    break;
+                }
+            }
+        });
+
+        // Forward the input and output without blocking, and resize the pseudo-terminal.
+        session.set_blocking(false);
+        let result = forward_shell(session, &mut channel, &rx, &mut output, size, window_size);
+        session.set_blocking(true);
+        result?;
+
+        // Close channel and retrieve exit status.
+        channel.close()?;
+        channel.wait_close()?;
+        Ok(channel.exit_status()?)
+    }
+
+    /// Attempts to download a file from the configured host.
+    ///
+    /// The file is first downloaded to a temporary file named `<path>.massh-tmp-XXXXXXXX` next
+    /// to its destination, which is renamed into place once the transfer succeeds, so that a
+    /// failed or interrupted download (which may be slow when throttled) never leaves a partial
+    /// file at the destination.
+    ///
+    /// Note that this method implicitly calls [`SshClient::connect`] if no session was
+    /// established prior. Otherwise, it reuses the cached session
+    /// (see [`SshClient::set_keepalive`] to check its health first).
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// if ssh.scp_download("remote.txt", "local.txt").is_ok() {
+    ///     println!("download worked!");
+    /// }
+    /// ```
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(user = %self.user, addr = %self.addr, remote_path = ?remote_path.as_ref()),
+            err
+        )
+    )]
+    pub fn scp_download<P: AsRef<Path>>(&mut self, remote_path: P, local_path: P) -> Result<()> {
+        #[cfg(feature = "tracing")]
+        let start = Instant::now();
+        self.download_file(remote_path.as_ref(), local_path.as_ref())?;
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            bytes = std::fs::metadata(local_path.as_ref()).map_or(0, |m| m.len()),
+            elapsed_ms = start.elapsed().as_millis() as u64,
+            "downloaded"
+        );
+        Ok(())
+    }
+
+    /// Downloads a file from the remote host, preserving the remote file's mode if specified.
+    fn download_file(&mut self, remote_path: &Path, local_path: &Path) -> Result<()> {
+        // Download to a temporary file next to the destination, then rename it into place.
+        let suffix = format!(".massh-tmp-{:08x}", rand::random::<u32>());
+        let mut temp_path = local_path.as_os_str().to_owned();
+        temp_path.push(suffix);
+        let temp_path = PathBuf::from(temp_path);
+        let result = self
+            .download_to(remote_path, &temp_path)
+            .and_then(|()| Ok(std::fs::rename(&temp_path, local_path)?));
+
+        // Remove the temporary file on failure, leaving the destination untouched.
+        if result.is_err() {
+            let _ = std::fs::remove_file(&temp_path);
+        }
+        result
+    }
+
+    /// Downloads a file from the remote host at exactly the given local path.
+    fn download_to(&mut self, remote_path: &Path, local_path: &Path) -> Result<()> {
+        // Download through `cat` with other backends, if configured.
+        if self.backend != SshBackend::Libssh2 {
+            return self.cat_download(remote_path, local_path);
+        }
+
+        // Establish authenticated SSH session and open channel.
+        let (mut channel, stat) = self.open_channel(|session| session.scp_recv(remote_path))?;
+
+        // Copy remote file to local file, throttled if specified.
+        let mut file = std::fs::File::create(local_path)?;
+        copy_throttled(&mut channel, &mut file, &self.rate_limiters())?;
+
+        // Apply the remote file's mode to the local file if specified.
+        #[cfg(unix)]
+        if self.preserve_attributes {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = stat.mode() as u32 & 0o7777;
+            file.set_permissions(std::fs::Permissions::from_mode(mode))?;
+        }
+        #[cfg(not(unix))]
+        let _ = stat;
+
+        // Close channel.
+        channel.send_eof()?;
+        channel.wait_eof()?;
+        channel.close()?;
+        channel.wait_close()?;
+
+        // Return successfully.
+        Ok(())
+    }
+
+    /// Attempts to upload a file to the configured host.
+    ///
+    /// Note that this method implicitly calls [`SshClient::connect`] if no session was
+    /// established prior. Otherwise, it reuses the cached session
+    /// (see [`SshClient::set_keepalive`] to check its health first).
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// if ssh.scp_upload("local.txt", "remote.txt").is_ok() {
+    ///     println!("upload worked!");
+    /// }
+    /// ```
+    pub fn scp_upload<P: AsRef<Path>>(&mut self, local_path: P, remote_path: P) -> Result<()> {
+        let preserve = self.preserve_attributes;
+        self.upload_file(local_path.as_ref(), remote_path.as_ref(), preserve)
+    }
+
+    /// Attempts to authorize a public key for the configured user, by appending it to
+    /// `~/.ssh/authorized_keys` on the configured host like `ssh-copy-id(1)`.
+    ///
+    /// The `~/.ssh` directory and `authorized_keys` file are created if they don't exist, and
+    /// their permissions are set to `700` and `600`. The key is the content of a public key file
+    /// (e.g. `~/.ssh/id_ed25519.pub`). It returns `false` if the key was already authorized.
+    ///
+    /// This is typically used with password authentication to bootstrap new hosts.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    /// ssh.set_auth_password("top-secret");
+    ///
+    /// let public_key = std::fs::read_to_string("/home/username/.ssh/id_ed25519.pub").unwrap();
+    /// if ssh.copy_id(&public_key).is_ok() {
+    ///     ssh.set_auth_pubkey("/home/username/.ssh/id_ed25519");
+    /// }
+    /// ```
+    pub fn copy_id(&mut self, public_key: &str) -> Result<bool> {
+        self.shell.ensure_posix()?;
+
+        // Check that the key is a single line with at least a type and a base64 blob.
+        let public_key = public_key.trim();
+        let blob = match public_key.split_whitespace().nth(1) {
+            Some(blob) if !public_key.contains('\n') => blob,
+            _ => return Err(anyhow::anyhow!("Invalid public key: {:?}", public_key)),
+        };
+
+        // Append the key unless its blob is already there, after a newline if missing.
+        let command = format!(
+            "umask 077 && mkdir -p ~/.ssh && touch ~/.ssh/authorized_keys && \
+             chmod 700 ~/.ssh && chmod 600 ~/.ssh/authorized_keys && \
+             if grep -qF -- {blob} ~/.ssh/authorized_keys; then echo present; else \
+             if [ -n \"$(tail -c 1 ~/.ssh/authorized_keys)\" ]; then \
+             echo >> ~/.ssh/authorized_keys; fi && \
+             echo {key} >> ~/.ssh/authorized_keys && echo added; fi",
+            blob = shell::quote(blob),
+            key = shell::quote(public_key),
+        );
+        let output = self.execute_checked(&command, "Failed to authorize public key")?;
+        Ok(output.stdout.starts_with(b"added"))
+    }
+
+    /// Uploads a file to the remote host, atomically if specified, and preserving the local
+    /// file's mode and times if `preserve` is true.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(user = %self.user, addr = %self.addr, remote_path = ?remote_path),
+            err
+        )
+    )]
+    pub(crate) fn upload_file(
+        &mut self,
+        local_path: &Path,
+        remote_path: &Path,
+        preserve: bool,
+    ) -> Result<()> {
+        #[cfg(feature = "tracing")]
+        let start = Instant::now();
+        self.upload_file_inner(local_path, remote_path, preserve)?;
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            bytes = std::fs::metadata(local_path).map_or(0, |m| m.len()),
+            elapsed_ms = start.elapsed().as_millis() as u64,
+            "uploaded"
+        );
+        Ok(())
+    }
+
+    /// Uploads a file to the remote host (see `SshClient::upload_file`).
+    fn upload_file_inner(
+        &mut self,
+        local_path: &Path,
+        remote_path: &Path,
+        preserve: bool,
+    ) -> Result<()> {
+        // Check the free space at the destination if specified.
+        if self.check_free_space {
+            let len = std::fs::metadata(local_path)?.len();
+            self.ensure_free_space(remote_path, len)?;
+        }
+
+        // Upload directly to the destination unless atomic uploads are enabled.
+        if !self.atomic_uploads {
+            return self.scp_upload_to(local_path, remote_path, preserve);
+        }
+
+        // Upload to a temporary file next to the destination, then rename it into place.
+        let suffix = format!(".massh-tmp-{:08x}", rand::random::<u32>());
+        let mut temp_path = remote_path.as_os_str().to_owned();
+        temp_path.push(suffix);
+        let temp_path = PathBuf::from(temp_path);
+        let result = self
+            .scp_upload_to(local_path, &temp_path, preserve)
+            .and_then(|()| {
+                let command = self
+                    .shell
+                    .rename(utf8_path(&temp_path)?, utf8_path(remote_path)?);
+                self.execute_checked(&command, "Rename after upload failed")
+                    .map(|_| ())
+            });
+
+        // Remove the temporary file on failure, leaving the destination untouched.
+        if result.is_err() && self.is_connected() {
+            if let Ok(path) = utf8_path(&temp_path) {
+                let _ = self.execute(&self.shell.remove(path));
+            }
+        }
+        result
+    }
+
+    /// Uploads a file to the remote host at exactly the given path.
+    fn scp_upload_to(
+        &mut self,
+        local_path: &Path,
+        remote_path: &Path,
+        preserve: bool,
+    ) -> Result<()> {
+        // Open local file, hashing its content as it's read.
+        let file = std::fs::File::open(local_path)?;
+        let metadata = file.metadata()?;
+        let mut reader = HashingReader {
+            inner: file,
+            hasher: Sha256::new(),
+        };
+
+        // Determine the remote file's mode and times from the configured options.
+        let (mode, times) = if preserve {
+            (local_mode(&metadata), local_times(&metadata))
+        } else {
+            (0o644, None)
+        };
+        let mode = self.upload_mode.unwrap_or(mode);
+
+        let len = metadata.len();
+        let chunks = self.chunk_count(len);
+        if chunks > 1 {
+            // Upload the file in chunks over parallel sessions, then verify its checksum.
+            self.upload_chunked(local_path, remote_path, len, chunks, mode, times)?;
+        } else {
+            self.send_file(&mut reader, remote_path, len, mode, times)?;
+
+            // Verify the checksum of the remote file if specified.
+            if self.verify_uploads {
+                let local_checksum = format!("{:x}", reader.hasher.finalize());
+                self.verify_checksum(remote_path, &local_checksum)?;
+            }
+        }
+
+        // Change the owner of the remote file if specified.
+        if let Some(owner) = &self.upload_owner {
+            self.shell.ensure_posix()?;
+            let command = format!(
+                "chown -- {} {}",
+                shell::quote(owner),
+                shell::quote(utf8_path(remote_path)?)
+            );
+            self.execute_checked(&command, "Chown after upload failed")?;
+        }
+
+        // Return successfully.
+        Ok(())
+    }
+
+    /// Writes the content of a reader of the specified length to a remote file, with `scp` or
+    /// through `cat` with backends other than libssh2, throttled if specified.
+    pub(crate) fn send_file(
+        &mut self,
+        reader: &mut impl Read,
+        remote_path: &Path,
+        len: u64,
+        mode: i32,
+        times: Option<(u64, u64)>,
+    ) -> Result<()> {
+        if self.backend != SshBackend::Libssh2 {
+            return self.cat_upload(reader, remote_path, mode, times);
+        }
+
+        // Establish authenticated SSH session and open channel.
+        let mut channel =
+            self.open_channel(|session| session.scp_send(remote_path, mode, len, times))?;
+
+        // Copy local file to remote file, throttled if specified.
+        copy_throttled(reader, &mut channel, &self.rate_limiters())?;
+
+        // Acknowledge the end of the file, without which the remote `scp` exits before
+        // applying the times.
+        if times.is_some() {
+            channel.write_all(&[0])?;
+        }
+
+        // Close channel.
+        channel.send_eof()?;
+        channel.wait_eof()?;
+        channel.close()?;
+        channel.wait_close()?;
+        Ok(())
+    }
+
+    /// Executes a command on the remote host, and fails with the given context and the
+    /// command's standard error if its exit status is nonzero.
+    pub(crate) fn execute_checked(&mut self, command: &str, context: &str) -> Result<SshOutput> {
+        let output = self.execute_raw(command, &[], None)?;
+        if !output.success() {
+            let stderr = output.stderr_lossy();
+            return Err(anyhow::anyhow!("{}: {}", context, stderr.trim_end()));
+        }
+        Ok(output)
+    }
+
+    /// Returns the command prefixed to run in the working directory, if any, and as the become
+    /// user, if any, in which case it also sets the environment variables, which `sudo` resets.
+    fn wrap_command(&self, command: &str, env: &[(&str, &str)]) -> Result<String> {
+        let command = match &self.working_dir {
+            Some(dir) => self.shell.in_dir(dir, command),
+            None => command.to_string(),
+        };
+        match &self.become_user {
+            Some(user) => {
+                check_env(env)?;
+                let command = self.shell.with_env(&command, env);
+                self.shell
+                    .as_user(user, self.become_password.is_some(), &command)
+            }
+            None => Ok(command),
+        }
+    }
+
+    /// Returns the standard input of commands executed as the become user, i.e. the line from
+    /// which `sudo` reads the password, if any.
+    fn become_input(&self) -> Option<Vec<u8>> {
+        match (&self.become_user, &self.become_password) {
+            (Some(_), Some(password)) => Some(format!("{}\n", password).into_bytes()),
+            _ => None,
+        }
+    }
+
+    /// Executes a command on the remote host with its standard input streamed from a reader,
+    /// throttled if specified, and fails with the given context if its exit status is nonzero.
+    pub(crate) fn execute_with_input(
+        &mut self,
+        command: &str,
+        input: &mut impl Read,
+        context: &str,
+    ) -> Result<()> {
+        if self.backend != SshBackend::Libssh2 {
+            let mut input = ThrottledReader {
+                inner: input,
+                limiters: self.rate_limiters(),
+            };
+            return self.run_piped(command, Some(&mut input), &mut |_| Ok(()), context);
+        }
+        let mut channel = self.open_channel(Session::channel_session)?;
+        channel.exec(command)?;
+        self.finish_piped(
+            channel,
+            Some(input as &mut dyn Read),
+            &mut std::io::sink(),
+            context,
+        )
+    }
+
+    /// Executes a command on the remote host with its standard output streamed to a writer,
+    /// throttled if specified, and fails with the given context if its exit status is nonzero.
+    pub(crate) fn execute_with_output(
+        &mut self,
+        command: &str,
+        output: &mut impl Write,
+        context: &str,
+    ) -> Result<()> {
+        if self.backend != SshBackend::Libssh2 {
+            let limiters = self.rate_limiters();
+            let mut write = |bytes: &[u8]| {
+                limiters
+                    .iter()
+                    .for_each(|limiter| limiter.acquire(bytes.len()));
+                Ok(output.write_all(bytes)?)
+            };
+            return self.run_piped(command, None, &mut write, context);
+        }
+        let mut channel = self.open_channel(Session::channel_session)?;
+        channel.exec(command)?;
+        self.finish_piped(channel, None, output, context)
+    }
+
+    /// Executes a command through the connection of a backend other than libssh2, with its
+    /// standard input streamed from a reader if specified, and its standard output passed to
+    /// a closure, and fails with the given context and the command's standard error if its exit
+    /// status is nonzero.
+    fn run_piped(
+        &mut self,
+        command: &str,
+        input: Option<&mut dyn Read>,
+        stdout: &mut dyn FnMut(&[u8]) -> Result<()>,
+        context: &str,
+    ) -> Result<()> {
+        let mut stderr = CappedBuffer::new(64 * 1024, SshOverflow::Truncate);
+        let (exit_status, ..) = self.ensure_connection()?.run(
+            command,
+            input,
+            None,
+            &mut |stream, bytes| match stream {
+                SshStream::Stdout => stdout(bytes),
+                SshStream::Stderr => stderr.push(bytes),
+            },
+        )?;
+        if exit_status != 0 {
+            let (stderr, _, _) = stderr.finish();
+            let stderr = String::from_utf8_lossy(&stderr);
+            return Err(anyhow::anyhow!("{}: {}", context, stderr.trim_end()));
+        }
+        Ok(())
+    }
+
+    /// Downloads a file from the remote host with `cat`, for backends other than libssh2.
+    fn cat_download(&mut self, remote_path: &Path, local_path: &Path) -> Result<()> {
+        let path = shell::quote(utf8_path(remote_path)?);
+        let mut file = std::fs::File::create(local_path)?;
+        let command = format!("cat -- {}", path);
+        self.execute_with_output(&command, &mut file, "Download failed")?;
+
+        // Apply the remote file's mode to the local file if specified.
+        #[cfg(unix)]
+        if self.preserve_attributes {
+            use std::os::unix::fs::PermissionsExt;
+            let command = format!("stat -c %a -- {0} 2>/dev/null || stat -f %Lp -- {0}", path);
+            let output = self.execute_checked(&command, "Stat after download failed")?;
+            let mode = u32::from_str_radix(output.stdout_lossy().trim(), 8)?;
+            file.set_permissions(std::fs::Permissions::from_mode(mode))?;
+        }
+
+        Ok(())
+    }
+
+    /// Uploads a file to the remote host with `cat`, for backends other than libssh2, then
+    /// applies its mode and times, if any (which requires GNU `touch`).
+    fn cat_upload(
+        &mut self,
+        reader: &mut impl Read,
+        remote_path: &Path,
+        mode: i32,
+        times: Option<(u64, u64)>,
+    ) -> Result<()> {
+        let path = shell::quote(utf8_path(remote_path)?);
+        let command = format!("cat > {0} && chmod {1:o} -- {0}", path, mode);
+        self.execute_with_input(&command, reader, "Upload failed")?;
+        if let Some(times) = times {
+            self.touch(remote_path, times)?;
+        }
+        Ok(())
+    }
+
+    /// Applies modification and access times to a remote file with `touch` (which requires
+    /// GNU `touch`).
+    pub(crate) fn touch(&mut self, remote_path: &Path, (mtime, atime): (u64, u64)) -> Result<()> {
+        let command = format!(
+            "touch -m -d @{1} -- {0} && touch -a -d @{2} -- {0}",
+            shell::quote(utf8_path(remote_path)?),
+            mtime,
+            atime
+        );
+        self.execute_checked(&command, "Touch after upload failed")?;
+        Ok(())
+    }
+
+    /// Streams the standard input and output of a piped command's channel while draining its
+    /// standard error, closes it, and fails with the given context and the standard error if
+    /// the command's exit status is nonzero.
+    fn finish_piped(
+        &self,
+        mut channel: ssh2::Channel,
+        input: Option<&mut dyn Read>,
+        output: &mut dyn Write,
+        context: &str,
+    ) -> Result<()> {
+        // Stream without blocking, so that a command filling its standard error while its
+        // other streams are busy can't exhaust the channel's window.
+        let session = self.session.as_ref().unwrap();
+        let limiters = self.rate_limiters();
+        session.set_blocking(false);
+        let result = pump_piped(session, &mut channel, input, output, &limiters);
+        session.set_blocking(true);
+        let stderr = result?;
+        channel.wait_close()?;
+        if channel.exit_status()? != 0 {
+            let stderr = String::from_utf8_lossy(&stderr);
+            return Err(anyhow::anyhow!("{}: {}", context, stderr.trim_end()));
+        }
+        Ok(())
+    }
+
+    /// Computes the SHA-256 checksum of a remote file with `sha256sum` (or `shasum -a 256`
+    /// as a fallback, or the equivalent command of other shells), and compares it to the
+    /// expected checksum in lowercase hexadecimal.
+    pub(crate) fn verify_checksum(&mut self, remote_path: &Path, expected: &str) -> Result<()> {
+        let command = self.shell.sha256(utf8_path(remote_path)?);
+        let output = self.execute_checked(&command, "Checksum computation failed")?;
+        let actual = parse_sha256(&output.stdout_lossy()).unwrap_or_default();
+        if actual != expected {
+            return Err(anyhow::anyhow!(
+                "Checksum mismatch after upload: local {} != remote {}",
+                expected,
+                actual
+            ));
+        }
+        Ok(())
+    }
+
+    /// Attempts to establish an authenticated session between this `SshClient`
+    /// and the configured host.
+    ///
+    /// If successful, the session is cached internally by the client and is reused when
+    /// running multiple commands with [`SshClient::execute`], [`SshClient::scp_download`],
+    /// or [`SshClient::scp_upload`].
+    ///
+    /// Note that it's not strictly necessary to call this method because the 3 methods
+    /// mentioned above will invoke it lazily if no session was established prior.
+    ///
+    /// Finally, if the first session succeeds but the second session fails,
+    /// the first session will remain cached internally by the client. If the second
+    /// session succeeds, it replaces the first session (which is dropped).
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// if ssh.connect().is_ok() {
+    ///     println!("agent authentication worked!");
+    /// }
+    ///
     /// if ssh.set_auth_password("top-secret").connect().is_ok() {
     ///     println!("password authentication also worked!");
     /// }
     /// ```
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(user = %self.user, addr = %self.addr, backend = %self.backend)
+        )
+    )]
     pub fn connect(&mut self) -> Result<&mut Self> {
+        let start = Instant::now();
+        if let Some((host, observer)) = &self.observer {
+            observer.on_connect_start(host);
+        }
+        let result = self.open_session();
+        if let Some((host, observer)) = &self.observer {
+            match &result {
+                Ok(()) => observer.on_connect_ok(host, start.elapsed()),
+                Err(e) => observer.on_connect_err(host, start.elapsed(), e),
+            }
+        }
+        result?;
+        Ok(self)
+    }
+
+    /// Establishes an authenticated session, or a connection through another backend
+    /// (see `SshClient::connect`).
+    fn open_session(&mut self) -> Result<()> {
+        // Establish a connection through another backend, if configured.
+        if self.backend != SshBackend::Libssh2 {
+            let start = Instant::now();
+            self.connection = Some(backend::connect(self)?);
+            self.latency = SshLatency {
+                connect: start.elapsed(),
+                ..SshLatency::default()
+            };
+            #[cfg(feature = "tracing")]
+            tracing::debug!(elapsed_ms = start.elapsed().as_millis() as u64, "connected");
+            return Ok(());
+        }
+
         // Initialize new SSH session.
         let mut session = Session::new()?;
 
-        // Open a TCP connection to the configured host and attach it to the SSH session.
-        let tcp_stream = if self.timeout == 0 {
-            // If timeout is zero, don't set a timeout.
-            TcpStream::connect(&self.addr)?
-        } else {
-            // If timeout is non-zero, set a timeout on both the SSH session and the TCP stream.
+        // If timeout is non-zero, set a timeout on both the SSH session and the TCP stream.
+        if self.timeout != 0 {
             session.set_timeout(self.timeout as u32);
-            TcpStream::connect_timeout(&self.addr, Duration::from_millis(self.timeout))?
-        };
+        }
+
+        // Open a TCP connection to an address of the configured host, then remember it and
+        // attach it to the SSH session.
+        let start = Instant::now();
+        let (tcp_stream, addr) = connect_any(&self.addrs_to_try(), self.timeout)?;
+        self.addr = addr;
         session.set_tcp_stream(tcp_stream);
+        self.latency = SshLatency {
+            connect: start.elapsed(),
+            ..SshLatency::default()
+        };
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            %addr,
+            elapsed_ms = start.elapsed().as_millis() as u64,
+            "connected"
+        );
+
+        self.establish(session)?;
+        Ok(())
+    }
+
+    /// Attempts to establish an authenticated session between this `SshClient` and the configured
+    /// host over an existing stream, instead of opening a TCP connection to its address.
+    ///
+    /// The stream can be any connected socket, such as a `TcpStream` obtained through a proxy,
+    /// a `UnixStream` to a local forwarder, or a `TcpStream` of a connection brokered by another
+    /// tool. To ride over a stream that isn't a socket (e.g. a TLS tunnel or the channel of
+    /// another session), one end of a `UnixStream::pair` can be passed to this method while a
+    /// thread copies bytes between the other end and that stream.
+    ///
+    /// Otherwise, it behaves like [`SshClient::connect`]. Note that the session can't be
+    /// re-established transparently, so a dead session in persistent-session mode
+    /// (see [`SshClient::set_keepalive`]) is replaced by a regular TCP connection.
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// # #[cfg(unix)]
+    /// # fn main() {
+    /// use std::os::unix::net::UnixStream;
+    ///
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// let stream = UnixStream::connect("/run/forwarder.sock").unwrap();
+    /// ssh.connect_over(stream).unwrap();
+    /// let output = ssh.execute("hostname").unwrap();
+    /// # }
+    /// # #[cfg(not(unix))]
+    /// # fn main() {}
+    /// ```
+    pub fn connect_over(&mut self, stream: impl SshTransport) -> Result<&mut Self> {
+        self.ensure_libssh2()?;
+
+        // Initialize new SSH session, with a timeout if non-zero, and attach the stream to it.
+        let mut session = Session::new()?;
+        if self.timeout != 0 {
+            session.set_timeout(self.timeout as u32);
+        }
+        session.set_tcp_stream(stream);
+        self.latency = SshLatency::default();
+
+        self.establish(session)
+    }
+
+    /// Performs the handshake and authentication of a new session attached to a stream,
+    /// then caches it.
+    fn establish(&mut self, mut session: Session) -> Result<&mut Self> {
+        // Negotiate compression if enabled, which must be done before the handshake.
+        // Delayed compression is preferred because it's the only one enabled by modern servers.
+        if self.compression {
+            session.set_compress(true);
+            for method in [MethodType::CompCs, MethodType::CompSc] {
+                session.method_pref(method, "zlib@openssh.com,zlib,none")?;
+            }
+        }
+
+        // Override the preferred algorithms, if configured.
+        for (method, prefs) in &self.method_prefs {
+            session.method_pref(*method, prefs).map_err(|e| {
+                anyhow::anyhow!("Invalid algorithm preferences {:?}: {}", prefs, e.message())
+            })?;
+        }
 
         // Perform SSH handshake.
+        let start = Instant::now();
         session.handshake()?;
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            elapsed_ms = start.elapsed().as_millis() as u64,
+            "handshake completed"
+        );
+
+        // Verify the host key against the pinned fingerprint, if any.
+        if let Some(expected) = &self.host_key {
+            verify_host_key(&session, expected)?;
+        }
+        self.latency.handshake = start.elapsed();
+        let start = Instant::now();
 
         // Perform SSH authentication based on selected method.
         match &self.auth {
-            SshAuth::Agent => session.userauth_agent(&self.user)?,
+            SshAuth::Agent => userauth_agent(&session, &self.user, None, None)?,
+            SshAuth::AgentKey(key) => userauth_agent(&session, &self.user, None, Some(key))?,
+            SshAuth::AgentSocket { path, key } => {
+                userauth_agent(&session, &self.user, Some(path), key.as_deref())?
+            }
             SshAuth::Password(password) => session.userauth_password(&self.user, password)?,
+            SshAuth::PasswordCmd(command) => {
+                let password = run_secret_command(command)?;
+                session.userauth_password(&self.user, &password)?
+            }
             SshAuth::Pubkey(path) => session.userauth_pubkey_file(&self.user, None, path, None)?,
+            SshAuth::PubkeyPassphraseCmd {
+                path,
+                passphrase_cmd,
+            } => {
+                let passphrase = run_secret_command(passphrase_cmd)?;
+                session.userauth_pubkey_file(&self.user, None, path, Some(&passphrase))?
+            }
         }
 
         // Confirm that the session is authenticated.
         if !session.authenticated() {
             return Err(anyhow::anyhow!("Authentication failed"));
         }
+        self.latency.auth = start.elapsed();
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            elapsed_ms = start.elapsed().as_millis() as u64,
+            "authenticated"
+        );
+
+        // Configure keepalive messages if the persistent-session mode is enabled.
+        if self.keepalive != 0 {
+            session.set_keepalive(true, self.keepalive);
+        }
 
         // Cache authenticated session and return successfully.
         self.session = Some(session);
+        self.sftp_unsupported = false;
         Ok(self)
     }
 
@@ -434,6 +2696,8 @@ impl SshClient {
     ///
     /// ## Example
     /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
     /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
     ///
     /// ssh.connect().unwrap();
@@ -441,7 +2705,896 @@ impl SshClient {
     /// ssh.disconnect();
     /// ```
     pub fn disconnect(&mut self) -> &mut Self {
+        self.connection = None;
         self.session = None;
         self
     }
+
+    /// Reports the run time and the exit status of a command started at an instant, or its error,
+    /// to the observer, if any.
+    fn observe_command(&self, start: Instant, result: Result<i32, &anyhow::Error>) {
+        if let Some((host, observer)) = &self.observer {
+            match result {
+                Ok(exit_status) => observer.on_command_done(host, start.elapsed(), exit_status),
+                Err(e) => observer.on_command_err(host, start.elapsed(), e),
+            }
+        }
+    }
+
+    /// Configures the other addresses of the configured host, which are tried after its address
+    /// when connecting, like the addresses resolved by `SshClient::try_from`.
+    pub(crate) fn set_fallback_addrs(&mut self, addrs: &[SocketAddr]) -> &mut Self {
+        self.addrs = std::iter::once(self.addr)
+            .chain(addrs.iter().copied())
+            .collect();
+        self
+    }
+
+    /// Returns the addresses of the configured host in the order in which to try them, starting
+    /// with the one which last accepted a connection.
+    pub(crate) fn addrs_to_try(&self) -> Vec<SocketAddr> {
+        let mut addrs = vec![self.addr];
+        addrs.extend(self.addrs.iter().filter(|addr| **addr != self.addr));
+        addrs
+    }
+
+    /// Returns the rate limiters that apply to a new file transfer.
+    pub(crate) fn rate_limiters(&self) -> Vec<Arc<RateLimiter>> {
+        let mut limiters = Vec::new();
+        if self.rate_limit != 0 {
+            limiters.push(Arc::new(RateLimiter::new(self.rate_limit)));
+        }
+        if let Some(limiter) = &self.shared_rate_limiter {
+            limiters.push(limiter.clone());
+        }
+        limiters
+    }
+
+    /// Returns a new `SshClient` with the same configuration as this one, except for its
+    /// observer and rate limits, to transfer files over an additional session.
+    pub(crate) fn fork(&self) -> SshClient {
+        SshClient {
+            addr: self.addr,
+            addrs: self.addrs.clone(),
+            atomic_uploads: self.atomic_uploads,
+            auth: self.auth.clone(),
+            auto_reconnect: self.auto_reconnect,
+            backend: self.backend,
+            become_password: self.become_password.clone(),
+            become_user: self.become_user.clone(),
+            check_free_space: self.check_free_space,
+            compression: self.compression,
+            connection: None,
+            host_key: self.host_key.clone(),
+            keepalive: self.keepalive,
+            latency: SshLatency::default(),
+            max_output: self.max_output,
+            merge_output: self.merge_output,
+            method_prefs: self.method_prefs.clone(),
+            observer: None,
+            overflow: self.overflow,
+            preserve_attributes: self.preserve_attributes,
+            rate_limit: 0,
+            shared_rate_limiter: None,
+            session: None,
+            sftp_unsupported: false,
+            shell: self.shell,
+            stop_signal: self.stop_signal,
+            timeout: self.timeout,
+            timestamps: self.timestamps,
+            upload_chunks: 0,
+            upload_mode: self.upload_mode,
+            upload_owner: self.upload_owner.clone(),
+            user: self.user.clone(),
+            verify_uploads: self.verify_uploads,
+            working_dir: self.working_dir.clone(),
+        }
+    }
+
+    /// Starts an SFTP session, or returns `None` if the server doesn't support SFTP or with
+    /// backends other than libssh2. A failure is remembered until a new session is established,
+    /// so that SFTP isn't requested again from a server which doesn't support it.
+    pub(crate) fn sftp(&mut self) -> Option<ssh2::Sftp> {
+        if self.backend != SshBackend::Libssh2 || self.sftp_unsupported {
+            return None;
+        }
+        let sftp = self.open_channel(Session::sftp).ok();
+        self.sftp_unsupported = sftp.is_none();
+        sftp
+    }
+
+    /// Returns the authenticated session, established first if needed (see `ensure_connected`).
+    pub(crate) fn connected_session(&mut self) -> Result<&Session> {
+        self.ensure_connected()?;
+        Ok(self.session.as_ref().unwrap())
+    }
+
+    /// Returns the durations of the phases of the last session establishment
+    /// (see `SshClient::bench`).
+    pub(crate) fn connect_latency(&self) -> SshLatency {
+        self.latency
+    }
+
+    /// Establishes an authenticated session if none was established prior,
+    /// or if the cached session fails its health check in persistent-session mode.
+    fn ensure_connected(&mut self) -> Result<()> {
+        self.ensure_libssh2()?;
+        if let Some(session) = &self.session {
+            if self.keepalive == 0 || session.keepalive_send().is_ok() {
+                return Ok(());
+            }
+            // The cached session is dead, so drop it before reconnecting.
+            self.session = None;
+        }
+        self.connect()?;
+        Ok(())
+    }
+
+    /// Establishes an authenticated session if needed (see `ensure_connected`), then opens a
+    /// channel on it. If opening the channel fails because the session's connection was lost and
+    /// auto-reconnect is enabled, a new session is established and the channel is opened again.
+    pub(crate) fn open_channel<T>(
+        &mut self,
+        open: impl Fn(&Session) -> Result<T, ssh2::Error>,
+    ) -> Result<T> {
+        self.ensure_connected()?;
+        match open(self.session.as_ref().unwrap()) {
+            Err(e) if self.auto_reconnect && is_disconnected(&e) => {
+                self.session = None;
+                self.connect()?;
+                Ok(open(self.session.as_ref().unwrap())?)
+            }
+            result => Ok(result?),
+        }
+    }
+
+    /// Establishes an authenticated connection through a backend other than libssh2 if none was
+    /// established prior, or if the cached connection was closed, then returns it.
+    fn ensure_connection(&mut self) -> Result<&mut dyn Connection> {
+        if self.connection.as_ref().map_or(true, |c| c.is_closed()) {
+            self.connection = None;
+            self.connect()?;
+        }
+        Ok(self.connection.as_deref_mut().unwrap())
+    }
+
+    /// Fails if this `SshClient` uses a backend other than libssh2, for operations
+    /// which require a libssh2 session.
+    fn ensure_libssh2(&self) -> Result<()> {
+        if self.backend != SshBackend::Libssh2 {
+            return Err(anyhow::anyhow!(
+                "This operation is not supported by the {} backend",
+                self.backend
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Returns whether a libssh2 error signifies that the connection of its session was lost,
+/// i.e. `LIBSSH2_ERROR_SOCKET_SEND`, `LIBSSH2_ERROR_SOCKET_DISCONNECT`, or
+/// `LIBSSH2_ERROR_SOCKET_RECV`.
+fn is_disconnected(error: &ssh2::Error) -> bool {
+    matches!(error.code(), ssh2::ErrorCode::Session(-7 | -13 | -43))
+}
+
+/// Opens a TCP connection to the first address which accepts it, within the timeout of each
+/// attempt, in milliseconds, unless it's zero.
+///
+/// As in Happy Eyeballs (RFC 8305), the addresses are tried in order, and each attempt starts
+/// when the previous one fails or after 250 milliseconds, whichever comes first, so that an
+/// unreachable address (e.g. the IPv6 address of a host with broken IPv6) doesn't delay the
+/// connection for the whole timeout.
+pub(crate) fn connect_any(
+    addrs: &[SocketAddr],
+    timeout: u64,
+) -> std::io::Result<(TcpStream, SocketAddr)> {
+    let connect = move |addr: SocketAddr| match timeout {
+        0 => TcpStream::connect(addr),
+        _ => TcpStream::connect_timeout(&addr, Duration::from_millis(timeout)),
+    };
+    if let [addr] = addrs {
+        return connect(*addr).map(|stream| (stream, *addr));
+    }
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let mut last_error = None;
+    let mut pending = 0;
+    for (i, addr) in addrs.iter().copied().enumerate() {
+        // Start the next attempt.
+        let sender = sender.clone();
+        std::thread::spawn(move || sender.send((addr, connect(addr))));
+        pending += 1;
+
+        // Wait for any attempt to succeed, until all the pending ones fail or 250 milliseconds
+        // have elapsed, unless this is the last attempt.
+        let deadline = Instant::now() + Duration::from_millis(250);
+        while pending > 0 && (i + 1 == addrs.len() || Instant::now() < deadline) {
+            let result = if i + 1 == addrs.len() {
+                receiver.recv().ok()
+            } else {
+                receiver.recv_timeout(deadline - Instant::now()).ok()
+            };
+            match result {
+                Some((addr, Ok(stream))) => return Ok((stream, addr)),
+                Some((_, Err(e))) => {
+                    last_error = Some(e);
+                    pending -= 1;
+                }
+                None => break,
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| std::io::ErrorKind::NotFound.into()))
+}
+
+/// Forwards the chunks of input received from a channel to the standard input of a shell,
+/// and its output to a writer, until the shell exits. The session must be non-blocking.
+fn forward_shell<W, F>(
+    session: &Session,
+    channel: &mut ssh2::Channel,
+    input: &std::sync::mpsc::Receiver<Vec<u8>>,
+    output: &mut W,
+    mut size: (u32, u32),
+    mut window_size: F,
+) -> Result<()>
+where
+    W: Write,
+    F: FnMut() -> (u32, u32),
+{
+    let (mut pending, mut input_eof) = (Vec::new(), false);
+    let mut chunk = [0; 16 * 1024];
+    loop {
+        // Write the pending input, or send EOF once all of it was written.
+        while let Ok(bytes) = input.try_recv() {
+            input_eof |= bytes.is_empty();
+            pending.extend_from_slice(&bytes);
+        }
+        if !pending.is_empty() {
+            match channel.write(&pending) {
+                Ok(len) => drop(pending.drain(..len)),
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e.into()),
+            }
+        } else if input_eof {
+            input_eof = false;
+            session.set_blocking(true);
+            let result = channel.send_eof();
+            session.set_blocking(false);
+            result?;
+        }
+
+        // Write the available output of both streams.
+        let mut idle = true;
+        for stream_id in 0..2 {
+            let len = match channel.stream(stream_id).read(&mut chunk) {
+                Ok(len) => len,
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => 0,
+                Err(e) => return Err(e.into()),
+            };
+            if len > 0 {
+                idle = false;
+                output.write_all(&chunk[..len])?;
+                output.flush()?;
+            }
+        }
+        if idle && channel.eof() {
+            return Ok(());
+        }
+
+        // Resize the pseudo-terminal if the size of the local terminal changed.
+        let new_size = window_size();
+        if new_size != size {
+            size = new_size;
+            session.set_blocking(true);
+            let result = channel.request_pty_size(size.0, size.1, None, None);
+            session.set_blocking(false);
+            result?;
+        }
+        if idle {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+/// Streams the standard input of a channel from a reader if specified, throttled, and its
+/// standard output to a writer, throttled, until its end. Returns the first 64 KiB of its
+/// standard error, which is read in the meantime. The session must be non-blocking.
+fn pump_piped(
+    session: &Session,
+    channel: &mut ssh2::Channel,
+    mut input: Option<&mut dyn Read>,
+    output: &mut dyn Write,
+    limiters: &[Arc<RateLimiter>],
+) -> Result<Vec<u8>> {
+    let mut stderr = CappedBuffer::new(64 * 1024, SshOverflow::Truncate);
+    let (mut pending, mut written) = (Vec::new(), 0);
+    let mut chunk = [0; 16 * 1024];
+    loop {
+        // Read the next chunk of input once the previous one was written, or send EOF at its
+        // end, and write as much of it as possible.
+        let mut idle = true;
+        if written == pending.len() {
+            if let Some(reader) = input.as_mut() {
+                pending.resize(chunk.len(), 0);
+                let len = loop {
+                    match reader.read(&mut pending) {
+                        Ok(len) => break len,
+                        Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                        Err(e) => return Err(e.into()),
+                    }
+                };
+                pending.truncate(len);
+                written = 0;
+                if len == 0 {
+                    input = None;
+                    session.set_blocking(true);
+                    let result = channel.send_eof();
+                    session.set_blocking(false);
+                    result?;
+                } else {
+                    limiters.iter().for_each(|limiter| limiter.acquire(len));
+                }
+            }
+        }
+        if written < pending.len() {
+            match channel.write(&pending[written..]) {
+                Ok(len) => {
+                    idle &= len == 0;
+                    written += len;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        // Write the available standard output, and buffer the available standard error.
+        for stream_id in 0..2 {
+            let len = match channel.stream(stream_id).read(&mut chunk) {
+                Ok(len) => len,
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => 0,
+                Err(e) => return Err(e.into()),
+            };
+            if len == 0 {
+                continue;
+            }
+            idle = false;
+            if stream_id == 0 {
+                limiters.iter().for_each(|limiter| limiter.acquire(len));
+                output.write_all(&chunk[..len])?;
+            } else {
+                stderr.push(&chunk[..len])?;
+            }
+        }
+        if idle && channel.eof() {
+            return Ok(stderr.finish().0);
+        }
+        if idle {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+/// Buffer of a streamed output, which delivers its complete lines to a closure, with the time
+/// at which they were received since the command was executed at `start`.
+struct LineBuffer {
+    buffer: Vec<u8>,
+    received: Option<SshTimestamp>,
+    start: Instant,
+}
+
+impl LineBuffer {
+    fn new(start: Instant) -> Self {
+        LineBuffer {
+            buffer: Vec::new(),
+            received: None,
+            start,
+        }
+    }
+
+    /// Reads the available bytes of a non-blocking stream, and delivers the complete lines.
+    /// Returns the number of bytes read, which is zero if none are available.
+    fn fill<F>(&mut self, reader: &mut impl Read, stream: SshStream, f: &mut F) -> Result<usize>
+    where
+        F: FnMut(SshStream, &[u8], SshTimestamp),
+    {
+        let mut chunk = [0; 16 * 1024];
+        let len = match reader.read(&mut chunk) {
+            Ok(len) => len,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => 0,
+            Err(e) => return Err(e.into()),
+        };
+        if len > 0 {
+            self.push(&chunk[..len], stream, f);
+        }
+        Ok(len)
+    }
+
+    /// Buffers a chunk of the stream, received now, and delivers the complete lines.
+    fn push<F>(&mut self, bytes: &[u8], stream: SshStream, f: &mut F)
+    where
+        F: FnMut(SshStream, &[u8], SshTimestamp),
+    {
+        let timestamp = SshTimestamp::since(self.start);
+        self.received = Some(timestamp);
+        self.buffer.extend_from_slice(bytes);
+        while let Some(index) = self.buffer.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=index).collect();
+            let line = line.strip_suffix(b"\n").unwrap_or(&line);
+            f(stream, line.strip_suffix(b"\r").unwrap_or(line), timestamp);
+        }
+    }
+
+    /// Delivers the remaining bytes as a last line, if any, with the time of their receipt.
+    fn flush<F>(&mut self, stream: SshStream, f: &mut F)
+    where
+        F: FnMut(SshStream, &[u8], SshTimestamp),
+    {
+        if let (false, Some(timestamp)) = (self.buffer.is_empty(), self.received) {
+            f(stream, &self.buffer, timestamp);
+            self.buffer.clear();
+        }
+    }
+}
+
+/// Reader that computes the SHA-256 checksum of the bytes read from an inner reader.
+struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.hasher.update(&buf[..len]);
+        Ok(len)
+    }
+}
+
+/// Returns the permission bits of a local file, or `0o644` on platforms without them.
+fn local_mode(metadata: &std::fs::Metadata) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        (metadata.permissions().mode() & 0o7777) as i32
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        0o644
+    }
+}
+
+/// Returns the modification and access times of a local file in seconds since the Unix epoch.
+fn local_times(metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    let seconds = |time: std::io::Result<std::time::SystemTime>| {
+        let duration = time.ok()?.duration_since(std::time::UNIX_EPOCH).ok()?;
+        Some(duration.as_secs())
+    };
+    let mtime = seconds(metadata.modified())?;
+    let atime = seconds(metadata.accessed()).unwrap_or(mtime);
+    Some((mtime, atime))
+}
+
+/// Fails if the name of any environment variable is invalid, i.e. not made of ASCII letters,
+/// digits and underscores, or starting with a digit.
+fn check_env(env: &[(&str, &str)]) -> Result<()> {
+    for (name, _) in env {
+        let mut chars = name.chars();
+        let valid = matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(anyhow::anyhow!(
+                "Invalid environment variable name: {:?}",
+                name
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Returns a remote path as a string slice, which is required to embed it in a command.
+pub(crate) fn utf8_path(path: &Path) -> Result<&str> {
+    path.to_str()
+        .ok_or_else(|| anyhow::anyhow!("Remote path {:?} is not UTF-8", path))
+}
+
+/// Limits the throughput of file transfers, possibly shared by several clients.
+pub(crate) struct RateLimiter {
+    bytes_per_sec: u64,
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    /// Constructs a new `RateLimiter` with a non-zero limit, in bytes per second.
+    pub(crate) fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Reserves a time slot to transfer the specified number of bytes,
+    /// then sleeps until the start of this time slot.
+    fn acquire(&self, bytes: usize) {
+        let now = Instant::now();
+        let start = {
+            let mut next = self.next.lock();
+            let start = (*next).max(now);
+            *next = start + Duration::from_secs_f64(bytes as f64 / self.bytes_per_sec as f64);
+            start
+        };
+        std::thread::sleep(start - now);
+    }
+}
+
+/// Copies a reader into a writer in chunks, respecting the specified rate limiters.
+pub(crate) fn copy_throttled(
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+    limiters: &[Arc<RateLimiter>],
+) -> std::io::Result<u64> {
+    let mut buffer = vec![0; 16 * 1024];
+    let mut total = 0;
+    loop {
+        let len = match reader.read(&mut buffer) {
+            Ok(0) => return Ok(total),
+            Ok(len) => len,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        limiters.iter().for_each(|limiter| limiter.acquire(len));
+        writer.write_all(&buffer[..len])?;
+        total += len as u64;
+    }
+}
+
+/// Reader which respects rate limiters, for transfers through backends other than libssh2.
+pub(crate) struct ThrottledReader<'a, R> {
+    pub(crate) inner: &'a mut R,
+    pub(crate) limiters: Vec<Arc<RateLimiter>>,
+}
+
+impl<R: Read> Read for ThrottledReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.limiters
+            .iter()
+            .for_each(|limiter| limiter.acquire(len));
+        Ok(len)
+    }
+}
+
+/// Captured output stream: its buffer, the number of bytes beyond the limit, and the path
+/// of the temporary file containing the whole stream if it was spilled.
+type Captured = (Vec<u8>, u64, Option<PathBuf>);
+
+/// Output streams of an executed command, which can be read without blocking.
+trait OutputStreams {
+    /// Reads the available bytes of a stream (0 for stdout, 1 for stderr), or fails with
+    /// `WouldBlock` if none are available yet.
+    fn read_stream(&mut self, stream_id: i32, buf: &mut [u8]) -> std::io::Result<usize>;
+
+    /// Returns whether the remote end has sent EOF.
+    fn eof(&self) -> bool;
+}
+
+impl OutputStreams for ssh2::Channel {
+    fn read_stream(&mut self, stream_id: i32, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.stream(stream_id).read(buf)
+    }
+
+    fn eof(&self) -> bool {
+        ssh2::Channel::eof(self)
+    }
+}
+
+/// Reads both output streams of a non-blocking channel concurrently until EOF, into capped
+/// buffers, and records their chunks in arrival order. It fails if no output is received
+/// within the timeout, in milliseconds, unless it's zero.
+fn read_output(
+    channel: &mut impl OutputStreams,
+    max: u64,
+    overflow: SshOverflow,
+    timeout: u64,
+) -> Result<(Captured, Captured, Vec<SshChunk>)> {
+    let mut outputs = read_outputs(std::slice::from_mut(channel), max, overflow, timeout)?;
+    Ok(outputs.pop().unwrap())
+}
+
+/// Reads the output streams of several non-blocking channels concurrently until EOF, like
+/// `read_output`. It fails if no output is received from any channel within the timeout.
+fn read_outputs(
+    channels: &mut [impl OutputStreams],
+    max: u64,
+    overflow: SshOverflow,
+    timeout: u64,
+) -> Result<Vec<(Captured, Captured, Vec<SshChunk>)>> {
+    let streams = [SshStream::Stdout, SshStream::Stderr];
+    let mut collectors: Vec<_> = channels
+        .iter()
+        .map(|_| OutputCollector::new(max, overflow))
+        .collect();
+    let mut done = vec![false; channels.len()];
+    let mut chunk = [0; 32 * 1024];
+    let mut last_received = Instant::now();
+    while done.contains(&false) {
+        let mut idle = true;
+        for (i, channel) in channels.iter_mut().enumerate() {
+            if done[i] {
+                continue;
+            }
+            let mut channel_idle = true;
+            for (index, stream) in streams.iter().enumerate() {
+                let len = match channel.read_stream(index as i32, &mut chunk) {
+                    Ok(len) => len,
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => 0,
+                    Err(e) => return Err(e.into()),
+                };
+                if len > 0 {
+                    channel_idle = false;
+                    collectors[i].push(*stream, &chunk[..len])?;
+                }
+            }
+            if channel_idle && channel.eof() {
+                done[i] = true;
+            }
+            idle &= channel_idle;
+        }
+        if !idle {
+            last_received = Instant::now();
+        } else if !done.contains(&false) {
+            break;
+        } else if timeout != 0 && last_received.elapsed() > Duration::from_millis(timeout) {
+            return Err(anyhow::anyhow!(
+                "Timed out waiting for the output of the command"
+            ));
+        } else {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+    Ok(collectors
+        .into_iter()
+        .map(OutputCollector::finish)
+        .collect())
+}
+
+/// Output of an executed command being collected: both streams into capped buffers,
+/// and their chunks in arrival order.
+struct OutputCollector {
+    buffers: [CappedBuffer; 2],
+    chunks: Vec<SshChunk>,
+    offsets: [u64; 2],
+    start: Instant,
+}
+
+impl OutputCollector {
+    fn new(max: u64, overflow: SshOverflow) -> Self {
+        OutputCollector {
+            buffers: [
+                CappedBuffer::new(max, overflow),
+                CappedBuffer::new(max, overflow),
+            ],
+            chunks: Vec::new(),
+            offsets: [0; 2],
+            start: Instant::now(),
+        }
+    }
+
+    /// Appends bytes received from a stream to its buffer, and records them as a chunk with the
+    /// time of their receipt.
+    fn push(&mut self, stream: SshStream, bytes: &[u8]) -> Result<()> {
+        let index = match stream {
+            SshStream::Stdout => 0,
+            SshStream::Stderr => 1,
+        };
+        self.chunks.push(SshChunk {
+            stream,
+            offset: self.offsets[index],
+            len: bytes.len(),
+            timestamp: Some(SshTimestamp::since(self.start)),
+        });
+        self.offsets[index] += bytes.len() as u64;
+        self.buffers[index].push(bytes)
+    }
+
+    /// Returns the captured stdout and stderr, and the chunks in arrival order.
+    fn finish(self) -> (Captured, Captured, Vec<SshChunk>) {
+        let [stdout, stderr] = self.buffers;
+        (stdout.finish(), stderr.finish(), self.chunks)
+    }
+}
+
+/// Buffer of an output stream, capped at a maximum number of bytes beyond which the output
+/// is handled according to an overflow policy.
+struct CappedBuffer {
+    buffer: Vec<u8>,
+    dropped: u64,
+    max: u64,
+    overflow: SshOverflow,
+    spill: Option<(std::fs::File, PathBuf)>,
+}
+
+impl CappedBuffer {
+    fn new(max: u64, overflow: SshOverflow) -> Self {
+        CappedBuffer {
+            buffer: Vec::new(),
+            dropped: 0,
+            max,
+            overflow,
+            spill: None,
+        }
+    }
+
+    /// Appends bytes to the buffer, or handles them according to the overflow policy
+    /// beyond the maximum. A temporary file is only created if the buffer overflows.
+    fn push(&mut self, bytes: &[u8]) -> Result<()> {
+        let room = match self.max {
+            0 => bytes.len(),
+            max => (max - self.buffer.len() as u64).min(bytes.len() as u64) as usize,
+        };
+        let (kept, rest) = bytes.split_at(room);
+        self.buffer.extend_from_slice(kept);
+        if rest.is_empty() {
+            return Ok(());
+        }
+        self.dropped += rest.len() as u64;
+        if self.overflow == SshOverflow::Spill {
+            if self.spill.is_none() {
+                let (mut file, path) = create_spill_file()?;
+                file.write_all(&self.buffer)?;
+                self.spill = Some((file, path));
+            }
+            self.spill.as_mut().unwrap().0.write_all(rest)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the buffer, the number of dropped bytes, and the path of the spill file, if any.
+    fn finish(self) -> Captured {
+        (self.buffer, self.dropped, self.spill.map(|(_, path)| path))
+    }
+}
+
+/// Creates a new, uniquely named temporary file for spilled output.
+fn create_spill_file() -> Result<(std::fs::File, PathBuf)> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    loop {
+        let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let name = format!("massh-{}-{}.out", std::process::id(), count);
+        let path = std::env::temp_dir().join(name);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((file, path)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Performs agent authentication with the agent listening on a socket if specified (or the default
+/// agent otherwise), and with the key matching a SHA-256 fingerprint or comment if specified
+/// (or each key in order until one is accepted otherwise).
+fn userauth_agent(
+    session: &Session,
+    user: &str,
+    socket: Option<&Path>,
+    key: Option<&str>,
+) -> Result<()> {
+    let mut agent = session.agent()?;
+    let name = match socket {
+        Some(socket) => {
+            agent.set_identity_path(socket)?;
+            format!("agent {:?}", socket)
+        }
+        None => String::from("the agent"),
+    };
+    agent
+        .connect()
+        .map_err(|e| anyhow::anyhow!("Failed to connect to {}: {}", name, e))?;
+    agent.list_identities()?;
+    let identities: Vec<_> = agent
+        .identities()?
+        .into_iter()
+        .filter(|identity| match key {
+            Some(key) => identity.comment() == key || sha256_fingerprint(identity.blob()) == key,
+            None => true,
+        })
+        .collect();
+    if identities.is_empty() {
+        return Err(match key {
+            Some(key) => anyhow::anyhow!("No key matching {:?} found in {}", key, name),
+            None => anyhow::anyhow!("No keys found in {}", name),
+        });
+    }
+    let mut result = Ok(());
+    for identity in &identities {
+        result = agent.userauth(user, identity);
+        if result.is_ok() {
+            break;
+        }
+    }
+    Ok(result?)
+}
+
+/// Secrets already retrieved by `run_secret_command`, keyed by command.
+static SECRETS: Mutex<Vec<(String, String)>> = parking_lot::const_mutex(Vec::new());
+
+/// Runs a local command and returns its standard output, without a trailing newline, as a secret.
+///
+/// The lock is held while the command runs, so that concurrent clients sharing the same command
+/// don't prompt the user (e.g. for a GPG passphrase) more than once.
+pub(crate) fn run_secret_command(command: &str) -> Result<String> {
+    let mut secrets = SECRETS.lock();
+    if let Some((_, secret)) = secrets.iter().find(|(cmd, _)| cmd == command) {
+        return Ok(secret.clone());
+    }
+
+    let output = if cfg!(windows) {
+        Command::new("cmd").arg("/C").arg(command).output()?
+    } else {
+        Command::new("sh").arg("-c").arg(command).output()?
+    };
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow::anyhow!(
+            "Secret command failed ({}): {}",
+            output.status,
+            stderr.trim_end()
+        ));
+    }
+
+    let mut secret = String::from_utf8(output.stdout)?;
+    if secret.ends_with('\n') {
+        secret.pop();
+        if secret.ends_with('\r') {
+            secret.pop();
+        }
+    }
+    secrets.push((command.to_owned(), secret.clone()));
+    Ok(secret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fake output streams of a command which floods stderr, and only writes to stdout once
+    /// stderr was read, like a server whose channel window was exhausted by stderr.
+    struct StderrFlood {
+        stdout: Vec<u8>,
+        stderr: Vec<u8>,
+    }
+
+    impl OutputStreams for StderrFlood {
+        fn read_stream(&mut self, stream_id: i32, buf: &mut [u8]) -> std::io::Result<usize> {
+            let stream = match stream_id {
+                0 if !self.stderr.is_empty() => {
+                    return Err(std::io::ErrorKind::WouldBlock.into());
+                }
+                0 => &mut self.stdout,
+                _ => &mut self.stderr,
+            };
+            let len = buf.len().min(stream.len());
+            buf[..len].copy_from_slice(&stream[..len]);
+            stream.drain(..len);
+            Ok(len)
+        }
+
+        fn eof(&self) -> bool {
+            self.stdout.is_empty() && self.stderr.is_empty()
+        }
+    }
+
+    #[test]
+    fn read_output_does_not_deadlock_on_stderr_flood() {
+        let mut streams = StderrFlood {
+            stdout: b"done\n".to_vec(),
+            stderr: vec![b'x'; 4 * 1024 * 1024],
+        };
+        let (stdout, stderr, chunks) =
+            read_output(&mut streams, 0, SshOverflow::Truncate, 1000).unwrap();
+        assert_eq!(stdout.0, b"done\n");
+        assert_eq!(stderr.0.len(), 4 * 1024 * 1024);
+        assert_eq!(chunks.last().unwrap().stream, SshStream::Stdout);
+    }
 }
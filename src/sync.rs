@@ -0,0 +1,691 @@
+use crate::shell;
+use crate::ssh_client::utf8_path;
+use crate::SshClient;
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread::JoinHandle;
+use std::time::UNIX_EPOCH;
+
+/// Maximum number of paths passed to a single `mkdir` or `rm` command.
+const BATCH_SIZE: usize = 100;
+
+/// Method used to transfer the files of a directory.
+///
+/// The default is [`DirTransfer::Scp`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DirTransfer {
+    /// Transfers each file over its own SCP channel, honoring all upload options.
+    #[default]
+    Scp,
+    /// Streams a gzipped `tar` archive over a single channel, which is much faster for many
+    /// small files. It requires `tar` on both machines, and it preserves the mode and times of
+    /// files, but it ignores the atomic, checksum, mode and owner options of uploads.
+    Tar,
+}
+
+/// Options of a directory synchronization.
+///
+/// See [`SshClient::sync_dir`] for more details.
+#[derive(Clone, Debug, Default)]
+pub struct SyncOptions {
+    /// Whether to compare files by SHA-256 checksum instead of size and modification time.
+    pub checksum: bool,
+    /// Whether to delete remote files and directories which don't exist locally.
+    pub delete: bool,
+    /// Whether to only report what would be changed, without changing anything.
+    pub dry_run: bool,
+    /// Method used to upload the missing or different files.
+    pub transfer: DirTransfer,
+}
+
+/// Changes made by a directory synchronization, with paths relative to the synchronized directory.
+///
+/// In dry-run mode, these are the changes that would have been made.
+#[derive(Clone, Debug, Default)]
+pub struct SyncReport {
+    /// Remote directories that were created.
+    pub created: Vec<PathBuf>,
+    /// Remote files and directories that were deleted.
+    pub deleted: Vec<PathBuf>,
+    /// Number of remote files that were already up to date.
+    pub unchanged: usize,
+    /// Files that were uploaded because they were missing or different on the remote host.
+    pub uploaded: Vec<PathBuf>,
+}
+
+/// Type, size and modification time (in seconds since the Unix epoch) of a directory entry.
+#[derive(Clone, Copy, PartialEq)]
+struct Entry {
+    is_dir: bool,
+    size: u64,
+    mtime: u64,
+}
+
+impl SshClient {
+    /// Attempts to synchronize a remote directory with a local directory.
+    ///
+    /// Only the files that are missing or different on the remote host are uploaded, with the mode
+    /// and times of the local files. By default, files are compared by size and modification time.
+    /// See [`SyncOptions`] to compare them by checksum, to delete extraneous remote files, or to
+    /// only report what would be changed.
+    ///
+    /// The remote host must provide GNU `find`, as well as `sha256sum` in checksum mode.
+    ///
+    /// Note that this method implicitly calls [`SshClient::connect`] if no session was
+    /// established prior. Otherwise, it reuses the cached session
+    /// (see [`SshClient::set_keepalive`] to check its health first).
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{SshClient, SyncOptions};
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// let options = SyncOptions {
+    ///     delete: true,
+    ///     ..SyncOptions::default()
+    /// };
+    /// if let Ok(report) = ssh.sync_dir("local_dir", "remote_dir", &options) {
+    ///     println!("uploaded {} files", report.uploaded.len());
+    /// }
+    /// ```
+    pub fn sync_dir<P: AsRef<Path>>(
+        &mut self,
+        local_path: P,
+        remote_path: P,
+        options: &SyncOptions,
+    ) -> Result<SyncReport> {
+        self.get_shell().ensure_posix()?;
+        let (local_path, remote_path) = (local_path.as_ref(), remote_path.as_ref());
+        let remote_root = match utf8_path(remote_path)?.trim_end_matches('/') {
+            "" => "/".to_string(),
+            root => root.to_string(),
+        };
+        let remote_join =
+            |relative: &str| format!("{}/{}", remote_root.trim_end_matches('/'), relative);
+
+        // List the local and remote directories.
+        let mut local = BTreeMap::new();
+        list_local(local_path, "", &mut Vec::new(), &mut local)?;
+        let remote = self.list_remote(&remote_root)?;
+        let remote_checksums = if options.checksum {
+            self.checksum_remote(&remote_root)?
+        } else {
+            HashMap::new()
+        };
+
+        // Compare both listings, knowing that a parent always precedes its children.
+        let mut report = SyncReport::default();
+        let mut replaced = Vec::new();
+        for (relative, entry) in &local {
+            let remote_entry = remote.get(relative);
+            if let Some(remote_entry) = remote_entry {
+                if remote_entry.is_dir != entry.is_dir {
+                    replaced.push(relative.clone());
+                }
+            }
+            let same_type = remote_entry.map(|e| e.is_dir == entry.is_dir) == Some(true);
+            if entry.is_dir {
+                if !same_type {
+                    report.created.push(PathBuf::from(relative));
+                }
+            } else if same_type && options.checksum {
+                let checksum = checksum_local(&local_path.join(relative))?;
+                if remote_checksums.get(relative) == Some(&checksum) {
+                    report.unchanged += 1;
+                } else {
+                    report.uploaded.push(PathBuf::from(relative));
+                }
+            } else if same_type {
+                let remote_entry = remote_entry.unwrap();
+                if (remote_entry.size, remote_entry.mtime) == (entry.size, entry.mtime) {
+                    report.unchanged += 1;
+                } else {
+                    report.uploaded.push(PathBuf::from(relative));
+                }
+            } else {
+                report.uploaded.push(PathBuf::from(relative));
+            }
+        }
+        if options.delete {
+            let mut deleted = BTreeSet::new();
+            for relative in remote.keys() {
+                let ancestor_deleted = relative
+                    .match_indices('/')
+                    .any(|(i, _)| deleted.contains(&relative[..i]));
+                if !relative.is_empty() && !local.contains_key(relative) && !ancestor_deleted {
+                    deleted.insert(relative.as_str());
+                }
+            }
+            report.deleted = deleted.into_iter().map(PathBuf::from).collect();
+        }
+        if options.dry_run {
+            return Ok(report);
+        }
+
+        // Remove remote entries whose type differs from the local ones, then create the missing
+        // directories, upload the missing or different files, and delete the extraneous entries.
+        let replaced: Vec<String> = replaced.iter().map(|r| remote_join(r)).collect();
+        self.batch("rm -rf --", &replaced, "Removal failed")?;
+        let mut created: Vec<String> = report
+            .created
+            .iter()
+            .map(|r| remote_join(&r.to_string_lossy()))
+            .collect();
+        if !remote.contains_key("") {
+            created.insert(0, remote_root.clone());
+        }
+        self.batch("mkdir -p --", &created, "Directory creation failed")?;
+        match options.transfer {
+            DirTransfer::Scp => {
+                for relative in &report.uploaded {
+                    let remote_file = remote_join(&relative.to_string_lossy());
+                    self.upload_file(&local_path.join(relative), Path::new(&remote_file), true)?;
+                }
+            }
+            DirTransfer::Tar if !report.uploaded.is_empty() => {
+                self.upload_tar(local_path, &remote_root, &report.uploaded)?;
+            }
+            DirTransfer::Tar => {}
+        }
+        let deleted: Vec<String> = report
+            .deleted
+            .iter()
+            .map(|r| remote_join(&r.to_string_lossy()))
+            .collect();
+        self.batch("rm -rf --", &deleted, "Deletion failed")?;
+
+        // Return successfully.
+        Ok(report)
+    }
+
+    /// Attempts to upload a local directory recursively to the configured host.
+    ///
+    /// The remote directory is created if needed, and existing remote files are overwritten.
+    /// See [`DirTransfer`] for the available transfer methods.
+    ///
+    /// Note that this method implicitly calls [`SshClient::connect`] if no session was
+    /// established prior. Otherwise, it reuses the cached session
+    /// (see [`SshClient::set_keepalive`] to check its health first).
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{DirTransfer, SshClient};
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// if ssh.upload_dir("local_dir", "remote_dir", DirTransfer::Tar).is_ok() {
+    ///     println!("upload worked!");
+    /// }
+    /// ```
+    pub fn upload_dir<P: AsRef<Path>>(
+        &mut self,
+        local_path: P,
+        remote_path: P,
+        transfer: DirTransfer,
+    ) -> Result<()> {
+        self.get_shell().ensure_posix()?;
+        let (local_path, remote_root) = (local_path.as_ref(), utf8_path(remote_path.as_ref())?);
+        match transfer {
+            DirTransfer::Scp => {
+                let mut local = BTreeMap::new();
+                list_local(local_path, "", &mut Vec::new(), &mut local)?;
+                let mut dirs = vec![remote_root.to_string()];
+                for (relative, entry) in &local {
+                    if entry.is_dir {
+                        dirs.push(format!("{}/{}", remote_root, relative));
+                    }
+                }
+                self.batch("mkdir -p --", &dirs, "Directory creation failed")?;
+                let preserve = self.get_preserve_attributes();
+                for (relative, entry) in &local {
+                    if !entry.is_dir {
+                        let remote_file = format!("{}/{}", remote_root, relative);
+                        self.upload_file(
+                            &local_path.join(relative),
+                            remote_file.as_ref(),
+                            preserve,
+                        )?;
+                    }
+                }
+                Ok(())
+            }
+            DirTransfer::Tar => self.upload_tar(local_path, remote_root, &[PathBuf::from(".")]),
+        }
+    }
+
+    /// Attempts to download a remote directory recursively from the configured host.
+    ///
+    /// The local directory is created if needed, and existing local files are overwritten.
+    /// See [`DirTransfer`] for the available transfer methods. The remote host must provide
+    /// GNU `find` with [`DirTransfer::Scp`].
+    ///
+    /// Note that this method implicitly calls [`SshClient::connect`] if no session was
+    /// established prior. Otherwise, it reuses the cached session
+    /// (see [`SshClient::set_keepalive`] to check its health first).
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::{DirTransfer, SshClient};
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// if ssh.download_dir("remote_dir", "local_dir", DirTransfer::Tar).is_ok() {
+    ///     println!("download worked!");
+    /// }
+    /// ```
+    pub fn download_dir<P: AsRef<Path>>(
+        &mut self,
+        remote_path: P,
+        local_path: P,
+        transfer: DirTransfer,
+    ) -> Result<()> {
+        self.get_shell().ensure_posix()?;
+        let (remote_root, local_path) = (utf8_path(remote_path.as_ref())?, local_path.as_ref());
+        std::fs::create_dir_all(local_path)?;
+        match transfer {
+            DirTransfer::Scp => {
+                let remote = self.list_remote(remote_root)?;
+                if !remote.contains_key("") {
+                    return Err(anyhow::anyhow!(
+                        "Remote directory {:?} not found",
+                        remote_root
+                    ));
+                }
+                for (relative, entry) in &remote {
+                    if entry.is_dir {
+                        std::fs::create_dir_all(local_path.join(relative))?;
+                    } else {
+                        let remote_file = format!("{}/{}", remote_root, relative);
+                        self.scp_download(Path::new(&remote_file), &local_path.join(relative))?;
+                    }
+                }
+                Ok(())
+            }
+            DirTransfer::Tar => {
+                let mut child = Command::new("tar")
+                    .arg("xzf")
+                    .arg("-")
+                    .arg("-C")
+                    .arg(local_path)
+                    .stdin(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .spawn()?;
+                let stderr = read_stderr(&mut child);
+                let command = format!("tar czf - -C {} .", shell::quote(remote_root));
+                let mut stdin = child.stdin.take().unwrap();
+                let result = self.execute_with_output(&command, &mut stdin, "Remote tar failed");
+                drop(stdin);
+                let local_result = wait_local_tar(child, stderr);
+                result?;
+                local_result
+            }
+        }
+    }
+
+    /// Attempts to download all remote files matching a glob pattern from the configured host.
+    ///
+    /// The pattern is expanded by the remote shell, so it supports the `*`, `?` and `[...]`
+    /// wildcards, and it only matches regular files. Each file is downloaded under `local_path`
+    /// at its path relative to the pattern's base directory (i.e. its longest leading path
+    /// without wildcards), recreating the intermediate directories. It fails if no file matches.
+    ///
+    /// It returns the local paths of the downloaded files.
+    ///
+    /// Note that this method implicitly calls [`SshClient::connect`] if no session was
+    /// established prior. Otherwise, it reuses the cached session
+    /// (see [`SshClient::set_keepalive`] to check its health first).
+    ///
+    /// ## Example
+    /// ```no_run
+    /// # use massh::SshClient;
+    /// # use std::net::Ipv4Addr;
+    /// let mut ssh = SshClient::from("username", (Ipv4Addr::LOCALHOST, 22));
+    ///
+    /// if let Ok(paths) = ssh.download_glob("/var/log/app/*.log", "logs") {
+    ///     println!("downloaded {} files", paths.len());
+    /// }
+    /// ```
+    pub fn download_glob<P: AsRef<Path>>(
+        &mut self,
+        pattern: &str,
+        local_path: P,
+    ) -> Result<Vec<PathBuf>> {
+        self.get_shell().ensure_posix()?;
+
+        // Expand the pattern remotely, listing the matching regular files.
+        let command = format!(
+            "set -- {}; for f; do [ -f \"$f\" ] && printf '%s\\0' \"$f\"; done; true",
+            glob_quote(pattern)
+        );
+        let output = self.execute_checked(&command, "Remote glob expansion failed")?;
+        if output.stdout_dropped > 0 {
+            return Err(anyhow::anyhow!(
+                "Remote glob expansion exceeded the output limit by {} bytes",
+                output.stdout_dropped
+            ));
+        }
+        let matches: Vec<String> = output
+            .stdout
+            .split(|&byte| byte == 0)
+            .filter(|path| !path.is_empty())
+            .map(|path| String::from_utf8_lossy(path).into_owned())
+            .collect();
+        if matches.is_empty() {
+            return Err(anyhow::anyhow!("No remote file matches {:?}", pattern));
+        }
+
+        // Download each file at its path relative to the pattern's base directory.
+        let base = glob_base(pattern);
+        let mut paths = Vec::new();
+        for remote_file in matches {
+            let relative = remote_file
+                .strip_prefix(base)
+                .unwrap_or(&remote_file)
+                .trim_start_matches('/');
+            let local_file = local_path.as_ref().join(relative);
+            if let Some(parent) = local_file.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            self.scp_download(Path::new(&remote_file), &local_file)?;
+            paths.push(local_file);
+        }
+        Ok(paths)
+    }
+
+    /// Uploads files and directories, given relative to a local directory, to a remote
+    /// directory with a single `tar` stream.
+    fn upload_tar(
+        &mut self,
+        local_path: &Path,
+        remote_root: &str,
+        paths: &[PathBuf],
+    ) -> Result<()> {
+        let mut child = Command::new("tar")
+            .arg("czf")
+            .arg("-")
+            .arg("-C")
+            .arg(local_path)
+            .args(paths.iter().map(|path| Path::new(".").join(path)))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let stderr = read_stderr(&mut child);
+        let command = format!(
+            "mkdir -p -- {0} && tar xzf - -C {0}",
+            shell::quote(remote_root)
+        );
+        let mut stdout = child.stdout.take().unwrap();
+        let result = self.execute_with_input(&command, &mut stdout, "Remote tar failed");
+        drop(stdout);
+        wait_local_tar(child, stderr)?;
+        result
+    }
+
+    /// Lists the remote directory recursively, including itself as the empty path if it exists.
+    fn list_remote(&mut self, root: &str) -> Result<BTreeMap<String, Entry>> {
+        let command = format!(
+            "if [ -d {0} ]; then cd {0} && find . -printf '%y %s %T@ %P\\0'; fi",
+            shell::quote(root)
+        );
+        let output = self.execute_checked(&command, "Remote listing failed")?;
+        if output.stdout_dropped > 0 {
+            return Err(anyhow::anyhow!(
+                "Remote listing exceeded the output limit by {} bytes",
+                output.stdout_dropped
+            ));
+        }
+        let mut entries = BTreeMap::new();
+        for record in output.stdout.split(|&byte| byte == 0) {
+            if record.is_empty() {
+                continue;
+            }
+            let record = String::from_utf8_lossy(record);
+            let mut fields = record.splitn(4, ' ');
+            let kind = fields.next().unwrap_or_default();
+            let size = fields.next().and_then(|f| f.parse().ok()).unwrap_or(0);
+            let mtime = fields
+                .next()
+                .and_then(|f| f.split('.').next()?.parse().ok())
+                .unwrap_or(0);
+            let relative = fields.next().unwrap_or_default().to_string();
+            let is_dir = kind == "d";
+            entries.insert(
+                relative,
+                Entry {
+                    is_dir,
+                    size,
+                    mtime,
+                },
+            );
+        }
+        Ok(entries)
+    }
+
+    /// Computes the SHA-256 checksums of the files in the remote directory, by relative path.
+    fn checksum_remote(&mut self, root: &str) -> Result<HashMap<String, String>> {
+        let command = format!(
+            "if [ -d {0} ]; then cd {0} && find . -type f -exec sha256sum -- {{}} +; fi",
+            shell::quote(root)
+        );
+        let output = self.execute_checked(&command, "Remote checksum failed")?;
+        if output.stdout_dropped > 0 {
+            return Err(anyhow::anyhow!(
+                "Remote checksum exceeded the output limit by {} bytes",
+                output.stdout_dropped
+            ));
+        }
+        let stdout = output.stdout_lossy();
+        let checksums = stdout
+            .lines()
+            .filter_map(|line| {
+                // Names containing a backslash or a line break are escaped, and their line is
+                // prefixed with a backslash.
+                let (escaped, line) = match line.strip_prefix('\\') {
+                    Some(line) => (true, line),
+                    None => (false, line),
+                };
+                let (checksum, path) = line.split_once("  ")?;
+                let path = match escaped {
+                    true => unescape_sha256sum(path)?,
+                    false => path.to_string(),
+                };
+                let relative = path.strip_prefix("./")?;
+                Some((relative.to_string(), checksum.to_string()))
+            })
+            .collect();
+        Ok(checksums)
+    }
+
+    /// Runs a command on batches of remote paths, which are quoted and appended to it.
+    fn batch(&mut self, command: &str, paths: &[String], context: &str) -> Result<()> {
+        for chunk in paths.chunks(BATCH_SIZE) {
+            let args = shell::quote_args(chunk);
+            self.execute_checked(&format!("{} {}", command, args), context)?;
+        }
+        Ok(())
+    }
+}
+
+/// Unescapes a name escaped by `sha256sum`, or returns `None` if it's invalid.
+fn unescape_sha256sum(name: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(name.len());
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        unescaped.push(match c {
+            '\\' => match chars.next()? {
+                '\\' => '\\',
+                'n' => '\n',
+                'r' => '\r',
+                _ => return None,
+            },
+            c => c,
+        });
+    }
+    Some(unescaped)
+}
+
+/// Lists the local directory recursively, with paths relative to the synchronized directory.
+///
+/// Symbolic links are followed, except to the directories being listed (i.e. `ancestors`), so
+/// that a cycle of links doesn't recurse forever.
+fn list_local(
+    root: &Path,
+    prefix: &str,
+    ancestors: &mut Vec<DirId>,
+    entries: &mut BTreeMap<String, Entry>,
+) -> Result<()> {
+    ancestors.push(dir_id(&root.join(prefix))?);
+    let mut children = Vec::new();
+    for child in std::fs::read_dir(root.join(prefix))? {
+        children.push(child?);
+    }
+    for child in children {
+        let name = child.file_name();
+        let name = name
+            .to_str()
+            .ok_or_else(|| anyhow::anyhow!("Local path {:?} is not UTF-8", child.path()))?;
+        let relative = if prefix.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}/{}", prefix, name)
+        };
+        let metadata = std::fs::metadata(child.path())?;
+        if metadata.is_dir() && ancestors.contains(&dir_id(&child.path())?) {
+            continue;
+        }
+        let mtime = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+        let entry = Entry {
+            is_dir: metadata.is_dir(),
+            size: if metadata.is_dir() { 0 } else { metadata.len() },
+            mtime,
+        };
+        entries.insert(relative.clone(), entry);
+        if entry.is_dir {
+            list_local(root, &relative, ancestors, entries)?;
+        }
+    }
+    ancestors.pop();
+    Ok(())
+}
+
+/// Identity of a local directory: its device and inode on Unix, or its canonical path elsewhere.
+#[cfg(unix)]
+type DirId = (u64, u64);
+#[cfg(not(unix))]
+type DirId = PathBuf;
+
+/// Returns the identity of a local directory, following symbolic links.
+#[cfg(unix)]
+fn dir_id(path: &Path) -> Result<DirId> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = std::fs::metadata(path)?;
+    Ok((metadata.dev(), metadata.ino()))
+}
+
+/// Returns the identity of a local directory, following symbolic links.
+#[cfg(not(unix))]
+fn dir_id(path: &Path) -> Result<DirId> {
+    Ok(std::fs::canonicalize(path)?)
+}
+
+/// Returns whether a character is a wildcard of a glob pattern.
+fn is_wildcard(c: char) -> bool {
+    matches!(c, '*' | '?' | '[')
+}
+
+/// Quotes a glob pattern for a POSIX shell, leaving only its wildcards unquoted.
+///
+/// Bracket expressions are kept verbatim if they only contain characters which are safe to leave
+/// unquoted. Otherwise, their opening bracket is quoted and matched literally.
+fn glob_quote(pattern: &str) -> String {
+    let mut quoted = String::new();
+    let mut literal = String::new();
+    let mut chars = pattern.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let bracket = if c == '[' {
+            pattern[i + 1..]
+                .find(']')
+                .map(|end| &pattern[i..i + end + 2])
+        } else {
+            None
+        };
+        let safe = |s: &str| {
+            s.len() > 2
+                && s[1..s.len() - 1]
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '!' | '^' | '_' | '.'))
+        };
+        let token = match bracket {
+            Some(bracket) if safe(bracket) => Some(bracket),
+            None if c == '*' || c == '?' => Some(&pattern[i..i + 1]),
+            _ => None,
+        };
+        match token {
+            Some(token) => {
+                if !literal.is_empty() {
+                    quoted.push_str(&shell::quote(&literal));
+                    literal.clear();
+                }
+                quoted.push_str(token);
+                for _ in 1..token.len() {
+                    chars.next();
+                }
+            }
+            None => literal.push(c),
+        }
+    }
+    if !literal.is_empty() || quoted.is_empty() {
+        quoted.push_str(&shell::quote(&literal));
+    }
+    quoted
+}
+
+/// Returns the base directory of a glob pattern, i.e. its longest leading path without wildcards.
+fn glob_base(pattern: &str) -> &str {
+    let end = pattern.find(is_wildcard).unwrap_or(pattern.len());
+    match pattern[..end].rfind('/') {
+        Some(0) => "/",
+        Some(slash) => &pattern[..slash],
+        None => "",
+    }
+}
+
+/// Reads the standard error of a local process on a thread, so that the process can't block
+/// on it while its other streams are in use.
+fn read_stderr(child: &mut Child) -> JoinHandle<Vec<u8>> {
+    let mut stderr = child.stderr.take().unwrap();
+    std::thread::spawn(move || {
+        let mut buffer = Vec::new();
+        let _ = stderr.read_to_end(&mut buffer);
+        buffer
+    })
+}
+
+/// Waits for a local `tar` process, and fails with its standard error, read by
+/// [`read_stderr`], if its exit status is nonzero.
+fn wait_local_tar(mut child: Child, stderr: JoinHandle<Vec<u8>>) -> Result<()> {
+    let status = child.wait()?;
+    let stderr = stderr.join().unwrap_or_default();
+    if !status.success() {
+        let stderr = String::from_utf8_lossy(&stderr);
+        return Err(anyhow::anyhow!("Local tar failed: {}", stderr.trim_end()));
+    }
+    Ok(())
+}
+
+/// Computes the SHA-256 checksum of a local file in lowercase hexadecimal.
+fn checksum_local(path: &Path) -> Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}